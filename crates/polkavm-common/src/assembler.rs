@@ -1,4 +1,4 @@
-use crate::program::{Instruction, Reg};
+use crate::program::{Instruction, InstructionFormat, InstructionSet, Reg, ISA32_V1};
 use crate::utils::{parse_imm, parse_immediate, parse_reg, ParsedImmediate};
 use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
@@ -164,6 +164,61 @@ fn parse_condition(text: &str) -> Option<Condition> {
 }
 
 pub fn assemble(code: &str) -> Result<Vec<u8>, String> {
+    assemble_impl(code, true).map(|(blob, _)| blob)
+}
+
+/// Assembles a 32-bit program.
+///
+/// The instructions which don't have an explicit `i32` marker are emitted as their 32-bit variants,
+/// so the same source can be assembled for both bitnesses.
+pub fn assemble_32bit(code: &str) -> Result<Vec<u8>, String> {
+    assemble_impl(code, false).map(|(blob, _)| blob)
+}
+
+/// Assembles a program, also returning the index of the basic block which each of its labels points to.
+///
+/// This includes the labels which aren't exported, which don't otherwise end up in the program.
+pub fn assemble_with_labels(code: &str, is_64_bit: bool) -> Result<(Vec<u8>, BTreeMap<String, u32>), String> {
+    assemble_impl(code, is_64_bit)
+}
+
+/// Replaces a 64-bit instruction with its 32-bit counterpart.
+fn lower_to_32bit(instruction: Instruction) -> Result<Instruction, String> {
+    use Instruction as I;
+    let instruction = match instruction {
+        I::add_64(dst, src1, src2) => I::add_32(dst, src1, src2),
+        I::sub_64(dst, src1, src2) => I::sub_32(dst, src1, src2),
+        I::mul_64(dst, src1, src2) => I::mul_32(dst, src1, src2),
+        I::div_unsigned_64(dst, src1, src2) => I::div_unsigned_32(dst, src1, src2),
+        I::div_signed_64(dst, src1, src2) => I::div_signed_32(dst, src1, src2),
+        I::rem_unsigned_64(dst, src1, src2) => I::rem_unsigned_32(dst, src1, src2),
+        I::rem_signed_64(dst, src1, src2) => I::rem_signed_32(dst, src1, src2),
+        I::shift_logical_left_64(dst, src1, src2) => I::shift_logical_left_32(dst, src1, src2),
+        I::shift_logical_right_64(dst, src1, src2) => I::shift_logical_right_32(dst, src1, src2),
+        I::shift_arithmetic_right_64(dst, src1, src2) => I::shift_arithmetic_right_32(dst, src1, src2),
+        I::add_imm_64(dst, src, imm) => I::add_imm_32(dst, src, imm),
+        I::mul_imm_64(dst, src, imm) => I::mul_imm_32(dst, src, imm),
+        I::negate_and_add_imm_64(dst, src, imm) => I::negate_and_add_imm_32(dst, src, imm),
+        I::shift_logical_left_imm_64(dst, src, imm) => I::shift_logical_left_imm_32(dst, src, imm),
+        I::shift_logical_right_imm_64(dst, src, imm) => I::shift_logical_right_imm_32(dst, src, imm),
+        I::shift_arithmetic_right_imm_64(dst, src, imm) => I::shift_arithmetic_right_imm_32(dst, src, imm),
+        I::shift_logical_left_imm_alt_64(dst, src, imm) => I::shift_logical_left_imm_alt_32(dst, src, imm),
+        I::shift_logical_right_imm_alt_64(dst, src, imm) => I::shift_logical_right_imm_alt_32(dst, src, imm),
+        I::shift_arithmetic_right_imm_alt_64(dst, src, imm) => I::shift_arithmetic_right_imm_alt_32(dst, src, imm),
+        instruction => instruction,
+    };
+
+    if ISA32_V1.opcode_from_u8(instruction.opcode() as u8).is_none() {
+        return Err(format!(
+            "instruction is not supported in 32-bit programs: '{}'",
+            instruction.display(&InstructionFormat::default())
+        ));
+    }
+
+    Ok(instruction)
+}
+
+fn assemble_impl(code: &str, is_64_bit: bool) -> Result<(Vec<u8>, BTreeMap<String, u32>), String> {
     enum MaybeInstruction {
         Instruction(Instruction),
         Jump(String),
@@ -923,7 +978,15 @@ pub fn assemble(code: &str) -> Result<Vec<u8>, String> {
         };
     }
 
-    let mut builder = crate::writer::ProgramBlobBuilder::new_64bit();
+    let mut builder = if is_64_bit {
+        crate::writer::ProgramBlobBuilder::new_64bit()
+    } else {
+        for instruction in &mut code {
+            *instruction = lower_to_32bit(*instruction)?;
+        }
+
+        crate::writer::ProgramBlobBuilder::new()
+    };
     builder.set_ro_data(ro_data);
     builder.set_ro_data_size(ro_data_size);
     builder.set_rw_data(rw_data);
@@ -934,13 +997,13 @@ pub fn assemble(code: &str) -> Result<Vec<u8>, String> {
         builder.add_export_by_basic_block(target_index, label.as_bytes());
     }
 
-    Ok(builder.to_vec())
+    let labels = label_to_index.into_iter().map(|(label, index)| (label.to_owned(), index)).collect();
+    Ok((builder.to_vec(), labels))
 }
 
 #[cfg(test)]
 #[track_caller]
 fn assert_assembler(input: &str, expected_output: &str) {
-    use crate::program::InstructionFormat;
     use alloc::string::ToString;
 
     let expected_output_clean: Vec<_> = expected_output.trim().split('\n').map(|line| line.trim()).collect();
@@ -987,3 +1050,54 @@ fn test_assembler_basics() {
     ",
     );
 }
+
+#[test]
+fn test_assembler_32bit() {
+    let blob = assemble_32bit(
+        "
+        a0 = a1 + a2
+        a3 = a4 << 3
+        i32 a5 = a0 - a1
+    ",
+    )
+    .unwrap();
+
+    let program = crate::program::ProgramBlob::parse(blob.into()).unwrap();
+    assert!(!program.is_64_bit());
+    let output: Vec<_> = program
+        .instructions(ISA32_V1)
+        .take_while(|inst| (inst.offset.0 as usize) < program.code().len())
+        .map(|inst| inst.kind)
+        .collect();
+    assert_eq!(
+        output,
+        [
+            Instruction::add_32(Reg::A0.into(), Reg::A1.into(), Reg::A2.into()),
+            Instruction::shift_logical_left_imm_32(Reg::A3.into(), Reg::A4.into(), 3),
+            Instruction::sub_32(Reg::A5.into(), Reg::A0.into(), Reg::A1.into()),
+        ]
+    );
+
+    let error = assemble_32bit("a0 = u64 [0x20000]").unwrap_err();
+    assert!(error.contains("not supported in 32-bit programs"), "{error}");
+}
+
+#[test]
+fn test_assembler_labels() {
+    let (_, labels) = assemble_with_labels(
+        "
+        pub @main:
+            a0 = 1
+            jump @next
+        @next:
+            a1 = 2
+        @fallthrough:
+            trap
+    ",
+        true,
+    )
+    .unwrap();
+
+    let labels: Vec<_> = labels.iter().map(|(label, index)| (label.as_str(), *index)).collect();
+    assert_eq!(labels, [("fallthrough", 2), ("main", 0), ("next", 1)]);
+}
@@ -814,6 +814,13 @@ macro_rules! define_opcodes {
                     _ => None
                 }
             }
+
+            /// Returns the name of the opcode, as it's defined.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Opcode::$name => stringify!($name),)+
+                }
+            }
         }
 
         define_opcodes!(@impl_instruction_set ISA32_V1         [I_32, I_SBRK]  $([$($tag),+] $name = $value,)+);
@@ -975,6 +982,73 @@ macro_rules! define_opcodes {
                     Self::invalid => Opcode::trap,
                 }
             }
+
+            /// Builds an instruction with the given opcode, taking as many of the given operands as it needs.
+            ///
+            /// The offsets are taken from `imms`, after any other immediates.
+            pub fn from_opcode(opcode: Opcode, regs: [RawReg; 3], imms: [u32; 2], imm64: u64) -> Self {
+                match opcode {
+                    $(Opcode::$name_argless => Self::$name_argless,)+
+                    $(Opcode::$name_reg_imm => Self::$name_reg_imm(regs[0], imms[0]),)+
+                    $(Opcode::$name_reg_imm_offset => Self::$name_reg_imm_offset(regs[0], imms[0], imms[1]),)+
+                    $(Opcode::$name_reg_imm_imm => Self::$name_reg_imm_imm(regs[0], imms[0], imms[1]),)+
+                    $(Opcode::$name_reg_reg_imm => Self::$name_reg_reg_imm(regs[0], regs[1], imms[0]),)+
+                    $(Opcode::$name_reg_reg_offset => Self::$name_reg_reg_offset(regs[0], regs[1], imms[0]),)+
+                    $(Opcode::$name_reg_reg_reg => Self::$name_reg_reg_reg(regs[0], regs[1], regs[2]),)+
+                    $(Opcode::$name_offset => Self::$name_offset(imms[0]),)+
+                    $(Opcode::$name_imm => Self::$name_imm(imms[0]),)+
+                    $(Opcode::$name_imm_imm => Self::$name_imm_imm(imms[0], imms[1]),)+
+                    $(Opcode::$name_reg_reg => Self::$name_reg_reg(regs[0], regs[1]),)+
+                    $(Opcode::$name_reg_reg_imm_imm => Self::$name_reg_reg_imm_imm(regs[0], regs[1], imms[0], imms[1]),)+
+                    $(Opcode::$name_reg_imm64 => Self::$name_reg_imm64(regs[0], imm64),)+
+                }
+            }
+
+            /// Returns the instruction's operands, laid out just like the arguments of [`Instruction::from_opcode`].
+            ///
+            /// The operands which the instruction doesn't have are zeroed.
+            pub fn operands(self) -> ([RawReg; 3], [u32; 2], u64) {
+                let zero = RawReg::from(Reg::RA);
+                match self {
+                    $(Self::$name_argless => ([zero; 3], [0; 2], 0),)+
+                    $(Self::$name_reg_imm(reg, imm) => ([reg, zero, zero], [imm, 0], 0),)+
+                    $(Self::$name_reg_imm_offset(reg, imm1, imm2) => ([reg, zero, zero], [imm1, imm2], 0),)+
+                    $(Self::$name_reg_imm_imm(reg, imm1, imm2) => ([reg, zero, zero], [imm1, imm2], 0),)+
+                    $(Self::$name_reg_reg_imm(reg1, reg2, imm) => ([reg1, reg2, zero], [imm, 0], 0),)+
+                    $(Self::$name_reg_reg_offset(reg1, reg2, imm) => ([reg1, reg2, zero], [imm, 0], 0),)+
+                    $(Self::$name_reg_reg_reg(reg1, reg2, reg3) => ([reg1, reg2, reg3], [0; 2], 0),)+
+                    $(Self::$name_offset(imm) => ([zero; 3], [imm, 0], 0),)+
+                    $(Self::$name_imm(imm) => ([zero; 3], [imm, 0], 0),)+
+                    $(Self::$name_imm_imm(imm1, imm2) => ([zero; 3], [imm1, imm2], 0),)+
+                    $(Self::$name_reg_reg(reg1, reg2) => ([reg1, reg2, zero], [0; 2], 0),)+
+                    $(Self::$name_reg_reg_imm_imm(reg1, reg2, imm1, imm2) => ([reg1, reg2, zero], [imm1, imm2], 0),)+
+                    $(Self::$name_reg_imm64(reg, imm) => ([reg, zero, zero], [0; 2], imm),)+
+                    Self::invalid => ([zero; 3], [0; 2], 0),
+                }
+            }
+        }
+
+        impl Opcode {
+            /// Returns the kinds of the opcode's operands, in the order of their encoding.
+            ///
+            /// Each kind is one of `reg`, `imm`, `offset` or `imm64`.
+            pub const fn operand_kinds(self) -> &'static [&'static str] {
+                match self {
+                    $(Self::$name_argless => &[],)+
+                    $(Self::$name_reg_imm => &["reg", "imm"],)+
+                    $(Self::$name_reg_imm_offset => &["reg", "imm", "offset"],)+
+                    $(Self::$name_reg_imm_imm => &["reg", "imm", "imm"],)+
+                    $(Self::$name_reg_reg_imm => &["reg", "reg", "imm"],)+
+                    $(Self::$name_reg_reg_offset => &["reg", "reg", "offset"],)+
+                    $(Self::$name_reg_reg_reg => &["reg", "reg", "reg"],)+
+                    $(Self::$name_offset => &["offset"],)+
+                    $(Self::$name_imm => &["imm"],)+
+                    $(Self::$name_imm_imm => &["imm", "imm"],)+
+                    $(Self::$name_reg_reg => &["reg", "reg"],)+
+                    $(Self::$name_reg_reg_imm_imm => &["reg", "reg", "imm", "imm"],)+
+                    $(Self::$name_reg_imm64 => &["reg", "imm64"],)+
+                }
+            }
         }
 
         pub mod asm {
@@ -3098,6 +3172,51 @@ where
     }
 }
 
+/// Set in the flags of a data export when the static lives in the read-write data.
+pub const DATA_EXPORT_FLAG_MUTABLE: u8 = 1;
+
+/// A static exported by the program, which the host can read directly from the program's memory.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProgramDataExport<T> {
+    address: u32,
+    length: u32,
+    is_mutable: bool,
+    symbol: ProgramSymbol<T>,
+}
+
+impl<T> ProgramDataExport<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub fn new(address: u32, length: u32, is_mutable: bool, symbol: ProgramSymbol<T>) -> Self {
+        Self {
+            address,
+            length,
+            is_mutable,
+            symbol,
+        }
+    }
+
+    /// The address of the static inside of the VM.
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// The size of the static.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Whether the static was placed in the read-write data, and so can change while the program is running.
+    pub fn is_mutable(&self) -> bool {
+        self.is_mutable
+    }
+
+    pub fn symbol(&self) -> &ProgramSymbol<T> {
+        &self.symbol
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct ProgramSymbol<T>(T);
 
@@ -3182,6 +3301,8 @@ pub struct ProgramBlob {
     debug_strings: ArcBytes,
     debug_line_program_ranges: ArcBytes,
     debug_line_programs: ArcBytes,
+
+    data_exports: ArcBytes,
 }
 
 struct Reader<'a, T>
@@ -4087,6 +4208,23 @@ fn test_instructions_iterator_start_at_invalid_offset_unbounded() {
     assert_eq!(i.next(), None);
 }
 
+#[test]
+fn test_instruction_from_opcode() {
+    for byte in 0..=255 {
+        let Some(opcode) = Opcode::from_u8_any(byte) else { continue };
+        let regs = [Reg::A0.into(), Reg::A1.into(), Reg::A2.into()];
+        let instruction = Instruction::from_opcode(opcode, regs, [1, 2], 3);
+        assert_eq!(instruction.opcode(), opcode);
+
+        let (operand_regs, imms, imm64) = instruction.operands();
+        assert_eq!(Instruction::from_opcode(opcode, operand_regs, imms, imm64), instruction);
+    }
+
+    assert_eq!(Opcode::add_64.name(), "add_64");
+    assert_eq!(Opcode::add_64.operand_kinds(), ["reg", "reg", "reg"]);
+    assert_eq!(Opcode::load_imm_and_jump.operand_kinds(), ["reg", "imm", "offset"]);
+}
+
 #[test]
 fn test_instructions_iterator_does_not_emit_unnecessary_invalid_instructions_if_bounded_and_ends_with_a_trap() {
     let code = [Opcode::trap as u8; 32];
@@ -4149,6 +4287,8 @@ pub struct ProgramParts {
     pub debug_strings: ArcBytes,
     pub debug_line_program_ranges: ArcBytes,
     pub debug_line_programs: ArcBytes,
+
+    pub data_exports: ArcBytes,
 }
 
 impl ProgramParts {
@@ -4231,6 +4371,7 @@ impl ProgramParts {
         parts.debug_strings = reader.read_section_as_bytes(&mut section, SECTION_OPT_DEBUG_STRINGS)?;
         parts.debug_line_programs = reader.read_section_as_bytes(&mut section, SECTION_OPT_DEBUG_LINE_PROGRAMS)?;
         parts.debug_line_program_ranges = reader.read_section_as_bytes(&mut section, SECTION_OPT_DEBUG_LINE_PROGRAM_RANGES)?;
+        parts.data_exports = reader.read_section_as_bytes(&mut section, SECTION_OPT_DATA_EXPORTS)?;
 
         while (section & 0b10000000) != 0 {
             // We don't know this section, but it's optional, so just skip it.
@@ -4295,6 +4436,8 @@ impl ProgramBlob {
             debug_strings: parts.debug_strings,
             debug_line_program_ranges: parts.debug_line_program_ranges,
             debug_line_programs: parts.debug_line_programs,
+
+            data_exports: parts.data_exports,
         };
 
         if blob.ro_data.len() > blob.ro_data_size as usize {
@@ -4413,6 +4556,7 @@ impl ProgramBlob {
             debug_strings,
             debug_line_program_ranges,
             debug_line_programs,
+            data_exports,
         } = self;
 
         let mut hasher = crate::hasher::Hasher::new();
@@ -4443,6 +4587,12 @@ impl ProgramBlob {
         hasher.update(import_symbols);
         hasher.update(exports);
 
+        // This is only hashed when present to keep the hashes of programs without any data exports unchanged.
+        if !data_exports.is_empty() {
+            hasher.update_u32_array([data_exports.len() as u32]);
+            hasher.update(data_exports);
+        }
+
         if include_debug {
             hasher.update_u32_array([
                 debug_strings.len() as u32,
@@ -4567,6 +4717,63 @@ impl ProgramBlob {
         }
     }
 
+    /// Returns an iterator over the statics exported by the program.
+    pub fn data_exports(&self) -> impl Iterator<Item = ProgramDataExport<&[u8]>> + Clone {
+        #[derive(Clone)]
+        enum State {
+            Uninitialized,
+            Pending(u32),
+            Finished,
+        }
+
+        #[derive(Clone)]
+        struct DataExportIterator<'a> {
+            state: State,
+            reader: Reader<'a, [u8]>,
+        }
+
+        impl<'a> Iterator for DataExportIterator<'a> {
+            type Item = ProgramDataExport<&'a [u8]>;
+            fn next(&mut self) -> Option<Self::Item> {
+                let remaining = match core::mem::replace(&mut self.state, State::Finished) {
+                    State::Uninitialized => self.reader.read_varint().ok()?,
+                    State::Pending(remaining) => remaining,
+                    State::Finished => return None,
+                };
+
+                if remaining == 0 {
+                    return None;
+                }
+
+                let address = self.reader.read_varint().ok()?;
+                let length = self.reader.read_varint().ok()?;
+                let flags = self.reader.read_byte().ok()?;
+                let symbol = self.reader.read_bytes_with_length().ok()?;
+                let export = ProgramDataExport {
+                    address,
+                    length,
+                    is_mutable: flags & DATA_EXPORT_FLAG_MUTABLE != 0,
+                    symbol: ProgramSymbol::new(symbol),
+                };
+
+                self.state = State::Pending(remaining - 1);
+                Some(export)
+            }
+        }
+
+        DataExportIterator {
+            state: if !self.data_exports.is_empty() {
+                State::Uninitialized
+            } else {
+                State::Finished
+            },
+            reader: Reader {
+                blob: &self.data_exports,
+                position: 0,
+            },
+        }
+    }
+
     /// Visits every instrution in the program.
     #[cfg_attr(not(debug_assertions), inline(always))]
     pub fn visit<T>(&self, dispatch_table: T, visitor: &mut T::State)
@@ -5177,6 +5384,7 @@ pub const SECTION_CODE_AND_JUMP_TABLE: u8 = 6;
 pub const SECTION_OPT_DEBUG_STRINGS: u8 = 128;
 pub const SECTION_OPT_DEBUG_LINE_PROGRAMS: u8 = 129;
 pub const SECTION_OPT_DEBUG_LINE_PROGRAM_RANGES: u8 = 130;
+pub const SECTION_OPT_DATA_EXPORTS: u8 = 131;
 pub const SECTION_END_OF_FILE: u8 = 0;
 
 pub const BLOB_VERSION_V1_64: u8 = 0;
@@ -1,4 +1,4 @@
-use crate::program::{self, Instruction, ProgramCounter, ProgramSymbol, BLOB_LEN_OFFSET, BLOB_LEN_SIZE};
+use crate::program::{self, Instruction, ProgramCounter, ProgramDataExport, ProgramSymbol, BLOB_LEN_OFFSET, BLOB_LEN_SIZE};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use core::ops::Range;
@@ -94,6 +94,7 @@ pub struct ProgramBlobBuilder {
     jump_table: Vec<u32>,
     custom: Vec<(u8, Vec<u8>)>,
     dispatch_table: Vec<Vec<u8>>,
+    data_exports: Vec<ProgramDataExport<Box<[u8]>>>,
 }
 
 struct SerializedCode {
@@ -144,6 +145,15 @@ impl ProgramBlobBuilder {
         self.exports.push((target_basic_block, ProgramSymbol::new(symbol.into())));
     }
 
+    pub fn add_data_export(&mut self, address: u32, length: u32, is_mutable: bool, symbol: &[u8]) {
+        self.data_exports.push(ProgramDataExport::new(
+            address,
+            length,
+            is_mutable,
+            ProgramSymbol::new(symbol.into()),
+        ));
+    }
+
     pub fn add_dispatch_table_entry(&mut self, symbol: impl Into<Vec<u8>>) {
         self.dispatch_table.push(symbol.into());
     }
@@ -399,6 +409,11 @@ impl ProgramBlobBuilder {
     }
 
     pub fn add_custom_section(&mut self, section: u8, contents: Vec<u8>) {
+        assert_ne!(
+            section,
+            program::SECTION_OPT_DATA_EXPORTS,
+            "the data exports section is written from the added data exports"
+        );
         self.custom.push((section, contents));
     }
 
@@ -463,7 +478,32 @@ impl ProgramBlobBuilder {
             writer.push_raw_bytes(&code.bitmask);
         });
 
-        for (section, contents) in &self.custom {
+        // The sections are read in the order of their IDs, so the custom ones are written around the data exports.
+        let mut custom: Vec<_> = self.custom.iter().collect();
+        custom.sort_by_key(|(section, _)| *section);
+        let custom_after_data_exports = custom.partition_point(|(section, _)| *section < program::SECTION_OPT_DATA_EXPORTS);
+        for (section, contents) in &custom[..custom_after_data_exports] {
+            writer.push_section(*section, contents);
+        }
+
+        if !self.data_exports.is_empty() {
+            writer.push_section_inplace(program::SECTION_OPT_DATA_EXPORTS, |writer| {
+                writer.push_varint(self.data_exports.len().try_into().expect("too many data exports"));
+                for export in &self.data_exports {
+                    writer.push_varint(export.address());
+                    writer.push_varint(export.length());
+                    let flags = if export.is_mutable() {
+                        program::DATA_EXPORT_FLAG_MUTABLE
+                    } else {
+                        0
+                    };
+                    writer.push_byte(flags);
+                    writer.push_bytes_with_length(export.symbol().as_bytes());
+                }
+            });
+        }
+
+        for (section, contents) in &custom[custom_after_data_exports..] {
             writer.push_section(*section, contents);
         }
 
@@ -16,8 +16,14 @@ impl core::fmt::Display for Args {
     }
 }
 
+/// The number of registers which the arguments of an import or an export can take in total, including the spilled ones.
+pub const MAXIMUM_ARGUMENT_REGS: usize = 16;
+
+/// The number of input registers of an export's trampoline; the arguments which need more are spilled.
+const EXPORT_INPUT_REGS: usize = 6;
+
 fn generate_tuple_joins() -> proc_macro2::TokenStream {
-    const COUNT: usize = 10;
+    const COUNT: usize = MAXIMUM_ARGUMENT_REGS;
     let mut code = String::new();
     for lhs in 0..=COUNT {
         for rhs in 0..=COUNT {
@@ -65,8 +71,8 @@ fn generate_tuple_joins() -> proc_macro2::TokenStream {
 
 fn generate_tuple_splits() -> proc_macro2::TokenStream {
     let mut code = String::new();
-    for target in 0..=6 {
-        for source in 0..=6 {
+    for target in 0..=MAXIMUM_ARGUMENT_REGS {
+        for source in 0..=MAXIMUM_ARGUMENT_REGS {
             if target > source {
                 continue;
             }
@@ -114,6 +120,125 @@ fn generate_tuple_splits() -> proc_macro2::TokenStream {
     code.parse().unwrap()
 }
 
+/// Returns a tuple of `count` registers, e.g. `(Reg, Reg,)`.
+fn regs_tuple(count: usize) -> String {
+    format!("({})", "self::private::Reg,".repeat(count))
+}
+
+/// Generates the conversions between the registers which the arguments need and the registers which are actually passed.
+///
+/// See `SpillRegs` for the layout of the spilled registers.
+fn generate_spills(maximum_input_regs: usize) -> proc_macro2::TokenStream {
+    let mut code = String::new();
+    for count in 0..=MAXIMUM_ARGUMENT_REGS {
+        let regs = Args {
+            range: 0..count,
+            upper: false,
+        };
+        if count <= maximum_input_regs {
+            writeln!(
+                &mut code,
+                concat!(
+                    "impl self::private::SpillRegs for {regs_ty} {{\n",
+                    "    type Spilled = Self;\n",
+                    "    type Storage = [self::private::Reg; 0];\n",
+                    "    #[inline(always)]\n",
+                    "    fn spill_regs(regs: Self, _: &mut Self::Storage) -> Self {{\n",
+                    "        regs\n",
+                    "    }}\n",
+                    "}}\n",
+                ),
+                regs_ty = regs_tuple(count),
+            )
+            .unwrap();
+        } else {
+            let in_regs = Args {
+                range: 0..maximum_input_regs - 1,
+                upper: false,
+            };
+            let spilled = Args {
+                range: maximum_input_regs - 1..count,
+                upper: false,
+            };
+            writeln!(
+                &mut code,
+                concat!(
+                    "impl self::private::SpillRegs for {regs_ty} {{\n",
+                    "    type Spilled = {spilled_ty};\n",
+                    "    type Storage = [self::private::Reg; {spilled_count}];\n",
+                    "    #[inline(always)]\n",
+                    "    fn spill_regs(({regs}): Self, storage: &mut Self::Storage) -> Self::Spilled {{\n",
+                    "        *storage = [{spilled}];\n",
+                    "        ({in_regs} storage.as_ptr() as self::private::Reg)\n",
+                    "    }}\n",
+                    "}}\n",
+                ),
+                regs_ty = regs_tuple(count),
+                spilled_ty = regs_tuple(maximum_input_regs),
+                spilled_count = count - (maximum_input_regs - 1),
+                regs = regs,
+                spilled = spilled,
+                in_regs = in_regs,
+            )
+            .unwrap();
+        }
+
+        if count <= EXPORT_INPUT_REGS {
+            let unused = "_,".repeat(EXPORT_INPUT_REGS - count);
+            // Special case to get rid of Clippy's 'unused_unit' warning.
+            let regs_tuple_value = if count != 0 { format!("({regs})") } else { String::new() };
+            writeln!(
+                &mut code,
+                concat!(
+                    "impl self::private::UnspillRegs for {regs_ty} {{\n",
+                    "    const INPUT_REGS: u8 = {count};\n",
+                    "    #[inline(always)]\n",
+                    "    fn unspill_regs(({regs}{unused}): {input_regs_ty}) -> Self {{\n",
+                    "        {regs_tuple_value}\n",
+                    "    }}\n",
+                    "}}\n",
+                ),
+                regs_ty = regs_tuple(count),
+                count = count,
+                regs = regs,
+                unused = unused,
+                input_regs_ty = regs_tuple(EXPORT_INPUT_REGS),
+                regs_tuple_value = regs_tuple_value,
+            )
+            .unwrap();
+        } else {
+            let in_regs = Args {
+                range: 0..EXPORT_INPUT_REGS - 1,
+                upper: false,
+            };
+            let spilled: String = (0..count - (EXPORT_INPUT_REGS - 1))
+                .map(|nth| format!("spilled.add({nth}).read(),"))
+                .collect();
+            writeln!(
+                &mut code,
+                concat!(
+                    "impl self::private::UnspillRegs for {regs_ty} {{\n",
+                    "    const INPUT_REGS: u8 = {input_regs};\n",
+                    "    #[inline(always)]\n",
+                    "    fn unspill_regs(({in_regs} spilled): {input_regs_ty}) -> Self {{\n",
+                    "        let spilled = spilled as usize as *const self::private::Reg;\n",
+                    "        unsafe {{ ({in_regs} {spilled}) }}\n",
+                    "    }}\n",
+                    "}}\n",
+                ),
+                regs_ty = regs_tuple(count),
+                input_regs = EXPORT_INPUT_REGS,
+                input_regs_ty = regs_tuple(EXPORT_INPUT_REGS),
+                in_regs = in_regs,
+                spilled = spilled,
+            )
+            .unwrap();
+        }
+    }
+
+    code.parse().unwrap()
+}
+
 const ABI_SUPPORT_COMMON_PUBLIC_RS: &str = include_str!("abi_support_impl/common_public.rs");
 const ABI_SUPPORT_COMMON_PRIVATE_RS: &str = include_str!("abi_support_impl/common_private.rs");
 const ABI_SUPPORT_COMMON_PRIVATE_EXTERA_REGISTERS_RS: &str = include_str!("abi_support_impl/common_private_extra_registers.rs");
@@ -193,12 +318,15 @@ pub fn polkavm_impl_abi_support(attributes: AbiSupportAttributes) -> proc_macro2
     };
 
     assert!(maximum_input_regs as usize <= polkavm_common::program::Reg::MAXIMUM_INPUT_REGS);
+    let spills = generate_spills(maximum_input_regs as usize);
+    let maximum_argument_regs = MAXIMUM_ARGUMENT_REGS as u8;
     quote! {
         #common_public_code
 
         #[doc(hidden)]
         pub mod private {
             pub const MAXIMUM_INPUT_REGS: u8 = #maximum_input_regs;
+            pub const MAXIMUM_ARGUMENT_REGS: u8 = #maximum_argument_regs;
             #common_private_code
             #extra_registers_code
         }
@@ -207,5 +335,47 @@ pub fn polkavm_impl_abi_support(attributes: AbiSupportAttributes) -> proc_macro2
         #riscv64_code
         #tuple_joins
         #tuple_splits
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        const _: () = {
+            #spills
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u128_is_split_across_registers() {
+        let output = polkavm_impl_abi_support(AbiSupportAttributes::new()).to_string();
+        // One for 64-bit targets, and one for 32-bit targets.
+        for regs in ["(u64 , u64)", "(u32 , u32 , u32 , u32)"] {
+            for trait_name in ["IntoHost", "FromHost"] {
+                let expected = format!("impl {trait_name} for u128 {{ type Regs = {regs} ;");
+                assert!(output.contains(&expected), "missing: {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_are_spilled_past_the_last_input_register() {
+        let spilled_storage = |allow_extra_input_registers: bool, count: usize| {
+            let mut attributes = AbiSupportAttributes::new();
+            attributes.set_allow_extra_input_registers(allow_extra_input_registers);
+            let output = polkavm_impl_abi_support(attributes).to_string();
+            let regs = vec!["self :: private :: Reg ,"; count].join(" ");
+            let expected = format!("impl self :: private :: SpillRegs for ({regs}) {{ type Spilled = ");
+            let storage = &output[output.find(&expected).unwrap_or_else(|| panic!("missing: {expected}"))..];
+            let storage = &storage[storage.find("type Storage = [self :: private :: Reg ; ").unwrap() + 41..];
+            storage[..storage.find(']').unwrap()].parse::<usize>().unwrap()
+        };
+
+        assert_eq!(spilled_storage(false, 6), 0);
+        assert_eq!(spilled_storage(false, 7), 2);
+        assert_eq!(spilled_storage(false, 16), 11);
+        assert_eq!(spilled_storage(true, 9), 0);
+        assert_eq!(spilled_storage(true, 10), 2);
     }
 }
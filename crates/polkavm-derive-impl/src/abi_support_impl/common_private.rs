@@ -101,11 +101,50 @@ impl<A, B, C, D, E, F, G, H, I, J, K, L> CountTuple for (A, B, C, D, E, F, G, H,
     const COUNT: u8 = 12;
 }
 
+impl<A, B, C, D, E, F, G, H, I, J, K, L, M> CountTuple for (A, B, C, D, E, F, G, H, I, J, K, L, M) {
+    const COUNT: u8 = 13;
+}
+
+impl<A, B, C, D, E, F, G, H, I, J, K, L, M, N> CountTuple for (A, B, C, D, E, F, G, H, I, J, K, L, M, N) {
+    const COUNT: u8 = 14;
+}
+
+impl<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O> CountTuple for (A, B, C, D, E, F, G, H, I, J, K, L, M, N, O) {
+    const COUNT: u8 = 15;
+}
+
+impl<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P> CountTuple for (A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P) {
+    const COUNT: u8 = 16;
+}
+
 pub trait SplitTuple<Target> {
     type Remainder;
     fn split_tuple(tuple: Self) -> (Target, Self::Remainder);
 }
 
+// Arguments which need more registers than are available are spilled into memory.
+//
+// All but the last of the available input registers are passed as usual, while the last one holds the address
+// of an array with the rest of the registers, in the same order. Every element of the array is as big as
+// a register (4 bytes on 32-bit targets, and 8 bytes on 64-bit targets) and the array is aligned to it.
+// The array must be left untouched until the call returns.
+//
+// Imports are passed `MAXIMUM_INPUT_REGS` registers, while exports are always passed the six `a` registers.
+
+#[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+pub trait SpillRegs {
+    type Spilled;
+    type Storage: Default;
+    fn spill_regs(regs: Self, storage: &mut Self::Storage) -> Self::Spilled;
+}
+
+#[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+pub trait UnspillRegs {
+    /// The number of registers which are actually passed.
+    const INPUT_REGS: u8;
+    fn unspill_regs(regs: (Reg, Reg, Reg, Reg, Reg, Reg)) -> Self;
+}
+
 #[cfg(all(target_arch = "riscv32", target_feature = "e"))]
 pub type Reg = u32;
 
@@ -399,6 +438,33 @@ impl PackReturnTy for (Reg, Reg) {
     }
 }
 
+/// Traps with the given code in `a0`, or split between `a0` and `a1` on 32-bit targets.
+#[cfg(all(target_arch = "riscv32", target_feature = "e"))]
+#[inline(always)]
+pub fn trap_with_code(code: u64) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "unimp",
+            in("a0") code as u32,
+            in("a1") (code >> 32) as u32,
+            options(noreturn),
+        );
+    }
+}
+
+/// Traps with the given code in `a0`.
+#[cfg(all(target_arch = "riscv64", target_feature = "e"))]
+#[inline(always)]
+pub fn trap_with_code(code: u64) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "unimp",
+            in("a0") code,
+            options(noreturn),
+        );
+    }
+}
+
 #[repr(transparent)]
 pub struct MetadataPointer(pub *const u8);
 unsafe impl Sync for MetadataPointer {}
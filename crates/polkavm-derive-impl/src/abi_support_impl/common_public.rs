@@ -26,3 +26,78 @@ impl FromHost for () {
     #[inline(always)]
     fn from_host((): ()) -> Self {}
 }
+
+// Slices and strings are passed as an address and a length.
+//
+// The host must pass a valid range of the guest's memory which, for the duration of the call, neither
+// it nor the guest modifies in any other way; nothing of it can be kept around past the call.
+
+impl<'a> IntoHost for &'a [u8] {
+    type Regs = <(usize, usize) as IntoHost>::Regs;
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        IntoHost::into_host((value.as_ptr() as usize, value.len()))
+    }
+}
+
+impl<'a> IntoHost for &'a mut [u8] {
+    type Regs = <(usize, usize) as IntoHost>::Regs;
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        IntoHost::into_host((value.as_mut_ptr() as usize, value.len()))
+    }
+}
+
+impl<'a> IntoHost for &'a str {
+    type Regs = <(usize, usize) as IntoHost>::Regs;
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        IntoHost::into_host(value.as_bytes())
+    }
+}
+
+impl<'a> FromHost for &'a [u8] {
+    type Regs = <(usize, usize) as FromHost>::Regs;
+
+    #[inline(always)]
+    fn from_host(value: Self::Regs) -> Self {
+        let (address, length): (usize, usize) = FromHost::from_host(value);
+        if length == 0 {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(address as *const u8, length) }
+    }
+}
+
+impl<'a> FromHost for &'a mut [u8] {
+    type Regs = <(usize, usize) as FromHost>::Regs;
+
+    #[inline(always)]
+    fn from_host(value: Self::Regs) -> Self {
+        let (address, length): (usize, usize) = FromHost::from_host(value);
+        if length == 0 {
+            return &mut [];
+        }
+
+        unsafe { core::slice::from_raw_parts_mut(address as *mut u8, length) }
+    }
+}
+
+impl<'a> FromHost for &'a str {
+    type Regs = <(usize, usize) as FromHost>::Regs;
+
+    #[inline(always)]
+    fn from_host(value: Self::Regs) -> Self {
+        match core::str::from_utf8(FromHost::from_host(value)) {
+            Ok(value) => value,
+            Err(_) => panic!("the host passed a string which isn't valid UTF-8"),
+        }
+    }
+}
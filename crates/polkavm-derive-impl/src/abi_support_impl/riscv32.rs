@@ -187,3 +187,47 @@ impl<T> IntoHost for *mut T {
         ((value as u32,), ())
     }
 }
+
+// There are only two return registers, so 128-bit values can only be passed as arguments.
+
+#[cfg(target_pointer_width = "32")]
+impl IntoHost for u128 {
+    type Regs = (u32, u32, u32, u32);
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        ((value as u32, (value >> 32) as u32, (value >> 64) as u32, (value >> 96) as u32), ())
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+impl IntoHost for i128 {
+    type Regs = (u32, u32, u32, u32);
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        IntoHost::into_host(value as u128)
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+impl FromHost for u128 {
+    type Regs = (u32, u32, u32, u32);
+
+    #[inline(always)]
+    fn from_host((a0, a1, a2, a3): Self::Regs) -> Self {
+        (a0 as u128) | ((a1 as u128) << 32) | ((a2 as u128) << 64) | ((a3 as u128) << 96)
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+impl FromHost for i128 {
+    type Regs = <u128 as FromHost>::Regs;
+
+    #[inline(always)]
+    fn from_host(regs: Self::Regs) -> Self {
+        u128::from_host(regs) as i128
+    }
+}
@@ -187,3 +187,45 @@ impl<T> IntoHost for *mut T {
         ((value as u64,), ())
     }
 }
+
+#[cfg(target_pointer_width = "64")]
+impl IntoHost for u128 {
+    type Regs = (u64, u64);
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        ((value as u64, (value >> 64) as u64), ())
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+impl IntoHost for i128 {
+    type Regs = (u64, u64);
+    type Destructor = ();
+
+    #[inline(always)]
+    fn into_host(value: Self) -> (Self::Regs, ()) {
+        IntoHost::into_host(value as u128)
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+impl FromHost for u128 {
+    type Regs = (u64, u64);
+
+    #[inline(always)]
+    fn from_host((a0, a1): Self::Regs) -> Self {
+        (a0 as u128) | ((a1 as u128) << 64)
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+impl FromHost for i128 {
+    type Regs = <u128 as FromHost>::Regs;
+
+    #[inline(always)]
+    fn from_host(regs: Self::Regs) -> Self {
+        u128::from_host(regs) as i128
+    }
+}
@@ -46,6 +46,24 @@ pub fn is_rustfmt(attr: &syn::Attribute) -> bool {
     }
 }
 
+/// Rejects owned buffers, which can't be passed through the registers; slices and strings can be passed instead.
+pub fn reject_owned_buffer(ty: &syn::Type) -> Result<(), syn::Error> {
+    let syn::Type::Path(syn::TypePath { path, .. }) = ty else {
+        return Ok(());
+    };
+
+    match path.segments.last() {
+        Some(segment) if segment.ident == "Vec" || segment.ident == "String" => Err(syn::Error::new(
+            syn::spanned::Spanned::span(ty),
+            format!(
+                "owned '{}' arguments are unsupported; pass a slice or a 'str' instead",
+                segment.ident
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
 pub fn default_abi_path() -> syn::Path {
     syn::Path {
         leading_colon: Some(Default::default()),
@@ -5,11 +5,15 @@ use crate::common::{is_cfg, is_doc, is_path_eq, is_rustfmt};
 
 mod kw {
     syn::custom_keyword!(abi);
+    syn::custom_keyword!(symbol);
+    syn::custom_keyword!(result_trap);
 }
 
 #[derive(Default)]
 pub struct ExportBlockAttributes {
     abi: Option<syn::Path>,
+    symbol: Option<syn::LitByteStr>,
+    result_trap: bool,
 }
 
 impl ExportBlockAttributes {
@@ -32,6 +36,8 @@ impl syn::parse::Parse for ExportBlockAttributes {
 
         enum ImportBlockAttribute {
             Abi(syn::Path),
+            Symbol(syn::LitByteStr),
+            ResultTrap(kw::result_trap),
         }
 
         let list = input.parse_terminated(
@@ -42,6 +48,26 @@ impl syn::parse::Parse for ExportBlockAttributes {
                     let _: syn::Token![=] = input.parse()?;
                     let path: syn::Path = input.parse()?;
                     Ok(ImportBlockAttribute::Abi(path))
+                } else if lookahead.peek(kw::symbol) {
+                    input.parse::<kw::symbol>()?;
+                    let _: syn::Token![=] = input.parse()?;
+                    let lookahead = input.lookahead1();
+                    let symbol = if lookahead.peek(syn::LitByteStr) {
+                        input.parse()?
+                    } else if lookahead.peek(syn::LitStr) {
+                        let value: syn::LitStr = input.parse()?;
+                        syn::LitByteStr::new(value.value().as_bytes(), value.span())
+                    } else {
+                        return Err(lookahead.error());
+                    };
+
+                    if symbol.value().is_empty() {
+                        return Err(syn::Error::new(symbol.span(), "the export's symbol cannot be empty"));
+                    }
+
+                    Ok(ImportBlockAttribute::Symbol(symbol))
+                } else if lookahead.peek(kw::result_trap) {
+                    Ok(ImportBlockAttribute::ResultTrap(input.parse()?))
                 } else {
                     Err(lookahead.error())
                 }
@@ -57,6 +83,18 @@ impl syn::parse::Parse for ExportBlockAttributes {
                     }
                     attributes.abi = Some(path);
                 }
+                ImportBlockAttribute::Symbol(symbol) => {
+                    if attributes.symbol.is_some() {
+                        return Err(syn::Error::new(symbol.span(), "duplicate 'symbol' attribute"));
+                    }
+                    attributes.symbol = Some(symbol);
+                }
+                ImportBlockAttribute::ResultTrap(keyword) => {
+                    if attributes.result_trap {
+                        return Err(syn::Error::new(keyword.span(), "duplicate 'result_trap' attribute"));
+                    }
+                    attributes.result_trap = true;
+                }
             }
         }
 
@@ -72,6 +110,100 @@ pub fn is_no_mangle(attr: &syn::Attribute) -> bool {
     }
 }
 
+/// Returns the `T` of a `Result<T, E>`.
+fn result_ok_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(syn::TypePath { qself: None, path }) = ty else {
+        return None;
+    };
+
+    let segment = path.segments.last()?;
+    let syn::PathArguments::AngleBracketed(ref arguments) = segment.arguments else {
+        return None;
+    };
+
+    let mut types = arguments.args.iter().filter_map(|argument| match argument {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+
+    let ok_ty = types.next()?;
+    if segment.ident != "Result" || types.next().is_none() || types.next().is_some() {
+        return None;
+    }
+
+    Some(ok_ty)
+}
+
+/// The version of the format of the entries in the `.polkavm_manifest` section.
+///
+/// The format must be kept in sync with its parser in `polkavm_derive::manifest`.
+const MANIFEST_VERSION: u8 = 1;
+
+/// The code of a type which the manifest doesn't know, followed by the type's name.
+const MANIFEST_KIND_OTHER: u8 = 0xff;
+
+/// Returns the manifest's code for the kind of an argument or a return value.
+fn manifest_kind(ty: &syn::Type) -> Option<u8> {
+    let kind = match ty {
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => 0,
+        syn::Type::Path(syn::TypePath { qself: None, path }) => {
+            let ident = path.get_ident()?;
+            let kinds = ["u32", "i32", "u64", "i64", "usize", "isize", "u128", "i128"];
+            1 + kinds.iter().position(|kind| ident == kind)? as u8
+        }
+        syn::Type::Ptr(..) => 9,
+        syn::Type::Reference(reference) => match &*reference.elem {
+            syn::Type::Slice(slice) if matches!(&*slice.elem, syn::Type::Path(path) if path.path.is_ident("u8")) => {
+                if reference.mutability.is_some() {
+                    11
+                } else {
+                    10
+                }
+            }
+            syn::Type::Path(path) if path.path.is_ident("str") && reference.mutability.is_none() => 12,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+fn push_manifest_kind(entry: &mut Vec<u8>, ty: &syn::Type) {
+    if let Some(kind) = manifest_kind(ty) {
+        entry.push(kind);
+    } else {
+        let name = quote! { #ty }.to_string();
+        entry.push(MANIFEST_KIND_OTHER);
+        entry.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        entry.extend_from_slice(name.as_bytes());
+    }
+}
+
+/// Serializes the manifest's entry of an export: its symbol, and the kinds of its arguments and of its return value.
+fn manifest_entry(symbol: &[u8], arg_types: &[&syn::Type], return_ty: &syn::Type) -> Vec<u8> {
+    let mut entry = vec![MANIFEST_VERSION];
+    entry.extend_from_slice(&(symbol.len() as u32).to_le_bytes());
+    entry.extend_from_slice(symbol);
+    entry.push(arg_types.len() as u8);
+    for ty in arg_types {
+        push_manifest_kind(&mut entry, ty);
+    }
+    push_manifest_kind(&mut entry, return_ty);
+    entry
+}
+
+/// Returns the name of the label which is defined for every exported symbol.
+fn symbol_label(symbol: &[u8]) -> String {
+    use core::fmt::Write;
+
+    let mut name = String::from("__polkavm_export_symbol_");
+    for byte in symbol {
+        write!(&mut name, "{byte:02x}").unwrap();
+    }
+    name
+}
+
 pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) -> Result<proc_macro2::TokenStream, syn::Error> {
     let mut cfg_attributes = Vec::new();
     let mut fn_attributes = Vec::new();
@@ -119,11 +251,42 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
 
     let abi_path = attributes.abi.unwrap_or_else(crate::common::default_abi_path);
 
-    let mut remaining_variables = ["a", "b", "c", "d", "e", "f"]
-        .into_iter()
-        .map(|ident| crate::common::expr_from_ident(syn::Ident::new(ident, proc_macro2::Span::call_site())));
+    // With `result_trap` only the `Ok` value is returned, while an `Err` traps.
+    let (return_ty, unwrap_result) = if attributes.result_trap {
+        let Some(ok_ty) = result_ok_type(&return_ty) else {
+            return Err(syn::Error::new(
+                return_ty.span(),
+                "'result_trap' requires the function to return a 'Result<T, E>'",
+            ));
+        };
+
+        let unwrap_result = quote! {
+            let result = match result {
+                ::core::result::Result::Ok(value) => value,
+                ::core::result::Result::Err(error) => #abi_path::private::trap_with_code(::core::convert::Into::<u64>::into(error)),
+            };
+        };
+
+        (ok_ty.clone(), unwrap_result)
+    } else {
+        (return_ty, quote! {})
+    };
+
+    if args.len() > crate::abi_support::MAXIMUM_ARGUMENT_REGS {
+        return Err(syn::Error::new(
+            sig.ident.span(),
+            format!(
+                "too many arguments to the exported function '{ident}': at most {} registers' worth of arguments are supported",
+                crate::abi_support::MAXIMUM_ARGUMENT_REGS
+            ),
+        ));
+    }
+
+    let mut remaining_variables = (b'a'..=b'z')
+        .map(|ident| crate::common::expr_from_ident(syn::Ident::new(&char::from(ident).to_string(), proc_macro2::Span::call_site())));
 
     let mut arg_variables = Vec::new();
+    let mut arg_types = Vec::new();
     let mut args_split = Vec::new();
     let mut args_from_host = Vec::new();
     let mut args_joined_regs_ty = quote! { () };
@@ -132,11 +295,11 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
             unsupported!(arg);
         };
 
-        let Some(arg_ident) = remaining_variables.next() else {
-            return Err(syn::Error::new(sig.ident.span(), "too many arguments"));
-        };
+        let arg_ident = remaining_variables.next().unwrap();
 
         let arg_ty = &arg.ty;
+        crate::common::reject_owned_buffer(arg_ty)?;
+        arg_types.push(&**arg_ty);
         args_split.push(quote! {
             let (#arg_ident, regs) = #abi_path::private::SplitTuple::<<#arg_ty as #abi_path::FromHost>::Regs>::split_tuple(regs);
         });
@@ -152,9 +315,24 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
         arg_variables.push(arg_ident);
     }
 
-    let symbol = syn::LitStr::new(&ident.to_string(), ident.span());
+    let symbol = attributes
+        .symbol
+        .unwrap_or_else(|| syn::LitByteStr::new(ident.to_string().as_bytes(), ident.span()));
+
+    // Exporting the same symbol twice defines this label twice, which the assembler or the linker then rejects.
+    let symbol_label = symbol_label(&symbol.value());
+    let symbol_label = syn::LitStr::new(&format!(".globl {symbol_label}\n{symbol_label}:\n"), symbol.span());
     let section_name = syn::LitStr::new(&format!(".text.polkavm_export.{}", ident), ident.span());
 
+    let assert_message = syn::LitStr::new(
+        &format!("too many registers required by the arguments to the exported function '{ident}'"),
+        ident.span(),
+    );
+
+    let manifest_entry = manifest_entry(&symbol.value(), &arg_types, &return_ty);
+    let manifest_entry: Vec<String> = manifest_entry.iter().map(|byte| byte.to_string()).collect();
+    let manifest_entry = syn::LitStr::new(&format!(".byte {}\n", manifest_entry.join(", ")), ident.span());
+
     Ok(quote! {
         #(#cfg_attributes)*
         #(#fn_attributes)*
@@ -166,8 +344,13 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
 
                 #[link_section = #section_name]
                 extern fn trampoline(a0: Reg, a1: Reg, a2: Reg, a3: Reg, a4: Reg, a5: Reg) -> #abi_path::private::ReturnTy {
+                    const _: () = {
+                        assert!(<#args_joined_regs_ty as #abi_path::private::CountTuple>::COUNT <= #abi_path::private::MAXIMUM_ARGUMENT_REGS, #assert_message);
+                    };
+
                     let result = {
-                        let regs = (a0, a1, a2, a3, a4, a5);
+                        // The arguments which don't fit into the registers are loaded from memory.
+                        let regs: #args_joined_regs_ty = #abi_path::private::UnspillRegs::unspill_regs((a0, a1, a2, a3, a4, a5));
                         #(#args_split)*
                         let _ = regs;
 
@@ -175,6 +358,7 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
                         let result = #unsafety {
                             super::#ident(#(#arg_variables),*)
                         };
+                        #unwrap_result
                         let (result, destructor) = #abi_path::IntoHost::into_host(result);
 
                         #[allow(forgetting_copy_types)]
@@ -186,7 +370,7 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
                 }
 
                 #[link_section = ".polkavm_metadata"]
-                static METADATA_SYMBOL: &str = #symbol;
+                static METADATA_SYMBOL: &[u8] = #symbol;
 
                 #[link_section = ".polkavm_metadata"]
                 static METADATA: #abi_path::private::ExternMetadataV1 = #abi_path::private::ExternMetadataV1 {
@@ -194,13 +378,14 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
                     flags: 0,
                     symbol_length: METADATA_SYMBOL.len() as u32,
                     symbol: #abi_path::private::MetadataPointer(METADATA_SYMBOL.as_ptr()),
-                    input_regs: <#args_joined_regs_ty as #abi_path::private::CountTuple>::COUNT,
+                    input_regs: <#args_joined_regs_ty as #abi_path::private::UnspillRegs>::INPUT_REGS,
                     output_regs: <<#return_ty as #abi_path::IntoHost>::Regs as #abi_path::private::CountTuple>::COUNT,
                 };
 
                 #[cfg(target_arch = "riscv32")]
                 ::core::arch::global_asm!(
                     ".pushsection .polkavm_exports,\"Ra\",@note\n",
+                    #symbol_label,
                     ".byte 1\n", // Version.
                     "auipc zero, %pcrel_hi({metadata})\n",
                     "auipc zero, %pcrel_hi({function})\n",
@@ -212,6 +397,7 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
                 #[cfg(target_arch = "riscv64")]
                 ::core::arch::global_asm!(
                     ".pushsection .polkavm_exports,\"Ra\",@note\n",
+                    #symbol_label,
                     ".byte 1\n", // Version.
                     "auipc zero, %pcrel_hi({metadata})\n",
                     ".4byte 0\n",
@@ -221,9 +407,177 @@ pub fn polkavm_export(attributes: ExportBlockAttributes, input: syn::ItemFn) ->
                     metadata = sym METADATA,
                     function = sym trampoline,
                 );
+
+                ::core::arch::global_asm!(
+                    ".pushsection .polkavm_manifest,\"R\",@note\n",
+                    #manifest_entry,
+                    ".popsection\n",
+                );
             }
 
             #(#body)*
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(attributes: &str) -> Result<ExportBlockAttributes, String> {
+        syn::parse_str(attributes).map_err(|error| error.to_string())
+    }
+
+    fn export(attributes: &str) -> String {
+        let input = syn::parse_quote! {
+            fn refine_entry_point(a0: u32) -> u32 { a0 }
+        };
+        polkavm_export(parse(attributes).unwrap(), input).unwrap().to_string()
+    }
+
+    #[test]
+    fn symbol_attribute() {
+        assert_eq!(parse("symbol = \"refine\"").unwrap().symbol.unwrap().value(), b"refine");
+        assert_eq!(parse("symbol = b\"\\x01\\x02\"").unwrap().symbol.unwrap().value(), [1, 2]);
+        assert!(parse("").unwrap().symbol.is_none());
+
+        assert_eq!(parse("symbol = \"\"").err().unwrap(), "the export's symbol cannot be empty");
+        assert_eq!(
+            parse("symbol = \"refine\", symbol = \"accumulate\"").err().unwrap(),
+            "duplicate 'symbol' attribute"
+        );
+        assert!(parse("symbol = 1").is_err());
+    }
+
+    #[test]
+    fn symbol_is_emitted_into_the_metadata() {
+        let output = export("symbol = \"refine\"");
+        assert!(output.contains("b\"refine\""), "{output}");
+        assert!(!output.contains("b\"refine_entry_point\""), "{output}");
+        assert!(output.contains(&symbol_label(b"refine")));
+        assert!(output.contains(".text.polkavm_export.refine_entry_point"));
+
+        let output = export("");
+        assert!(output.contains("b\"refine_entry_point\""), "{output}");
+    }
+
+    #[test]
+    fn result_trap_attribute() {
+        assert!(parse("result_trap").unwrap().result_trap);
+        assert!(!parse("").unwrap().result_trap);
+        assert_eq!(
+            parse("result_trap, result_trap").err().unwrap(),
+            "duplicate 'result_trap' attribute"
+        );
+
+        let input = syn::parse_quote! {
+            fn checked_divide(a0: u32, a1: u32) -> Result<u32, u32> { a0.checked_div(a1).ok_or(0xdead) }
+        };
+        let output = polkavm_export(parse("result_trap").unwrap(), input).unwrap().to_string();
+        assert!(output.contains("trap_with_code"), "{output}");
+        // Only the `Ok` type is returned through the registers.
+        assert!(
+            output.contains("< u32 as :: polkavm_derive :: default_abi :: IntoHost > :: Regs"),
+            "{output}"
+        );
+
+        let output = export("");
+        assert!(!output.contains("trap_with_code"), "{output}");
+
+        let input = syn::parse_quote! {
+            fn not_a_result() -> u32 { 0 }
+        };
+        let error = polkavm_export(parse("result_trap").unwrap(), input).err().unwrap();
+        assert_eq!(error.to_string(), "'result_trap' requires the function to return a 'Result<T, E>'");
+    }
+
+    #[test]
+    fn owned_buffers_are_rejected() {
+        let input = syn::parse_quote! {
+            fn read(bytes: &mut [u8], text: &str) {}
+        };
+        let output = polkavm_export(parse("").unwrap(), input).unwrap().to_string();
+        assert!(
+            output.contains("< & mut [u8] as :: polkavm_derive :: default_abi :: FromHost > :: Regs"),
+            "{output}"
+        );
+
+        let input = syn::parse_quote! {
+            fn read(text: String) {}
+        };
+        let error = polkavm_export(parse("").unwrap(), input).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "owned 'String' arguments are unsupported; pass a slice or a 'str' instead"
+        );
+    }
+
+    #[test]
+    fn arguments_which_dont_fit_into_the_registers_are_unspilled() {
+        for arg_count in [7, 10] {
+            let args: Vec<String> = (0..arg_count).map(|nth| format!("a{nth}: u32")).collect();
+            let input = syn::parse_str(&format!("fn many({}) {{}}", args.join(", "))).unwrap();
+            let output = polkavm_export(parse("").unwrap(), input).unwrap().to_string();
+            assert!(
+                output
+                    .contains(":: polkavm_derive :: default_abi :: private :: UnspillRegs :: unspill_regs ((a0 , a1 , a2 , a3 , a4 , a5))"),
+                "{output}"
+            );
+            assert!(
+                output.contains("as :: polkavm_derive :: default_abi :: private :: UnspillRegs > :: INPUT_REGS"),
+                "{output}"
+            );
+            let last_variable = char::from(b'a' + arg_count - 1);
+            assert!(output.contains(&format!("let {last_variable} =")), "{output}");
+        }
+
+        let args: Vec<String> = (0..17).map(|nth| format!("a{nth}: u32")).collect();
+        let input = syn::parse_str(&format!("fn many({}) {{}}", args.join(", "))).unwrap();
+        let error = polkavm_export(parse("").unwrap(), input).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "too many arguments to the exported function 'many': at most 16 registers' worth of arguments are supported"
+        );
+    }
+
+    #[test]
+    fn symbol_labels_are_unique() {
+        assert_eq!(symbol_label(b"refine"), "__polkavm_export_symbol_726566696e65");
+        assert_ne!(symbol_label(b"a"), symbol_label(b"b"));
+    }
+
+    #[test]
+    fn manifest_entries_describe_the_signature() {
+        let manifest = |attributes: &str, input: syn::ItemFn| {
+            let output = polkavm_export(parse(attributes).unwrap(), input).unwrap().to_string();
+            let entry = output.split(".polkavm_manifest").nth(1).unwrap();
+            let entry = &entry[entry.find(".byte ").unwrap() + ".byte ".len()..];
+            let entry = &entry[..entry.find("\\n").unwrap()];
+            entry.split(", ").map(|byte| byte.parse().unwrap()).collect::<Vec<u8>>()
+        };
+
+        let input = syn::parse_quote! {
+            fn add(a0: u32, a1: u64) -> u32 { a0 + a1 as u32 }
+        };
+        assert_eq!(manifest("", input), [1, 3, 0, 0, 0, b'a', b'd', b'd', 2, 1, 3, 1]);
+
+        let input = syn::parse_quote! {
+            fn fill_from_text(bytes: &mut [u8], text: &str) {}
+        };
+        assert_eq!(
+            manifest("symbol = \"fill\"", input),
+            [1, 4, 0, 0, 0, b'f', b'i', b'l', b'l', 2, 11, 12, 0]
+        );
+
+        let input = syn::parse_quote! {
+            fn pointer(pair: (u32, u32)) -> Result<*const u8, u32> { todo!() }
+        };
+        let mut expected = vec![1, 7, 0, 0, 0];
+        expected.extend_from_slice(b"pointer");
+        expected.extend_from_slice(&[1, MANIFEST_KIND_OTHER, 11, 0, 0, 0]);
+        expected.extend_from_slice(b"(u32 , u32)");
+        // With `result_trap` only the `Ok` type is returned.
+        expected.push(9);
+        assert_eq!(manifest("result_trap", input), expected);
+    }
+}
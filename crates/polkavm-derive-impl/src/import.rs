@@ -8,8 +8,16 @@ mod kw {
     syn::custom_keyword!(symbol);
     syn::custom_keyword!(abi);
     syn::custom_keyword!(index);
+    syn::custom_keyword!(optional);
+    syn::custom_keyword!(default);
 }
 
+/// The symbol of the host function through which optional imports query whether they're available.
+///
+/// It takes the address and the length of the import's symbol in `a0` and `a1`, and returns a non-zero `a0` if the
+/// host defines a function with that symbol. The `polkavm` crate's linker answers it unless the host defines it itself.
+const IMPORT_PROBE_SYMBOL: &[u8] = b"__polkavm_has_import";
+
 fn ident_from_pattern_strict(syn::PatType { attrs, pat, .. }: &syn::PatType) -> Result<Option<syn::Ident>, syn::Error> {
     unsupported_if_some!(attrs.first());
     match &**pat {
@@ -88,6 +96,8 @@ impl syn::parse::Parse for ImportBlockAttributes {
 enum ImportAttribute {
     Symbol(syn::LitByteStr),
     Index(u32),
+    Optional,
+    Default(syn::Expr),
 }
 
 impl syn::parse::Parse for ImportAttribute {
@@ -137,6 +147,14 @@ impl syn::parse::Parse for ImportAttribute {
             let value: syn::LitInt = input.parse()?;
             let value = value.base10_parse::<u32>().map_err(|err| syn::Error::new(value.span(), err))?;
             Ok(ImportAttribute::Index(value))
+        } else if lookahead.peek(kw::optional) {
+            input.parse::<kw::optional>()?;
+            Ok(ImportAttribute::Optional)
+        } else if lookahead.peek(kw::default) {
+            input.parse::<kw::default>()?;
+            let _: Token![=] = input.parse()?;
+            let value: syn::LitStr = input.parse()?;
+            Ok(ImportAttribute::Default(value.parse()?))
         } else {
             Err(lookahead.error())
         }
@@ -157,6 +175,107 @@ fn parse_import_attributes(attr: &syn::Attribute) -> Result<Option<Vec<ImportAtt
     Ok(Some(parsed_attrs.into_iter().collect()))
 }
 
+/// Returns the `T` of an `Option<T>`.
+fn option_some_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(syn::TypePath { qself: None, path }) = ty else {
+        return None;
+    };
+
+    let segment = path.segments.last()?;
+    let syn::PathArguments::AngleBracketed(ref arguments) = segment.arguments else {
+        return None;
+    };
+
+    match (segment.ident == "Option", arguments.args.first(), arguments.args.len()) {
+        (true, Some(syn::GenericArgument::Type(ty)), 1) => Some(ty),
+        _ => None,
+    }
+}
+
+/// Generates a block which calls the import with the arguments' registers in `regs`, and converts its return value.
+fn import_call(
+    abi_path: &syn::Path,
+    symbol: &syn::LitByteStr,
+    (has_index, index): (bool, u32),
+    args_joined_regs_ty: &proc_macro2::TokenStream,
+    return_ty: &syn::Type,
+) -> proc_macro2::TokenStream {
+    quote! {{
+        #[link_section = ".polkavm_metadata"]
+        static METADATA_SYMBOL: &[u8] = #symbol;
+
+        #[link_section = ".polkavm_metadata"]
+        static METADATA: #abi_path::private::ExternMetadataV2 = #abi_path::private::ExternMetadataV2 {
+            version: 2,
+            flags: 0,
+            symbol_length: METADATA_SYMBOL.len() as u32,
+            symbol: #abi_path::private::MetadataPointer(METADATA_SYMBOL.as_ptr()),
+            input_regs: <<#args_joined_regs_ty as #abi_path::private::SpillRegs>::Spilled as #abi_path::private::CountTuple>::COUNT,
+            output_regs: <<#return_ty as #abi_path::FromHost>::Regs as #abi_path::private::CountTuple>::COUNT,
+            has_index: #has_index,
+            index: #index,
+        };
+
+        struct Sym;
+
+        #[cfg(target_arch = "riscv32")]
+        impl #abi_path::private::ImportSymbol for Sym {
+            extern fn trampoline(a0: u32, a1: u32, a2: u32, a3: u32, a4: u32, a5: u32) -> #abi_path::private::PackedReturnTy {
+                let mut out0: u32;
+                let mut out1: u32;
+                unsafe {
+                    core::arch::asm!(
+                        ".insn r 0xb, 0, 0, zero, zero, zero\n",
+                        "auipc zero, %pcrel_hi({metadata})\n",
+                        in("a0") a0,
+                        in("a1") a1,
+                        in("a2") a2,
+                        in("a3") a3,
+                        in("a4") a4,
+                        in("a5") a5,
+                        lateout("a0") out0,
+                        lateout("a1") out1,
+                        metadata = sym METADATA,
+                    );
+                }
+                #abi_path::private::PackedReturnTy(out0, out1)
+            }
+        }
+
+        #[cfg(target_arch = "riscv64")]
+        impl #abi_path::private::ImportSymbol for Sym {
+            extern fn trampoline(a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> #abi_path::private::PackedReturnTy {
+                let mut out0: u64;
+                let mut out1: u64;
+                unsafe {
+                    core::arch::asm!(
+                        ".insn r 0xb, 0, 0, zero, zero, zero\n",
+                        "auipc zero, %pcrel_hi({metadata})\n",
+                        ".4byte 0\n",
+                        in("a0") a0,
+                        in("a1") a1,
+                        in("a2") a2,
+                        in("a3") a3,
+                        in("a4") a4,
+                        in("a5") a5,
+                        lateout("a0") out0,
+                        lateout("a1") out1,
+                        metadata = sym METADATA,
+                    );
+                }
+                #abi_path::private::PackedReturnTy(out0, out1)
+            }
+        }
+
+        // The arguments which don't fit into the registers are passed through memory.
+        let mut spilled = <<#args_joined_regs_ty as #abi_path::private::SpillRegs>::Storage as ::core::default::Default>::default();
+        let regs = #abi_path::private::SpillRegs::spill_regs(regs, &mut spilled);
+        let result = #abi_path::private::CallImport::call_import::<Sym>(regs);
+        let result = #abi_path::private::IntoTuple::into_tuple(result.0, result.1);
+        #abi_path::FromHost::from_host(result)
+    }}
+}
+
 pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeignMod) -> Result<proc_macro2::TokenStream, syn::Error> {
     let mut outer_cfg_attributes = Vec::new();
     for attr in input.attrs {
@@ -175,6 +294,7 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
 
     let mut passthrough_tokens = Vec::new();
     let mut tokens = Vec::new();
+    let mut seen_indexes = std::collections::HashMap::new();
     for item in input.items {
         match item {
             syn::ForeignItem::Fn(syn::ForeignItemFn { attrs, sig, vis, .. }) => {
@@ -182,6 +302,8 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
                 let mut inner_doc_attributes = Vec::new();
                 let mut symbol = None;
                 let mut index = None;
+                let mut optional = None;
+                let mut default = None;
 
                 for attr in attrs {
                     if is_rustfmt(&attr) {
@@ -205,7 +327,13 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
                                     symbol = Some(bytes);
                                 }
                                 ImportAttribute::Index(value) => {
-                                    index = Some(value);
+                                    index = Some((value, attr.span()));
+                                }
+                                ImportAttribute::Optional => {
+                                    optional = Some(attr.span());
+                                }
+                                ImportAttribute::Default(value) => {
+                                    default = Some(value);
                                 }
                             }
                         }
@@ -240,6 +368,16 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
                 let symbol = symbol.unwrap_or_else(|| syn::LitByteStr::new(ident.to_string().as_bytes(), ident.span()));
                 let abi_path = attributes.abi.clone().unwrap_or_else(crate::common::default_abi_path);
 
+                if args.len() > crate::abi_support::MAXIMUM_ARGUMENT_REGS {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        format!(
+                            "too many arguments to the imported function '{ident}': at most {} registers' worth of arguments are supported",
+                            crate::abi_support::MAXIMUM_ARGUMENT_REGS
+                        ),
+                    ));
+                }
+
                 let mut args_into_host = Vec::new();
                 let mut args_join = Vec::new();
                 let mut args_joined_regs_ty = quote! { () };
@@ -259,6 +397,7 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
 
                     let arg_ident = crate::common::expr_from_ident(arg_ident);
                     let arg_ty = &arg.ty;
+                    crate::common::reject_owned_buffer(arg_ty)?;
 
                     args_join.push(quote! {
                         let regs = #abi_path::private::JoinTuple::join_tuple((regs, #arg_ident));
@@ -280,7 +419,71 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
                     ident.span(),
                 );
 
-                let (has_index, index) = index.map_or((false, 0), |index| (true, index));
+                if let Some((index, span)) = index {
+                    // The same index can be reused by imports which are enabled under different conditions.
+                    let cfg = inner_cfg_attributes
+                        .iter()
+                        .map(|attr| quote! { #attr }.to_string())
+                        .collect::<Vec<_>>();
+                    if let Some(previous) = seen_indexes.insert((index, cfg), ident.clone()) {
+                        return Err(syn::Error::new(
+                            span,
+                            format!("duplicate import index {index}: it's already used by '{previous}'"),
+                        ));
+                    }
+                }
+
+                let index = index.map_or((false, 0), |(index, _)| (true, index));
+
+                // An optional import first asks the host whether it's available, and if it isn't returns either `None` or its default.
+                let (probe, call_and_return) = match (optional, default) {
+                    (None, Some(default)) => return Err(syn::Error::new(default.span(), "'default' requires 'optional'")),
+                    (None, None) => (quote! {}, import_call(&abi_path, &symbol, index, &args_joined_regs_ty, &return_ty)),
+                    (Some(span), default) => {
+                        let (return_ty, fallback, returns_option) = match (option_some_type(&return_ty), default) {
+                            (None, Some(default)) => (&return_ty, quote! { #default }, false),
+                            (Some(some_ty), None) => (some_ty, quote! { ::core::option::Option::None }, true),
+                            (Some(_), Some(default)) => {
+                                return Err(syn::Error::new(
+                                    default.span(),
+                                    "'default' cannot be used with an 'Option<T>' return type",
+                                ));
+                            }
+                            (None, None) => {
+                                return Err(syn::Error::new(
+                                    span,
+                                    "optional imports must either return an 'Option<T>' or have a 'default'",
+                                ));
+                            }
+                        };
+
+                        let probe_symbol = syn::LitByteStr::new(IMPORT_PROBE_SYMBOL, span);
+                        let probe_regs_ty = quote! { <&[u8] as #abi_path::IntoHost>::Regs };
+                        let probe_return_ty: syn::Type = syn::parse_quote! { u32 };
+                        let probe_call = import_call(&abi_path, &probe_symbol, (false, 0), &probe_regs_ty, &probe_return_ty);
+                        let probe = quote! {
+                            let is_available: u32 = {
+                                let symbol: &[u8] = #symbol;
+                                let (symbol, _destructor) = #abi_path::IntoHost::into_host(symbol);
+                                let regs = #abi_path::private::JoinTuple::join_tuple(((), symbol));
+                                #probe_call
+                            };
+
+                            if is_available == 0 {
+                                return #fallback;
+                            }
+                        };
+
+                        let call = import_call(&abi_path, &symbol, index, &args_joined_regs_ty, return_ty);
+                        let call_and_return = if returns_option {
+                            quote! { ::core::option::Option::Some::<#return_ty>(#call) }
+                        } else {
+                            call
+                        };
+
+                        (probe, call_and_return)
+                    }
+                };
 
                 tokens.push(quote! {
                     #(#outer_cfg_attributes)*
@@ -289,82 +492,16 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
                     #[link_section = ".text.polkavm_import"]
                     #vis unsafe fn #ident(#args) #output {
                         const _: () = {
-                            assert!(<#args_joined_regs_ty as #abi_path::private::CountTuple>::COUNT <= #abi_path::private::MAXIMUM_INPUT_REGS, #assert_message);
+                            assert!(<#args_joined_regs_ty as #abi_path::private::CountTuple>::COUNT <= #abi_path::private::MAXIMUM_ARGUMENT_REGS, #assert_message);
                         };
 
+                        #probe
+
                         #(#args_into_host)*
                         let regs = ();
                         #(#args_join)*
 
-                        #[link_section = ".polkavm_metadata"]
-                        static METADATA_SYMBOL: &[u8] = #symbol;
-
-                        #[link_section = ".polkavm_metadata"]
-                        static METADATA: #abi_path::private::ExternMetadataV2 = #abi_path::private::ExternMetadataV2 {
-                            version: 2,
-                            flags: 0,
-                            symbol_length: METADATA_SYMBOL.len() as u32,
-                            symbol: #abi_path::private::MetadataPointer(METADATA_SYMBOL.as_ptr()),
-                            input_regs: <#args_joined_regs_ty as #abi_path::private::CountTuple>::COUNT,
-                            output_regs: <<#return_ty as #abi_path::FromHost>::Regs as #abi_path::private::CountTuple>::COUNT,
-                            has_index: #has_index,
-                            index: #index,
-                        };
-
-                        struct Sym;
-
-                        #[cfg(target_arch = "riscv32")]
-                        impl #abi_path::private::ImportSymbol for Sym {
-                            extern fn trampoline(a0: u32, a1: u32, a2: u32, a3: u32, a4: u32, a5: u32) -> #abi_path::private::PackedReturnTy {
-                                let mut out0: u32;
-                                let mut out1: u32;
-                                unsafe {
-                                    core::arch::asm!(
-                                        ".insn r 0xb, 0, 0, zero, zero, zero\n",
-                                        "auipc zero, %pcrel_hi({metadata})\n",
-                                        in("a0") a0,
-                                        in("a1") a1,
-                                        in("a2") a2,
-                                        in("a3") a3,
-                                        in("a4") a4,
-                                        in("a5") a5,
-                                        lateout("a0") out0,
-                                        lateout("a1") out1,
-                                        metadata = sym METADATA,
-                                    );
-                                }
-                                #abi_path::private::PackedReturnTy(out0, out1)
-                            }
-                        }
-
-                        #[cfg(target_arch = "riscv64")]
-                        impl #abi_path::private::ImportSymbol for Sym {
-                            extern fn trampoline(a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> #abi_path::private::PackedReturnTy {
-                                let mut out0: u64;
-                                let mut out1: u64;
-                                unsafe {
-                                    core::arch::asm!(
-                                        ".insn r 0xb, 0, 0, zero, zero, zero\n",
-                                        "auipc zero, %pcrel_hi({metadata})\n",
-                                        ".4byte 0\n",
-                                        in("a0") a0,
-                                        in("a1") a1,
-                                        in("a2") a2,
-                                        in("a3") a3,
-                                        in("a4") a4,
-                                        in("a5") a5,
-                                        lateout("a0") out0,
-                                        lateout("a1") out1,
-                                        metadata = sym METADATA,
-                                    );
-                                }
-                                #abi_path::private::PackedReturnTy(out0, out1)
-                            }
-                        }
-
-                        let result = #abi_path::private::CallImport::call_import::<Sym>(regs);
-                        let result = #abi_path::private::IntoTuple::into_tuple(result.0, result.1);
-                        #abi_path::FromHost::from_host(result)
+                        #call_and_return
                     }
                 });
             }
@@ -375,6 +512,8 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
     tokens.push(quote! {
         #[cfg(not(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e")))]
         #(#outer_cfg_attributes)*
+        // Slices and strings have no C equivalent, but this is never called anyway.
+        #[allow(improper_ctypes)]
         extern "C" {
             #(#passthrough_tokens)*
         }
@@ -384,3 +523,171 @@ pub fn polkavm_import(attributes: ImportBlockAttributes, input: syn::ItemForeign
         #(#tokens)*
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(input: syn::ItemForeignMod) -> Result<String, String> {
+        polkavm_import(ImportBlockAttributes::new(), input)
+            .map(|output| output.to_string())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn indexed_imports_are_mixed_with_symbol_only_imports() {
+        let output = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(index = 7)]
+                fn fetch(a0: u32) -> u32;
+                fn log(a0: u32);
+            }
+        })
+        .unwrap();
+
+        let metadata: Vec<_> = output.split("ExternMetadataV2 {").skip(1).collect();
+        assert_eq!(metadata.len(), 2);
+        assert!(metadata[0].contains("has_index : true , index : 7u32"), "{output}");
+        assert!(metadata[1].contains("has_index : false , index : 0u32"), "{output}");
+        assert!(output.contains("b\"fetch\""));
+        assert!(output.contains("b\"log\""));
+    }
+
+    #[test]
+    fn slices_and_strings_are_passed_as_an_address_and_a_length() {
+        let output = import(syn::parse_quote! {
+            extern "C" {
+                fn write(bytes: &[u8], text: &str);
+            }
+        })
+        .unwrap();
+        assert!(
+            output.contains("< & [u8] as :: polkavm_derive :: default_abi :: IntoHost > :: Regs"),
+            "{output}"
+        );
+        assert!(
+            output.contains("< & str as :: polkavm_derive :: default_abi :: IntoHost > :: Regs"),
+            "{output}"
+        );
+
+        let error = import(syn::parse_quote! {
+            extern "C" {
+                fn write(bytes: Vec<u8>);
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, "owned 'Vec' arguments are unsupported; pass a slice or a 'str' instead");
+    }
+
+    #[test]
+    fn duplicate_indexes_are_rejected() {
+        let error = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(index = 1)]
+                fn first();
+                #[polkavm_import(index = 1)]
+                fn second();
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, "duplicate import index 1: it's already used by 'first'");
+
+        import(syn::parse_quote! {
+            extern "C" {
+                #[cfg(feature = "a")]
+                #[polkavm_import(index = 1)]
+                fn first();
+                #[cfg(not(feature = "a"))]
+                #[polkavm_import(index = 1)]
+                fn second();
+            }
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn optional_imports_probe_the_host_first() {
+        let output = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(optional, default = "u32::MAX")]
+                fn fetch(a0: u32) -> u32;
+                #[polkavm_import(optional)]
+                fn lookup(a0: u32) -> Option<u64>;
+                fn log(a0: u32);
+            }
+        })
+        .unwrap();
+
+        let functions: Vec<_> = output.split("unsafe fn").skip(1).collect();
+        assert_eq!(functions.len(), 3);
+        assert!(functions[0].contains("b\"__polkavm_has_import\""), "{output}");
+        assert!(functions[0].contains("return u32 :: MAX ;"), "{output}");
+        assert!(functions[0].contains("< < u32 as :: polkavm_derive :: default_abi :: FromHost > :: Regs"));
+
+        // The import itself returns the `Option`'s value.
+        assert!(functions[1].contains("b\"__polkavm_has_import\""), "{output}");
+        assert!(functions[1].contains("return :: core :: option :: Option :: None ;"), "{output}");
+        assert!(functions[1].contains(":: core :: option :: Option :: Some :: < u64 >"), "{output}");
+        assert!(functions[1].contains("< < u64 as :: polkavm_derive :: default_abi :: FromHost > :: Regs"));
+
+        assert!(!functions[2].contains("__polkavm_has_import"), "{output}");
+        assert!(!functions[2].contains("is_available"), "{output}");
+    }
+
+    #[test]
+    fn arguments_which_dont_fit_into_the_registers_are_spilled() {
+        let many = |arg_count: usize| {
+            let args: Vec<String> = (0..arg_count).map(|nth| format!("a{nth}: u32")).collect();
+            let input = syn::parse_str(&format!("extern \"C\" {{ fn many({}); }}", args.join(", "))).unwrap();
+            import(input)
+        };
+
+        for arg_count in [7, 10] {
+            let output = many(arg_count).unwrap();
+            assert!(
+                output
+                    .contains("let regs = :: polkavm_derive :: default_abi :: private :: SpillRegs :: spill_regs (regs , & mut spilled) ;"),
+                "{output}"
+            );
+            assert!(
+                output.contains("as :: polkavm_derive :: default_abi :: private :: SpillRegs > :: Spilled as"),
+                "{output}"
+            );
+        }
+
+        assert_eq!(
+            many(17).unwrap_err(),
+            "too many arguments to the imported function 'many': at most 16 registers' worth of arguments are supported"
+        );
+    }
+
+    #[test]
+    fn optional_imports_need_a_fallback() {
+        let error = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(optional)]
+                fn fetch() -> u32;
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, "optional imports must either return an 'Option<T>' or have a 'default'");
+
+        let error = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(default = "0")]
+                fn fetch() -> u32;
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, "'default' requires 'optional'");
+
+        let error = import(syn::parse_quote! {
+            extern "C" {
+                #[polkavm_import(optional, default = "None")]
+                fn fetch() -> Option<u32>;
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, "'default' cannot be used with an 'Option<T>' return type");
+    }
+}
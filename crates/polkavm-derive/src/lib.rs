@@ -2,13 +2,52 @@
 #![doc = include_str!("../README.md")]
 
 pub use polkavm_derive_impl_macro::__PRIVATE_DO_NOT_USE_polkavm_define_abi as polkavm_define_abi;
+/// Exports a function from the program.
+///
+/// The export's symbol defaults to the name of the function, and can be overridden with `symbol = "..."`,
+/// which cannot be empty:
+///
+/// ```compile_fail
+/// #[polkavm_derive::polkavm_export(symbol = "")]
+/// extern "C" fn refine() {}
+/// ```
 pub use polkavm_derive_impl_macro::__PRIVATE_DO_NOT_USE_polkavm_export as polkavm_export;
+/// Imports host functions into the program.
+///
+/// An import can be given an explicit index with `#[polkavm_import(index = ...)]`, but two imports of the same block
+/// cannot share an index:
+///
+/// ```compile_fail
+/// #[polkavm_derive::polkavm_import]
+/// extern "C" {
+///     #[polkavm_import(index = 1)]
+///     fn first();
+///     #[polkavm_import(index = 1)]
+///     fn second();
+/// }
+/// ```
 pub use polkavm_derive_impl_macro::__PRIVATE_DO_NOT_USE_polkavm_import as polkavm_import;
 
 pub mod default_abi {
     polkavm_derive_impl_macro::__PRIVATE_DO_NOT_USE_polkavm_impl_abi_support!();
 }
 
+#[cfg(feature = "manifest")]
+extern crate alloc;
+
+#[cfg(feature = "manifest")]
+pub mod manifest;
+
+#[cfg(any(
+    all(
+        feature = "replace-mem-intrinsics",
+        any(target_arch = "riscv32", target_arch = "riscv64"),
+        target_feature = "e"
+    ),
+    test
+))]
+mod mem_intrinsics;
+
 /// Increases the size of the program's heap by a given number of bytes, allocating memory if necessary.
 /// If successful returns a pointer to the *end* of the heap. If unsuccessful returns a null pointer.
 ///
@@ -30,6 +69,54 @@ pub fn sbrk(size: usize) -> *mut u8 {
     }
 }
 
+/// Increases the size of the program's heap by a given number of bytes, like [`sbrk`].
+/// If successful returns a pointer to the *end* of the heap. If unsuccessful returns `None`.
+///
+/// Outside of a guest program there's no heap to grow, so this always returns `None`.
+#[inline]
+pub fn sbrk_checked(size: usize) -> Option<core::ptr::NonNull<u8>> {
+    #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+    {
+        core::ptr::NonNull::new(sbrk(size))
+    }
+
+    #[cfg(not(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e")))]
+    {
+        let _ = size;
+        None
+    }
+}
+
+/// Returns a pointer to the current end of the program's heap.
+///
+/// Outside of a guest program this is always a null pointer.
+#[inline]
+pub fn heap_end() -> *mut u8 {
+    sbrk_checked(0).map_or(core::ptr::null_mut(), core::ptr::NonNull::as_ptr)
+}
+
+static HEAP_START: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Returns how many bytes the program's heap has grown by since this function was first called.
+///
+/// Outside of a guest program this is always 0.
+pub fn heap_used_since_start() -> usize {
+    heap_used_since(&HEAP_START, heap_end() as usize)
+}
+
+/// Returns how far `end` is past the start, recording `end` as the start if there isn't one yet.
+fn heap_used_since(start: &core::sync::atomic::AtomicUsize, end: usize) -> usize {
+    use core::sync::atomic::Ordering;
+
+    let mut start_address = start.load(Ordering::Relaxed);
+    if start_address == 0 {
+        start.store(end, Ordering::Relaxed);
+        start_address = end;
+    }
+
+    end.saturating_sub(start_address)
+}
+
 /// A hardware accelerated memset.
 #[inline]
 #[allow(unused_assignments)]
@@ -53,9 +140,45 @@ pub unsafe fn memset(mut dst: *mut u8, value: usize, mut count: usize) {
     }
 }
 
+/// Copies `count` bytes from `src` to `dst`.
+///
+/// The source and the destination must not overlap.
+///
+/// PolkaVM has no dedicated instruction for copying memory (unlike for [`memset`]), so this is always
+/// a plain [`core::ptr::copy_nonoverlapping`]; guests can already call it in place of a byte loop,
+/// and will get the hardware acceleration once such an instruction is added.
+#[inline]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn memcpy(dst: *mut u8, src: *const u8, count: usize) {
+    // SAFETY: The caller guarantees that both pointers are valid for `count` bytes and don't overlap.
+    unsafe {
+        core::ptr::copy_nonoverlapping(src, dst, count);
+    }
+}
+
+/// Copies `count` bytes from `src` to `dst`, where the source and the destination can overlap.
+///
+/// Disjoint ranges are copied through [`memcpy`]; overlapping ones through [`core::ptr::copy`].
+#[inline]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn memmove(dst: *mut u8, src: *const u8, count: usize) {
+    let (dst_address, src_address) = (dst as usize, src as usize);
+    if dst_address.abs_diff(src_address) >= count {
+        // SAFETY: The caller guarantees that both pointers are valid for `count` bytes, and they don't overlap.
+        unsafe { memcpy(dst, src, count) }
+    } else {
+        // SAFETY: The caller guarantees that both pointers are valid for `count` bytes.
+        unsafe { core::ptr::copy(src, dst, count) }
+    }
+}
+
+/// Returns a pointer to the start of the program's heap, as laid out by the linker.
+///
+/// Outside of a guest program this is always a null pointer.
 #[inline]
 pub fn heap_base() -> *mut core::ffi::c_void {
     #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+    // SAFETY: Loading the heap base has no side effects.
     unsafe {
         let mut output;
         core::arch::asm!(
@@ -71,6 +194,32 @@ pub fn heap_base() -> *mut core::ffi::c_void {
     }
 }
 
+/// Returns the address past which the program's heap can never grow, as laid out by the linker.
+///
+/// The linker computes this for the largest supported page size and without any auxiliary data,
+/// so the limit at runtime can differ slightly; growing the heap past it is refused by the allocators
+/// even if the VM would allow it.
+///
+/// Outside of a guest program no limit is known, so this is always `usize::MAX`.
+#[inline]
+pub fn heap_limit() -> usize {
+    #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+    // SAFETY: Loading the heap limit has no side effects.
+    unsafe {
+        let mut output;
+        core::arch::asm!(
+            ".insn r 0xb, 4, 0, {dst}, zero, zero",
+            dst = out(reg) output,
+        );
+        output
+    }
+
+    #[cfg(not(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e")))]
+    {
+        usize::MAX
+    }
+}
+
 /// A basic memory allocator which doesn't support deallocation.
 pub struct LeakingAllocator;
 
@@ -78,18 +227,247 @@ pub struct LeakingAllocator;
 unsafe impl core::alloc::GlobalAlloc for LeakingAllocator {
     #[inline]
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let pointer = crate::sbrk(0);
-        let padding = (-(pointer as isize)) as usize & (layout.align() - 1);
-        let size = layout.size().wrapping_add(padding);
-        if crate::sbrk(size).is_null() {
+        leaking_alloc(&Sbrk, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+        leaking_alloc_zeroed(&Sbrk, layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+}
+
+/// Allocates memory for the given layout right at the end of the heap, growing it through the [`ProgramBreak`].
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), test))]
+#[inline]
+fn leaking_alloc(program_break: &impl ProgramBreak, layout: core::alloc::Layout) -> *mut u8 {
+    let pointer = program_break.sbrk(0);
+    let padding = (pointer as usize).wrapping_neg() & (layout.align() - 1);
+    let Some(size) = layout.size().checked_add(padding) else {
+        return core::ptr::null_mut();
+    };
+
+    if size > program_break.limit().saturating_sub(pointer as usize) || program_break.sbrk(size).is_null() {
+        return core::ptr::null_mut();
+    }
+
+    let pointer = pointer.wrapping_add(padding);
+    debug_assert_eq!(pointer as usize & (layout.align() - 1), 0);
+    pointer
+}
+
+/// Like [`leaking_alloc`], but also zeroes the memory.
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), test))]
+#[inline]
+fn leaking_alloc_zeroed(program_break: &impl ProgramBreak, layout: core::alloc::Layout) -> *mut u8 {
+    let pointer = leaking_alloc(program_break, layout);
+    if !pointer.is_null() {
+        // SAFETY: The pointer was just allocated with the layout's size.
+        unsafe { memset(pointer, 0, layout.size()) }
+    }
+
+    pointer
+}
+
+/// A primitive which grows the program's heap, like [`sbrk`].
+///
+/// This is what [`FreeListAllocator`] gets its memory from.
+pub trait ProgramBreak {
+    /// Increases the size of the heap by a given number of bytes.
+    ///
+    /// If successful returns a pointer to the *end* of the heap. If unsuccessful returns a null pointer.
+    fn sbrk(&self, size: usize) -> *mut u8;
+
+    /// Returns the address past which the heap can't grow.
+    ///
+    /// Allocators check this before growing the heap, so that they fail cleanly instead of relying on [`sbrk`](ProgramBreak::sbrk)
+    /// of an exhausted heap. Defaults to no limit.
+    fn limit(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// The program's break, as grown through [`sbrk`].
+#[derive(Copy, Clone, Default)]
+pub struct Sbrk;
+
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), doc))]
+impl ProgramBreak for Sbrk {
+    #[inline]
+    fn sbrk(&self, size: usize) -> *mut u8 {
+        crate::sbrk(size)
+    }
+
+    #[inline]
+    fn limit(&self) -> usize {
+        crate::heap_limit()
+    }
+}
+
+const FREE_LIST_MIN_BLOCK_SIZE: usize = 16;
+const FREE_LIST_PAGE_SIZE: usize = 4096;
+const FREE_LIST_SIZE_CLASS_COUNT: usize = (FREE_LIST_PAGE_SIZE.trailing_zeros() - FREE_LIST_MIN_BLOCK_SIZE.trailing_zeros() + 1) as usize;
+
+/// A free block, stored inside of the block's own memory.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    size: usize,
+}
+
+/// A memory allocator which supports deallocation by keeping the freed blocks on free lists.
+///
+/// Allocations of up to 4096 bytes are rounded up to a power of two, and each such size class has its own free list.
+/// Bigger allocations are rounded up to a multiple of 4096 bytes and share a single free list, whose blocks are split
+/// when they're bigger than necessary. When no free block fits an allocation the heap is grown through the [`ProgramBreak`].
+///
+/// Alignments of up to 4096 bytes are supported.
+pub struct FreeListAllocator<B = Sbrk> {
+    program_break: B,
+    small_blocks: core::cell::UnsafeCell<[*mut FreeBlock; FREE_LIST_SIZE_CLASS_COUNT]>,
+    large_blocks: core::cell::UnsafeCell<*mut FreeBlock>,
+}
+
+// SAFETY: Guest programs are single threaded.
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), doc))]
+unsafe impl<B: Sync> Sync for FreeListAllocator<B> {}
+
+impl FreeListAllocator<Sbrk> {
+    /// Creates an allocator which grows the heap through [`sbrk`].
+    pub const fn new() -> Self {
+        Self::with_program_break(Sbrk)
+    }
+}
+
+impl Default for FreeListAllocator<Sbrk> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B> FreeListAllocator<B> {
+    /// Creates an allocator which grows the heap through the given [`ProgramBreak`].
+    pub const fn with_program_break(program_break: B) -> Self {
+        FreeListAllocator {
+            program_break,
+            small_blocks: core::cell::UnsafeCell::new([core::ptr::null_mut(); FREE_LIST_SIZE_CLASS_COUNT]),
+            large_blocks: core::cell::UnsafeCell::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Returns the index of the size class of a small allocation, or `None` for a large one.
+    fn size_class(layout: core::alloc::Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(FREE_LIST_MIN_BLOCK_SIZE).next_power_of_two();
+        if size > FREE_LIST_PAGE_SIZE {
+            return None;
+        }
+
+        Some((size.trailing_zeros() - FREE_LIST_MIN_BLOCK_SIZE.trailing_zeros()) as usize)
+    }
+
+    /// Returns the size of the block of a large allocation.
+    fn large_block_size(layout: core::alloc::Layout) -> usize {
+        // This can't overflow since the size of a layout can be at most `isize::MAX`.
+        (layout.size() + FREE_LIST_PAGE_SIZE - 1) & !(FREE_LIST_PAGE_SIZE - 1)
+    }
+}
+
+impl<B: ProgramBreak> FreeListAllocator<B> {
+    /// Grows the heap by a new block with the given size and alignment.
+    unsafe fn grow(&self, size: usize, align: usize) -> *mut u8 {
+        let pointer = self.program_break.sbrk(0);
+        let padding = (pointer as usize).wrapping_neg() & (align - 1);
+        let Some(size) = size.checked_add(padding) else {
+            return core::ptr::null_mut();
+        };
+
+        if size > self.program_break.limit().saturating_sub(pointer as usize) || self.program_break.sbrk(size).is_null() {
             return core::ptr::null_mut();
         }
 
         pointer.add(padding)
     }
+}
 
-    #[inline]
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+// SAFETY: Every block is handed out only once until it's freed, and is big enough and aligned for its layout.
+unsafe impl<B: ProgramBreak> core::alloc::GlobalAlloc for FreeListAllocator<B> {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        if let Some(class) = Self::size_class(layout) {
+            let free_list = &mut (*self.small_blocks.get())[class];
+            if free_list.is_null() {
+                let size = FREE_LIST_MIN_BLOCK_SIZE << class;
+                return self.grow(size, size);
+            }
+
+            let block = *free_list;
+            *free_list = (*block).next;
+            return block.cast();
+        }
+
+        if layout.align() > FREE_LIST_PAGE_SIZE {
+            return core::ptr::null_mut();
+        }
+
+        let size = Self::large_block_size(layout);
+        let mut link = self.large_blocks.get();
+        while !(*link).is_null() {
+            let block = *link;
+            if (*block).size >= size {
+                if (*block).size == size {
+                    *link = (*block).next;
+                } else {
+                    // Keep the rest of the block on the free list.
+                    let rest = block.byte_add(size);
+                    rest.write(FreeBlock {
+                        next: (*block).next,
+                        size: (*block).size - size,
+                    });
+                    *link = rest;
+                }
+
+                return block.cast();
+            }
+
+            link = core::ptr::addr_of_mut!((*block).next);
+        }
+
+        self.grow(size, FREE_LIST_PAGE_SIZE)
+    }
+
+    // Every block is aligned to at least 16 bytes.
+    #[allow(clippy::cast_ptr_alignment)]
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: core::alloc::Layout) {
+        let block = pointer.cast::<FreeBlock>();
+        let (free_list, size) = match Self::size_class(layout) {
+            Some(class) => (&mut (*self.small_blocks.get())[class], FREE_LIST_MIN_BLOCK_SIZE << class),
+            None => (&mut *self.large_blocks.get(), Self::large_block_size(layout)),
+        };
+
+        block.write(FreeBlock { next: *free_list, size });
+        *free_list = block;
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: core::alloc::Layout, new_size: usize) -> *mut u8 {
+        let new_layout = core::alloc::Layout::from_size_align_unchecked(new_size, layout.align());
+        let fits_in_place = match (Self::size_class(layout), Self::size_class(new_layout)) {
+            (Some(class), Some(new_class)) => class == new_class,
+            (None, None) => Self::large_block_size(layout) == Self::large_block_size(new_layout),
+            _ => false,
+        };
+
+        if fits_in_place {
+            return pointer;
+        }
+
+        let new_pointer = self.alloc(new_layout);
+        if !new_pointer.is_null() {
+            crate::memcpy(new_pointer, pointer, layout.size().min(new_size));
+            self.dealloc(pointer, layout);
+        }
+
+        new_pointer
+    }
 }
 
 /// Sets the minimum stack size.
@@ -106,6 +484,599 @@ macro_rules! min_stack_size {
     }
 }
 
+/// Defines a static and exports its address and length, so that the host can read it directly from the program's memory.
+///
+/// ```ignore
+/// polkavm_derive::polkavm_export_static!("config", static CONFIG: [u8; 16] = [0; 16]);
+/// ```
+///
+/// The export shows up in the program blob's data exports, which also say whether the static was placed
+/// in the read-write data (e.g. because it's a `static mut`) and so can change while the program is running.
+#[macro_export]
+macro_rules! polkavm_export_static {
+    (@export $symbol:literal, $name:ident, $ty:ty) => {
+        #[cfg(all(target_arch = "riscv32", target_feature = "e"))]
+        ::core::arch::global_asm!(
+            ".pushsection .polkavm_data_exports,\"Ra\",@note\n",
+            ".byte 1\n", // Version.
+            ".4byte {symbol_length}\n",
+            ::core::concat!(".ascii \"", $symbol, "\"\n"),
+            "auipc zero, %pcrel_hi({data})\n",
+            ".4byte {length}\n",
+            ".popsection\n",
+            symbol_length = const $symbol.len(),
+            data = sym $name,
+            length = const ::core::mem::size_of::<$ty>(),
+        );
+
+        #[cfg(all(target_arch = "riscv64", target_feature = "e"))]
+        ::core::arch::global_asm!(
+            ".pushsection .polkavm_data_exports,\"Ra\",@note\n",
+            ".byte 1\n", // Version.
+            ".4byte {symbol_length}\n",
+            ::core::concat!(".ascii \"", $symbol, "\"\n"),
+            "auipc zero, %pcrel_hi({data})\n",
+            ".4byte 0\n",
+            ".4byte {length}\n",
+            ".popsection\n",
+            symbol_length = const $symbol.len(),
+            data = sym $name,
+            length = const ::core::mem::size_of::<$ty>(),
+        );
+    };
+
+    ($symbol:literal, $(#[$attr:meta])* $vis:vis static mut $name:ident: $ty:ty = $value:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static mut $name: $ty = $value;
+        $crate::polkavm_export_static!(@export $symbol, $name, $ty);
+    };
+
+    ($symbol:literal, $(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $value:expr $(;)?) => {
+        $(#[$attr])*
+        $vis static $name: $ty = $value;
+        $crate::polkavm_export_static!(@export $symbol, $name, $ty);
+    };
+}
+
+/// The byte with which [`paint_stack`] fills the unused part of the stack.
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), test))]
+const STACK_SENTINEL: u8 = 0xa5;
+
+/// The stack pointer at which the stack was last painted, or 0 if it wasn't.
+#[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+static STACK_PAINTED_AT: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Returns the lowest address of the stack, as laid out by the linker.
+///
+/// The stack can only be bigger at runtime, so this is always inside of it unless it was moved down completely past it.
+#[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+#[inline]
+fn stack_floor() -> usize {
+    // SAFETY: Loading the stack floor has no side effects.
+    unsafe {
+        let mut output;
+        core::arch::asm!(
+            ".insn r 0xb, 5, 0, {dst}, zero, zero",
+            dst = out(reg) output,
+        );
+        output
+    }
+}
+
+/// Fills the unused part of the stack, from the current stack pointer down to the bottom of the stack, with a sentinel pattern.
+///
+/// Call this early, e.g. at the start of an exported entry point, and then call [`measure_stack_high_water`]
+/// to find out how deep the stack went since. Use this to pick a value for [`min_stack_size!`].
+///
+/// Outside of a guest program this does nothing.
+#[inline(never)]
+pub fn paint_stack() {
+    #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+    {
+        let stack_pointer: usize;
+        // SAFETY: This just reads the stack pointer.
+        unsafe {
+            core::arch::asm!("mv {dst}, sp", dst = out(reg) stack_pointer, options(nomem, nostack));
+        }
+
+        let floor = stack_floor();
+        if floor < stack_pointer {
+            // SAFETY: Everything between the stack's floor and the stack pointer is unused stack memory.
+            unsafe { memset(floor as *mut u8, usize::from(STACK_SENTINEL), stack_pointer - floor) }
+        }
+
+        STACK_PAINTED_AT.store(stack_pointer, core::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Returns how many bytes below the point where [`paint_stack`] was called the stack has been used at its deepest.
+///
+/// This finds the deepest overwritten sentinel, so a value which happens to be equal to the sentinel can make
+/// the usage appear slightly smaller than it really was.
+///
+/// Returns 0 if the stack wasn't painted, and outside of a guest program.
+#[inline(never)]
+pub fn measure_stack_high_water() -> usize {
+    #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+    {
+        let painted_at = STACK_PAINTED_AT.load(core::sync::atomic::Ordering::Relaxed);
+        // SAFETY: Everything between the stack's floor and where it was painted is stack memory.
+        unsafe { stack_high_water(stack_floor() as *const u8, painted_at as *const u8) }
+    }
+
+    #[cfg(not(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e")))]
+    {
+        0
+    }
+}
+
+/// Returns how many bytes below `painted_at` are no longer the sentinel, scanning up from `floor`.
+///
+/// # Safety
+///
+/// Every byte between the two pointers must be readable.
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), test))]
+unsafe fn stack_high_water(floor: *const u8, painted_at: *const u8) -> usize {
+    let mut pointer = floor;
+    // The memory is read volatilely since parts of it can be in use by the stack frames,
+    // including the current one, which rules out creating a slice of it.
+    while pointer < painted_at && pointer.read_volatile() == STACK_SENTINEL {
+        pointer = pointer.add(1);
+    }
+
+    (painted_at as usize).saturating_sub(pointer as usize)
+}
+
+/// Defines a guest program's entry point along with the usual boilerplate of a `no_std` guest:
+/// a global allocator, a panic handler which traps and, optionally, the minimum stack size.
+///
+/// ```ignore
+/// polkavm_derive::polkavm_main! {
+///     stack_size = 65536,
+///     allocator = free_list,
+///     panic_message = true,
+///     fn main() -> u32 {
+///         42
+///     }
+/// }
+/// ```
+///
+/// Every knob is optional and can be given at most once:
+///   - `stack_size`: passed to [`min_stack_size!`];
+///   - `allocator`: either `leaking` (the default) for [`LeakingAllocator`] or `free_list` for [`FreeListAllocator`];
+///   - `panic_message`: whether the panic handler should record the panic's message through [`polkavm_panic_handler!`]
+///     (`false` by default, in which case it just traps); or `custom` to not define a panic handler at all,
+///     for programs which define theirs elsewhere.
+///
+/// A program's own panic handler goes between the knobs and the entry point, in which case no other panic handler is defined:
+///
+/// ```ignore
+/// polkavm_derive::polkavm_main! {
+///     #[panic_handler]
+///     fn panic(_info: &core::panic::PanicInfo) -> ! {
+///         unsafe { core::arch::asm!("unimp", options(noreturn)) }
+///     }
+///
+///     fn main() {}
+/// }
+/// ```
+///
+/// Asking for both, by also passing `panic_message = true` or `panic_message = false`, is a compile error:
+///
+/// ```compile_fail
+/// polkavm_derive::polkavm_main! {
+///     panic_message = false,
+///     #[panic_handler]
+///     fn panic(_info: &core::panic::PanicInfo) -> ! {
+///         loop {}
+///     }
+///
+///     fn entry_point() {}
+/// }
+/// ```
+///
+/// Every panic handler of this crate also defines a marker item, so additionally invoking [`polkavm_panic_handler!`]
+/// without passing `panic_message = custom` fails with a "the name `__polkavm_panic_handler_is_defined_more_than_once`
+/// is defined multiple times" error:
+///
+/// ```compile_fail,E0428
+/// polkavm_derive::polkavm_main! {
+///     fn entry_point() {}
+/// }
+///
+/// polkavm_derive::polkavm_panic_handler!();
+/// ```
+///
+/// The function is exported through [`polkavm_export`].
+#[macro_export]
+macro_rules! polkavm_main {
+    (@parse [] [$($allocator:tt)*] [$($panic_message:tt)*] [] stack_size = $stack_size:expr, $($rest:tt)*) => {
+        $crate::polkavm_main!(@parse [$stack_size] [$($allocator)*] [$($panic_message)*] [] $($rest)*);
+    };
+
+    (@parse [$($stack_size:tt)*] [] [$($panic_message:tt)*] [] allocator = $allocator:ident, $($rest:tt)*) => {
+        $crate::polkavm_main!(@parse [$($stack_size)*] [$allocator] [$($panic_message)*] [] $($rest)*);
+    };
+
+    (@parse [$($stack_size:tt)*] [$($allocator:tt)*] [] [] panic_message = $panic_message:tt, $($rest:tt)*) => {
+        $crate::polkavm_main!(@parse [$($stack_size)*] [$($allocator)*] [$panic_message] [] $($rest)*);
+    };
+
+    (
+        @parse [$($stack_size:tt)*] [$($allocator:tt)*] [$($panic_message:tt)*] []
+        #[panic_handler] $(#[$handler_attribute:meta])* fn $handler:ident($($handler_args:tt)*) -> ! $handler_body:block
+        $($rest:tt)*
+    ) => {
+        $crate::polkavm_main!(
+            @parse [$($stack_size)*] [$($allocator)*] [$($panic_message)*]
+            [#[panic_handler] $(#[$handler_attribute])* fn $handler($($handler_args)*) -> ! $handler_body]
+            $($rest)*
+        );
+    };
+
+    (
+        @parse [$($stack_size:expr)?] [$($allocator:ident)?] [$($panic_message:tt)?] [$($panic_handler:tt)*]
+        $vis:vis fn $name:ident() $(-> $return_ty:ty)? $body:block
+    ) => {
+        $(
+            #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+            $crate::min_stack_size!($stack_size);
+        )?
+
+        $crate::polkavm_main!(@allocator $($allocator)?);
+        $crate::polkavm_main!(@panic_handler [$($panic_message)?] $($panic_handler)*);
+
+        #[$crate::polkavm_export]
+        $vis extern "C" fn $name() $(-> $return_ty)? $body
+    };
+
+    (@allocator) => {
+        $crate::polkavm_main!(@allocator leaking);
+    };
+
+    (@allocator leaking) => {
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        #[global_allocator]
+        static __POLKAVM_MAIN_ALLOCATOR: $crate::LeakingAllocator = $crate::LeakingAllocator;
+    };
+
+    (@allocator free_list) => {
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        #[global_allocator]
+        static __POLKAVM_MAIN_ALLOCATOR: $crate::FreeListAllocator = $crate::FreeListAllocator::new();
+    };
+
+    (@allocator $allocator:ident) => {
+        ::core::compile_error!(::core::concat!(
+            "unknown allocator '",
+            ::core::stringify!($allocator),
+            "'; expected either 'leaking' or 'free_list'"
+        ));
+    };
+
+    (@panic_handler []) => {
+        $crate::polkavm_main!(@panic_handler [false]);
+    };
+
+    (@panic_handler [] $($panic_handler:tt)+) => {
+        $($panic_handler)+
+    };
+
+    (@panic_handler [custom] $($panic_handler:tt)*) => {
+        $($panic_handler)*
+    };
+
+    (@panic_handler [true]) => {
+        $crate::polkavm_panic_handler!();
+    };
+
+    (@panic_handler [false]) => {
+        $crate::polkavm_panic_handler!(@marker);
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        #[panic_handler]
+        fn __polkavm_main_panic_handler(_info: &::core::panic::PanicInfo) -> ! {
+            $crate::__private_trap(&[])
+        }
+    };
+
+    (@panic_handler [$panic_message:literal] $($panic_handler:tt)+) => {
+        ::core::compile_error!(::core::concat!(
+            "the program defines its own panic handler, so 'panic_message = ",
+            ::core::stringify!($panic_message),
+            "' can't define another one; remove the 'panic_message' knob"
+        ));
+    };
+
+    (@panic_handler [$panic_message:tt] $($panic_handler:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "invalid 'panic_message' value '",
+            ::core::stringify!($panic_message),
+            "'; expected either 'true', 'false' or 'custom'"
+        ));
+    };
+
+    (@parse $($tokens:tt)*) => {
+        ::core::compile_error!(
+            "expected 'fn name() { ... }', optionally preceded by the 'stack_size', 'allocator' and 'panic_message' knobs, each given at most once, and by a '#[panic_handler]'"
+        );
+    };
+
+    ($($tokens:tt)*) => {
+        $crate::polkavm_main!(@parse [] [] [] [] $($tokens)*);
+    };
+}
+
+/// Defines a panic handler which formats the panic's message into a statically reserved buffer before trapping.
+///
+/// ```ignore
+/// polkavm_derive::polkavm_panic_handler!(buffer_size = 256);
+/// ```
+///
+/// The buffer's size defaults to 256 bytes; longer messages are truncated at a character boundary.
+///
+/// After the program traps the host can read the message back in one of two ways:
+///   - the address and the length of the message are in `a0` and `a1`;
+///   - the `__polkavm_panic_message` export returns them, also in `a0` and `a1`.
+#[macro_export]
+macro_rules! polkavm_panic_handler {
+    () => {
+        $crate::polkavm_panic_handler!(buffer_size = 256);
+    };
+
+    (@marker) => {
+        // Defined along with every panic handler so that a second one is reported by name.
+        #[doc(hidden)]
+        #[allow(non_camel_case_types, dead_code)]
+        struct __polkavm_panic_handler_is_defined_more_than_once;
+    };
+
+    (buffer_size = $buffer_size:expr $(,)?) => {
+        $crate::polkavm_panic_handler!(@marker);
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        static __POLKAVM_PANIC_MESSAGE: $crate::PanicMessageBuffer<{ $buffer_size }> = $crate::PanicMessageBuffer::new();
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        #[panic_handler]
+        fn __polkavm_panic_handler(info: &::core::panic::PanicInfo) -> ! {
+            $crate::__private_trap(__POLKAVM_PANIC_MESSAGE.record(info))
+        }
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        #[$crate::polkavm_export]
+        extern "C" fn __polkavm_panic_message() -> &'static [u8] {
+            __POLKAVM_PANIC_MESSAGE.get()
+        }
+    };
+}
+
+/// The buffer into which [`polkavm_panic_handler!`] formats the panic's message.
+#[doc(hidden)]
+pub struct PanicMessageBuffer<const N: usize> {
+    buffer: core::cell::UnsafeCell<[u8; N]>,
+    length: core::cell::Cell<usize>,
+    is_recording: core::cell::Cell<bool>,
+}
+
+// SAFETY: Guest programs are single threaded.
+#[cfg(any(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"), doc))]
+unsafe impl<const N: usize> Sync for PanicMessageBuffer<N> {}
+
+impl<const N: usize> PanicMessageBuffer<N> {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        PanicMessageBuffer {
+            buffer: core::cell::UnsafeCell::new([0; N]),
+            length: core::cell::Cell::new(0),
+            is_recording: core::cell::Cell::new(false),
+        }
+    }
+
+    /// Formats the message into the buffer, replacing the previous one, and returns it.
+    ///
+    /// A message which doesn't fit is truncated at a character boundary. A panic while formatting the message
+    /// (e.g. in a `Display` impl) makes the nested call return an empty message.
+    pub fn record(&self, message: impl core::fmt::Display) -> &[u8] {
+        if self.is_recording.replace(true) {
+            return &[];
+        }
+
+        struct Writer<'a> {
+            buffer: &'a mut [u8],
+            length: usize,
+            is_truncated: bool,
+        }
+
+        impl<'a> core::fmt::Write for Writer<'a> {
+            fn write_str(&mut self, string: &str) -> core::fmt::Result {
+                if self.is_truncated {
+                    return Ok(());
+                }
+
+                let mut length = string.len().min(self.buffer.len() - self.length);
+                while !string.is_char_boundary(length) {
+                    length -= 1;
+                }
+
+                self.buffer[self.length..self.length + length].copy_from_slice(&string.as_bytes()[..length]);
+                self.length += length;
+                self.is_truncated = length < string.len();
+                Ok(())
+            }
+        }
+
+        // SAFETY: The buffer is only ever borrowed here and in `get`, and `is_recording` prevents
+        //         reentrancy, so this is the only reference to the buffer.
+        let buffer = unsafe { &mut *self.buffer.get() };
+        let mut writer = Writer {
+            buffer,
+            length: 0,
+            is_truncated: false,
+        };
+        self.length.set(0);
+        let _ = core::fmt::Write::write_fmt(&mut writer, format_args!("{message}"));
+        self.length.set(writer.length);
+        self.is_recording.set(false);
+
+        self.get()
+    }
+
+    /// Returns the last recorded message.
+    pub fn get(&self) -> &[u8] {
+        // SAFETY: The buffer is only ever mutably borrowed while a message is being recorded.
+        let buffer = unsafe { &*self.buffer.get() };
+        &buffer[..self.length.get()]
+    }
+}
+
+/// Traps, putting the address and the length of the given message into `a0` and `a1`.
+#[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+#[doc(hidden)]
+pub fn __private_trap(message: &[u8]) -> ! {
+    // SAFETY: This just traps.
+    unsafe {
+        core::arch::asm!(
+            "unimp",
+            in("a0") message.as_ptr(),
+            in("a1") message.len(),
+            options(noreturn),
+        );
+    }
+}
+
+/// Calls a host function by its raw index, without declaring a typed import.
+///
+/// ```ignore
+/// let (a0, a1) = polkavm_derive::hostcall!(7, a0 = pointer as usize, a1 = length);
+/// ```
+///
+/// The index must be an integer literal which fits in 32 bits. Only the argument registers `a0` to `a5` can be given,
+/// each at most once and as a `usize`; the rest are zero. Returns the values of `a0` and `a1` after the call.
+///
+/// The call is imported as `__polkavm_hostcall_<index>` with that fixed index, so the host can handle it either
+/// by defining a function with that name or through its fallback handler. Any other imports get the indexes which are still free.
+///
+/// Outside of a guest program there's no host to call, so this panics.
+#[macro_export]
+macro_rules! hostcall {
+    (@reg a0) => { 0 };
+    (@reg a1) => { 1 };
+    (@reg a2) => { 2 };
+    (@reg a3) => { 3 };
+    (@reg a4) => { 4 };
+    (@reg a5) => { 5 };
+    (@reg $reg:tt) => {
+        ::core::compile_error!(::core::concat!(
+            "'",
+            ::core::stringify!($reg),
+            "' is not an argument register; expected one of 'a0', 'a1', 'a2', 'a3', 'a4' or 'a5'"
+        ))
+    };
+
+    ($index:literal $(, $reg:ident = $value:expr)* $(,)?) => {{
+        const INDEX: u32 = $crate::__private_hostcall_index($index);
+        const _: () = $crate::__private_check_hostcall_regs(&[$($crate::hostcall!(@reg $reg)),*]);
+
+        #[allow(unused_mut)]
+        let mut regs: [usize; 6] = [0; 6];
+        $(regs[$crate::hostcall!(@reg $reg)] = $value;)*
+
+        #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+        {
+            #[link_section = ".polkavm_metadata"]
+            static METADATA_SYMBOL: &[u8] = ::core::concat!("__polkavm_hostcall_", ::core::stringify!($index)).as_bytes();
+
+            #[link_section = ".polkavm_metadata"]
+            static METADATA: $crate::default_abi::private::ExternMetadataV2 = $crate::default_abi::private::ExternMetadataV2 {
+                version: 2,
+                flags: 0,
+                symbol_length: METADATA_SYMBOL.len() as u32,
+                symbol: $crate::default_abi::private::MetadataPointer(METADATA_SYMBOL.as_ptr()),
+                input_regs: 6,
+                output_regs: 2,
+                has_index: true,
+                index: INDEX,
+            };
+
+            let (out0, out1): (usize, usize);
+
+            // SAFETY: The host call only clobbers the caller saved registers.
+            #[cfg(target_arch = "riscv32")]
+            unsafe {
+                ::core::arch::asm!(
+                    ".insn r 0xb, 0, 0, zero, zero, zero\n",
+                    "auipc zero, %pcrel_hi({metadata})\n",
+                    inlateout("a0") regs[0] => out0,
+                    inlateout("a1") regs[1] => out1,
+                    in("a2") regs[2],
+                    in("a3") regs[3],
+                    in("a4") regs[4],
+                    in("a5") regs[5],
+                    metadata = sym METADATA,
+                    clobber_abi("C"),
+                );
+            }
+
+            // SAFETY: The host call only clobbers the caller saved registers.
+            #[cfg(target_arch = "riscv64")]
+            unsafe {
+                ::core::arch::asm!(
+                    ".insn r 0xb, 0, 0, zero, zero, zero\n",
+                    "auipc zero, %pcrel_hi({metadata})\n",
+                    ".4byte 0\n",
+                    inlateout("a0") regs[0] => out0,
+                    inlateout("a1") regs[1] => out1,
+                    in("a2") regs[2],
+                    in("a3") regs[3],
+                    in("a4") regs[4],
+                    in("a5") regs[5],
+                    metadata = sym METADATA,
+                    clobber_abi("C"),
+                );
+            }
+
+            (out0, out1)
+        }
+
+        #[cfg(not(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e")))]
+        {
+            let _ = (INDEX, regs);
+            ::core::panic!("host calls can only be made from within a guest program")
+        }
+    }};
+
+    ($($tokens:tt)*) => {
+        ::core::compile_error!("expected an integer literal index, optionally followed by 'register = value' pairs");
+    };
+}
+
+/// Checks that every register of a [`hostcall!`] is given at most once.
+#[doc(hidden)]
+pub const fn __private_check_hostcall_regs(regs: &[usize]) {
+    let mut i = 0;
+    while i < regs.len() {
+        let mut j = i + 1;
+        while j < regs.len() {
+            if regs[i] == regs[j] {
+                panic!("a register was given more than once to a host call");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Checks that the index of a [`hostcall!`] fits into the immediate of `ecalli`.
+#[doc(hidden)]
+pub const fn __private_hostcall_index(index: i128) -> u32 {
+    if index < 0 || index > 0xffff_ffff {
+        panic!("the index of a host call must fit in 32 bits");
+    }
+
+    index as u32
+}
+
 #[cfg(target_pointer_width = "32")]
 #[cfg(any(all(target_arch = "riscv32", target_feature = "e"), doc))]
 #[no_mangle]
@@ -116,3 +1087,373 @@ pub unsafe extern "C" fn __atomic_fetch_add_8(address: *mut u64, value: u64) ->
         old_value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use crate::{FreeListAllocator, ProgramBreak};
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::Cell;
+    use std::vec::Vec;
+
+    /// A heap of a fixed size, simulating the program's break.
+    struct Arena {
+        memory: Vec<u8>,
+        base: *mut u8,
+        length: Cell<usize>,
+        limit: Cell<usize>,
+    }
+
+    impl Arena {
+        fn new(capacity: usize) -> Self {
+            let mut memory = std::vec![0; capacity];
+            Arena {
+                base: memory.as_mut_ptr(),
+                limit: Cell::new(memory.as_ptr() as usize + capacity),
+                memory,
+                length: Cell::new(0),
+            }
+        }
+    }
+
+    impl ProgramBreak for &Arena {
+        fn sbrk(&self, size: usize) -> *mut u8 {
+            let length = self.length.get();
+            if size > self.memory.len() - length {
+                return core::ptr::null_mut();
+            }
+
+            self.length.set(length + size);
+            self.base.wrapping_add(length + size)
+        }
+
+        fn limit(&self) -> usize {
+            self.limit.get()
+        }
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn free_list_allocator_reuses_freed_blocks() {
+        let arena = Arena::new(64 * 1024);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: Every pointer is only accessed within the bounds of its allocation.
+        unsafe {
+            let first = allocator.alloc(layout(24, 8));
+            let second = allocator.alloc(layout(24, 8));
+            assert!(!first.is_null() && !second.is_null());
+            assert_ne!(first, second);
+
+            let length = arena.length.get();
+            allocator.dealloc(first, layout(24, 8));
+            // Both allocations are in the same 32 byte size class.
+            assert_eq!(allocator.alloc(layout(17, 1)), first);
+            assert_eq!(arena.length.get(), length);
+            assert_ne!(allocator.alloc(layout(24, 8)), first);
+        }
+    }
+
+    #[test]
+    fn free_list_allocator_honors_alignment() {
+        let arena = Arena::new(256 * 1024);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: Every pointer is only accessed within the bounds of its allocation.
+        unsafe {
+            for align in (0..=12).map(|shift| 1 << shift) {
+                for size in [1, align, 5000] {
+                    let pointer = allocator.alloc(layout(size, align));
+                    assert!(!pointer.is_null());
+                    assert_eq!(pointer as usize % align, 0, "size = {size}, align = {align}");
+                }
+            }
+
+            assert!(allocator.alloc(layout(1, 8192)).is_null());
+        }
+    }
+
+    #[test]
+    fn free_list_allocator_splits_large_blocks() {
+        let arena = Arena::new(64 * 1024);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: Every pointer is only accessed within the bounds of its allocation.
+        unsafe {
+            let block = allocator.alloc(layout(4 * 4096, 8));
+            assert!(!block.is_null());
+            allocator.dealloc(block, layout(4 * 4096, 8));
+
+            let length = arena.length.get();
+            assert_eq!(allocator.alloc(layout(5000, 8)), block);
+            assert_eq!(allocator.alloc(layout(2 * 4096, 4096)), block.add(2 * 4096));
+            assert_eq!(arena.length.get(), length);
+            assert!(!allocator.alloc(layout(4097, 8)).is_null());
+            assert!(arena.length.get() > length);
+        }
+    }
+
+    #[test]
+    fn free_list_allocator_reallocates() {
+        let arena = Arena::new(64 * 1024);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: Every pointer is only accessed within the bounds of its allocation.
+        unsafe {
+            let pointer = allocator.alloc(layout(8, 1));
+            for index in 0..8 {
+                *pointer.add(index) = index as u8;
+            }
+
+            // This stays within the same size class.
+            assert_eq!(allocator.realloc(pointer, layout(8, 1), 16), pointer);
+
+            let new_pointer = allocator.realloc(pointer, layout(16, 1), 100);
+            assert_ne!(new_pointer, pointer);
+            assert_eq!(core::slice::from_raw_parts(new_pointer, 8), [0, 1, 2, 3, 4, 5, 6, 7]);
+            // The old block was freed.
+            assert_eq!(allocator.alloc(layout(16, 1)), pointer);
+        }
+    }
+
+    #[test]
+    fn free_list_allocator_fails_when_the_heap_is_exhausted() {
+        let arena = Arena::new(4096);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: Every pointer is only accessed within the bounds of its allocation.
+        unsafe {
+            assert!(allocator.alloc(layout(8192, 1)).is_null());
+            assert!(!allocator.alloc(layout(2048, 1)).is_null());
+        }
+    }
+
+    #[test]
+    fn heap_usage_is_counted_from_the_first_call() {
+        let start = core::sync::atomic::AtomicUsize::new(0);
+        assert_eq!(crate::heap_used_since(&start, 0x2000), 0);
+        assert_eq!(crate::heap_used_since(&start, 0x2800), 0x800);
+        assert_eq!(crate::heap_used_since(&start, 0x2000), 0);
+
+        assert_eq!(crate::sbrk_checked(16), None);
+        assert!(crate::heap_end().is_null());
+        assert_eq!(crate::heap_used_since_start(), 0);
+    }
+
+    #[test]
+    fn memory_intrinsics_fall_back_on_the_host() {
+        let mut buffer = [0_u8; 8];
+        // SAFETY: Both accesses are within the bounds of the buffers.
+        unsafe {
+            crate::memset(buffer.as_mut_ptr(), 0xaa, 4);
+            crate::memcpy(buffer.as_mut_ptr().add(4), [1, 2, 3].as_ptr(), 3);
+        }
+
+        assert_eq!(buffer, [0xaa, 0xaa, 0xaa, 0xaa, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn memmove_handles_overlapping_ranges() {
+        let move_within = |dst: usize, src: usize, count: usize| {
+            let mut buffer = [1_u8, 2, 3, 4, 5, 6, 7, 8];
+            let pointer = buffer.as_mut_ptr();
+            // SAFETY: Both ranges are within the bounds of the buffer.
+            unsafe { crate::memmove(pointer.add(dst), pointer.add(src), count) }
+            buffer
+        };
+
+        // The destination is after the source.
+        assert_eq!(move_within(2, 0, 5), [1, 2, 1, 2, 3, 4, 5, 8]);
+        // The destination is before the source.
+        assert_eq!(move_within(0, 2, 5), [3, 4, 5, 6, 7, 6, 7, 8]);
+        // The ranges are disjoint.
+        assert_eq!(move_within(5, 0, 3), [1, 2, 3, 4, 5, 1, 2, 3]);
+        assert_eq!(move_within(3, 3, 0), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn panic_messages_are_truncated_at_a_character_boundary() {
+        let buffer = crate::PanicMessageBuffer::<8>::new();
+        assert_eq!(buffer.get(), b"");
+        assert_eq!(buffer.record("short"), b"short");
+        assert_eq!(buffer.record(format_args!("{}{}", "abc", 1234)), b"abc1234");
+        assert_eq!(buffer.record("exactly8"), b"exactly8");
+        assert_eq!(buffer.record("too long a message"), b"too long");
+        assert_eq!(buffer.record("azażółć"), "azażó".as_bytes());
+        assert_eq!(buffer.get(), "azażó".as_bytes());
+
+        // Nothing is appended after a truncated fragment, even if it'd fit.
+        assert_eq!(buffer.record(format_args!("{}{}", "abcdefgż", "h")), b"abcdefg");
+    }
+
+    #[test]
+    fn panic_messages_can_panic_while_being_formatted() {
+        struct Nested<'a>(&'a crate::PanicMessageBuffer<16>);
+        impl<'a> core::fmt::Display for Nested<'a> {
+            fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+                fmt.write_str("outer")?;
+                assert_eq!(self.0.record("inner"), b"");
+                Ok(())
+            }
+        }
+
+        let buffer = crate::PanicMessageBuffer::<16>::new();
+        assert_eq!(buffer.record(Nested(&buffer)), b"outer");
+    }
+
+    #[test]
+    fn leaking_allocator_aligns_and_never_overlaps_allocations() {
+        let arena = Arena::new(4 * 1024 * 1024);
+        // Start from an odd break.
+        (&arena).sbrk(3);
+
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut previous_end = arena.base as usize + 3;
+        for _ in 0..1000 {
+            let align = 1 << (random() % 13);
+            let size = (random() % 5000) as usize;
+            let pointer = crate::leaking_alloc(&&arena, layout(size, align)) as usize;
+            assert_ne!(pointer, 0);
+            assert_eq!(pointer % align, 0, "size = {size}, align = {align}");
+            assert!(pointer >= previous_end && pointer - previous_end < align);
+            previous_end = pointer + size;
+            assert_eq!(previous_end, arena.base as usize + arena.length.get());
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_zeroes_memory_and_handles_exhaustion() {
+        let mut arena = Arena::new(8192);
+        arena.memory.fill(0xff);
+
+        let pointer = crate::leaking_alloc_zeroed(&&arena, layout(100, 16));
+        assert!(!pointer.is_null());
+        // SAFETY: The pointer was allocated with a size of 100.
+        assert_eq!(unsafe { core::slice::from_raw_parts(pointer, 100) }, [0; 100]);
+
+        let length = arena.length.get();
+        assert!(crate::leaking_alloc(&&arena, layout(8192, 1)).is_null());
+        assert!(crate::leaking_alloc_zeroed(&&arena, layout(8192, 1)).is_null());
+        assert_eq!(arena.length.get(), length);
+    }
+
+    #[test]
+    fn allocators_fail_cleanly_past_the_heap_limit() {
+        let arena = Arena::new(64 * 1024);
+        // The arena itself has room to spare; only the limit stops the allocations.
+        arena.limit.set(arena.base as usize + 16 * 1024);
+
+        let pointer = crate::leaking_alloc(&&arena, layout(8 * 1024, 1));
+        assert_eq!(pointer, arena.base);
+        assert!(crate::leaking_alloc(&&arena, layout(8 * 1024 + 1, 1)).is_null());
+        assert!(crate::leaking_alloc(&&arena, layout(usize::MAX / 2, 1)).is_null());
+        assert_eq!(arena.length.get(), 8 * 1024);
+
+        // The padding counts towards the limit too.
+        (&arena).sbrk(1);
+        let padding = (arena.base as usize + 8 * 1024 + 1).wrapping_neg() & 15;
+        let remaining = 8 * 1024 - 1;
+        assert!(crate::leaking_alloc(&&arena, layout(remaining - padding + 1, 16)).is_null());
+        assert!(!crate::leaking_alloc(&&arena, layout(remaining - padding, 16)).is_null());
+        assert_eq!(arena.length.get(), 16 * 1024);
+
+        arena.length.set(0);
+        arena.limit.set(usize::MAX);
+        let allocator = FreeListAllocator::with_program_break(&arena);
+        // SAFETY: The pointers are never accessed.
+        unsafe {
+            assert!(!allocator.alloc(layout(12 * 1024, 8)).is_null());
+            let length = arena.length.get();
+            arena.limit.set(arena.base as usize + length + 4096);
+            assert!(allocator.alloc(layout(8 * 1024, 8)).is_null());
+            assert_eq!(arena.length.get(), length);
+            assert!(!allocator.alloc(layout(4096, 8)).is_null());
+            assert!(allocator.alloc(layout(16, 8)).is_null());
+        }
+    }
+
+    #[test]
+    fn hostcall_arguments_and_indexes_are_validated() {
+        crate::__private_check_hostcall_regs(&[]);
+        crate::__private_check_hostcall_regs(&[0, 5, 2]);
+        assert_eq!(crate::__private_hostcall_index(0), 0);
+        assert_eq!(crate::__private_hostcall_index(0xffff_ffff), u32::MAX);
+
+        let panics = |callback: fn()| std::panic::catch_unwind(callback).is_err();
+        assert!(panics(|| crate::__private_check_hostcall_regs(&[1, 3, 1])));
+        assert!(panics(|| {
+            crate::__private_hostcall_index(-1);
+        }));
+        assert!(panics(|| {
+            crate::__private_hostcall_index(0x1_0000_0000);
+        }));
+    }
+
+    #[test]
+    #[should_panic(expected = "host calls can only be made from within a guest program")]
+    fn hostcall_panics_on_the_host() {
+        let value = 7;
+        let _: (usize, usize) = crate::hostcall!(0x10, a2 = value, a0 = value * 2,);
+    }
+
+    #[test]
+    fn stack_high_water_is_the_deepest_overwritten_sentinel() {
+        let high_water = |stack: &[u8]| {
+            let range = stack.as_ptr_range();
+            // SAFETY: The pointers are within the same slice.
+            unsafe { crate::stack_high_water(range.start, range.end) }
+        };
+
+        let mut stack = [crate::STACK_SENTINEL; 256];
+        assert_eq!(high_water(&stack), 0);
+
+        stack[200] = 0;
+        assert_eq!(high_water(&stack), 56);
+        stack[100] = 0;
+        stack[150] = crate::STACK_SENTINEL;
+        assert_eq!(high_water(&stack), 156);
+        stack[0] = 0;
+        assert_eq!(high_water(&stack), 256);
+
+        // SAFETY: There's nothing to read when the stack wasn't painted.
+        assert_eq!(unsafe { crate::stack_high_water(stack.as_ptr(), core::ptr::null()) }, 0);
+        assert_eq!(crate::measure_stack_high_water(), 0);
+    }
+
+    mod main {
+        crate::polkavm_main! {
+            stack_size = 4096,
+            allocator = free_list,
+            panic_message = true,
+            pub fn entry_point() -> u32 {
+                42
+            }
+        }
+    }
+
+    mod main_with_own_panic_handler {
+        crate::polkavm_main! {
+            #[panic_handler]
+            #[cfg(all(any(target_arch = "riscv32", target_arch = "riscv64"), target_feature = "e"))]
+            fn panic(info: &core::panic::PanicInfo) -> ! {
+                crate::__private_trap_on_panic(info, false)
+            }
+
+            pub fn entry_point() -> u32 {
+                7
+            }
+        }
+    }
+
+    #[test]
+    fn main_is_callable_on_the_host() {
+        assert_eq!(main::entry_point(), 42);
+        assert_eq!(main_with_own_panic_handler::entry_point(), 7);
+    }
+}
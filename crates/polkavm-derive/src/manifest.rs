@@ -0,0 +1,274 @@
+//! A parser of the manifest which [`polkavm_export`](crate::polkavm_export) emits for every export.
+//!
+//! Every export emits an entry into the `.polkavm_manifest` section of the ELF file, describing its symbol
+//! along with the kinds of its arguments and of its return value. The linker doesn't copy the section into
+//! the program blob, so the manifest can only be read from the ELF file.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The name of the section into which the manifest is emitted.
+pub const MANIFEST_SECTION: &str = ".polkavm_manifest";
+
+/// The version of the manifest's format which this parser supports.
+pub const MANIFEST_VERSION: u8 = 1;
+
+/// The kind of an export's argument or return value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ExportKind {
+    Unit,
+    U32,
+    I32,
+    U64,
+    I64,
+    Usize,
+    Isize,
+    U128,
+    I128,
+    Pointer,
+    /// A `&[u8]`, passed as its address and length.
+    Slice,
+    /// A `&mut [u8]`, passed as its address and length.
+    MutSlice,
+    /// A `&str`, passed as its address and length.
+    Str,
+    /// Any other type, along with its name as written in the export's signature.
+    Other(String),
+}
+
+/// An entry of the manifest, describing a single export.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ManifestEntry {
+    pub symbol: Vec<u8>,
+    pub args: Vec<ExportKind>,
+    /// The kind of the return value; for exports with `result_trap` this is the kind of the `Ok` value.
+    pub return_kind: ExportKind,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if length > self.bytes.len() {
+            return Err("the manifest is truncated".into());
+        }
+
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_kind(&mut self) -> Result<ExportKind, String> {
+        // These must be kept in sync with the codes which `polkavm_export` emits.
+        let kind = match self.read_u8()? {
+            0 => ExportKind::Unit,
+            1 => ExportKind::U32,
+            2 => ExportKind::I32,
+            3 => ExportKind::U64,
+            4 => ExportKind::I64,
+            5 => ExportKind::Usize,
+            6 => ExportKind::Isize,
+            7 => ExportKind::U128,
+            8 => ExportKind::I128,
+            9 => ExportKind::Pointer,
+            10 => ExportKind::Slice,
+            11 => ExportKind::MutSlice,
+            12 => ExportKind::Str,
+            0xff => {
+                let length = self.read_u32()? as usize;
+                let name = core::str::from_utf8(self.read_bytes(length)?).map_err(|_| "the name of a type is not valid UTF-8")?;
+                ExportKind::Other(name.into())
+            }
+            kind => return Err(alloc::format!("unknown kind: {kind}")),
+        };
+
+        Ok(kind)
+    }
+}
+
+/// Parses the contents of the manifest's section.
+pub fn parse_manifest(section: &[u8]) -> Result<Vec<ManifestEntry>, String> {
+    let mut reader = Reader { bytes: section };
+    let mut entries = Vec::new();
+    while !reader.bytes.is_empty() {
+        let version = reader.read_u8()?;
+        if version != MANIFEST_VERSION {
+            return Err(alloc::format!("unsupported manifest version: {version}"));
+        }
+
+        let symbol_length = reader.read_u32()? as usize;
+        let symbol = reader.read_bytes(symbol_length)?.into();
+        let arg_count = reader.read_u8()?;
+        let args = (0..arg_count).map(|_| reader.read_kind()).collect::<Result<_, _>>()?;
+        let return_kind = reader.read_kind()?;
+        entries.push(ManifestEntry { symbol, args, return_kind });
+    }
+
+    Ok(entries)
+}
+
+/// Extracts the manifest from a little endian ELF file, either 32-bit or 64-bit.
+///
+/// Returns an empty manifest if the ELF file has no manifest, and an error for a program blob.
+pub fn extract_manifest(elf: &[u8]) -> Result<Vec<ManifestEntry>, String> {
+    if elf.starts_with(b"PVM\0") {
+        return Err("program blobs don't contain the manifest; pass the ELF file instead".into());
+    }
+
+    if !elf.starts_with(b"\x7fELF") {
+        return Err("not an ELF file".into());
+    }
+
+    let read = |offset: usize, length: usize| -> Result<u64, String> {
+        let bytes = elf
+            .get(offset..offset + length)
+            .ok_or_else(|| String::from("the ELF file is truncated"))?;
+        Ok(bytes.iter().rev().fold(0, |value, &byte| (value << 8) | u64::from(byte)))
+    };
+
+    if read(5, 1)? != 1 {
+        return Err("big endian ELF files are unsupported".into());
+    }
+
+    // The offsets of `e_shoff`, `e_shentsize`, `e_shnum`, `e_shstrndx`; and of `sh_name`, `sh_offset` and `sh_size`.
+    let (header, section_header) = match read(4, 1)? {
+        1 => ([(0x20, 4), (0x2e, 2), (0x30, 2), (0x32, 2)], [(0x00, 4), (0x10, 4), (0x14, 4)]),
+        2 => ([(0x28, 8), (0x3a, 2), (0x3c, 2), (0x3e, 2)], [(0x00, 4), (0x18, 8), (0x20, 8)]),
+        class => return Err(alloc::format!("unsupported ELF class: {class}")),
+    };
+
+    let [section_headers_offset, section_header_size, section_count, string_table_index] =
+        header.map(|(offset, length)| read(offset, length).map(|value| value as usize));
+    let (section_headers_offset, section_header_size) = (section_headers_offset?, section_header_size?);
+    let section = |index: usize| -> Result<(usize, &[u8]), String> {
+        let base = section_headers_offset + index * section_header_size;
+        let [name, offset, size] = section_header.map(|(offset, length)| read(base + offset, length).map(|value| value as usize));
+        let (offset, size) = (offset?, size?);
+        let data = elf
+            .get(offset..offset + size)
+            .ok_or_else(|| String::from("a section is out of bounds of the ELF file"))?;
+        Ok((name?, data))
+    };
+
+    let (_, string_table) = section(string_table_index?)?;
+    let mut entries = Vec::new();
+    for index in 0..section_count? {
+        let (name, data) = section(index)?;
+        let name = string_table.get(name..).unwrap_or_default();
+        let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+        if name == MANIFEST_SECTION.as_bytes() {
+            entries.extend(parse_manifest(data)?);
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The manifest of a crate with three exports, as emitted by `polkavm_export`.
+    const MANIFEST: &[u8] = &[
+        1, 3, 0, 0, 0, b'a', b'd', b'd', 2, 1, 3, 1, // fn add(a0: u32, a1: u64) -> u32
+        1, 4, 0, 0, 0, b'f', b'i', b'l', b'l', 2, 11, 12, 0, // fn fill(bytes: &mut [u8], text: &str)
+        1, 1, 0, 0, 0, b'p', 1, 0xff, 11, 0, 0, 0, b'(', b'u', b'3', b'2', b' ', b',', b' ', b'u', b'3', b'2', b')',
+        9, // fn p(pair: (u32, u32)) -> *const u8
+    ];
+
+    fn expected_manifest() -> Vec<ManifestEntry> {
+        let entry = |symbol: &[u8], args: Vec<ExportKind>, return_kind| ManifestEntry {
+            symbol: symbol.into(),
+            args,
+            return_kind,
+        };
+
+        alloc::vec![
+            entry(b"add", alloc::vec![ExportKind::U32, ExportKind::U64], ExportKind::U32),
+            entry(b"fill", alloc::vec![ExportKind::MutSlice, ExportKind::Str], ExportKind::Unit),
+            entry(b"p", alloc::vec![ExportKind::Other("(u32 , u32)".into())], ExportKind::Pointer),
+        ]
+    }
+
+    /// Builds a minimal little endian ELF file with the manifest's section, along with the section names' string table.
+    fn elf(is_64_bit: bool, manifest: &[u8]) -> Vec<u8> {
+        let strings = b"\0.polkavm_manifest\0.shstrtab\0";
+        let (header_size, section_header_size) = if is_64_bit { (0x40, 0x40) } else { (0x34, 0x28) };
+        let manifest_offset = header_size;
+        let strings_offset = manifest_offset + manifest.len();
+        let section_headers_offset = strings_offset + strings.len();
+
+        let mut elf = alloc::vec![0; section_headers_offset + 3 * section_header_size];
+        let mut write = |offset: usize, length: usize, value: usize| {
+            elf[offset..offset + length].copy_from_slice(&value.to_le_bytes()[..length]);
+        };
+
+        let word = if is_64_bit { 8 } else { 4 };
+        if is_64_bit {
+            write(0x28, 8, section_headers_offset);
+            write(0x3a, 2, section_header_size);
+            write(0x3c, 2, 3);
+            write(0x3e, 2, 2);
+        } else {
+            write(0x20, 4, section_headers_offset);
+            write(0x2e, 2, section_header_size);
+            write(0x30, 2, 3);
+            write(0x32, 2, 2);
+        }
+
+        // The first section header is the null section.
+        let (offset_field, size_field) = if is_64_bit { (0x18, 0x20) } else { (0x10, 0x14) };
+        for (index, (name, offset, size)) in [(1, manifest_offset, manifest.len()), (19, strings_offset, strings.len())]
+            .into_iter()
+            .enumerate()
+        {
+            let base = section_headers_offset + (index + 1) * section_header_size;
+            write(base, 4, name);
+            write(base + offset_field, word, offset);
+            write(base + size_field, word, size);
+        }
+
+        elf[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', if is_64_bit { 2 } else { 1 }, 1]);
+        elf[manifest_offset..strings_offset].copy_from_slice(manifest);
+        elf[strings_offset..section_headers_offset].copy_from_slice(strings);
+        elf
+    }
+
+    #[test]
+    fn manifest_is_parsed() {
+        assert_eq!(parse_manifest(MANIFEST).unwrap(), expected_manifest());
+        assert_eq!(parse_manifest(&[]).unwrap(), []);
+
+        assert_eq!(
+            parse_manifest(&MANIFEST[..MANIFEST.len() - 1]).unwrap_err(),
+            "the manifest is truncated"
+        );
+        assert_eq!(parse_manifest(&[2]).unwrap_err(), "unsupported manifest version: 2");
+        assert_eq!(parse_manifest(&[1, 0, 0, 0, 0, 0, 13]).unwrap_err(), "unknown kind: 13");
+    }
+
+    #[test]
+    fn manifest_is_extracted_from_elf_files() {
+        for is_64_bit in [false, true] {
+            assert_eq!(extract_manifest(&elf(is_64_bit, MANIFEST)).unwrap(), expected_manifest());
+            assert_eq!(extract_manifest(&elf(is_64_bit, &[])).unwrap(), []);
+        }
+
+        let mut elf = elf(true, MANIFEST);
+        assert_eq!(extract_manifest(&elf[..0x30]).unwrap_err(), "the ELF file is truncated");
+        elf[5] = 2;
+        assert_eq!(extract_manifest(&elf).unwrap_err(), "big endian ELF files are unsupported");
+        assert!(extract_manifest(b"PVM\0\x01").unwrap_err().contains("pass the ELF file instead"));
+    }
+}
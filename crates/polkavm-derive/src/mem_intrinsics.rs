@@ -0,0 +1,231 @@
+//! Replacements of the `memcpy`, `memset`, `memmove` and `memcmp` symbols which the compiler calls on its own,
+//! e.g. for struct copies and array initialization, enabled through the `replace-mem-intrinsics` feature.
+//!
+//! The copies are done word by word whenever both pointers are equally aligned, and `memset` uses the accelerated instruction.
+//! Every access is volatile so that the compiler can't turn the loops back into calls to the very symbols they implement.
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// How many words are copied per iteration of the main loop.
+const UNROLL: usize = 8;
+
+#[inline(always)]
+fn is_aligned(address: usize) -> bool {
+    address & (WORD - 1) == 0
+}
+
+/// Copies a single word from `src` to `dst`; both must be word aligned.
+#[inline(always)]
+#[allow(clippy::cast_ptr_alignment)]
+unsafe fn copy_word(dst: *mut u8, src: *const u8) {
+    dst.cast::<usize>().write_volatile(src.cast::<usize>().read_volatile());
+}
+
+#[inline(always)]
+unsafe fn copy_byte(dst: *mut u8, src: *const u8) {
+    dst.write_volatile(src.read_volatile());
+}
+
+/// Copies `count` bytes from `src` to `dst`, starting from the lowest address.
+///
+/// The ranges can overlap as long as `dst` is lower than `src`.
+unsafe fn copy_forward(mut dst: *mut u8, mut src: *const u8, mut count: usize) {
+    if is_aligned(dst as usize ^ src as usize) {
+        while count > 0 && !is_aligned(dst as usize) {
+            copy_byte(dst, src);
+            (dst, src, count) = (dst.add(1), src.add(1), count - 1);
+        }
+
+        while count >= UNROLL * WORD {
+            for nth_word in 0..UNROLL {
+                copy_word(dst.add(nth_word * WORD), src.add(nth_word * WORD));
+            }
+
+            (dst, src, count) = (dst.add(UNROLL * WORD), src.add(UNROLL * WORD), count - UNROLL * WORD);
+        }
+
+        while count >= WORD {
+            copy_word(dst, src);
+            (dst, src, count) = (dst.add(WORD), src.add(WORD), count - WORD);
+        }
+    }
+
+    while count > 0 {
+        copy_byte(dst, src);
+        (dst, src, count) = (dst.add(1), src.add(1), count - 1);
+    }
+}
+
+/// Copies `count` bytes from `src` to `dst`, starting from the highest address.
+///
+/// The ranges can overlap as long as `dst` is higher than `src`.
+unsafe fn copy_backward(dst: *mut u8, src: *const u8, mut count: usize) {
+    let (mut dst_end, mut src_end) = (dst.add(count), src.add(count));
+    if is_aligned(dst as usize ^ src as usize) {
+        while count > 0 && !is_aligned(dst_end as usize) {
+            (dst_end, src_end, count) = (dst_end.sub(1), src_end.sub(1), count - 1);
+            copy_byte(dst_end, src_end);
+        }
+
+        while count >= UNROLL * WORD {
+            for nth_word in 1..=UNROLL {
+                copy_word(dst_end.sub(nth_word * WORD), src_end.sub(nth_word * WORD));
+            }
+
+            (dst_end, src_end, count) = (dst_end.sub(UNROLL * WORD), src_end.sub(UNROLL * WORD), count - UNROLL * WORD);
+        }
+
+        while count >= WORD {
+            (dst_end, src_end, count) = (dst_end.sub(WORD), src_end.sub(WORD), count - WORD);
+            copy_word(dst_end, src_end);
+        }
+    }
+
+    while count > 0 {
+        (dst_end, src_end, count) = (dst_end.sub(1), src_end.sub(1), count - 1);
+        copy_byte(dst_end, src_end);
+    }
+}
+
+/// Compares `count` bytes, returning the difference between the first pair of bytes which differ, or 0 if there's none.
+#[allow(clippy::cast_ptr_alignment)]
+unsafe fn compare(lhs: *const u8, rhs: *const u8, count: usize) -> i32 {
+    let mut offset = 0;
+    if is_aligned(lhs as usize | rhs as usize) {
+        while offset + WORD <= count && lhs.add(offset).cast::<usize>().read_volatile() == rhs.add(offset).cast::<usize>().read_volatile() {
+            offset += WORD;
+        }
+    }
+
+    while offset < count {
+        let (lhs, rhs) = (lhs.add(offset).read_volatile(), rhs.add(offset).read_volatile());
+        if lhs != rhs {
+            return i32::from(lhs) - i32::from(rhs);
+        }
+
+        offset += 1;
+    }
+
+    0
+}
+
+#[cfg(all(
+    feature = "replace-mem-intrinsics",
+    any(target_arch = "riscv32", target_arch = "riscv64"),
+    target_feature = "e"
+))]
+mod symbols {
+    use core::ffi::{c_int, c_void};
+
+    #[no_mangle]
+    unsafe extern "C" fn memcpy(dst: *mut c_void, src: *const c_void, count: usize) -> *mut c_void {
+        super::copy_forward(dst.cast(), src.cast(), count);
+        dst
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn memmove(dst: *mut c_void, src: *const c_void, count: usize) -> *mut c_void {
+        if (dst as usize) <= (src as usize) {
+            super::copy_forward(dst.cast(), src.cast(), count);
+        } else {
+            super::copy_backward(dst.cast(), src.cast(), count);
+        }
+
+        dst
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn memset(dst: *mut c_void, value: c_int, count: usize) -> *mut c_void {
+        // Just like in C the value is converted to an `unsigned char`.
+        crate::memset(dst.cast(), usize::from(value as u8), count);
+        dst
+    }
+
+    #[no_mangle]
+    unsafe extern "C" fn memcmp(lhs: *const c_void, rhs: *const c_void, count: usize) -> c_int {
+        super::compare(lhs.cast(), rhs.cast(), count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    fn pattern(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn copies_match_a_plain_copy() {
+        // Cover both the word by word and the byte by byte paths, with every misalignment.
+        for length in [0, 1, 7, 8, 63, 64, 65, 200] {
+            for dst_offset in 0..8 {
+                for src_offset in 0..8 {
+                    let source = pattern(length + 8);
+                    let mut forward = std::vec![0; length + 8];
+                    let mut backward = std::vec![0; length + 8];
+                    // SAFETY: Both buffers have at least `length` bytes past the offsets.
+                    unsafe {
+                        super::copy_forward(forward.as_mut_ptr().add(dst_offset), source.as_ptr().add(src_offset), length);
+                        super::copy_backward(backward.as_mut_ptr().add(dst_offset), source.as_ptr().add(src_offset), length);
+                    }
+
+                    let expected = &source[src_offset..src_offset + length];
+                    assert_eq!(&forward[dst_offset..dst_offset + length], expected);
+                    assert_eq!(&backward[dst_offset..dst_offset + length], expected);
+                    assert!(forward[..dst_offset]
+                        .iter()
+                        .chain(&forward[dst_offset + length..])
+                        .all(|&byte| byte == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_copies_match_a_plain_move() {
+        for length in [1, 9, 100] {
+            for shift in 1..20 {
+                let original = pattern(length + shift);
+
+                let mut expected = original.clone();
+                expected.copy_within(shift..shift + length, 0);
+                let mut buffer = original.clone();
+                // SAFETY: The destination is lower than the source, and both are within the buffer.
+                unsafe { super::copy_forward(buffer.as_mut_ptr(), buffer.as_ptr().add(shift), length) };
+                assert_eq!(buffer, expected);
+
+                let mut expected = original.clone();
+                expected.copy_within(0..length, shift);
+                let mut buffer = original;
+                // SAFETY: The destination is higher than the source, and both are within the buffer.
+                unsafe { super::copy_backward(buffer.as_mut_ptr().add(shift), buffer.as_ptr(), length) };
+                assert_eq!(buffer, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn comparisons_match_the_ordering_of_slices() {
+        let lhs = pattern(100);
+        for index in [0, 5, 8, 50, 99] {
+            let mut rhs = lhs.clone();
+            rhs[index] = rhs[index].wrapping_add(100);
+            for length in [index, index + 1, 100] {
+                // SAFETY: Both buffers have 100 bytes.
+                let result = unsafe { super::compare(lhs.as_ptr(), rhs.as_ptr(), length) };
+                let expected = if length > index {
+                    i32::from(lhs[index]) - i32::from(rhs[index])
+                } else {
+                    0
+                };
+                assert_eq!(result, expected, "index = {index}, length = {length}");
+            }
+        }
+
+        // SAFETY: Both pointers have 99 bytes past them.
+        assert_ne!(unsafe { super::compare(lhs.as_ptr().add(1), lhs.as_ptr(), 99) }, 0);
+    }
+}
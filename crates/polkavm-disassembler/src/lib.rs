@@ -1,6 +1,6 @@
 use std::{collections::HashMap, io::Write};
 
-use polkavm_common::program::{ParsedInstruction, ProgramBlob, ProgramCounter, ISA32_V1, ISA64_V1};
+use polkavm_common::program::{Instruction, ParsedInstruction, ProgramBlob, ProgramCounter, ISA32_V1, ISA64_V1};
 
 #[derive(Copy, Clone, Debug, clap::ValueEnum)]
 pub enum DisassemblyFormat {
@@ -114,11 +114,14 @@ pub struct Disassembler<'a> {
     blob: &'a ProgramBlob,
     format: DisassemblyFormat,
     gas_cost_map: Option<HashMap<ProgramCounter, i64>>,
+    instruction_gas_cost_map: Option<HashMap<ProgramCounter, i64>>,
     native: Option<NativeCode>,
     show_raw_bytes: bool,
     prefer_non_abi_reg_names: bool,
     prefer_unaliased: bool,
     prefer_offset_jump_targets: bool,
+    prefer_export_jump_targets: bool,
+    prefer_hex_offsets: bool,
     emit_header: bool,
     emit_exports: bool,
     show_offsets: bool,
@@ -136,11 +139,14 @@ impl<'a> Disassembler<'a> {
             blob,
             format,
             gas_cost_map: None,
+            instruction_gas_cost_map: None,
             native,
             show_raw_bytes: false,
             prefer_non_abi_reg_names: false,
             prefer_unaliased: false,
             prefer_offset_jump_targets: false,
+            prefer_export_jump_targets: false,
+            prefer_hex_offsets: false,
             emit_header: true,
             emit_exports: true,
             show_offsets: true,
@@ -163,6 +169,20 @@ impl<'a> Disassembler<'a> {
         self.prefer_offset_jump_targets = value;
     }
 
+    /// Whether to show the jump targets which coincide with an export as the export's symbol (e.g. `@main`).
+    ///
+    /// If there are multiple exports at the same target the alphabetically first symbol is shown;
+    /// the symbols which aren't valid UTF-8 are ignored.
+    /// This takes precedence over [`Disassembler::prefer_offset_jump_targets`].
+    pub fn prefer_export_jump_targets(&mut self, value: bool) {
+        self.prefer_export_jump_targets = value;
+    }
+
+    /// Whether to show the offsets of the instructions and the jump targets in hexadecimal (e.g. `0x001a`) instead of in decimal.
+    pub fn prefer_hex_offsets(&mut self, value: bool) {
+        self.prefer_hex_offsets = value;
+    }
+
     pub fn emit_header(&mut self, value: bool) {
         self.emit_header = value;
     }
@@ -183,7 +203,8 @@ impl<'a> Disassembler<'a> {
         }
     }
 
-    pub fn display_gas(&mut self) -> Result<(), polkavm::Error> {
+    /// Creates a module with the cost model which the interpreter charges.
+    fn gas_metered_module(&self) -> Result<polkavm::Module, polkavm::Error> {
         let mut config = polkavm::Config::from_env()?;
         config.set_worker_count(0);
         config.set_backend(Some(polkavm::BackendKind::Interpreter));
@@ -193,7 +214,35 @@ impl<'a> Disassembler<'a> {
         let mut config = polkavm::ModuleConfig::default();
         config.set_gas_metering(Some(polkavm::GasMeteringKind::Sync));
 
-        let module = polkavm::Module::from_blob(&engine, &config, self.blob.clone())?;
+        polkavm::Module::from_blob(&engine, &config, self.blob.clone())
+    }
+
+    /// Appends the gas cost of every instruction as a trailing comment, and ends every basic block with the total cost of its instructions.
+    ///
+    /// The costs come from the same cost model which the interpreter charges.
+    pub fn show_gas_costs(&mut self, value: bool) -> Result<(), polkavm::Error> {
+        if !value {
+            self.instruction_gas_cost_map = None;
+            return Ok(());
+        }
+
+        let module = self.gas_metered_module()?;
+        self.show_gas_costs_with(|instruction| module.calculate_gas_cost_for_instruction(instruction));
+        Ok(())
+    }
+
+    /// Like [`Disassembler::show_gas_costs`], but with the cost of every instruction calculated by `cost_of`.
+    pub fn show_gas_costs_with(&mut self, mut cost_of: impl FnMut(Instruction) -> i64) {
+        let costs = self
+            .instructions()
+            .into_iter()
+            .map(|instruction| (instruction.offset, cost_of(instruction.kind)))
+            .collect();
+        self.instruction_gas_cost_map = Some(costs);
+    }
+
+    pub fn display_gas(&mut self) -> Result<(), polkavm::Error> {
+        let module = self.gas_metered_module()?;
 
         let mut in_new_block = true;
         let mut gas_cost_map = HashMap::new();
@@ -307,19 +356,41 @@ impl<'a> Disassembler<'a> {
             buf
         };
 
+        let mut export_symbol_for_jump_target = HashMap::new();
+        if self.prefer_export_jump_targets {
+            for (&target_offset, exports) in &exports_for_code_offset {
+                let symbols = exports
+                    .iter()
+                    .filter_map(|(_, export)| core::str::from_utf8(export.symbol().as_bytes()).ok());
+                if let Some(symbol) = symbols.min() {
+                    export_symbol_for_jump_target.insert(target_offset.0, symbol);
+                }
+            }
+        }
+
         let prefer_offset_jump_targets = self.prefer_offset_jump_targets;
+        let prefer_hex_offsets = self.prefer_hex_offsets;
+        let format_offset = |offset: u32, fmt: &mut core::fmt::Formatter| {
+            if prefer_hex_offsets {
+                write!(fmt, "0x{offset:04x}")
+            } else {
+                write!(fmt, "{offset}")
+            }
+        };
         let mut disassembly_format = polkavm_common::program::InstructionFormat::default();
         disassembly_format.prefer_non_abi_reg_names = self.prefer_non_abi_reg_names;
         disassembly_format.prefer_unaliased = self.prefer_unaliased;
         disassembly_format.is_64_bit = self.blob.is_64_bit();
 
         let jump_target_formatter = |target: u32, fmt: &mut core::fmt::Formatter| {
-            if prefer_offset_jump_targets {
-                write!(fmt, "{}", target)
+            if let Some(symbol) = export_symbol_for_jump_target.get(&target) {
+                write!(fmt, "@{symbol}")
+            } else if prefer_offset_jump_targets {
+                format_offset(target, fmt)
             } else if let Some(basic_block_index) = instruction_offset_to_basic_block.get(&polkavm::ProgramCounter(target)) {
                 write!(fmt, "@{basic_block_index}")
             } else {
-                write!(fmt, "{}", target)
+                format_offset(target, fmt)
             }
         };
         disassembly_format.jump_target_formatter = Some(&jump_target_formatter);
@@ -328,6 +399,7 @@ impl<'a> Disassembler<'a> {
         let mut last_line_program_entry = None;
         let mut last_full_name = String::new();
         let mut basic_block_counter = 0;
+        let mut basic_block_gas_cost = 0;
         let mut pending_label = true;
         for (nth_instruction, instruction) in instructions.iter().copied().enumerate() {
             let offset = instruction.offset;
@@ -434,8 +506,20 @@ impl<'a> Disassembler<'a> {
 
                 w!("    {}", string);
             } else if matches!(self.format, DisassemblyFormat::Guest | DisassemblyFormat::GuestAndNative) {
+                let instruction_s = match self.instruction_gas_cost_map.as_ref().and_then(|map| map.get(&offset)) {
+                    Some(gas_cost) => {
+                        basic_block_gas_cost += gas_cost;
+                        format!("{instruction_s:32} // gas: {gas_cost}")
+                    }
+                    None => instruction_s,
+                };
+
                 if self.show_offsets {
-                    w!(@no_newline "{offset:6}: ");
+                    if self.prefer_hex_offsets {
+                        w!(@no_newline "0x{:04x}: ", offset.0);
+                    } else {
+                        w!(@no_newline "{offset:6}: ");
+                    }
                 }
                 if self.show_raw_bytes {
                     let raw_bytes = raw_bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
@@ -466,6 +550,21 @@ impl<'a> Disassembler<'a> {
                 }
             }
 
+            let is_last_in_basic_block = instruction.opcode().starts_new_basic_block() || nth_instruction + 1 == instructions.len();
+            if is_last_in_basic_block
+                && self.instruction_gas_cost_map.is_some()
+                && matches!(self.format, DisassemblyFormat::Guest | DisassemblyFormat::GuestAndNative)
+            {
+                if self.show_offsets {
+                    w!(@no_newline "      : ");
+                }
+                if self.show_raw_bytes {
+                    w!(@no_newline "{:24} ", "");
+                }
+                w!("// gas of @{basic_block_counter}: {basic_block_gas_cost}");
+                basic_block_gas_cost = 0;
+            }
+
             if instruction.opcode().starts_new_basic_block() {
                 if nth_instruction + 1 != instructions.len() {
                     pending_label = true;
@@ -556,4 +655,156 @@ mod tests {
 
         assert_eq!(&assembly_text, expected);
     }
+
+    #[test]
+    fn gas_costs_are_shown_for_every_instruction() {
+        let mut builder = ProgramBlobBuilder::new();
+        builder.set_code(
+            &[
+                asm::add_32(S0, A0, A1),
+                asm::jump(2),
+                asm::trap(),
+                asm::add_32(A0, A0, S0),
+                asm::ret(),
+            ],
+            &[],
+        );
+        let blob = ProgramBlob::parse(builder.into_vec().into()).unwrap();
+
+        let mut disassembler = Disassembler::new(&blob, DisassemblyFormat::Guest).unwrap();
+        disassembler.emit_header(false);
+        disassembler.show_gas_costs_with(|instruction| if instruction == asm::trap() { 10 } else { 2 });
+
+        let mut buffer = Vec::new();
+        disassembler.disassemble_into(&mut buffer).unwrap();
+        let expected = &[
+            "      : @0",
+            "     0: s0 = a0 + a1                     // gas: 2",
+            "     3: jump @2                          // gas: 2",
+            "      : // gas of @0: 4",
+            "      : @1",
+            "     5: trap                             // gas: 10",
+            "      : // gas of @1: 10",
+            "      : @2",
+            "     6: a0 = a0 + s0                     // gas: 2",
+            "     9: ret                              // gas: 2",
+            "      : // gas of @2: 4",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(String::from_utf8(buffer).unwrap(), *expected);
+
+        // The costs of the instructions of a block add up to what the interpreter charges for it.
+        disassembler.show_gas_costs(true).unwrap();
+        disassembler.display_gas().unwrap();
+        let mut buffer = Vec::new();
+        disassembler.disassemble_into(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains("      : @0 (gas: 2)\n"), "{text}");
+        assert!(text.contains("      : // gas of @0: 2\n"), "{text}");
+        assert!(text.contains("      : // gas of @2: 2\n"), "{text}");
+
+        disassembler.show_gas_costs(false).unwrap();
+        let mut buffer = Vec::new();
+        disassembler.disassemble_into(&mut buffer).unwrap();
+        assert!(!String::from_utf8(buffer).unwrap().contains("// gas"));
+    }
+
+    #[test]
+    fn offsets_are_shown_in_hexadecimal() {
+        let mut builder = ProgramBlobBuilder::new();
+        builder.set_code(
+            &[
+                asm::load_imm(A1, 10),
+                asm::add_32(S0, A0, A1),
+                asm::add_32(S0, S0, A1),
+                asm::jump(2),
+                asm::trap(),
+                asm::add_32(A0, A0, S0),
+                asm::ret(),
+            ],
+            &[],
+        );
+        let blob = ProgramBlob::parse(builder.into_vec().into()).unwrap();
+
+        let disassemble = |prefer_hex_offsets: bool| {
+            let mut disassembler = Disassembler::new(&blob, DisassemblyFormat::Guest).unwrap();
+            disassembler.emit_header(false);
+            disassembler.prefer_offset_jump_targets(true);
+            disassembler.prefer_hex_offsets(prefer_hex_offsets);
+
+            let mut buffer = Vec::new();
+            disassembler.disassemble_into(&mut buffer).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+
+        let expected = &[
+            "      : @0",
+            "     0: a1 = 0xa",
+            "     3: s0 = a0 + a1",
+            "     6: s0 = s0 + a1",
+            "     9: jump 12",
+            "      : @1",
+            "    11: trap",
+            "      : @2",
+            "    12: a0 = a0 + s0",
+            "    15: ret",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(disassemble(false), *expected);
+
+        let expected = &[
+            "      : @0",
+            "0x0000: a1 = 0xa",
+            "0x0003: s0 = a0 + a1",
+            "0x0006: s0 = s0 + a1",
+            "0x0009: jump 0x000c",
+            "      : @1",
+            "0x000b: trap",
+            "      : @2",
+            "0x000c: a0 = a0 + s0",
+            "0x000f: ret",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(disassemble(true), *expected);
+    }
+
+    #[test]
+    fn jump_targets_are_shown_as_exports() {
+        let mut builder = ProgramBlobBuilder::new();
+        builder.add_export_by_basic_block(0, b"main");
+        builder.add_export_by_basic_block(3, b"second");
+        builder.add_export_by_basic_block(3, b"first");
+        builder.set_code(&[asm::jump(3), asm::branch_eq_imm(A0, 0, 0), asm::jump(1), asm::ret()], &[]);
+        let blob = ProgramBlob::parse(builder.into_vec().into()).unwrap();
+
+        let disassemble = |prefer_export_jump_targets: bool, prefer_offset_jump_targets: bool| {
+            let mut disassembler = Disassembler::new(&blob, DisassemblyFormat::Guest).unwrap();
+            disassembler.emit_header(false);
+            disassembler.emit_exports(false);
+            disassembler.prefer_export_jump_targets(prefer_export_jump_targets);
+            disassembler.prefer_offset_jump_targets(prefer_offset_jump_targets);
+
+            let mut buffer = Vec::new();
+            disassembler.disassemble_into(&mut buffer).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+
+        let with_exports = disassemble(true, false);
+        assert!(with_exports.contains(": jump @first\n"), "{with_exports}");
+        assert!(with_exports.contains(": jump @main if a0 == 0\n"), "{with_exports}");
+        assert!(with_exports.contains(": jump @1\n"), "{with_exports}");
+        assert!(!with_exports.contains("@second"), "{with_exports}");
+
+        // The exports take precedence over the offsets, which are still used for everything else.
+        let with_exports_and_offsets = disassemble(true, true);
+        assert!(with_exports_and_offsets.contains(": jump @first\n"), "{with_exports_and_offsets}");
+        assert!(!with_exports_and_offsets.contains(": jump @1\n"), "{with_exports_and_offsets}");
+
+        let without_exports = disassemble(false, false);
+        assert!(without_exports.contains(": jump @3\n"), "{without_exports}");
+        assert!(without_exports.contains(": jump @0 if a0 == 0\n"), "{without_exports}");
+    }
 }
@@ -652,6 +652,12 @@ enum BasicInst<T> {
     LoadHeapBase {
         dst: Reg,
     },
+    LoadHeapLimit {
+        dst: Reg,
+    },
+    LoadStackFloor {
+        dst: Reg,
+    },
 }
 
 #[derive(Copy, Clone)]
@@ -674,6 +680,8 @@ impl<T> BasicInst<T> {
         match *self {
             BasicInst::Nop
             | BasicInst::LoadHeapBase { .. }
+            | BasicInst::LoadHeapLimit { .. }
+            | BasicInst::LoadStackFloor { .. }
             | BasicInst::LoadImmediate { .. }
             | BasicInst::LoadImmediate64 { .. }
             | BasicInst::LoadAbsolute { .. }
@@ -697,6 +705,8 @@ impl<T> BasicInst<T> {
             BasicInst::Nop | BasicInst::StoreAbsolute { .. } | BasicInst::StoreIndirect { .. } => RegMask::empty(),
             BasicInst::MoveReg { dst, .. }
             | BasicInst::LoadHeapBase { dst }
+            | BasicInst::LoadHeapLimit { dst }
+            | BasicInst::LoadStackFloor { dst }
             | BasicInst::LoadImmediate { dst, .. }
             | BasicInst::LoadImmediate64 { dst, .. }
             | BasicInst::LoadAbsolute { dst, .. }
@@ -723,6 +733,8 @@ impl<T> BasicInst<T> {
             BasicInst::LoadAbsolute { .. } | BasicInst::LoadIndirect { .. } => !config.elide_unnecessary_loads,
             BasicInst::Nop
             | BasicInst::LoadHeapBase { .. }
+            | BasicInst::LoadHeapLimit { .. }
+            | BasicInst::LoadStackFloor { .. }
             | BasicInst::MoveReg { .. }
             | BasicInst::Reg { .. }
             | BasicInst::LoadImmediate { .. }
@@ -817,6 +829,12 @@ impl<T> BasicInst<T> {
             BasicInst::LoadHeapBase { dst } => Some(BasicInst::LoadHeapBase {
                 dst: map(dst, OpKind::Write),
             }),
+            BasicInst::LoadHeapLimit { dst } => Some(BasicInst::LoadHeapLimit {
+                dst: map(dst, OpKind::Write),
+            }),
+            BasicInst::LoadStackFloor { dst } => Some(BasicInst::LoadStackFloor {
+                dst: map(dst, OpKind::Write),
+            }),
             BasicInst::Nop => Some(BasicInst::Nop),
         }
     }
@@ -886,6 +904,8 @@ impl<T> BasicInst<T> {
             BasicInst::Ecalli { nth_import } => BasicInst::Ecalli { nth_import },
             BasicInst::Sbrk { dst, size } => BasicInst::Sbrk { dst, size },
             BasicInst::LoadHeapBase { dst } => BasicInst::LoadHeapBase { dst },
+            BasicInst::LoadHeapLimit { dst } => BasicInst::LoadHeapLimit { dst },
+            BasicInst::LoadStackFloor { dst } => BasicInst::LoadStackFloor { dst },
             BasicInst::Memset => BasicInst::Memset,
             BasicInst::Nop => BasicInst::Nop,
         })
@@ -900,6 +920,8 @@ impl<T> BasicInst<T> {
             BasicInst::LoadAddress { target, .. } | BasicInst::LoadAddressIndirect { target, .. } => (None, Some(*target)),
             BasicInst::Nop
             | BasicInst::LoadHeapBase { .. }
+            | BasicInst::LoadHeapLimit { .. }
+            | BasicInst::LoadStackFloor { .. }
             | BasicInst::MoveReg { .. }
             | BasicInst::LoadImmediate { .. }
             | BasicInst::LoadImmediate64 { .. }
@@ -1155,6 +1177,8 @@ struct MemoryConfig {
     rw_data_size: u32,
     min_stack_size: u32,
     heap_base: u32,
+    heap_limit: u32,
+    stack_floor: u32,
 }
 
 fn get_padding(memory_end: u64, align: u64) -> Option<u64> {
@@ -1282,7 +1306,7 @@ where
     let rw_data_size = u32::try_from(rw_data_size).expect("overflow");
 
     // Sanity check that the memory configuration is actually valid.
-    let heap_base = {
+    let (heap_base, heap_limit, stack_floor) = {
         let rw_data_size_physical: u64 = rw_data.iter().map(|x| x.size() as u64).sum();
         let rw_data_size_physical = u32::try_from(rw_data_size_physical).expect("overflow");
         assert!(rw_data_size_physical <= rw_data_size);
@@ -1302,7 +1326,11 @@ where
         assert_eq!(u64::from(config.ro_data_address()), ro_data_address);
         assert_eq!(u64::from(config.rw_data_address()), rw_data_address);
 
-        config.heap_base()
+        // The auxiliary data is only configured at runtime, so the actual limit can differ.
+        // The stack can only grow bigger at runtime, either through a bigger page size or by being moved down by the auxiliary data,
+        // so this is an address which is always inside of the stack, unless the stack is moved down completely past it.
+        let stack_floor = config.stack_address_high() - min_stack_size;
+        (config.heap_base(), config.heap_base() + config.max_heap_size(), stack_floor)
     };
 
     let memory_config = MemoryConfig {
@@ -1312,6 +1340,8 @@ where
         rw_data_size,
         min_stack_size,
         heap_base,
+        heap_limit,
+        stack_floor,
     };
 
     Ok(memory_config)
@@ -1429,6 +1459,97 @@ where
     Ok(exports)
 }
 
+/// A static exported through `polkavm_export_static!`.
+#[derive(Clone, Debug)]
+struct DataExport {
+    symbol: Vec<u8>,
+    target: SectionTarget,
+    length: u32,
+}
+
+fn extract_data_exports<H>(
+    elf: &Elf<H>,
+    relocations: &BTreeMap<SectionTarget, RelocationKind>,
+    data_sections_set: &HashSet<SectionIndex>,
+    section: &Section,
+) -> Result<Vec<DataExport>, ProgramFromElfError>
+where
+    H: object::read::elf::FileHeader<Endian = object::LittleEndian>,
+{
+    let mut b = polkavm_common::elf::Reader::from(section.data());
+    let mut data_exports = Vec::new();
+    loop {
+        let Ok(version) = b.read_byte() else { break };
+
+        if version != 1 {
+            return Err(ProgramFromElfError::other(format!(
+                "failed to parse data export metadata: unsupported data export metadata version: {}",
+                version
+            )));
+        }
+
+        let symbol = b
+            .read_u32()
+            .and_then(|length| b.read(length as usize))
+            .map_err(|error| ProgramFromElfError::other(format!("failed to parse data export metadata: {}", error)))?
+            .to_vec();
+
+        let location = SectionTarget {
+            section_index: section.index(),
+            offset: b.offset() as u64,
+        };
+
+        // Ignore the address as written; we'll just use the relocations instead.
+        let error = if elf.is_64() { b.read_u64().err() } else { b.read_u32().err() };
+        if let Some(error) = error {
+            return Err(ProgramFromElfError::other(format!(
+                "failed to parse data export metadata: {}",
+                error
+            )));
+        }
+
+        let target = match relocations.get(&location) {
+            Some(RelocationKind::Abs {
+                target,
+                size: RelocationSize::U64,
+            }) if elf.is_64() => target,
+            Some(RelocationKind::Abs {
+                target,
+                size: RelocationSize::U32,
+            }) if !elf.is_64() => target,
+            Some(relocation) => {
+                return Err(ProgramFromElfError::other(format!(
+                    "found a data export with an unexpected relocation at {location}: {relocation:?}"
+                )));
+            }
+            None => {
+                return Err(ProgramFromElfError::other(format!(
+                    "found a data export without a relocation for a pointer to the data at {location}"
+                )));
+            }
+        };
+
+        if !data_sections_set.contains(&target.section_index) {
+            return Err(ProgramFromElfError::other(format!(
+                "data export '{}' doesn't point to a data section: {target}",
+                ProgramSymbol::new(&*symbol)
+            )));
+        }
+
+        let length = b
+            .read_u32()
+            .map_err(|error| ProgramFromElfError::other(format!("failed to parse data export metadata: {}", error)))?;
+
+        data_exports.push(DataExport {
+            symbol,
+            target: *target,
+            length,
+        });
+    }
+
+    Ok(data_exports)
+}
+
 #[derive(Clone, Debug)]
 struct Import {
     metadata: ExternMetadata,
@@ -1576,6 +1697,9 @@ where
         .map_err(|error| ProgramFromElfError::other(format!("failed to parse extern metadata: {}", error)))
 }
 
+/// The prefix of the symbols of the imports behind `polkavm_derive::hostcall!`, which always specify their index.
+const RAW_HOST_CALL_SYMBOL_PREFIX: &[u8] = b"__polkavm_hostcall_";
+
 fn check_imports_and_assign_indexes(imports: &mut Vec<Import>, used_imports: &HashSet<usize>) -> Result<(), ProgramFromElfError> {
     let mut import_by_symbol: HashMap<Vec<u8>, usize> = HashMap::new();
     for (nth_import, import) in imports.iter().enumerate() {
@@ -1595,9 +1719,22 @@ fn check_imports_and_assign_indexes(imports: &mut Vec<Import>, used_imports: &Ha
     }
 
     if imports.iter().any(|import| import.metadata.index.is_some()) {
+        // Only the raw host calls can be mixed with imports which don't specify an index.
+        let only_raw_host_calls_are_indexed = imports
+            .iter()
+            .filter(|import| import.index.is_some())
+            .all(|import| import.symbol.starts_with(RAW_HOST_CALL_SYMBOL_PREFIX));
+
         let mut import_by_index: HashMap<u32, ExternMetadata> = HashMap::new();
         let mut max_index = 0;
         for import in &*imports {
+            if import.index.is_none() && !only_raw_host_calls_are_indexed {
+                return Err(ProgramFromElfError::other(format!(
+                    "import without a specified index: {}",
+                    ProgramSymbol::new(&*import.metadata.symbol)
+                )));
+            }
+
             if let Some(index) = import.index {
                 if let Some(old_metadata) = import_by_index.get(&index) {
                     if *old_metadata != import.metadata {
@@ -1612,14 +1749,29 @@ fn check_imports_and_assign_indexes(imports: &mut Vec<Import>, used_imports: &Ha
                 }
 
                 max_index = core::cmp::max(max_index, index);
-            } else {
-                return Err(ProgramFromElfError::other(format!(
-                    "import without a specified index: {}",
-                    ProgramSymbol::new(&*import.metadata.symbol)
-                )));
             }
         }
 
+        // Imports without a specified index, mixed with raw host calls, get the indexes which are still free.
+        let mut ordered: Vec<_> = used_imports
+            .iter()
+            .copied()
+            .filter(|&nth_import| imports[nth_import].metadata.index.is_none())
+            .collect();
+        ordered.sort_by(|&a, &b| imports[a].metadata.symbol.cmp(&imports[b].metadata.symbol));
+
+        let mut next_free_index = 0;
+        for &nth_import in &ordered {
+            while import_by_index.contains_key(&next_free_index) {
+                next_free_index += 1;
+            }
+
+            let index = next_free_index;
+            imports[nth_import].metadata.index = Some(index);
+            import_by_index.insert(index, imports[nth_import].metadata.clone());
+            max_index = core::cmp::max(max_index, index);
+        }
+
         // If there are any holes in the indexes then insert dummy imports.
         for index in 0..max_index {
             if !import_by_index.contains_key(&index) {
@@ -1655,6 +1807,69 @@ fn check_imports_and_assign_indexes(imports: &mut Vec<Import>, used_imports: &Ha
     Ok(())
 }
 
+#[cfg(test)]
+fn test_import(symbol: &str, index: Option<u32>) -> Import {
+    Import {
+        metadata: ExternMetadata {
+            index,
+            symbol: symbol.as_bytes().to_vec(),
+            input_regs: 1,
+            output_regs: 1,
+        },
+    }
+}
+
+#[test]
+fn test_unindexed_imports_are_assigned_the_free_indexes() {
+    let mut imports = vec![
+        test_import("__polkavm_hostcall_1", Some(1)),
+        test_import("second", None),
+        test_import("first", None),
+        test_import("unused", None),
+    ];
+    let used_imports = [0, 1, 2].into_iter().collect();
+    check_imports_and_assign_indexes(&mut imports, &used_imports).unwrap();
+
+    let indexes: Vec<_> = imports.iter().map(|import| (import.symbol.as_slice(), import.index)).collect();
+    assert_eq!(
+        indexes,
+        [
+            (&b"__polkavm_hostcall_1"[..], Some(1)),
+            (&b"second"[..], Some(2)),
+            (&b"first"[..], Some(0)),
+            (&b"unused"[..], None),
+        ]
+    );
+}
+
+#[test]
+fn test_unindexed_imports_are_rejected_when_mixed_with_indexed_ones() {
+    let mut imports = vec![test_import("explicit", Some(1)), test_import("unindexed", None)];
+    let used_imports = [0, 1].into_iter().collect();
+    let error = check_imports_and_assign_indexes(&mut imports, &used_imports).unwrap_err();
+    assert_eq!(error.to_string(), "import without a specified index: unindexed");
+}
+
+#[test]
+fn test_holes_left_by_unindexed_imports_are_filled_with_dummy_imports() {
+    let mut imports = vec![test_import("__polkavm_hostcall_3", Some(3)), test_import("unindexed", None)];
+    let used_imports = [0, 1].into_iter().collect();
+    check_imports_and_assign_indexes(&mut imports, &used_imports).unwrap();
+
+    assert_eq!(imports[0].index, Some(3));
+    assert_eq!(imports[1].index, Some(0));
+
+    let mut dummy_indexes: Vec<_> = imports[2..]
+        .iter()
+        .map(|import| {
+            assert!(import.symbol.is_empty());
+            import.index.unwrap()
+        })
+        .collect();
+    dummy_indexes.sort_unstable();
+    assert_eq!(dummy_indexes, [1, 2]);
+}
+
 fn get_relocation_target<H>(elf: &Elf<H>, relocation: &crate::elf::Relocation) -> Result<Option<SectionTarget>, ProgramFromElfError>
 where
     H: object::read::elf::FileHeader<Endian = object::LittleEndian>,
@@ -2664,6 +2879,8 @@ const FUNC3_ECALLI: u32 = 0b000;
 const FUNC3_SBRK: u32 = 0b001;
 const FUNC3_MEMSET: u32 = 0b010;
 const FUNC3_HEAP_BASE: u32 = 0b011;
+const FUNC3_HEAP_LIMIT: u32 = 0b100;
+const FUNC3_STACK_FLOOR: u32 = 0b101;
 
 #[allow(clippy::too_many_arguments)]
 fn parse_code_section<H>(
@@ -2813,6 +3030,38 @@ where
             continue;
         }
 
+        if let (crate::riscv::OPCODE_CUSTOM_0, FUNC3_HEAP_LIMIT, 0, dst, RReg::Zero, RReg::Zero) = crate::riscv::R(raw_inst).unpack() {
+            output.push((
+                Source {
+                    section_index,
+                    offset_range: (relative_offset as u64..relative_offset as u64 + inst_size).into(),
+                },
+                match cast_reg_non_zero(dst)? {
+                    Some(dst) => InstExt::Basic(BasicInst::LoadHeapLimit { dst }),
+                    None => InstExt::Basic(BasicInst::Nop),
+                },
+            ));
+
+            relative_offset += inst_size as usize;
+            continue;
+        }
+
+        if let (crate::riscv::OPCODE_CUSTOM_0, FUNC3_STACK_FLOOR, 0, dst, RReg::Zero, RReg::Zero) = crate::riscv::R(raw_inst).unpack() {
+            output.push((
+                Source {
+                    section_index,
+                    offset_range: (relative_offset as u64..relative_offset as u64 + inst_size).into(),
+                },
+                match cast_reg_non_zero(dst)? {
+                    Some(dst) => InstExt::Basic(BasicInst::LoadStackFloor { dst }),
+                    None => InstExt::Basic(BasicInst::Nop),
+                },
+            ));
+
+            relative_offset += inst_size as usize;
+            continue;
+        }
+
         let source = Source {
             section_index,
             offset_range: AddressRange::from(relative_offset as u64..relative_offset as u64 + inst_size),
@@ -5683,7 +5932,7 @@ mod test {
             let mut section_to_block = build_section_to_block_map(&all_blocks).unwrap();
             let mut all_blocks = resolve_basic_block_references(&data_sections_set, &section_to_block, &all_blocks).unwrap();
             let mut reachability_graph =
-                calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &relocations).unwrap();
+                calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &[], &relocations).unwrap();
             if matches!(config.opt_level, OptLevel::O2) {
                 optimize_program(&config, &elf, &imports, &mut all_blocks, &mut reachability_graph, &mut exports);
             }
@@ -5696,7 +5945,7 @@ mod test {
             }
 
             let expected_reachability_graph =
-                calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &relocations).unwrap();
+                calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &[], &relocations).unwrap();
             assert!(reachability_graph == expected_reachability_graph);
 
             let used_imports = HashSet::new();
@@ -6875,6 +7124,7 @@ fn calculate_reachability(
     all_blocks: &[BasicBlock<AnyTarget, BlockTarget>],
     data_sections_set: &HashSet<SectionIndex>,
     exports: &[Export],
+    data_exports: &[DataExport],
     relocations: &BTreeMap<SectionTarget, RelocationKind>,
 ) -> Result<ReachabilityGraph, ProgramFromElfError> {
     let mut graph = ReachabilityGraph::default();
@@ -6898,6 +7148,11 @@ fn calculate_reachability(
         block_queue.push(block_target);
     }
 
+    for data_export in data_exports {
+        graph.mark_data_section_reachable(data_export.target.section_index);
+        data_queue.push(data_export.target);
+    }
+
     while !block_queue.is_empty() || !data_queue.is_empty() {
         while let Some(current_block) = block_queue.pop_unique() {
             each_reference(&all_blocks[current_block.index()], |ext| match ext {
@@ -7225,6 +7480,8 @@ fn emit_code(
     is_optimized: bool,
     is_rv64: bool,
     heap_base: u32,
+    heap_limit: u32,
+    stack_floor: u32,
 ) -> Result<Vec<(SourceStack, Instruction)>, ProgramFromElfError> {
     use polkavm_common::program::Reg as PReg;
     fn conv_reg(reg: Reg) -> polkavm_common::program::RawReg {
@@ -7249,6 +7506,15 @@ fn emit_code(
         .into()
     }
 
+    // On 64-bit `load_imm` sign extends its immediate, and these addresses can be above 2GB.
+    let load_address = |dst: Reg, address: u32| {
+        if is_rv64 && address > i32::MAX as u32 {
+            Instruction::load_imm64(conv_reg(dst), u64::from(address))
+        } else {
+            Instruction::load_imm(conv_reg(dst), address)
+        }
+    };
+
     let can_fallthrough_to_next_block = calculate_whether_can_fallthrough(all_blocks, used_blocks);
     let get_data_address = |source: &SourceStack, target: SectionTarget| -> Result<u32, ProgramFromElfError> {
         if let Some(&base_address) = base_address_for_section.get(&target.section_index) {
@@ -7322,6 +7588,8 @@ fn emit_code(
                     }
                 }
                 BasicInst::LoadHeapBase { dst } => Instruction::load_imm(conv_reg(dst), heap_base),
+                BasicInst::LoadHeapLimit { dst } => load_address(dst, heap_limit),
+                BasicInst::LoadStackFloor { dst } => load_address(dst, stack_floor),
                 BasicInst::LoadAbsolute { kind, dst, target } => {
                     codegen! {
                         args = (conv_reg(dst), get_data_address(source, target)?),
@@ -7950,7 +8218,7 @@ where
             == (object::RelocationFlags::Elf {
                 r_type: object::elf::R_RISCV_PCREL_HI20,
             })
-            && section_name == ".polkavm_exports"
+            && (section_name == ".polkavm_exports" || section_name == ".polkavm_data_exports")
         {
             relocations.insert(
                 SectionTarget {
@@ -8997,6 +9265,7 @@ where
     let mut sections_code = Vec::new();
     let mut sections_metadata = Vec::new();
     let mut sections_exports = Vec::new();
+    let mut sections_data_exports = Vec::new();
     let mut sections_min_stack_size = Vec::new();
     let mut sections_other = Vec::new();
 
@@ -9069,6 +9338,8 @@ where
             sections_metadata.push(section.index());
         } else if name == ".polkavm_exports" {
             sections_exports.push(section.index());
+        } else if name == ".polkavm_data_exports" {
+            sections_data_exports.push(section.index());
         } else if name == ".polkavm_min_stack_size" {
             sections_min_stack_size.push(section.index());
         } else if name == ".eh_frame" || name == ".got" || name == ".dynsym" || name == ".dynstr" || name == ".dynamic" {
@@ -9109,6 +9380,7 @@ where
         .chain(sections_other.iter())
         .chain(sections_metadata.iter())
         .chain(sections_exports.iter())
+        .chain(sections_data_exports.iter())
         .copied();
 
     let mut relocations = BTreeMap::new();
@@ -9174,13 +9446,6 @@ where
         .iter()
         .all(|(source, _)| source.offset_range.start < source.offset_range.end));
 
-    {
-        let strip_relocations_for_sections: HashSet<_> =
-            sections_metadata.iter().copied().chain(sections_exports.iter().copied()).collect();
-
-        relocations.retain(|relocation_target, _| !strip_relocations_for_sections.contains(&relocation_target.section_index));
-    }
-
     let data_sections_set: HashSet<SectionIndex> = sections_ro_data
         .iter()
         .chain(sections_rw_data.iter())
@@ -9188,6 +9453,26 @@ where
         .copied()
         .collect();
 
+    let data_exports = sections_data_exports
+        .iter()
+        .map(|&section_index| {
+            let section = elf.section_by_index(section_index);
+            extract_data_exports(&elf, &relocations, &data_sections_set, section)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let data_exports: Vec<_> = data_exports.into_iter().flatten().collect();
+
+    {
+        let strip_relocations_for_sections: HashSet<_> = sections_metadata
+            .iter()
+            .copied()
+            .chain(sections_exports.iter().copied())
+            .chain(sections_data_exports.iter().copied())
+            .collect();
+
+        relocations.retain(|relocation_target, _| !strip_relocations_for_sections.contains(&relocation_target.section_index));
+    }
+
     let section_to_function_name = elf.section_to_function_name();
     let all_jump_targets = harvest_all_jump_targets(&elf, &data_sections_set, &code_sections_set, &instructions, &relocations, &exports)?;
     let all_blocks = split_code_into_basic_blocks(&elf, &section_to_function_name, &all_jump_targets, instructions)?;
@@ -9204,7 +9489,14 @@ where
 
     let mut regspill_size = 0;
     if matches!(config.opt_level, OptLevel::O1 | OptLevel::O2) {
-        reachability_graph = calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &relocations)?;
+        reachability_graph = calculate_reachability(
+            &section_to_block,
+            &all_blocks,
+            &data_sections_set,
+            &exports,
+            &data_exports,
+            &relocations,
+        )?;
         if matches!(config.opt_level, OptLevel::O2) {
             optimize_program(&config, &elf, &imports, &mut all_blocks, &mut reachability_graph, &mut exports);
         } else {
@@ -9228,8 +9520,14 @@ where
             replace_immediates_with_registers(&mut all_blocks, &imports, &used_blocks);
         }
 
-        let expected_reachability_graph =
-            calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &exports, &relocations)?;
+        let expected_reachability_graph = calculate_reachability(
+            &section_to_block,
+            &all_blocks,
+            &data_sections_set,
+            &exports,
+            &data_exports,
+            &relocations,
+        )?;
         if reachability_graph != expected_reachability_graph {
             if std::env::var("POLKAVM_LINKER_DUMP_REACHABILITY_GRAPH")
                 .map(|value| value == "1")
@@ -9402,6 +9700,8 @@ where
         matches!(config.opt_level, OptLevel::O2),
         is_rv64,
         memory_config.heap_base,
+        memory_config.heap_limit,
+        memory_config.stack_floor,
     )?;
 
     {
@@ -9731,6 +10031,26 @@ where
     }
     assert_eq!(export_count, exports.len());
 
+    for data_export in &data_exports {
+        let section_index = data_export.target.section_index;
+        let Some(&section_base) = base_address_for_section.get(&section_index) else {
+            return Err(ProgramFromElfError::other(format!(
+                "internal error: data export '{}' points to a section without a base address",
+                ProgramSymbol::new(&*data_export.symbol)
+            )));
+        };
+
+        let Ok(address) = u32::try_from(section_base + data_export.target.offset) else {
+            return Err(ProgramFromElfError::other(format!(
+                "data export '{}' is out of bounds of the address space",
+                ProgramSymbol::new(&*data_export.symbol)
+            )));
+        };
+
+        let is_mutable = sections_rw_data.contains(&section_index) || sections_bss.contains(&section_index);
+        builder.add_data_export(address, data_export.length, is_mutable, &data_export.symbol);
+    }
+
     let mut locations_for_instruction: Vec<Option<Arc<[Location]>>> = Vec::with_capacity(code.len());
     let mut raw_code = Vec::with_capacity(code.len());
 
@@ -6,7 +6,7 @@ use alloc::vec::Vec;
 use polkavm_common::abi::{MemoryMap, MemoryMapBuilder, VM_ADDR_RETURN_TO_HOST};
 use polkavm_common::cast::cast;
 use polkavm_common::program::{
-    FrameKind, ISA32_V1_NoSbrk, Imports, InstructionSet, Instructions, JumpTable, Opcode, ProgramBlob, Reg, ISA32_V1, ISA64_V1,
+    FrameKind, ISA32_V1_NoSbrk, Imports, Instruction, InstructionSet, Instructions, JumpTable, Opcode, ProgramBlob, Reg, ISA32_V1, ISA64_V1,
 };
 use polkavm_common::utils::{ArcBytes, AsUninitSliceMut};
 
@@ -734,6 +734,11 @@ impl Module {
         self.state().blob.exports()
     }
 
+    /// Returns the statics exported by the module, which can be read through [`RawInstance::read_memory`].
+    pub fn data_exports(&self) -> impl Iterator<Item = crate::program::ProgramDataExport<&[u8]>> + Clone {
+        self.state().blob.data_exports()
+    }
+
     /// Returns the module's imports.
     pub fn imports(&self) -> Imports {
         self.state().blob.imports()
@@ -821,6 +826,13 @@ impl Module {
         Some(i64::from(gas.0))
     }
 
+    /// Calculates the gas cost of a single instruction under the module's cost model.
+    ///
+    /// The cost of a basic block, as returned by [`Module::calculate_gas_cost_for`], is the sum of the costs of its instructions.
+    pub fn calculate_gas_cost_for_instruction(&self, instruction: Instruction) -> Gas {
+        i64::from(crate::gas::calculate_for_instruction(self.state().cost_model.clone(), instruction))
+    }
+
     pub(crate) fn debug_print_location(&self, log_level: log::Level, pc: ProgramCounter) {
         log::log!(log_level, "  At #{pc}:");
 
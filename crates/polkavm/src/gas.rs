@@ -1,5 +1,5 @@
 use alloc::sync::Arc;
-use polkavm_common::program::{InstructionSet, InstructionVisitor, Instructions, RawReg};
+use polkavm_common::program::{Instruction, InstructionSet, InstructionVisitor, Instructions, RawReg};
 
 #[derive(Clone)]
 pub struct CostModelRef {
@@ -1129,6 +1129,13 @@ where
     }
 }
 
+/// Calculates how much a single instruction contributes to the cost of its basic block.
+pub fn calculate_for_instruction(cost_model: CostModelRef, instruction: Instruction) -> u32 {
+    let mut visitor = GasVisitor::new(cost_model);
+    instruction.visit(&mut visitor);
+    visitor.take_block_cost().unwrap_or(visitor.cost)
+}
+
 pub fn trap_cost(cost_model: CostModelRef) -> u32 {
     let mut gas_visitor = GasVisitor::new(cost_model);
     gas_visitor.trap();
@@ -132,7 +132,7 @@ pub mod debug_info {
 pub mod program {
     pub use polkavm_common::program::{
         ISA32_V1_NoSbrk, Imports, ImportsIter, Instruction, InstructionSet, Instructions, JumpTable, JumpTableIter, Opcode,
-        ParsedInstruction, ProgramExport, ProgramParseError, ProgramSymbol, RawReg, ISA32_V1, ISA64_V1,
+        ParsedInstruction, ProgramDataExport, ProgramExport, ProgramParseError, ProgramSymbol, RawReg, ISA32_V1, ISA64_V1,
     };
 }
 
@@ -142,7 +142,7 @@ pub use crate::api::{Engine, MemoryAccessError, Module, RawInstance, RegValue};
 pub use crate::config::{BackendKind, Config, CustomCodegen, GasMeteringKind, ModuleConfig, SandboxKind};
 pub use crate::error::Error;
 pub use crate::gas::{Cost, CostModel, CostModelRef};
-pub use crate::linker::{CallError, Caller, Instance, InstancePre, Linker};
+pub use crate::linker::{CallError, Caller, Instance, InstancePre, Linker, IMPORT_PROBE_SYMBOL};
 pub use crate::utils::{InterruptKind, Segfault};
 
 pub const RETURN_TO_HOST: u64 = polkavm_common::abi::VM_ADDR_RETURN_TO_HOST as u64;
@@ -26,6 +26,19 @@ trait CallFn<UserData, UserError>: Send + Sync {
 #[repr(transparent)]
 pub struct CallFnArc<UserData, UserError>(Arc<dyn CallFn<UserData, UserError>>);
 
+/// The symbol of the host function through which guests query whether an import is available.
+///
+/// This is what `#[polkavm_import(optional)]` calls before calling the import itself. It takes the address and
+/// the length of the import's symbol in `A0` and `A1`, and returns `1` in `A0` if a host function with that
+/// symbol is defined, or `0` otherwise. When a fallback handler is defined every import of the module is
+/// reported as available, since the fallback handler is what's called for the imports which aren't defined.
+///
+/// Unless the host defines a function with this symbol itself, the [`Linker`] answers it for every module which imports it.
+///
+/// The optional imports are otherwise ordinary imports, so in strict mode the module still fails to instantiate
+/// when any of them is missing.
+pub const IMPORT_PROBE_SYMBOL: &[u8] = b"__polkavm_has_import";
+
 type FallbackHandlerArc<UserData, UserError> = Arc<dyn Fn(Caller<UserData>, u32) -> Result<(), UserError> + Send + Sync + 'static>;
 
 impl<UserData, UserError> Clone for CallFnArc<UserData, UserError> {
@@ -686,7 +699,9 @@ impl<UserData, UserError> Linker<UserData, UserError> {
         }
 
         let mut imports: Vec<Option<CallFnArc<UserData, UserError>>> = Vec::with_capacity(module.imports().len() as usize);
-        for symbol in module.imports() {
+        let mut import_probe = None;
+        let mut available_imports = Vec::new();
+        for (index, symbol) in module.imports().into_iter().enumerate() {
             let Some(symbol) = symbol else {
                 if module.is_strict() {
                     return Err("failed to parse an import".into());
@@ -697,8 +712,13 @@ impl<UserData, UserError> Linker<UserData, UserError> {
             };
 
             let host_fn = if let Some(host_fn) = self.host_functions.get(symbol.as_bytes()) {
+                available_imports.push(symbol.as_bytes().to_owned());
                 Some(host_fn.clone())
+            } else if symbol.as_bytes() == IMPORT_PROBE_SYMBOL {
+                import_probe = Some(index as u32);
+                None
             } else if self.fallback_handler.is_some() {
+                available_imports.push(symbol.as_bytes().to_owned());
                 None
             } else if module.is_strict() {
                 return Err(format!("missing host function: {}", symbol).into());
@@ -711,11 +731,16 @@ impl<UserData, UserError> Linker<UserData, UserError> {
         }
 
         assert_eq!(imports.len(), module.imports().len() as usize);
+        available_imports.sort_unstable();
+        let longest_available_import = available_imports.iter().map(|symbol| symbol.len()).max().unwrap_or(0);
         Ok(InstancePre(Arc::new(InstancePreState {
             module: module.clone(),
             imports,
             exports,
             fallback_handler: self.fallback_handler.clone(),
+            import_probe,
+            available_imports,
+            longest_available_import,
         })))
     }
 }
@@ -725,6 +750,12 @@ struct InstancePreState<UserData, UserError> {
     imports: Vec<Option<CallFnArc<UserData, UserError>>>,
     exports: LookupMap<Vec<u8>, ProgramCounter>,
     fallback_handler: Option<FallbackHandlerArc<UserData, UserError>>,
+    /// The index of the import of the [`IMPORT_PROBE_SYMBOL`], if the linker answers it.
+    import_probe: Option<u32>,
+    /// The sorted symbols of the module's imports which are defined by the host.
+    available_imports: Vec<Vec<u8>>,
+    /// The length of the longest symbol in `available_imports`, past which the guest's symbols aren't even read.
+    longest_available_import: usize,
 }
 
 pub struct InstancePre<UserData = (), UserError = core::convert::Infallible>(Arc<InstancePreState<UserData, UserError>>);
@@ -827,6 +858,15 @@ impl<UserData, UserError> Instance<UserData, UserError> {
                 InterruptKind::Ecalli(hostcall) => {
                     if let Some(host_fn) = self.pre.0.imports.get(hostcall as usize).and_then(|host_fn| host_fn.as_ref()) {
                         host_fn.0.call(user_data, &mut self.instance).map_err(CallError::User)?;
+                    } else if self.pre.0.import_probe == Some(hostcall) {
+                        let address = self.instance.reg(Reg::A0) as u32;
+                        let length = self.instance.reg(Reg::A1) as u32;
+                        let is_available = length as usize <= self.pre.0.longest_available_import
+                            && self
+                                .instance
+                                .read_memory(address, length)
+                                .is_ok_and(|symbol| self.pre.0.available_imports.binary_search(&symbol).is_ok());
+                        self.instance.set_reg(Reg::A0, u64::from(is_available));
                     } else if let Some(ref fallback_handler) = self.pre.0.fallback_handler {
                         let caller = Caller {
                             user_data,
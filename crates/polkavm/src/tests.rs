@@ -1,7 +1,7 @@
 use crate::mutex::Mutex;
 use crate::{
     BackendKind, CallError, Caller, Config, Engine, GasMeteringKind, InterruptKind, Linker, MemoryAccessError, Module, ModuleConfig,
-    ProgramBlob, ProgramCounter, Reg, Segfault,
+    ProgramBlob, ProgramCounter, RawInstance, Reg, Segfault,
 };
 use alloc::collections::BTreeMap;
 use alloc::format;
@@ -362,6 +362,36 @@ fn fallback_hostcall_handler_works(config: Config) {
     assert_eq!(result, 111);
 }
 
+fn import_probe_reports_imports_handled_by_the_fallback(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_ro_data_size(0x1000);
+    builder.set_ro_data(b"missing".to_vec());
+    builder.add_export_by_basic_block(0, b"main");
+    builder.add_import(crate::IMPORT_PROBE_SYMBOL);
+    builder.add_import(b"missing");
+    builder.set_code(&[asm::ecalli(0), asm::ret()], &[]);
+    let blob = ProgramBlob::parse(builder.into_vec().into()).unwrap();
+
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), blob).unwrap();
+    let address = module.memory_map().ro_data_address();
+    let is_available = |linker: &Linker, length: u32| {
+        let mut instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+        instance
+            .call_typed_and_get_result::<u32, (u32, u32)>(&mut (), "main", (address, length))
+            .unwrap()
+    };
+
+    let mut linker = Linker::new();
+    assert_eq!(is_available(&linker, 7), 0);
+
+    linker.define_fallback(|_caller: Caller<()>, _num: u32| -> Result<(), core::convert::Infallible> { Ok(()) });
+    assert_eq!(is_available(&linker, 7), 1);
+    // A symbol longer than any of the imports isn't even read, so it can't make the host read a huge amount of memory.
+    assert_eq!(is_available(&linker, u32::MAX), 0);
+}
+
 macro_rules! match_interrupt {
     ($interrupt:expr, $pattern:pat) => {
         let i = $interrupt;
@@ -2563,11 +2593,15 @@ struct TestInstance {
 
 impl TestInstance {
     fn new(config: &Config, elf: &'static [u8], optimize: bool) -> Self {
+        Self::new_with_module_config(config, &Default::default(), elf, optimize)
+    }
+
+    fn new_with_module_config(config: &Config, module_config: &ModuleConfig, elf: &'static [u8], optimize: bool) -> Self {
         let _ = env_logger::try_init();
         let blob = get_blob_impl(optimize, false, elf);
 
         let engine = Engine::new(config).unwrap();
-        let module = Module::from_blob(&engine, &Default::default(), blob).unwrap();
+        let module = Module::from_blob(&engine, module_config, blob).unwrap();
         let mut linker = Linker::new();
         linker
             .define_typed("multiply_by_2", |_caller: Caller<()>, value: u32| -> u32 { value * 2 })
@@ -2613,6 +2647,53 @@ impl TestInstance {
             })
             .unwrap();
 
+        linker
+            .define_untyped("double_u128", |caller: Caller<()>| {
+                let value = u128::from(caller.instance.reg(Reg::A0)) | (u128::from(caller.instance.reg(Reg::A1)) << 64);
+                let value = value.wrapping_mul(2);
+                caller.instance.set_reg(Reg::A0, value as u64);
+                caller.instance.set_reg(Reg::A1, (value >> 64) as u64);
+                Ok(())
+            })
+            .unwrap();
+
+        linker
+            .define_untyped("weigh_spilled_arguments", |caller: Caller<()>| {
+                let is_64_bit = caller.instance.is_64_bit();
+                let regs = read_spilled_regs(caller.instance, if is_64_bit { 10 } else { 13 });
+                let result = weigh(&join_regs(is_64_bit, &regs));
+                if is_64_bit {
+                    caller.instance.set_reg(Reg::A0, result);
+                } else {
+                    caller.instance.set_reg(Reg::A0, result & 0xffffffff);
+                    caller.instance.set_reg(Reg::A1, result >> 32);
+                }
+
+                Ok(())
+            })
+            .unwrap();
+
+        // The echo behind `polkavm_derive::hostcall!(3, ...)`, which sends back its last two arguments.
+        linker
+            .define_untyped("__polkavm_hostcall_3", |caller: Caller<()>| {
+                caller.instance.set_reg(Reg::A0, caller.instance.reg(Reg::A4));
+                caller.instance.set_reg(Reg::A1, caller.instance.reg(Reg::A5));
+                Ok(())
+            })
+            .unwrap();
+
+        linker
+            .define_untyped("fold_u128", |caller: Caller<()>| {
+                let value = [Reg::A3, Reg::A2, Reg::A1, Reg::A0]
+                    .into_iter()
+                    .fold(0, |value, reg| (value << 32) | u128::from(caller.instance.reg(reg) & 0xffffffff));
+                let folded = (value as u64) ^ ((value >> 64) as u64);
+                caller.instance.set_reg(Reg::A0, folded & 0xffffffff);
+                caller.instance.set_reg(Reg::A1, folded >> 32);
+                Ok(())
+            })
+            .unwrap();
+
         linker
             .define_untyped("return_tuple_usize", move |caller: Caller<()>| {
                 if caller.instance.is_64_bit() {
@@ -2729,6 +2810,185 @@ fn test_blob_hostcall(args: TestBlobArgs) {
     assert_eq!(i.call::<(u32,), u32>("test_multiply_by_6", (10,)).unwrap(), 60);
 }
 
+fn test_blob_optional_imports(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    // `multiply_by_2` is defined by the host, so it's called.
+    assert_eq!(i.call::<(u32,), u32>("call_available_optional_import", (10,)).unwrap(), 20);
+    // `not_provided_by_the_host` isn't, so its default is returned instead of trapping.
+    assert_eq!(i.call::<(u32,), u32>("call_missing_optional_import", (10,)).unwrap(), 0xdead);
+}
+
+fn test_blob_raw_hostcall(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    assert_eq!(i.call::<(u32, u32), u32>("call_raw_hostcall", (0x12, 0x34)).unwrap(), 0x12_0034);
+
+    // The raw host call keeps its index even though the typed imports don't specify any.
+    let symbol = i.module.imports().into_iter().nth(3).flatten().unwrap();
+    assert_eq!(symbol.as_bytes(), b"__polkavm_hostcall_3");
+}
+
+fn test_blob_measure_stack_high_water(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    let mut previous_usage = 0;
+    for depth in [4, 16, 64] {
+        // Every level of the recursion has a 256 byte buffer on the stack, plus some overhead.
+        let usage = i.call::<(u32,), u32>("measure_recursive_stack_usage", (depth,)).unwrap();
+        assert!(usage >= depth * 256, "depth = {depth}, usage = {usage}");
+        assert!(usage <= depth * 1024 + 4096, "depth = {depth}, usage = {usage}");
+        assert!(usage > previous_usage);
+        previous_usage = usage;
+    }
+}
+
+fn test_blob_replaced_mem_intrinsics(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+    let mut i = TestInstance::new_with_module_config(&args.config, &module_config, elf, args.optimize);
+
+    const GAS: i64 = 1_000_000;
+    i.instance.set_gas(GAS);
+    assert_eq!(i.call::<(u32,), u32>("copy_big_struct", (7,)).unwrap(), 7 * 1023);
+
+    // The struct has 4096 bytes; copying it byte by byte would take several instructions, and so several units of gas, per byte.
+    let gas_used = GAS - i.instance.gas();
+    assert!(gas_used < 2 * 4096, "gas_used = {gas_used}");
+
+    assert_eq!(i.call::<(u32, u32), i32>("compare_and_move_bytes", (3, 10)).unwrap(), -1);
+}
+
+/// The arguments of `weigh_ten_arguments` and `weigh_spilled_arguments`, along with whether each one is a `u64`.
+const SPILLED_ARGUMENTS: [(u64, bool); 10] = [
+    (1, false),
+    (0x1122334455667788, true),
+    (3, false),
+    (4, false),
+    (0x99aabbccddeeff00, true),
+    (6, false),
+    (7, false),
+    (0x0123456789abcdef, true),
+    (9, false),
+    (10, false),
+];
+
+/// Multiplies every argument by its position (starting from one) and sums them up, just like the guest does.
+fn weigh(args: &[u64]) -> u64 {
+    args.iter()
+        .zip(1..)
+        .fold(0, |sum, (&arg, weight)| sum.wrapping_add(arg.wrapping_mul(weight)))
+}
+
+/// Splits `SPILLED_ARGUMENTS` into registers; on 32-bit every `u64` takes two of them.
+fn split_into_regs(is_64_bit: bool) -> Vec<u64> {
+    let mut regs = Vec::new();
+    for (arg, is_u64) in SPILLED_ARGUMENTS {
+        if is_u64 && !is_64_bit {
+            regs.extend([arg & 0xffffffff, arg >> 32]);
+        } else {
+            regs.push(arg);
+        }
+    }
+
+    regs
+}
+
+fn join_regs(is_64_bit: bool, mut regs: &[u64]) -> Vec<u64> {
+    let mut args = Vec::new();
+    for (_, is_u64) in SPILLED_ARGUMENTS {
+        if is_u64 && !is_64_bit {
+            args.push((regs[0] & 0xffffffff) | (regs[1] << 32));
+            regs = &regs[2..];
+        } else {
+            args.push(if is_u64 { regs[0] } else { regs[0] & 0xffffffff });
+            regs = &regs[1..];
+        }
+    }
+
+    assert!(regs.is_empty());
+    args
+}
+
+/// Reads the registers of a call which didn't fit into the input registers: the first five are passed as usual,
+/// while the last input register points to the rest.
+fn read_spilled_regs(instance: &RawInstance, count: usize) -> Vec<u64> {
+    let slot_size = if instance.is_64_bit() { 8 } else { 4 };
+    let mut regs: Vec<u64> = [Reg::A0, Reg::A1, Reg::A2, Reg::A3, Reg::A4].map(|reg| instance.reg(reg)).into();
+    let address = instance.reg(Reg::A5) as u32;
+    let spilled = instance.read_memory(address, ((count - regs.len()) * slot_size) as u32).unwrap();
+    regs.extend(spilled.chunks(slot_size).map(|slot| {
+        let mut bytes = [0; 8];
+        bytes[..slot_size].copy_from_slice(slot);
+        u64::from_le_bytes(bytes)
+    }));
+
+    regs
+}
+
+/// Calls an export which needs more registers than there are input registers, passing the rest through memory.
+fn call_with_spilled_regs(i: &mut TestInstance, name: &str, regs: &[u64]) -> u64 {
+    let is_64_bit = i.instance.is_64_bit();
+    let slot_size = if is_64_bit { 8 } else { 4 };
+    let spilled: Vec<u8> = regs[5..].iter().flat_map(|reg| reg.to_le_bytes()[..slot_size].to_vec()).collect();
+
+    let heap_top = i.instance.sbrk(spilled.len() as u32 + 8).unwrap().unwrap();
+    let address = (heap_top - spilled.len() as u32) & !7;
+    i.instance.write_memory(address, &spilled).unwrap();
+    if is_64_bit {
+        i.call::<(u64, u64, u64, u64, u64, u64), u64>(name, (regs[0], regs[1], regs[2], regs[3], regs[4], u64::from(address)))
+    } else {
+        let regs: Vec<u32> = regs.iter().map(|&reg| reg as u32).collect();
+        i.call::<(u32, u32, u32, u32, u32, u32), u64>(name, (regs[0], regs[1], regs[2], regs[3], regs[4], address))
+    }
+    .unwrap()
+}
+
+fn test_blob_spilled_arguments(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    let is_64_bit = i.instance.is_64_bit();
+
+    let seven = [1, 2, 3, 4, 5, 6, 7];
+    assert_eq!(call_with_spilled_regs(&mut i, "weigh_seven_arguments", &seven), weigh(&seven));
+
+    let ten: Vec<u64> = SPILLED_ARGUMENTS.iter().map(|&(arg, _)| arg).collect();
+    let regs = split_into_regs(is_64_bit);
+    assert_eq!(regs.len(), if is_64_bit { 10 } else { 13 });
+    assert_eq!(join_regs(is_64_bit, &regs), ten);
+    assert_eq!(call_with_spilled_regs(&mut i, "weigh_ten_arguments", &regs), weigh(&ten));
+
+    // The guest spills the arguments of an import in the same way.
+    assert_eq!(i.call::<(), u64>("call_import_with_spilled_arguments", ()).unwrap(), weigh(&ten));
+}
+
+fn test_blob_data_exports(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    let find = |symbol: &str| {
+        let export = i.module.data_exports().find(|export| export.symbol() == symbol).unwrap();
+        (export.address(), export.length(), export.is_mutable())
+    };
+
+    let (address, length, is_mutable) = find("config_table");
+    assert_eq!(length, 16);
+    assert!(!is_mutable);
+    assert!(i.module.memory_map().ro_data_range().contains(&address));
+    assert_eq!(i.instance.read_memory(address, length).unwrap(), b"polkavm-config\x01\x02");
+
+    let (address, length, is_mutable) = find("counters");
+    assert_eq!(length, 16);
+    assert!(is_mutable);
+    assert!(i.module.memory_map().rw_data_range().contains(&address));
+
+    assert_eq!(i.call::<(u32,), u32>("bump_counter", (2,)).unwrap(), 1);
+    assert_eq!(i.call::<(u32,), u32>("bump_counter", (2,)).unwrap(), 2);
+    assert_eq!(i.call::<(u32,), u32>("bump_counter", (0,)).unwrap(), 1);
+    let counters = i.instance.read_memory(address, length).unwrap();
+    assert_eq!(counters, [1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+}
+
 fn test_blob_define_abi(args: TestBlobArgs) {
     let elf = args.get_test_program();
     let mut i = TestInstance::new(&args.config, elf, args.optimize);
@@ -2965,6 +3225,103 @@ fn test_blob_get_heap_base(args: TestBlobArgs) {
     let mut i = TestInstance::new(&args.config, elf, args.optimize);
     let heap_base = i.call::<(), u32>("get_heap_base", ()).unwrap();
     assert_eq!(heap_base, i.instance.module().memory_map().heap_base());
+
+    let heap_limit = i.call::<(), u32>("get_heap_limit", ()).unwrap();
+    let memory_map = i.instance.module().memory_map();
+    assert!(heap_limit > heap_base);
+    assert!(u64::from(heap_limit) <= u64::from(heap_base) + u64::from(memory_map.max_heap_size()));
+}
+
+fn test_blob_export_result_trap(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    assert_eq!(i.call::<(u32, u32), u32>("checked_divide", (10, 2)).unwrap(), 5);
+    assert!(matches!(i.call::<(u32, u32), u32>("checked_divide", (10, 0)), Err(CallError::Trap)));
+    assert_eq!(i.instance.reg(Reg::A0), 0xdead);
+}
+
+fn test_blob_u128_arguments_and_return_values(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    if args.is_64_bit {
+        let (a, b) = (0x1_0000_0000_0000_0001_u128, 3_u128);
+        let args = (a as u64, (a >> 64) as u64, b as u64, (b >> 64) as u64);
+        i.call::<(u64, u64, u64, u64), ()>("multiply_and_double_u128", args).unwrap();
+        let result = u128::from(i.instance.reg(Reg::A0)) | (u128::from(i.instance.reg(Reg::A1)) << 64);
+        assert_eq!(result, a * b * 2);
+    } else {
+        let value = 0x8123_4567_89ab_cdef_fedc_ba98_7654_3210_u128;
+        let args = (value as u32, (value >> 32) as u32, (value >> 64) as u32, (value >> 96) as u32);
+        i.call::<(u32, u32, u32, u32), ()>("double_and_fold_u128", args).unwrap();
+        let result = (i.instance.reg(Reg::A0) & 0xffffffff) | ((i.instance.reg(Reg::A1) & 0xffffffff) << 32);
+        let doubled = value.wrapping_mul(2);
+        assert_eq!(result, (doubled as u64) ^ ((doubled >> 64) as u64));
+    }
+}
+
+fn test_blob_export_slices_and_strings(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    let address = i.instance.module().memory_map().heap_base();
+    i.instance.sbrk(4096).unwrap().unwrap();
+
+    i.instance.write_memory(address, &[1, 2, 3, 4, 5]).unwrap();
+    i.call::<(u32, u32), ()>("reverse_bytes", (address, 5)).unwrap();
+    assert_eq!(i.instance.read_memory(address, 6).unwrap(), [5, 4, 3, 2, 1, 0]);
+    i.call::<(u32, u32), ()>("reverse_bytes", (0, 0)).unwrap();
+
+    let text = "zażółć";
+    i.instance.write_memory(address, text.as_bytes()).unwrap();
+    let length = text.len() as u32;
+    assert_eq!(i.call::<(u32, u32), u32>("count_chars", (address, length)).unwrap(), 6);
+}
+
+fn test_blob_export_manifest(args: TestBlobArgs) {
+    use polkavm_derive::manifest::{ExportKind, ManifestEntry};
+
+    let manifest = polkavm_derive::manifest::extract_manifest(args.get_test_program()).unwrap();
+    let entry = |symbol: &str| manifest.iter().find(|entry| entry.symbol == symbol.as_bytes()).unwrap();
+    let expected = |symbol: &str, args, return_kind| ManifestEntry {
+        symbol: symbol.into(),
+        args,
+        return_kind,
+    };
+
+    assert_eq!(
+        *entry("checked_divide"),
+        expected("checked_divide", vec![ExportKind::U32, ExportKind::U32], ExportKind::U32)
+    );
+    assert_eq!(
+        *entry("reverse_bytes"),
+        expected("reverse_bytes", vec![ExportKind::MutSlice], ExportKind::Unit)
+    );
+    assert_eq!(
+        *entry("count_chars"),
+        expected("count_chars", vec![ExportKind::Str], ExportKind::U32)
+    );
+}
+
+fn test_blob_panic_message_is_recorded(args: TestBlobArgs) {
+    let elf = args.get_test_program();
+    let mut i = TestInstance::new(&args.config, elf, args.optimize);
+    let read_message = |i: &mut TestInstance| {
+        let (address, length) = (i.instance.reg(Reg::A0) as u32, i.instance.reg(Reg::A1) as u32);
+        String::from_utf8(i.instance.read_memory(address, length).unwrap()).unwrap()
+    };
+
+    assert!(matches!(i.call::<(u32,), ()>("panic_with_message", (3,)), Err(CallError::Trap)));
+    let message = read_message(&mut i);
+    assert!(message.starts_with("panicked at "), "{message}");
+    assert!(message.ends_with("deliberate panic: xxx"), "{message}");
+
+    i.call::<(), ()>("__polkavm_panic_message", ()).unwrap();
+    assert_eq!(read_message(&mut i), message);
+
+    // The message doesn't fit into the buffer.
+    assert!(matches!(i.call::<(u32,), ()>("panic_with_message", (200,)), Err(CallError::Trap)));
+    let message = read_message(&mut i);
+    assert_eq!(message.len(), 128);
+    assert!(message.starts_with("panicked at ") && message.ends_with('x'), "{message}");
 }
 
 fn test_asm_reloc_add_sub(config: Config, optimize: bool) {
@@ -3550,6 +3907,23 @@ fn blob_len_works() {
     assert_eq!(Some(EXAMPLE_BLOB.len() as BlobLen), ProgramBlob::blob_length(EXAMPLE_BLOB));
 }
 
+#[test]
+fn data_exports_survive_custom_sections_with_higher_ids() {
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export_by_basic_block(0, b"main");
+    builder.set_code(&[asm::ret()], &[]);
+    builder.add_custom_section(255, vec![1, 2, 3]);
+    builder.add_data_export(0x10000, 16, false, b"TABLE");
+    builder.add_custom_section(polkavm_common::program::SECTION_OPT_DEBUG_STRINGS, vec![0]);
+
+    let blob = ProgramBlob::parse(builder.into_vec().into()).unwrap();
+    let exports: Vec<_> = blob
+        .data_exports()
+        .map(|export| (export.symbol().to_string(), export.address(), export.length(), export.is_mutable()))
+        .collect();
+    assert_eq!(exports, [("TABLE".to_string(), 0x10000, 16, false)]);
+}
+
 #[cfg(not(feature = "std"))]
 fn spawn_stress_test(_config: Config) {}
 
@@ -3705,6 +4079,7 @@ include!("tests_riscv.rs");
 run_tests! {
     basic_test
     fallback_hostcall_handler_works
+    import_probe_reports_imports_handled_by_the_fallback
     step_tracing_basic
     step_tracing_invalid_store
     step_tracing_invalid_load
@@ -3778,6 +4153,12 @@ run_test_blob_tests! {
     test_blob_atomic_fetch_swap
     test_blob_atomic_fetch_minmax
     test_blob_hostcall
+    test_blob_optional_imports
+    test_blob_raw_hostcall
+    test_blob_measure_stack_high_water
+    test_blob_replaced_mem_intrinsics
+    test_blob_data_exports
+    test_blob_spilled_arguments
     test_blob_define_abi
     test_blob_input_registers
     test_blob_call_sbrk_from_guest
@@ -3797,6 +4178,11 @@ run_test_blob_tests! {
     test_blob_return_tuple_from_import
     test_blob_return_tuple_from_export
     test_blob_get_heap_base
+    test_blob_export_result_trap
+    test_blob_export_slices_and_strings
+    test_blob_panic_message_is_recorded
+    test_blob_export_manifest
+    test_blob_u128_arguments_and_return_values
 }
 
 run_asm_tests! {
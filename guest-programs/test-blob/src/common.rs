@@ -8,12 +8,7 @@ polkavm_derive::min_stack_size!(2);
 #[global_allocator]
 static mut GLOBAL_ALLOC: simplealloc::SimpleAlloc<{ 1024 * 1024 }> = simplealloc::SimpleAlloc::new();
 
-#[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    unsafe {
-        core::arch::asm!("unimp", options(noreturn));
-    }
-}
+polkavm_derive::polkavm_panic_handler!(buffer_size = 128);
 
 static mut VEC: Vec<u8> = Vec::new();
 
@@ -175,6 +170,103 @@ extern "C" fn test_multiply_by_6(value: u32) -> u32 {
     unsafe { a::multiply_by_2(value * 3) }
 }
 
+#[polkavm_derive::polkavm_import]
+extern "C" {
+    #[polkavm_import(optional, symbol = "multiply_by_2")]
+    fn multiply_by_2_if_available(value: u32) -> Option<u32>;
+
+    #[polkavm_import(optional, default = "0xdead")]
+    fn not_provided_by_the_host(value: u32) -> u32;
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn call_available_optional_import(value: u32) -> u32 {
+    unsafe { multiply_by_2_if_available(value) }.unwrap_or(0)
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn call_missing_optional_import(value: u32) -> u32 {
+    unsafe { not_provided_by_the_host(value) }
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn call_raw_hostcall(first: u32, second: u32) -> u32 {
+    let (a0, a1) = polkavm_derive::hostcall!(3, a0 = 1, a4 = first as usize, a5 = second as usize);
+    (a0 << 16 | a1) as u32
+}
+
+#[inline(never)]
+fn recurse_with_a_big_frame(depth: u32) -> u32 {
+    let buffer = core::hint::black_box([depth as u8; 256]);
+    if depth == 0 {
+        return u32::from(buffer[0]);
+    }
+
+    recurse_with_a_big_frame(depth - 1) + u32::from(buffer[255])
+}
+
+#[derive(Clone, Copy)]
+struct BigStruct {
+    words: [u32; 1024],
+}
+
+static mut BIG_STRUCT_SOURCE: BigStruct = BigStruct { words: [0; 1024] };
+static mut BIG_STRUCT_TARGET: BigStruct = BigStruct { words: [0; 1024] };
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn copy_big_struct(multiplier: u32) -> u32 {
+    unsafe {
+        let source = &mut *core::ptr::addr_of_mut!(BIG_STRUCT_SOURCE);
+        source.words[1023] = multiplier * 1023;
+
+        // The compiler turns this into a call to `memcpy`.
+        let target = &mut *core::ptr::addr_of_mut!(BIG_STRUCT_TARGET);
+        *core::hint::black_box(&mut *target) = *core::hint::black_box(&*source);
+        target.words[1023]
+    }
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn compare_and_move_bytes(shift: u32, length: u32) -> i32 {
+    let mut original = [0_u8; 64];
+    for (index, byte) in original.iter_mut().enumerate() {
+        *byte = index as u8;
+    }
+
+    // These go through `memmove` and `memcmp`.
+    let (shift, length) = (shift as usize, length as usize);
+    let mut buffer = original;
+    buffer.copy_within(0..length, shift);
+    if buffer[shift..shift + length] != original[..length] {
+        return 100;
+    }
+
+    buffer[..length].cmp(&original[..length]) as i32
+}
+
+polkavm_derive::polkavm_export_static!(
+    "config_table",
+    static CONFIG_TABLE: [u8; 16] = *b"polkavm-config\x01\x02"
+);
+
+polkavm_derive::polkavm_export_static!("counters", static mut COUNTERS: [u32; 4] = [0; 4]);
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn bump_counter(index: u32) -> u32 {
+    unsafe {
+        let counter = &mut (*core::ptr::addr_of_mut!(COUNTERS))[index as usize];
+        *counter += 1;
+        *counter
+    }
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn measure_recursive_stack_usage(depth: u32) -> u32 {
+    polkavm_derive::paint_stack();
+    core::hint::black_box(recurse_with_a_big_frame(depth));
+    polkavm_derive::measure_stack_high_water() as u32
+}
+
 #[polkavm_derive::polkavm_define_abi(allow_extra_input_registers)]
 mod test_abi {}
 
@@ -234,6 +326,41 @@ fn test_input_registers() {
     );
 }
 
+#[polkavm_derive::polkavm_import]
+extern "C" {
+    #[allow(clippy::too_many_arguments)]
+    fn weigh_spilled_arguments(a0: u32, a1: u64, a2: u32, a3: u32, a4: u64, a5: u32, a6: u32, a7: u64, a8: u32, a9: u32) -> u64;
+}
+
+/// Multiplies every argument by its position (starting from one) and sums them up.
+fn weigh(args: &[u64]) -> u64 {
+    args.iter()
+        .zip(1..)
+        .fold(0, |sum, (&arg, weight)| sum.wrapping_add(arg.wrapping_mul(weight)))
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn weigh_seven_arguments(a0: u32, a1: u32, a2: u32, a3: u32, a4: u32, a5: u32, a6: u32) -> u64 {
+    weigh(&[a0, a1, a2, a3, a4, a5, a6].map(u64::from))
+}
+
+#[polkavm_derive::polkavm_export]
+#[allow(clippy::too_many_arguments)]
+extern "C" fn weigh_ten_arguments(a0: u32, a1: u64, a2: u32, a3: u32, a4: u64, a5: u32, a6: u32, a7: u64, a8: u32, a9: u32) -> u64 {
+    let [a0, a2, a3, a5, a6, a8, a9] = [a0, a2, a3, a5, a6, a8, a9].map(u64::from);
+    weigh(&[a0, a1, a2, a3, a4, a5, a6, a7, a8, a9])
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn call_import_with_spilled_arguments() -> u64 {
+    let result = unsafe { weigh_spilled_arguments(1, 0x1122334455667788, 3, 4, 0x99aabbccddeeff00, 6, 7, 0x0123456789abcdef, 9, 10) };
+    assert_eq!(
+        result,
+        weigh(&[1, 0x1122334455667788, 3, 4, 0x99aabbccddeeff00, 6, 7, 0x0123456789abcdef, 9, 10])
+    );
+    result
+}
+
 #[polkavm_derive::polkavm_export]
 extern "C" fn add_u32(a0: u32, a1: u32) -> u32 {
     a0.wrapping_add(a1)
@@ -413,3 +540,53 @@ extern "C" fn export_return_tuple_usize() -> (usize, usize) {
 extern "C" fn get_heap_base() -> u32 {
     polkavm_derive::heap_base() as u32
 }
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn get_heap_limit() -> u32 {
+    polkavm_derive::heap_limit() as u32
+}
+
+#[polkavm_derive::polkavm_export(result_trap)]
+extern "C" fn checked_divide(dividend: u32, divisor: u32) -> Result<u32, u32> {
+    dividend.checked_div(divisor).ok_or(0xdead)
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn reverse_bytes(bytes: &mut [u8]) {
+    bytes.reverse();
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn count_chars(text: &str) -> u32 {
+    text.chars().count() as u32
+}
+
+#[polkavm_derive::polkavm_export]
+extern "C" fn panic_with_message(repeat: u32) {
+    panic!("deliberate panic: {}", "x".repeat(repeat as usize));
+}
+
+#[cfg(target_pointer_width = "64")]
+#[polkavm_derive::polkavm_import]
+extern "C" {
+    fn double_u128(value: u128) -> u128;
+}
+
+#[cfg(target_pointer_width = "64")]
+#[polkavm_derive::polkavm_export]
+extern "C" fn multiply_and_double_u128(a: u128, b: u128) -> u128 {
+    unsafe { double_u128(a.wrapping_mul(b)) }
+}
+
+// There are only two return registers on 32-bit targets, so there a 128-bit value can only be passed as an argument.
+#[cfg(target_pointer_width = "32")]
+#[polkavm_derive::polkavm_import]
+extern "C" {
+    fn fold_u128(value: u128) -> u64;
+}
+
+#[cfg(target_pointer_width = "32")]
+#[polkavm_derive::polkavm_export]
+extern "C" fn double_and_fold_u128(value: u128) -> u64 {
+    unsafe { fold_u128(value.wrapping_mul(2)) }
+}
@@ -0,0 +1,62 @@
+//! Decoding of the JAM-codec encoded arguments of `resetGenericWithMemory`.
+//!
+//! This module only depends on `std`, so that `spectool-wasm` can check its own encoding against it.
+
+pub(crate) fn read_u32(source: &[u8], index: usize) -> u32 {
+    let mut val = [0u8; 4];
+    val.copy_from_slice(&source[index..index + 4]);
+    u32::from_le_bytes(val)
+}
+
+pub(crate) fn read_u64(source: &[u8], index: usize) -> u64 {
+    let mut val = [0u8; 8];
+    val.copy_from_slice(&source[index..index + 8]);
+    u64::from_le_bytes(val)
+}
+
+/// Page Map is defined in JAM codec lingo as: `sequence(tuple(u32, u32, bool))`
+pub(crate) fn read_pages(page_map: Vec<u8>) -> Vec<Page> {
+    let mut pages = vec![];
+    let mut index = 0;
+    while index < page_map.len() {
+        let address = read_u32(&page_map, index);
+        index += 4;
+        let length = read_u32(&page_map, index);
+        index += 4;
+        let is_writable = page_map[index] > 0;
+        index += 1;
+        pages.push(Page {
+            address,
+            length,
+            is_writable,
+        });
+    }
+    pages
+}
+
+/// Chunks is defined in JAM codec lingo as: `sequence(tuple(u32, u32, bytes))`
+pub(crate) fn read_chunks(chunks: Vec<u8>) -> Vec<Chunk> {
+    let mut res = vec![];
+    let mut index = 0;
+    while index < chunks.len() {
+        let address = read_u32(&chunks, index);
+        index += 4;
+        let length = read_u32(&chunks, index) as usize;
+        index += 4;
+        let data = chunks[index..index + length].to_vec();
+        res.push(Chunk { address, data });
+        index += length;
+    }
+    res
+}
+
+pub(crate) struct Page {
+    pub(crate) address: u32,
+    pub(crate) length: u32,
+    pub(crate) is_writable: bool,
+}
+
+pub(crate) struct Chunk {
+    pub(crate) address: u32,
+    pub(crate) data: Vec<u8>,
+}
@@ -5,6 +5,10 @@ use polkavm::{ArcBytes, Engine, InterruptKind, Module, ModuleConfig, ProgramBlob
 use polkavm_common::program::ProgramParts;
 use wasm_bindgen::prelude::wasm_bindgen;
 
+mod codec;
+
+use codec::{read_chunks, read_pages, read_u64, Chunk};
+
 #[repr(C)]
 #[wasm_bindgen]
 #[derive(Copy, Clone, Debug)]
@@ -102,48 +106,66 @@ pub fn resetGenericWithMemory(
     nextStep();
 }
 
+/// Runs a single step of the given instance.
+///
+/// Returns whether the execution can continue, the resulting status
+/// and the exit argument to publish (if any).
+fn step(pvm: &mut RawInstance) -> (bool, Status, Option<u32>) {
+    match pvm.run() {
+        Ok(InterruptKind::Finished) => {
+            (false, Status::Halt, None)
+        },
+        Ok(InterruptKind::Trap) => {
+            (false, Status::Panic, None)
+        },
+        Ok(InterruptKind::Ecalli(call)) => {
+            (false, Status::Host, Some(call))
+        },
+        Ok(InterruptKind::Segfault(page)) => {
+            (false, Status::Fault, Some(page.page_address))
+        },
+        Ok(InterruptKind::NotEnoughGas) => {
+            (false, Status::OutOfGas, None)
+        },
+        Ok(InterruptKind::Step) => {
+            (true, Status::Ok, None)
+        },
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            (false, Status::Panic, None)
+        },
+    }
+}
+
 #[wasm_bindgen]
 pub fn nextStep() -> bool {
-    let (can_continue, status) = with_pvm(|pvm| {
-        match pvm.run() {
-            Ok(InterruptKind::Finished) => {
-                (false, Status::Halt)
-            },
-            Ok(InterruptKind::Trap) => {
-                (false, Status::Panic)
-            },
-            Ok(InterruptKind::Ecalli(call)) => {
-                *EXIT_ARG.lock().unwrap() = call;
-                (false, Status::Host)
-            },
-            Ok(InterruptKind::Segfault(page)) => {
-                *EXIT_ARG.lock().unwrap() = page.page_address;
-                (false, Status::Fault)
-            },
-            Ok(InterruptKind::NotEnoughGas) => {
-                (false, Status::OutOfGas)
-            },
-            Ok(InterruptKind::Step) => {
-                (true, Status::Ok)
-            },
-            Err(e) => {
-                eprintln!("Error: {:?}", e);
-                (false, Status::Panic)
-            },
-        }
-    }, (false, Status::Panic));
-    *STATUS.lock().unwrap() = status;
-    can_continue
+    nSteps(1)
 }
 
 #[wasm_bindgen]
 pub fn nSteps(steps: u32) -> bool {
-    for _ in 0..steps {
-        if !nextStep() {
-            return false;
+    if steps == 0 {
+        return true;
+    }
+
+    // Only the last executed step can change the status or the exit argument,
+    // so there's no need to publish them on every iteration.
+    let (can_continue, status, exit_arg) = with_pvm(|pvm| {
+        let mut result = (true, Status::Ok, None);
+        for _ in 0..steps {
+            result = step(pvm);
+            if !result.0 {
+                break;
+            }
         }
+        result
+    }, (false, Status::Panic, None));
+
+    if let Some(exit_arg) = exit_arg {
+        *EXIT_ARG.lock().unwrap() = exit_arg;
     }
-    return true;
+    *STATUS.lock().unwrap() = status;
+    can_continue
 }
 
 #[wasm_bindgen]
@@ -292,66 +314,6 @@ pub fn setup_memory(
 
 }
 
-fn read_u32(source: &[u8], index: usize) -> u32 {
-    let mut val = [0u8; 4];
-    val.copy_from_slice(&source[index .. index + 4]);
-    u32::from_le_bytes(val)
-}
-
-fn read_u64(source: &[u8], index: usize) -> u64 {
-    let mut val = [0u8; 8];
-    val.copy_from_slice(&source[index .. index + 8]);
-    u64::from_le_bytes(val)
-}
-
-/// Page Map is defined in JAM codec lingo as: `sequence(tuple(u32, u32, bool))`
-fn read_pages(page_map: Vec<u8>) -> Vec<Page> {
-    let mut pages = vec![];
-    let mut index = 0;
-    while index < page_map.len() {
-        let address = read_u32(&page_map, index);
-        index += 4;
-        let length = read_u32(&page_map, index);
-        index += 4;
-        let is_writable = page_map[index] > 0;
-        index += 1;
-        pages.push(Page {
-            address, length, is_writable
-        });
-    }
-    pages
-}
-
-/// Chunks is defined in JAM codec lingo as: `sequence(tuple(u32, u32, bytes))`
-fn read_chunks(chunks: Vec<u8>) -> Vec<Chunk> {
-    let mut res = vec![];
-    let mut index = 0;
-    while index < chunks.len() {
-        let address = read_u32(&chunks, index);
-        index += 4;
-        let length = read_u32(&chunks, index) as usize;
-        index += 4;
-        let data = chunks[index .. index + length].to_vec();
-        res.push(Chunk {
-            address,
-            data,
-        });
-        index += length;
-    }
-    res
-}
-
-struct Page {
-    address: u32,
-    length: u32,
-    is_writable: bool,
-}
-
-struct Chunk {
-    address: u32,
-    data: Vec<u8>,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -416,6 +378,66 @@ mod tests {
         assert_eq!(getProgramCounter(), 323);
     }
 
+    #[test]
+    fn n_steps_should_match_next_step() {
+        let _lock = TEST.lock();
+        let mut registers = vec![0u8; 13 * 8];
+        registers[7 * 8] = 9;
+
+        resetGeneric(FIB.to_vec(), registers.clone(), 10_000);
+        let mut expected_steps = 0;
+        while nextStep() {
+            expected_steps += 1;
+        }
+        let expected = (getStatus(), getProgramCounter(), getGasLeft(), getRegisters());
+
+        resetGeneric(FIB.to_vec(), registers, 10_000);
+        assert!(nSteps(expected_steps));
+        assert_eq!(getStatus(), 255);
+        assert!(!nSteps(u32::MAX));
+        assert_eq!((getStatus(), getProgramCounter(), getGasLeft(), getRegisters()), expected);
+    }
+
+    /// The pre-batching implementation of `nSteps`, which publishes the status on every step.
+    fn n_steps_per_step_locking(steps: u32) -> bool {
+        for _ in 0..steps {
+            let (can_continue, status, exit_arg) = with_pvm(step, (false, Status::Panic, None));
+            if let Some(exit_arg) = exit_arg {
+                *EXIT_ARG.lock().unwrap() = exit_arg;
+            }
+            *STATUS.lock().unwrap() = status;
+            if !can_continue {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    // Run with: cargo test --release bench_n_steps -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_n_steps() {
+        let _lock = TEST.lock();
+        const STEPS: u32 = 1_000_000;
+
+        let run = |name: &str, f: fn(u32) -> bool| {
+            resetGeneric(LOOP.to_vec(), vec![0u8; 13 * 8], i64::MAX);
+            let timestamp = std::time::Instant::now();
+            assert!(f(STEPS));
+            let elapsed = timestamp.elapsed();
+            assert_eq!(getStatus(), 255);
+            println!("{name}: {STEPS} steps in {elapsed:?}");
+            elapsed
+        };
+
+        let old = run("per-step locking", n_steps_per_step_locking);
+        let new = run("nSteps", nSteps);
+        println!("speedup: {:.2}x", old.as_secs_f64() / new.as_secs_f64());
+    }
+
+    // @loop: jump @loop
+    const LOOP: &[u8] = &[0, 0, 2, 40, 0, 1];
+
     const FIB: &[u8] = &[
         0, 0, 33, 51, 8, 1, 51, 9, 1, 40, 3, 0, 149, 119, 255, 81, 7, 12, 100, 138, 200, 152, 8, 100, 169, 40, 243, 100, 135, 51, 8, 51, 9, 1, 50, 0, 73, 147, 82, 213, 0
     ];
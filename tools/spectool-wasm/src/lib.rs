@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use wasm_bindgen::prelude::wasm_bindgen;
 
 #[wasm_bindgen]
@@ -9,16 +10,353 @@ pub fn compile_assembly(assembly: &str) -> Result<String, String> {
     Ok(serde_json::to_string(&testcase.json).unwrap())
 }
 
+/// Like [`compile_assembly`], but overrides the initial gas and registers given in the source's `pre:` directives.
+///
+/// The options are a JSON object with an optional `initial-gas` and an optional `initial-regs` object mapping register
+/// names to their values. The result is a JSON object with the `testcase` and the `warnings` about every directive of
+/// the source which was overridden.
+#[wasm_bindgen(js_name = compileAssemblyWithOptions)]
+pub fn compile_assembly_with_options(assembly: &str, options_json: &str) -> Result<String, String> {
+    let options: serde_json::Value = serde_json::from_str(options_json).map_err(|error| format!("invalid options: {error}"))?;
+    let options = options.as_object().ok_or("invalid options: expected an object")?;
+    let mut initial_gas = None;
+    let mut initial_regs = BTreeMap::new();
+    for (key, value) in options {
+        match key.as_str() {
+            "initial-gas" => {
+                initial_gas = Some(value.as_i64().ok_or("invalid options: 'initial-gas' must be an integer")?);
+            }
+            "initial-regs" => {
+                let regs = value.as_object().ok_or("invalid options: 'initial-regs' must be an object")?;
+                for (reg, value) in regs {
+                    let value = value
+                        .as_u64()
+                        .ok_or_else(|| format!("invalid options: the value of '{reg}' must be an unsigned integer"))?;
+                    initial_regs.insert(reg.clone(), value);
+                }
+            }
+            _ => return Err(format!("invalid options: unknown option '{key}'")),
+        }
+    }
+
+    let (assembly, warnings) = spectool::override_pre_directives(assembly, initial_gas, &initial_regs)?;
+    let engine = spectool::new_engine();
+    let testcase = spectool::prepare_input(&assembly, &engine, "wasm_asm", "wasm_asm", false)?;
+
+    Ok(serde_json::json!({ "testcase": testcase.json, "warnings": warnings }).to_string())
+}
+
+/// Compiles several programs at once with a single engine, in the same way as [`compile_assembly`].
+///
+/// Takes a JSON array of `{name, source}` objects and returns a JSON array with a `{name, ok, json}` object for every
+/// program which compiled and a `{name, ok, error}` one for every other; a failing program doesn't affect the others.
+#[wasm_bindgen(js_name = compileAssemblyBatch)]
+pub fn compile_assembly_batch(sources_json: &str) -> String {
+    let sources: Vec<serde_json::Value> = match serde_json::from_str(sources_json) {
+        Ok(sources) => sources,
+        Err(error) => {
+            let error = format!("invalid batch: {error}");
+            return serde_json::json!([{ "name": null, "ok": false, "error": error }]).to_string();
+        }
+    };
+
+    let engine = spectool::new_engine();
+    let results = sources
+        .iter()
+        .map(|entry| {
+            let name = entry["name"].as_str().unwrap_or("wasm_asm");
+            let Some(source) = entry["source"].as_str() else {
+                return serde_json::json!({ "name": name, "ok": false, "error": "the entry has no 'source'" });
+            };
+
+            match spectool::prepare_input(source, &engine, name, name, false) {
+                Ok(testcase) => serde_json::json!({ "name": name, "ok": true, "json": testcase.json }),
+                Err(error) => serde_json::json!({ "name": name, "ok": false, "error": error }),
+            }
+        })
+        .collect();
+
+    serde_json::Value::Array(results).to_string()
+}
+
+/// Checks a program in the same way as [`compile_assembly`], returning a JSON array of diagnostics.
+///
+/// Each diagnostic has a `line` and a `column` (both 1-based, or `null` if they can't be derived), a `severity` and
+/// a `message`; an empty array means that the program is valid.
+#[wasm_bindgen(js_name = validateAssembly)]
+pub fn validate_assembly(assembly: &str) -> String {
+    let engine = spectool::new_engine();
+    let diagnostics = match spectool::prepare_input(assembly, &engine, "wasm_asm", "wasm_asm", false) {
+        Ok(_) => Vec::new(),
+        Err(error) => vec![to_diagnostic(assembly, &error)],
+    };
+
+    serde_json::Value::Array(diagnostics).to_string()
+}
+
+/// Finds the column (1-based) at which `label` is referenced in a line, ignoring the line which defines it.
+fn find_label_reference(line: &str, label: &str) -> Option<usize> {
+    let reference = format!("@{label}");
+    line.match_indices(&reference)
+        .find(|(position, _)| {
+            let next = line[position + reference.len()..].chars().next();
+            !next.map_or(false, |ch| ch.is_alphanumeric() || ch == '_' || ch == ':')
+        })
+        .map(|(position, _)| line[..position].chars().count() + 1)
+}
+
+/// Turns an error of [`spectool::prepare_input`] into a diagnostic, locating it in the source where possible.
+///
+/// The errors are formatted as `wasm_asm:<line>: <message>`, optionally followed by the offending line, or as
+/// `wasm_asm: <message>` if the line isn't known; an undefined label is then located by its first reference.
+fn to_diagnostic(assembly: &str, error: &str) -> serde_json::Value {
+    let error = error.strip_prefix("Failed to assemble ").unwrap_or(error);
+    let error = error.strip_prefix("wasm_asm:").unwrap_or(error);
+    let error = error.lines().next().unwrap_or_default();
+    let digits = error.bytes().take_while(u8::is_ascii_digit).count();
+    let (line, message) = match error[digits..].strip_prefix(':') {
+        Some(message) if digits > 0 => (error[..digits].parse::<usize>().ok(), message.trim()),
+        _ => (None, error.trim()),
+    };
+
+    let location = match line {
+        Some(line) => {
+            let text = assembly.lines().nth(line.wrapping_sub(1)).unwrap_or_default();
+            Some((line, text.chars().take_while(|ch| ch.is_whitespace()).count() + 1))
+        }
+        None => message
+            .strip_prefix("label is not defined: \"")
+            .and_then(|label| label.strip_suffix('"'))
+            .and_then(|label| {
+                assembly
+                    .lines()
+                    .enumerate()
+                    .find_map(|(nth_line, text)| Some((nth_line + 1, find_label_reference(text, label)?)))
+            }),
+    };
+
+    serde_json::json!({
+        "line": location.map(|(line, _)| line),
+        "column": location.map(|(_, column)| column),
+        "severity": "error",
+        "message": message,
+    })
+}
+
+/// Converts a JSON testcase into the arguments of pvm-shell's `resetGenericWithMemory`.
+///
+/// Returns a JSON object with the `program`, the `registers` (13 little-endian `u64`s), the `page_map` (a `(u32, u32, bool)`
+/// tuple for every page), the memory `chunks` (a `(u32, u32, bytes)` tuple for every chunk), the `gas` and the `initial_pc`;
+/// the binary fields are arrays of bytes. If the testcase can't be converted the object only has an `error`.
+#[wasm_bindgen(js_name = testcaseToResetArgs)]
+pub fn testcase_to_reset_args(testcase_json: &str) -> String {
+    let testcase: spectool::TestcaseJson = match serde_json::from_str(testcase_json) {
+        Ok(testcase) => testcase,
+        Err(error) => return serde_json::json!({ "error": format!("invalid testcase: {error}") }).to_string(),
+    };
+
+    if testcase.program.is_empty() {
+        return serde_json::json!({ "error": "the testcase doesn't embed its program" }).to_string();
+    }
+
+    let registers: Vec<u8> = testcase.initial_regs.iter().flat_map(|value| value.to_le_bytes()).collect();
+    let mut page_map = Vec::new();
+    for page in &testcase.initial_page_map {
+        page_map.extend_from_slice(&page.address.to_le_bytes());
+        page_map.extend_from_slice(&page.length.to_le_bytes());
+        page_map.push(u8::from(page.is_writable));
+    }
+
+    let mut chunks = Vec::new();
+    for chunk in &testcase.initial_memory {
+        chunks.extend_from_slice(&chunk.address.to_le_bytes());
+        chunks.extend_from_slice(&(chunk.contents.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(&chunk.contents);
+    }
+
+    serde_json::json!({
+        "program": testcase.program,
+        "registers": registers,
+        "page_map": page_map,
+        "chunks": chunks,
+        "gas": testcase.initial_gas,
+        "initial_pc": testcase.initial_pc,
+    })
+    .to_string()
+}
+
+/// Assembles a program, returning its code and jump table (as in a testcase's `program`) without preparing a testcase.
+///
+/// The testcase directives are ignored, and the program doesn't need a `pub @main` export.
+#[wasm_bindgen]
+pub fn assemble(assembly: &str) -> Result<Vec<u8>, String> {
+    spectool::assemble_with_symbols(assembly).map(|program| program.code_and_jump_table)
+}
+
+/// Like [`compile_assembly`], but returns a JSON object with both the `testcase` and its `disassembly`.
+///
+/// The disassembly is rendered in the same style as by [`disassemble`] with the default options.
+#[wasm_bindgen(js_name = compileAssemblyFull)]
+pub fn compile_assembly_full(assembly: &str) -> Result<String, String> {
+    let engine = spectool::new_engine();
+    let options = spectool::PrepareOptions {
+        disassembly_style: spectool::DisassemblyStyle {
+            show_raw_bytes: false,
+            prefer_offset_jump_targets: false,
+            prefer_abi_reg_names: false,
+            show_gas_costs: false,
+            prefer_export_jump_targets: false,
+            prefer_hex_offsets: false,
+        },
+        ..spectool::PrepareOptions::default()
+    };
+    let testcase = spectool::prepare_input_with_options(assembly, &engine, "wasm_asm", "wasm_asm", false, options)?;
+
+    Ok(serde_json::json!({ "testcase": testcase.json, "disassembly": testcase.disassembly }).to_string())
+}
+
+/// Assembles and runs a program, returning the testcase along with the state in which the execution ended.
+///
+/// A program without a `pre: gas = ...` directive is given [`spectool::DEFAULT_GAS`], so an endless loop runs out of gas
+/// and is reported as an error instead of hanging; so is an unmet `post:` expectation.
+#[wasm_bindgen(js_name = executeAssembly)]
+pub fn execute_assembly(assembly: &str) -> Result<String, String> {
+    execute_assembly_with_gas(assembly, spectool::DEFAULT_GAS)
+}
+
+/// Like [`execute_assembly`], but gives `default_gas` to a program without a `pre: gas = ...` directive.
+#[wasm_bindgen(js_name = executeAssemblyWithGas)]
+pub fn execute_assembly_with_gas(assembly: &str, default_gas: i64) -> Result<String, String> {
+    let engine = spectool::new_engine();
+    let options = spectool::PrepareOptions {
+        default_gas: Some(default_gas),
+        ..spectool::PrepareOptions::default()
+    };
+    let testcase = spectool::prepare_input_with_options(assembly, &engine, "wasm_asm", "wasm_asm", true, options)?;
+
+    Ok(serde_json::to_string(&testcase.json).unwrap())
+}
+
+/// Disassembles a whole `.polkavm` blob, including its header and the names of its exports.
+///
+/// Bytes which don't start with the blob's magic are disassembled as a bare code and jump table instead.
+#[wasm_bindgen(js_name = disassembleBlob)]
+pub fn disassemble_blob(bytes: Vec<u8>) -> Result<String, String> {
+    spectool::disassemble_blob(bytes)
+}
+
+/// Describes the ISA for editors, as a JSON object with its `instructions` and the names of its `registers`.
+///
+/// Each instruction has its `name`, `opcode`, `syntax` (e.g. `<reg1> = <reg2> + <reg3>`), `operands`, whether it's a
+/// basic block's terminator and in which ISAs it's available; each register has its `abi` and `non-abi` name.
+#[wasm_bindgen(js_name = getInstructionList)]
+pub fn get_instruction_list() -> String {
+    let registers: Vec<_> = spectool::register_aliases()
+        .into_iter()
+        .map(|(abi, non_abi)| serde_json::json!({ "abi": abi, "non-abi": non_abi }))
+        .collect();
+
+    serde_json::json!({
+        "instructions": spectool::instruction_descriptions(),
+        "registers": registers,
+    })
+    .to_string()
+}
+
+/// How [`disassemble`] renders a program; the defaults match the disassemblies of the testcases.
+#[wasm_bindgen]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DisassemblerOptions {
+    /// Show the raw bytes of every instruction next to it.
+    pub show_raw_bytes: bool,
+    /// Name the registers `r0`, `r1`, etc. instead of by their ABI names (`ra`, `sp`, etc.).
+    pub prefer_non_abi_reg_names: bool,
+    /// Print the jump targets as program counters instead of as basic block labels.
+    pub prefer_offset_jump_targets: bool,
+    /// Decode the program as a 32-bit one instead of as a 64-bit one.
+    pub is_32_bit: bool,
+    /// Annotate every instruction and basic block with its gas cost.
+    pub show_gas_costs: bool,
+    /// Print the offsets of the instructions and the jump targets in hexadecimal instead of in decimal.
+    pub prefer_hex_offsets: bool,
+    /// Emit assembly which [`compile_assembly`] accepts, and fail unless it reassembles into the same program.
+    pub reassemblable: bool,
+}
+
+impl Default for DisassemblerOptions {
+    fn default() -> Self {
+        DisassemblerOptions {
+            show_raw_bytes: false,
+            prefer_non_abi_reg_names: true,
+            prefer_offset_jump_targets: false,
+            is_32_bit: false,
+            show_gas_costs: false,
+            prefer_hex_offsets: false,
+            reassemblable: false,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl DisassemblerOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl From<DisassemblerOptions> for spectool::DisassembleOptions {
+    fn from(options: DisassemblerOptions) -> Self {
+        spectool::DisassembleOptions {
+            is_64_bit: !options.is_32_bit,
+            show_raw_bytes: options.show_raw_bytes,
+            prefer_non_abi_reg_names: options.prefer_non_abi_reg_names,
+            prefer_offset_jump_targets: options.prefer_offset_jump_targets,
+            show_gas_costs: options.show_gas_costs,
+            // A bare code and jump table has no exports.
+            prefer_export_jump_targets: false,
+            prefer_hex_offsets: options.prefer_hex_offsets,
+            reassemblable: options.reassemblable,
+        }
+    }
+}
+
 #[wasm_bindgen]
-pub fn disassemble(bytecode: Vec<u8>) -> Result<String, String> {
-    spectool::disassemble(bytecode)
+pub fn disassemble(bytecode: Vec<u8>, options: Option<DisassemblerOptions>) -> Result<String, String> {
+    spectool::disassemble_with_options(bytecode, options.unwrap_or_default().into())
 }
 
+/// Disassembles a program into a JSON array with an object for every instruction, instead of into text.
+///
+/// Each object has the instruction's `offset`, `length`, `raw_bytes`, `mnemonic`, `operands` (each with its `kind` and
+/// `value`), the index of its `basic_block` and the static `targets` of a jump or a branch. Only the `is_32_bit` and
+/// `prefer_non_abi_reg_names` options apply.
+#[wasm_bindgen(js_name = disassembleStructured)]
+pub fn disassemble_structured(bytecode: Vec<u8>, options: Option<DisassemblerOptions>) -> Result<String, String> {
+    spectool::disassemble_structured_with_options(bytecode, options.unwrap_or_default().into())
+}
+
+/// Disassembles a program, reassembles the disassembly and returns it, or the first place where the result diverges from
+/// the original. Only the `is_32_bit` option applies.
+#[wasm_bindgen]
+pub fn roundtrip(bytecode: Vec<u8>, options: Option<DisassemblerOptions>) -> Result<String, String> {
+    spectool::roundtrip(bytecode, !options.unwrap_or_default().is_32_bit)
+}
+
+// The decoder of pvm-shell, to check that what it reads back is what `testcase_to_reset_args` encoded.
+#[cfg(test)]
+#[path = "../../../pvm-shell/src/codec.rs"]
+mod pvm_shell_codec;
+
 #[cfg(test)]
 mod tests {
     use spectool::disassemble;
 
-    use crate::compile_assembly;
+    use crate::{
+        assemble, compile_assembly, compile_assembly_batch, compile_assembly_full, compile_assembly_with_options, disassemble_blob,
+        execute_assembly, execute_assembly_with_gas, get_instruction_list, roundtrip, testcase_to_reset_args, validate_assembly,
+        DisassemblerOptions,
+    };
 
     const ASSEMBLY: &'static str = r#"
 pre: a0 = 9
@@ -77,6 +415,7 @@ pub @expected_exit:
             r#"
 pre: r0 = 4294901760
 pre: r7 = 9
+post: pc = @block5
 
 pub @main:
 	r8 = 0x1
@@ -105,6 +444,191 @@ pub @main:
         assert_eq!(result, Ok(()));
     }
 
+    #[test]
+    fn should_assemble_into_raw_bytes() {
+        let code_and_jump_table = assemble(ASSEMBLY).unwrap();
+        assert_eq!(disassemble(code_and_jump_table).unwrap(), DISASSEMBLED_CODE);
+
+        let code_and_jump_table = assemble("@start:\n    a0 = 1\n    trap\n").unwrap();
+        assert_eq!(
+            disassemble(code_and_jump_table).unwrap(),
+            "      : @0\n     0: r7 = 0x1\n     3: trap\n"
+        );
+    }
+
+    #[test]
+    fn should_report_assembler_errors() {
+        let error = assemble("pub @main:\n    jump @nowhere\n").unwrap_err();
+        assert!(error.contains("label is not defined: \"nowhere\""), "{error}");
+    }
+
+    #[test]
+    fn should_compile_assembly_with_its_disassembly() {
+        for assembly in [ASSEMBLY, "pub @main:\n    a0 = 1\n    jump @main if a0 == 2\n    trap\n"] {
+            let result: serde_json::Value = serde_json::from_str(&compile_assembly_full(assembly).unwrap()).unwrap();
+            let testcase: serde_json::Value = serde_json::from_str(&compile_assembly(assembly).unwrap()).unwrap();
+            assert_eq!(result["testcase"], testcase);
+
+            let program: Vec<u8> = serde_json::from_value(testcase["program"].clone()).unwrap();
+            assert_eq!(result["disassembly"], disassemble(program).unwrap());
+        }
+
+        let result: serde_json::Value = serde_json::from_str(&compile_assembly_full(ASSEMBLY).unwrap()).unwrap();
+        assert_eq!(result["disassembly"], DISASSEMBLED_CODE);
+    }
+
+    #[test]
+    fn should_override_the_initial_state() {
+        let options = r#"{ "initial-gas": 777, "initial-regs": { "a0": 5 } }"#;
+        let result = compile_assembly_with_options(ASSEMBLY, options).unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(result["testcase"]["initial-regs"][7], 5);
+        assert_eq!(result["testcase"]["initial-regs"][0], 0xffff0000_u64);
+        assert_eq!(result["testcase"]["initial-gas"], 777);
+        assert_eq!(
+            result["warnings"],
+            serde_json::json!(["line 2: 'pre: a0 = 9' is overridden by the options"])
+        );
+
+        let result = compile_assembly_with_options(ASSEMBLY, "{}").unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(
+            result["testcase"],
+            serde_json::from_str::<serde_json::Value>(&compile_assembly(ASSEMBLY).unwrap()).unwrap()
+        );
+        assert_eq!(result["warnings"], serde_json::json!([]));
+
+        assert!(compile_assembly_with_options(ASSEMBLY, r#"{ "initial-regs": { "x0": 1 } }"#).is_err());
+        assert!(compile_assembly_with_options(ASSEMBLY, r#"{ "gas": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn should_convert_a_testcase_into_reset_args() {
+        use crate::pvm_shell_codec::{read_chunks, read_pages, read_u64};
+
+        let testcase = compile_assembly(include_str!("../../spectool/spec/src/inst_load_i16.txt")).unwrap();
+        let json: spectool::TestcaseJson = serde_json::from_str(&testcase).unwrap();
+        assert!(!json.initial_page_map.is_empty());
+        assert!(!json.initial_memory.is_empty());
+
+        let args: serde_json::Value = serde_json::from_str(&testcase_to_reset_args(&testcase)).unwrap();
+        let bytes = |field: &str| serde_json::from_value::<Vec<u8>>(args[field].clone()).unwrap();
+        assert_eq!(bytes("program"), json.program);
+        assert_eq!(args["gas"], json.initial_gas);
+        assert_eq!(args["initial_pc"], json.initial_pc);
+
+        let registers = bytes("registers");
+        assert_eq!(registers.len(), 13 * 8);
+        for (nth, value) in json.initial_regs.iter().enumerate() {
+            assert_eq!(read_u64(&registers, nth * 8), *value);
+        }
+
+        let pages: Vec<_> = read_pages(bytes("page_map"))
+            .into_iter()
+            .map(|page| spectool::Page {
+                address: page.address,
+                length: page.length,
+                is_writable: page.is_writable,
+            })
+            .collect();
+        assert_eq!(pages, json.initial_page_map);
+
+        let chunks: Vec<_> = read_chunks(bytes("chunks"))
+            .into_iter()
+            .map(|chunk| spectool::MemoryChunk {
+                address: chunk.address,
+                contents: chunk.data,
+            })
+            .collect();
+        assert_eq!(chunks, json.initial_memory);
+
+        let args: serde_json::Value = serde_json::from_str(&testcase_to_reset_args("{")).unwrap();
+        assert!(args["error"].as_str().unwrap().starts_with("invalid testcase: "));
+    }
+
+    #[test]
+    fn should_compile_a_batch() {
+        let sources = serde_json::json!([
+            { "name": "fib", "source": ASSEMBLY },
+            { "name": "broken", "source": "pub @main:\n    this is not an instruction\n" },
+            { "name": "trap", "source": "pub @main:\n    trap\n" },
+        ]);
+        let results: serde_json::Value = serde_json::from_str(&compile_assembly_batch(&sources.to_string())).unwrap();
+
+        let results = results.as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["name"], "fib");
+        assert_eq!(results[0]["ok"], true);
+        assert_eq!(results[0]["json"]["name"], "fib");
+        assert_eq!(results[0]["json"]["initial-regs"][7], 9);
+        assert_eq!(results[1]["name"], "broken");
+        assert_eq!(results[1]["ok"], false);
+        assert!(results[1]["error"].as_str().unwrap().contains("broken:2: cannot parse line 2"));
+        assert!(results[1].get("json").is_none());
+        assert_eq!(results[2]["ok"], true);
+        assert_eq!(results[2]["json"]["name"], "trap");
+
+        let results: serde_json::Value = serde_json::from_str(&compile_assembly_batch("{")).unwrap();
+        assert_eq!(results[0]["ok"], false);
+    }
+
+    #[test]
+    fn should_report_diagnostics() {
+        assert_eq!(validate_assembly(ASSEMBLY), "[]");
+
+        let diagnostics: serde_json::Value = serde_json::from_str(&validate_assembly("pub @main:\n    jump @nowhere\n")).unwrap();
+        assert_eq!(
+            diagnostics,
+            serde_json::json!([{
+                "line": 2,
+                "column": 10,
+                "severity": "error",
+                "message": "label is not defined: \"nowhere\"",
+            }])
+        );
+
+        let diagnostics: serde_json::Value =
+            serde_json::from_str(&validate_assembly("pre: a0 = 1\npre: a1 = ?\n\npub @main:\n    trap\n")).unwrap();
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["line"], 2);
+        assert_eq!(diagnostics[0]["column"], 1);
+        assert_eq!(diagnostics[0]["message"], "invalid 'pre' / 'post' directive: failed to parse rhs");
+
+        let diagnostics: serde_json::Value =
+            serde_json::from_str(&validate_assembly("pub @main:\n    a0 = 1\n    not an instruction\n")).unwrap();
+        assert_eq!(diagnostics[0]["line"], 3);
+        assert_eq!(diagnostics[0]["column"], 5);
+    }
+
+    #[test]
+    fn should_execute_assembly() {
+        let result = execute_assembly(&ASSEMBLY).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(json["expected-regs"][7], 55);
+        assert_eq!(json["expected-status"], "halt");
+    }
+
+    #[test]
+    fn should_report_unmet_expectations() {
+        let result = execute_assembly(&format!("{ASSEMBLY}\npost: a0 = 56\n"));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_run_out_of_gas_instead_of_hanging() {
+        let assembly = "pub @main:\n    jump @main\n";
+        let result = execute_assembly_with_gas(assembly, 100).unwrap_err();
+        assert!(result.contains("ran out of the gas given by the default gas of 100"), "{result}");
+
+        let result = execute_assembly_with_gas(&format!("{assembly}\npost: status = out-of-gas\n"), 100).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(json["initial-gas"], 100);
+        assert_eq!(json["expected-status"], "out-of-gas");
+    }
+
     #[test]
     fn should_disassemble_code() {
         let engine = spectool::new_engine();
@@ -115,6 +639,173 @@ pub @main:
         assert_eq!(result, DISASSEMBLED_CODE);
     }
 
+    #[test]
+    fn should_disassemble_whole_blobs() {
+        let program = spectool::assemble_with_symbols(ASSEMBLY).unwrap();
+
+        let from_blob = disassemble_blob(program.blob).unwrap();
+        assert!(from_blob.contains("// Code size = "), "{from_blob}");
+        assert!(from_blob.contains("[export #0: main]"), "{from_blob}");
+        assert!(from_blob.contains("[export #1: expected_exit]"), "{from_blob}");
+        assert!(from_blob.contains("    24: r7 = r8\n"), "{from_blob}");
+
+        let from_raw = disassemble_blob(program.code_and_jump_table).unwrap();
+        assert_eq!(from_raw, DISASSEMBLED_CODE);
+
+        assert!(disassemble_blob(b"PVM\0broken".to_vec()).is_err());
+    }
+
+    #[test]
+    fn should_disassemble_code_with_options() {
+        let engine = spectool::new_engine();
+        let result = spectool::prepare_input(ASSEMBLY, &engine, "wasm_asm", "wasm_asm", false).unwrap();
+        let code_and_jump_table = result.json.program;
+        let disassemble_with = |options| crate::disassemble(code_and_jump_table.clone(), options).unwrap();
+
+        assert_eq!(disassemble_with(None), DISASSEMBLED_CODE);
+        assert_eq!(disassemble_with(Some(DisassemblerOptions::new())), DISASSEMBLED_CODE);
+
+        let with_raw_bytes = disassemble_with(Some(DisassemblerOptions {
+            show_raw_bytes: true,
+            ..DisassemblerOptions::new()
+        }));
+        assert!(with_raw_bytes.contains("     8: 00 "), "{with_raw_bytes}");
+        assert_eq!(with_raw_bytes.lines().count(), DISASSEMBLED_CODE.lines().count());
+
+        let with_abi_names = disassemble_with(Some(DisassemblerOptions {
+            prefer_non_abi_reg_names: false,
+            ..DisassemblerOptions::new()
+        }));
+        assert!(with_abi_names.contains("    24: a0 = a1\n"), "{with_abi_names}");
+        assert!(with_abi_names.contains("    31: jump [ra + 0]\n"), "{with_abi_names}");
+
+        let with_offsets = disassemble_with(Some(DisassemblerOptions {
+            prefer_offset_jump_targets: true,
+            ..DisassemblerOptions::new()
+        }));
+        assert_eq!(
+            with_offsets,
+            DISASSEMBLED_CODE.replace("jump @2", "jump 9").replace("jump @4", "jump 24")
+        );
+
+        let with_gas_costs = disassemble_with(Some(DisassemblerOptions {
+            show_gas_costs: true,
+            ..DisassemblerOptions::new()
+        }));
+        let annotated_lines = with_gas_costs.lines().filter(|line| line.contains("// gas: ")).count();
+        assert_eq!(
+            annotated_lines,
+            DISASSEMBLED_CODE.lines().filter(|line| !line.contains(": @")).count()
+        );
+        assert!(with_gas_costs.contains("// gas of @0: "), "{with_gas_costs}");
+
+        let with_hex_offsets = disassemble_with(Some(DisassemblerOptions {
+            prefer_offset_jump_targets: true,
+            prefer_hex_offsets: true,
+            ..DisassemblerOptions::new()
+        }));
+        assert_eq!(with_hex_offsets, DISASSEMBLED_CODE_WITH_HEX_OFFSETS);
+
+        let as_32_bit = crate::disassemble(
+            code_and_jump_table,
+            Some(DisassemblerOptions {
+                is_32_bit: true,
+                ..DisassemblerOptions::new()
+            }),
+        )
+        .unwrap();
+        // The 64-bit additions aren't part of the 32-bit instruction set.
+        assert!(as_32_bit.contains("     0: r8 = 0x1\n"), "{as_32_bit}");
+        assert!(!as_32_bit.contains("r7 = r7 + 0xffffffffffffffff"), "{as_32_bit}");
+    }
+
+    #[test]
+    fn should_roundtrip_code() {
+        let code_and_jump_table = assemble(ASSEMBLY).unwrap();
+        let assembly = roundtrip(code_and_jump_table.clone(), None).unwrap();
+        assert_eq!(assemble(&assembly).unwrap(), code_and_jump_table);
+
+        let reassemblable = crate::disassemble(
+            code_and_jump_table,
+            Some(DisassemblerOptions {
+                reassemblable: true,
+                ..DisassemblerOptions::new()
+            }),
+        )
+        .unwrap();
+        assert_eq!(reassemblable, assembly);
+
+        assert!(roundtrip(b"PVM\0broken".to_vec(), None).is_err());
+    }
+
+    #[test]
+    fn should_disassemble_code_into_json() {
+        let code_and_jump_table = assemble(ASSEMBLY).unwrap();
+        let result = crate::disassemble_structured(code_and_jump_table.clone(), None).unwrap();
+        let instructions: Vec<serde_json::Value> = serde_json::from_str(&result).unwrap();
+
+        // The offsets must be the same as in the textual disassembly.
+        let expected_offsets: Vec<u64> = DISASSEMBLED_CODE
+            .lines()
+            .filter_map(|line| line.split_once(": ").unwrap().0.trim().parse().ok())
+            .collect();
+        let offsets: Vec<u64> = instructions
+            .iter()
+            .map(|instruction| instruction["offset"].as_u64().unwrap())
+            .collect();
+        assert_eq!(offsets, expected_offsets);
+
+        let branch = &instructions[5];
+        assert_eq!(branch["mnemonic"], "branch_eq_imm");
+        assert_eq!(branch["basic_block"], 2);
+        assert_eq!(branch["targets"], serde_json::json!([24]));
+        assert_eq!(branch["operands"][0], serde_json::json!({ "kind": "reg", "value": "r7" }));
+
+        let with_abi_names = crate::disassemble_structured(
+            code_and_jump_table,
+            Some(DisassemblerOptions {
+                prefer_non_abi_reg_names: false,
+                ..DisassemblerOptions::new()
+            }),
+        )
+        .unwrap();
+        assert!(with_abi_names.contains(r#"{"kind":"reg","value":"a0"}"#), "{with_abi_names}");
+    }
+
+    #[test]
+    fn should_list_the_instructions() {
+        let list: serde_json::Value = serde_json::from_str(&get_instruction_list()).unwrap();
+        let instructions = list["instructions"].as_array().unwrap();
+        let find = |name: &str| {
+            instructions
+                .iter()
+                .find(|instruction| instruction["name"] == name)
+                .unwrap_or_else(|| panic!("missing '{name}'"))
+        };
+
+        let add = find("add_64");
+        assert_eq!(add["syntax"], "<reg1> = <reg2> + <reg3>");
+        assert_eq!(add["operands"], serde_json::json!(["reg", "reg", "reg"]));
+        assert_eq!(add["is-terminator"], false);
+        assert_eq!(add["is-32-bit"], false);
+        assert_eq!(add["is-64-bit"], true);
+        assert_eq!(find("add_32")["is-32-bit"], true);
+
+        let jump = find("jump");
+        assert_eq!(jump["syntax"], "jump <offset>");
+        assert_eq!(jump["operands"], serde_json::json!(["offset"]));
+        assert_eq!(jump["is-terminator"], true);
+
+        assert_eq!(find("ecalli")["syntax"], "ecalli <imm1>");
+        assert_eq!(find("ecalli")["is-terminator"], false);
+        assert_eq!(find("trap")["is-terminator"], true);
+        assert_eq!(find("trap")["operands"], serde_json::json!([]));
+
+        let registers = list["registers"].as_array().unwrap();
+        assert_eq!(registers.len(), 13);
+        assert!(registers.contains(&serde_json::json!({ "abi": "a0", "non-abi": "r7" })));
+    }
+
     const DISASSEMBLED_CODE: &str = r#"      : @0
      0: r8 = 0x1
      3: r9 = 0x1
@@ -136,5 +827,28 @@ pub @main:
     30: fallthrough
       : @5
     31: jump [r0 + 0]
+"#;
+
+    const DISASSEMBLED_CODE_WITH_HEX_OFFSETS: &str = r#"      : @0
+0x0000: r8 = 0x1
+0x0003: r9 = 0x1
+0x0006: jump 0x0009
+      : @1
+0x0008: trap
+      : @2
+0x0009: r7 = r7 + 0xffffffffffffffff
+0x000c: jump 0x0018 if r7 == 0
+      : @3
+0x000f: r10 = r8
+0x0011: r8 = r8 + r9
+0x0014: r9 = r10
+0x0016: jump 0x0009
+      : @4
+0x0018: r7 = r8
+0x001a: r8 = 0
+0x001c: r9 = 0
+0x001e: fallthrough
+      : @5
+0x001f: jump [r0 + 0]
 "#;
 }
@@ -0,0 +1,226 @@
+//! The control-flow graph of a program, rendered into Graphviz's dot format to visualize the testcases.
+
+use core::fmt::Write;
+use polkavm::program::{Instruction, ISA64_V1};
+use polkavm::{ProgramBlob, ProgramParts};
+use polkavm_common::program::InstructionFormat;
+
+/// A basic block of a program, along with its disassembly.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BasicBlock {
+    /// The program counter of the first instruction of the block.
+    pub start: u32,
+    /// The program counter and the disassembly of every instruction of the block.
+    pub instructions: Vec<(u32, String)>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EdgeKind {
+    /// The execution continues with the next block, either unconditionally or because a branch wasn't taken.
+    Fallthrough,
+    /// A conditional branch which was taken.
+    Branch,
+    /// An unconditional jump to a static target.
+    Jump,
+    /// A jump whose target is only known at runtime, e.g. a `ret`.
+    IndirectJump,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Edge {
+    /// The start of the block from which the edge leaves.
+    pub from: u32,
+    /// The start of the block which the edge enters; `None` for indirect jumps.
+    pub to: Option<u32>,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControlFlowGraph {
+    /// Every basic block, in the order of the code.
+    pub blocks: Vec<BasicBlock>,
+    /// Every edge, ordered by the block from which it leaves.
+    pub edges: Vec<Edge>,
+}
+
+/// Returns the static target of a jump or a branch.
+pub(crate) fn jump_target(instruction: Instruction) -> Option<u32> {
+    match instruction {
+        Instruction::jump(target)
+        | Instruction::load_imm_and_jump(_, _, target)
+        | Instruction::branch_eq_imm(_, _, target)
+        | Instruction::branch_not_eq_imm(_, _, target)
+        | Instruction::branch_less_unsigned_imm(_, _, target)
+        | Instruction::branch_less_signed_imm(_, _, target)
+        | Instruction::branch_greater_or_equal_unsigned_imm(_, _, target)
+        | Instruction::branch_greater_or_equal_signed_imm(_, _, target)
+        | Instruction::branch_less_or_equal_signed_imm(_, _, target)
+        | Instruction::branch_less_or_equal_unsigned_imm(_, _, target)
+        | Instruction::branch_greater_signed_imm(_, _, target)
+        | Instruction::branch_greater_unsigned_imm(_, _, target)
+        | Instruction::branch_eq(_, _, target)
+        | Instruction::branch_not_eq(_, _, target)
+        | Instruction::branch_less_unsigned(_, _, target)
+        | Instruction::branch_less_signed(_, _, target)
+        | Instruction::branch_greater_or_equal_unsigned(_, _, target)
+        | Instruction::branch_greater_or_equal_signed(_, _, target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Builds the control-flow graph of a program (its code and jump table, as stored in a testcase's `program`).
+pub fn control_flow_graph(program: &[u8]) -> Result<ControlFlowGraph, String> {
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = program.to_vec().into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts).map_err(|error| error.to_string())?;
+
+    let jump_target_formatter = |target: u32, fmt: &mut core::fmt::Formatter| write!(fmt, "@{target}");
+    let mut format = InstructionFormat::default();
+    format.prefer_non_abi_reg_names = true;
+    format.prefer_unaliased = true;
+    format.jump_target_formatter = Some(&jump_target_formatter);
+    format.is_64_bit = true;
+
+    let code_length = blob.code().len();
+    let mut blocks = Vec::new();
+    let mut edges = Vec::new();
+    let mut current_block: Option<BasicBlock> = None;
+    for instruction in blob
+        .instructions(ISA64_V1)
+        .take_while(|instruction| (instruction.offset.0 as usize) < code_length)
+    {
+        let block = current_block.get_or_insert_with(|| BasicBlock {
+            start: instruction.offset.0,
+            instructions: Vec::new(),
+        });
+        block
+            .instructions
+            .push((instruction.offset.0, instruction.kind.display(&format).to_string()));
+
+        if !instruction.kind.starts_new_basic_block() {
+            continue;
+        }
+
+        let opcode = instruction.kind.opcode();
+        let next_block = ((instruction.next_offset.0 as usize) < code_length).then_some(instruction.next_offset.0);
+        match jump_target(instruction.kind) {
+            Some(target) => {
+                let kind = if opcode.can_fallthrough() {
+                    EdgeKind::Branch
+                } else {
+                    EdgeKind::Jump
+                };
+                edges.push(Edge {
+                    from: block.start,
+                    to: Some(target),
+                    kind,
+                });
+            }
+            None if matches!(
+                instruction.kind,
+                Instruction::jump_indirect(..) | Instruction::load_imm_and_jump_indirect(..)
+            ) =>
+            {
+                edges.push(Edge {
+                    from: block.start,
+                    to: None,
+                    kind: EdgeKind::IndirectJump,
+                });
+            }
+            None => {}
+        }
+
+        if opcode.can_fallthrough() {
+            if let Some(next_block) = next_block {
+                edges.push(Edge {
+                    from: block.start,
+                    to: Some(next_block),
+                    kind: EdgeKind::Fallthrough,
+                });
+            }
+        }
+
+        blocks.extend(current_block.take());
+    }
+
+    // The last block can end without a terminator, in which case it falls off the end of the code.
+    blocks.extend(current_block);
+    Ok(ControlFlowGraph { blocks, edges })
+}
+
+/// Escapes a string to be used inside a quoted dot identifier.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl ControlFlowGraph {
+    /// Renders the graph into Graphviz's dot format; every block is a node labeled with its disassembly.
+    ///
+    /// Each indirect jump gets its own node, since its targets are unknown.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut output = String::new();
+        writeln!(&mut output, "digraph \"{}\" {{", escape(name)).unwrap();
+        writeln!(&mut output, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in &self.blocks {
+            let mut label = format!("@{}:\\l", block.start);
+            for (pc, instruction) in &block.instructions {
+                write!(&mut label, "{pc:>6}: {}\\l", escape(instruction)).unwrap();
+            }
+            writeln!(&mut output, "    block_{} [label=\"{label}\"];", block.start).unwrap();
+        }
+
+        for edge in &self.edges {
+            let (to, attributes) = match (edge.to, edge.kind) {
+                (Some(to), EdgeKind::Fallthrough) => (format!("block_{to}"), "style=dashed"),
+                (Some(to), EdgeKind::Branch) => (format!("block_{to}"), "label=\"taken\""),
+                (Some(to), _) => (format!("block_{to}"), "style=bold"),
+                (None, _) => {
+                    let node = format!("block_{}_indirect", edge.from);
+                    writeln!(&mut output, "    {node} [shape=diamond, label=\"indirect\"];").unwrap();
+                    (node, "style=dotted")
+                }
+            };
+            writeln!(&mut output, "    block_{} -> {to} [{attributes}];", edge.from).unwrap();
+        }
+
+        writeln!(&mut output, "}}").unwrap();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fibonacci_edges() {
+        let program = crate::assemble_with_symbols(include_str!("../spec/src/fib.txt")).unwrap();
+        let graph = control_flow_graph(&program.code_and_jump_table).unwrap();
+
+        let label = |name: &str| program.local_labels[name];
+        let starts: Vec<u32> = graph.blocks.iter().map(|block| block.start).collect();
+        let (main, trap, loop_body) = (program.exports["main"], starts[1], starts[3]);
+        let expected_exit = program.exports["expected_exit"];
+        assert_eq!(starts, [main, trap, label("loop"), loop_body, label("end"), expected_exit]);
+        assert_eq!(graph.blocks[1].instructions.len(), 1);
+
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            graph.edges,
+            [
+                edge(main, Some(label("loop")), EdgeKind::Jump),
+                edge(label("loop"), Some(label("end")), EdgeKind::Branch),
+                edge(label("loop"), Some(loop_body), EdgeKind::Fallthrough),
+                edge(loop_body, Some(label("loop")), EdgeKind::Jump),
+                edge(label("end"), Some(expected_exit), EdgeKind::Fallthrough),
+                edge(expected_exit, None, EdgeKind::IndirectJump),
+            ]
+        );
+
+        let dot = graph.to_dot("fib");
+        assert!(dot.starts_with("digraph \"fib\" {\n"));
+        assert!(dot.contains(&format!("block_{main} -> block_{} [style=bold];", label("loop"))));
+        assert!(dot.contains(&format!("block_{expected_exit} -> block_{expected_exit}_indirect [style=dotted];")));
+    }
+}
@@ -1,14 +1,236 @@
 #![allow(clippy::print_stderr)]
 
-use polkavm::{program::ISA64_V1, Engine, InterruptKind, Module, ModuleConfig, ProgramBlob, ProgramCounter, ProgramParts, Reg};
-use polkavm_common::assembler::assemble;
+use core::fmt::Write;
+use polkavm::{
+    program::{Instruction, InstructionSet, Opcode, ParsedInstruction, ISA32_V1, ISA64_V1},
+    Engine, InterruptKind, Module, ModuleConfig, ProgramBlob, ProgramCounter, ProgramParts, RawInstance, Reg, Segfault,
+};
+use polkavm_common::assembler::assemble_with_labels;
+use polkavm_common::program::InstructionFormat;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+pub mod cfg;
+pub mod scale;
+pub mod schema;
+pub mod sha256;
 
 pub struct Testcase {
     pub disassembly: String,
     pub json: TestcaseJson,
+    /// A step-by-step trace of the execution, if requested through [`PrepareOptions::record_trace`].
+    pub trace: Option<String>,
+    /// The gas cost of every basic block of the program.
+    pub block_costs: Vec<BlockCost>,
+    /// The label selected as the entry point through an `entry: @label` directive, if any.
+    pub entry_point: Option<String>,
+    /// The stack size requested through a `stack-size: <bytes>` directive, if any.
+    pub stack_size: Option<u32>,
+    /// Whether the program is a 64-bit one; see the `modes:` directive.
+    pub is_64_bit: bool,
+    /// The category set through a `category: <name>` directive, if any.
+    pub category: Option<String>,
+    /// The program counter at which the execution is asserted to run out of gas through `post: out-of-gas-at = ...`, if any.
+    pub out_of_gas_at: Option<u32>,
+}
+
+/// The gas cost of a single basic block of a program.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockCost {
+    /// The program counter of the first instruction of the block.
+    pub start: u32,
+    pub instruction_count: usize,
+    /// The gas which is charged every time the block is entered.
+    pub cost: i64,
+}
+
+/// Calculates the gas cost of every basic block of a program, using the same cost model as the interpreter.
+pub fn basic_block_costs(engine: &Engine, blob: &ProgramBlob) -> Result<Vec<BlockCost>, String> {
+    let mut module_config = ModuleConfig::default();
+    module_config.set_strict(true);
+    module_config.set_gas_metering(Some(polkavm::GasMeteringKind::Sync));
+    let module = Module::from_blob(engine, &module_config, blob.clone()).map_err(to_string)?;
+    Ok(block_costs_of_module(&module, blob))
+}
+
+/// Decodes the program's instructions with the instruction set matching its bitness.
+fn blob_instructions(blob: &ProgramBlob) -> Vec<ParsedInstruction> {
+    if blob.is_64_bit() {
+        blob.instructions(ISA64_V1).collect()
+    } else {
+        blob.instructions(ISA32_V1).collect()
+    }
+}
+
+fn block_costs_of_module(module: &Module, blob: &ProgramBlob) -> Vec<BlockCost> {
+    let mut blocks = Vec::new();
+    let mut current_block: Option<(u32, usize)> = None;
+    for instruction in blob_instructions(blob) {
+        let (start, instruction_count) = current_block.get_or_insert((instruction.offset.0, 0));
+        *instruction_count += 1;
+        if instruction.kind.opcode().starts_new_basic_block() {
+            blocks.push((*start, *instruction_count));
+            current_block = None;
+        }
+    }
+
+    blocks.extend(current_block);
+    blocks
+        .into_iter()
+        .map(|(start, instruction_count)| BlockCost {
+            start,
+            instruction_count,
+            cost: module.calculate_gas_cost_for(ProgramCounter(start)).unwrap_or(0),
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct PrepareOptions<'a> {
+    /// If given the testcase is also executed with this engine (without step tracing)
+    /// and the results of both runs must be identical.
+    pub cross_check_engine: Option<&'a Engine>,
+    /// Whether to record a step-by-step trace of the execution.
+    pub record_trace: bool,
+    /// The maximum number of zero bytes between two non-zero runs of memory which are still emitted as a single chunk.
+    pub max_chunk_gap: usize,
+    /// The directory against which the paths of `pre: memory[...] = file "<path>"` are resolved;
+    /// the current directory is used if not given.
+    pub source_directory: Option<&'a Path>,
+    /// How the disassembly of the testcase is rendered.
+    pub disassembly_style: DisassemblyStyle,
+    /// The gas given to a testcase without a `pre: gas = ...` directive; [`DEFAULT_GAS`] if not given.
+    pub default_gas: Option<i64>,
+    /// Whether every register changed by the execution must be asserted or waived, as if the source had a `strict-post` directive.
+    pub strict_post: bool,
+    /// The gas model of a testcase without a `gas-model: ...` directive; also gives the cost of a `gas-model: flat` one.
+    pub gas_model: GasModel,
+}
+
+/// The gas given to a testcase without a `pre: gas = ...` directive, unless overridden through [`PrepareOptions::default_gas`].
+pub const DEFAULT_GAS: i64 = 10000;
+
+/// The cost of every instruction under the flat gas model, unless given explicitly.
+pub const DEFAULT_FLAT_GAS_COST: u32 = 1;
+
+/// How the gas consumed by a testcase is calculated.
+///
+/// Under [`GasModel::Polkavm`] the gas is charged in the same way as by the interpreter with synchronous metering: the
+/// cost of a whole basic block is charged when it's entered, so the execution runs out of gas at the start of a block
+/// even if it would've had enough for some of its instructions. (Asynchronous metering is never used, since with it the
+/// point at which the execution runs out of gas isn't precise.)
+///
+/// Under [`GasModel::Flat`] every instruction costs the same, and is charged right before it's executed; this implies
+/// per-instruction accounting, so it's layered over the interpreter by disabling its own metering and counting the
+/// executed steps instead. The instruction which doesn't have enough gas isn't executed nor charged.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum GasModel {
+    #[default]
+    Polkavm,
+    #[serde(rename_all = "kebab-case")]
+    Flat { cost_per_instruction: u32 },
+}
+
+impl GasModel {
+    /// The metering of the interpreter over which the model is layered.
+    fn metering(self) -> Option<polkavm::GasMeteringKind> {
+        match self {
+            GasModel::Polkavm => Some(polkavm::GasMeteringKind::Sync),
+            GasModel::Flat { .. } => None,
+        }
+    }
+
+    /// Charges the gas for the instruction which is about to be executed; returns `false` if there isn't enough of it.
+    fn charge_step(self, instance: &mut RawInstance) -> bool {
+        let GasModel::Flat { cost_per_instruction } = self else {
+            return true;
+        };
+
+        let cost = i64::from(cost_per_instruction);
+        if instance.gas() < cost {
+            return false;
+        }
+
+        instance.set_gas(instance.gas() - cost);
+        true
+    }
+
+    /// Recalculates the costs of the basic blocks (as charged by the interpreter) under this model.
+    fn apply_to_block_costs(self, blocks: Vec<BlockCost>) -> Vec<BlockCost> {
+        match self {
+            GasModel::Polkavm => blocks,
+            GasModel::Flat { cost_per_instruction } => blocks
+                .into_iter()
+                .map(|block| BlockCost {
+                    cost: block.instruction_count as i64 * i64::from(cost_per_instruction),
+                    ..block
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_gas_model(text: &str, default: GasModel) -> Result<GasModel, String> {
+    let text = text.trim();
+    if text == "polkavm" {
+        return Ok(GasModel::Polkavm);
+    }
+
+    let Some(cost) = text.strip_prefix("flat") else {
+        return Err(format!(
+            "invalid 'gas-model' directive: unknown model '{text}'; expected 'polkavm' or 'flat'"
+        ));
+    };
+
+    let cost_per_instruction = if cost.trim().is_empty() {
+        match default {
+            GasModel::Flat { cost_per_instruction } => cost_per_instruction,
+            GasModel::Polkavm => DEFAULT_FLAT_GAS_COST,
+        }
+    } else {
+        cost.trim()
+            .strip_prefix(',')
+            .and_then(|cost| cost.split_once('='))
+            .filter(|(lhs, _)| lhs.trim() == "cost")
+            .and_then(|(_, rhs)| rhs.trim().parse().ok())
+            .ok_or_else(|| format!("invalid 'gas-model' directive: expected 'flat' or 'flat, cost = <gas>', got '{text}'"))?
+    };
+
+    Ok(GasModel::Flat { cost_per_instruction })
+}
+
+/// How the disassembly of a testcase is rendered.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DisassemblyStyle {
+    /// Whether to show the raw bytes of each instruction.
+    pub show_raw_bytes: bool,
+    /// Whether to show jump targets as offsets instead of labels.
+    pub prefer_offset_jump_targets: bool,
+    /// Whether to name the registers after their ABI role (e.g. `a0`) instead of their index (e.g. `r7`).
+    pub prefer_abi_reg_names: bool,
+    /// Whether to annotate every instruction and basic block with its gas cost under the testcase's gas model.
+    pub show_gas_costs: bool,
+    /// Whether to show the jump targets which coincide with an exported label by its name (e.g. `@expected_exit`).
+    pub prefer_export_jump_targets: bool,
+    /// Whether to show the offsets of the instructions and the jump targets in hexadecimal instead of in decimal.
+    pub prefer_hex_offsets: bool,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+impl Default for DisassemblyStyle {
+    fn default() -> Self {
+        DisassemblyStyle {
+            show_raw_bytes: true,
+            prefer_offset_jump_targets: true,
+            prefer_abi_reg_names: false,
+            show_gas_costs: false,
+            prefer_export_jump_targets: false,
+            prefer_hex_offsets: false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Page {
     pub address: u32,
@@ -16,23 +238,37 @@ pub struct Page {
     pub is_writable: bool,
 }
 
-#[derive(PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct MemoryChunk {
     pub address: u32,
     pub contents: Vec<u8>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct TestcaseJson {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     pub initial_regs: [u64; 13],
     pub initial_pc: u32,
     pub initial_page_map: Vec<Page>,
     pub initial_memory: Vec<MemoryChunk>,
     pub initial_gas: i64,
+    /// The program itself; empty if it's referenced through `program_ref` instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub program: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_ref: Option<ProgramRef>,
+    /// The algorithm with which `program_hash` was calculated; one of [`PROGRAM_HASH_ALGORITHMS`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_hash_algorithm: Option<String>,
+    /// The hex-encoded hash of the program's bytes, to detect a divergence from any external copies of the program.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_hash: Option<String>,
     pub expected_status: String,
     pub expected_regs: Vec<u64>,
     pub expected_pc: u32,
@@ -40,6 +276,228 @@ pub struct TestcaseJson {
     pub expected_gas: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expected_page_fault_address: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_page_fault_access: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_exit_code: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_host_calls: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_interrupts: Option<Vec<Interrupt>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_step_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub faults_handled: Option<Vec<HandledFault>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoints: Option<Vec<Checkpoint>>,
+    /// The gas model under which `expected_gas` was calculated; [`GasModel::Polkavm`] if missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_model: Option<GasModel>,
+    /// The version of the format of the testcase; see [`FORMAT_VERSION`]. Missing from the testcases written before it was introduced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<u32>,
+    /// The name and version of the tool which wrote the testcase; see [`GENERATOR`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+}
+
+/// The version of the format of the testcases written by this tool; bumped whenever the meaning of a field changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The `generator` of the testcases written by this tool.
+pub const GENERATOR: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// Checks the `format-version` of a testcase against [`FORMAT_VERSION`].
+///
+/// A newer version is an error since the testcase might mean something else than what it appears to, while an older one
+/// (or none at all) only results in a warning, since it can be brought up to date with `spectool migrate`.
+pub fn check_format_version(testcase: &TestcaseJson) -> Result<Option<String>, String> {
+    match testcase.format_version {
+        Some(version) if version > FORMAT_VERSION => Err(format!(
+            "format-version: {version} is newer than the supported version {FORMAT_VERSION}; update spectool to read it"
+        )),
+        Some(version) if version == FORMAT_VERSION => Ok(None),
+        Some(version) => Ok(Some(format!(
+            "format-version: {version} is older than the current version {FORMAT_VERSION}; update it with 'spectool migrate'"
+        ))),
+        None => Ok(Some(format!(
+            "format-version: missing, so the testcase predates version {FORMAT_VERSION}; update it with 'spectool migrate'"
+        ))),
+    }
+}
+
+/// Brings a testcase written with an older format version up to date; returns whether anything changed.
+pub fn migrate_testcase(testcase: &mut TestcaseJson) -> Result<bool, String> {
+    check_format_version(testcase)?;
+    if testcase.format_version == Some(FORMAT_VERSION) && testcase.generator.is_some() {
+        return Ok(false);
+    }
+
+    // Version 1 only added the `format-version` and `generator` fields themselves.
+    testcase.format_version = Some(FORMAT_VERSION);
+    if testcase.generator.is_none() {
+        testcase.generator = Some(GENERATOR.to_owned());
+    }
+
+    Ok(true)
+}
+
+/// Serializes a testcase in the canonical style in which every testcase is written.
+///
+/// The fields are always in the same order (the one of [`TestcaseJson`]), none of them is a float, and the output is
+/// indented with two spaces and ends with a newline, so regenerating a testcase only changes the lines which differ.
+pub fn to_canonical_json(testcase: &TestcaseJson) -> String {
+    let mut payload = serde_json::to_string_pretty(testcase).unwrap();
+    payload.push('\n');
+    payload
+}
+
+/// A reference to a program which is shared between testcases instead of being embedded in each of them.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProgramRef {
+    /// The BLAKE3 hash of the program, as a hex string.
+    pub hash: String,
+    /// The path of the program, relative to the directory of the testcase.
+    pub path: String,
+}
+
+/// The algorithms which can be used for the `program-hash` of a testcase; the first one is used for new testcases.
+pub const PROGRAM_HASH_ALGORITHMS: &[&str] = &["sha256"];
+
+/// Calculates the hex-encoded hash of `program` with the given algorithm; returns `None` if the algorithm is unknown.
+pub fn program_hash(algorithm: &str, program: &[u8]) -> Option<String> {
+    match algorithm {
+        "sha256" => Some(sha256::sha256_hex(program)),
+        _ => None,
+    }
+}
+
+/// Checks that the `program-hash` of a testcase (if any) matches its program.
+pub fn verify_program_hash(testcase: &TestcaseJson) -> Result<(), String> {
+    match (&testcase.program_hash_algorithm, &testcase.program_hash) {
+        (None, None) => Ok(()),
+        (Some(algorithm), Some(expected_hash)) => match program_hash(algorithm, &testcase.program) {
+            Some(hash) if hash == *expected_hash => Ok(()),
+            Some(hash) => Err(format!(
+                "program-hash: doesn't match the program (expected {expected_hash}, found {hash})"
+            )),
+            None => Err(format!("program-hash-algorithm: unknown algorithm '{algorithm}'")),
+        },
+        _ => Err("program-hash: must be present if and only if 'program-hash-algorithm' is present".to_owned()),
+    }
+}
+
+/// Returns the reference under which `program` is shared, namely `blobs/<hash>.bin`.
+pub fn program_ref_for(program: &[u8]) -> ProgramRef {
+    let mut hasher = polkavm_common::hasher::Hasher::new();
+    hasher.update(program);
+    let hash = hasher.finalize().to_string();
+    ProgramRef {
+        path: format!("blobs/{hash}.bin"),
+        hash,
+    }
+}
+
+/// Replaces the `program_ref` of a testcase with the program it references, which is loaded relative to `base`.
+pub fn resolve_program_ref(testcase: &mut TestcaseJson, base: &Path) -> Result<(), String> {
+    let Some(program_ref) = testcase.program_ref.take() else {
+        return Ok(());
+    };
+
+    if !testcase.program.is_empty() {
+        return Err("program-ref: cannot be used together with 'program'".to_owned());
+    }
+
+    let path = base.join(&program_ref.path);
+    let program = std::fs::read(&path).map_err(|error| format!("program-ref: failed to read '{}': {error}", path.display()))?;
+    if program_ref_for(&program).hash != program_ref.hash {
+        return Err(format!(
+            "program-ref: the hash of '{}' doesn't match the expected hash {}",
+            path.display(),
+            program_ref.hash
+        ));
+    }
+
+    testcase.program = program;
+    Ok(())
+}
+
+/// An interrupt observed while running a testcase.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Interrupt {
+    /// One of the final statuses; every interrupt except the last one is a `host` call or a `page-fault` which was handled.
+    pub kind: String,
+    /// The index of the host call, for `host` interrupts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_call: Option<u32>,
+    /// The address of the faulting page, for `page-fault` interrupts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<u32>,
+}
+
+/// A page fault which was handled by an `on-fault` directive.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HandledFault {
+    /// The address of the faulting page.
+    pub address: u32,
+    /// One of `map-rw` and `map-ro` (the page was mapped, zeroed, and the execution resumed) or `abort`.
+    pub action: String,
+}
+
+const FAULT_ACTIONS: &[&str] = &["map-rw", "map-ro", "abort"];
+
+/// The state asserted when the execution first reaches a label, through `assert @label: ...` directives.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Checkpoint {
+    pub label: String,
+    /// The program counter of the label.
+    pub pc: u32,
+    pub expected_regs: Vec<CheckpointReg>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_gas: Option<i64>,
+    /// The (1-based) step at which the label was first reached; `None` if it never was, or if the testcase wasn't executed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reached_at_step: Option<u64>,
+}
+
+/// The value of a register asserted by a [`Checkpoint`].
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckpointReg {
+    /// The index of the register, as in `initial-regs`.
+    pub reg: u8,
+    pub value: u64,
+}
+
+/// The kinds of accesses which can cause a page fault; see `expected_page_fault_access`.
+const PAGE_FAULT_ACCESSES: &[&str] = &["read", "write"];
+
+impl Interrupt {
+    fn new(kind: &str) -> Self {
+        Interrupt {
+            kind: kind.to_owned(),
+            host_call: None,
+            address: None,
+        }
+    }
+}
+
+impl core::fmt::Display for Interrupt {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match (self.host_call, self.address) {
+            (Some(host_call), _) => write!(fmt, "{}({host_call})", self.kind),
+            (None, Some(address)) => write!(fmt, "{}(0x{address:x})", self.kind),
+            (None, None) => fmt.write_str(&self.kind),
+        }
+    }
+}
+
+pub fn format_interrupts(interrupts: &[Interrupt]) -> String {
+    interrupts.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
 }
 
 pub fn new_engine() -> Engine {
@@ -50,19 +508,59 @@ pub fn new_engine() -> Engine {
     Engine::new(&config).unwrap()
 }
 
+#[derive(Copy, Clone)]
+pub struct DisassembleOptions {
+    pub is_64_bit: bool,
+    pub show_raw_bytes: bool,
+    pub prefer_non_abi_reg_names: bool,
+    pub prefer_offset_jump_targets: bool,
+    pub show_gas_costs: bool,
+    pub prefer_export_jump_targets: bool,
+    pub prefer_hex_offsets: bool,
+    /// Emit assembly which assembles back into the same program, as checked by [`roundtrip`]; the other options
+    /// except for `is_64_bit` are ignored then.
+    pub reassemblable: bool,
+}
+
+impl Default for DisassembleOptions {
+    fn default() -> Self {
+        DisassembleOptions {
+            is_64_bit: true,
+            show_raw_bytes: false,
+            prefer_non_abi_reg_names: true,
+            prefer_offset_jump_targets: false,
+            show_gas_costs: false,
+            prefer_export_jump_targets: false,
+            prefer_hex_offsets: false,
+            reassemblable: false,
+        }
+    }
+}
+
 pub fn disassemble(bytecode: Vec<u8>) -> Result<String, String> {
+    disassemble_with_options(bytecode, DisassembleOptions::default())
+}
+
+pub fn disassemble_with_options(bytecode: Vec<u8>, options: DisassembleOptions) -> Result<String, String> {
+    if options.reassemblable {
+        return roundtrip(bytecode, options.is_64_bit);
+    }
+
     let mut parts = ProgramParts::default();
     parts.code_and_jump_table = bytecode.into();
-    parts.is_64_bit = true;
+    parts.is_64_bit = options.is_64_bit;
     let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
 
     let mut disassembler =
         polkavm_disassembler::Disassembler::new(&blob, polkavm_disassembler::DisassemblyFormat::Guest).map_err(to_string)?;
 
-    disassembler.show_raw_bytes(false);
-    disassembler.prefer_non_abi_reg_names(true);
+    disassembler.show_raw_bytes(options.show_raw_bytes);
+    disassembler.prefer_non_abi_reg_names(options.prefer_non_abi_reg_names);
     disassembler.prefer_unaliased(true);
-    disassembler.prefer_offset_jump_targets(false);
+    disassembler.prefer_offset_jump_targets(options.prefer_offset_jump_targets);
+    disassembler.prefer_export_jump_targets(options.prefer_export_jump_targets);
+    disassembler.prefer_hex_offsets(options.prefer_hex_offsets);
+    disassembler.show_gas_costs(options.show_gas_costs).map_err(to_string)?;
     disassembler.emit_header(false);
     disassembler.emit_exports(false);
 
@@ -73,313 +571,6069 @@ pub fn disassemble(bytecode: Vec<u8>) -> Result<String, String> {
     Ok(disassembly)
 }
 
-pub fn prepare_input(input: &str, engine: &Engine, name: &str, internal_name: &str, execute: bool) -> Result<Testcase, String> {
-    let mut pre = PrePost::default();
-    let mut post = PrePost::default();
+/// Disassembles either a whole program blob, as produced by the linker, or a bare code and jump table.
+///
+/// A whole blob is recognized by its magic bytes, and its disassembly includes its header and the names of its exports;
+/// anything else is disassembled through [`disassemble`].
+pub fn disassemble_blob(bytes: Vec<u8>) -> Result<String, String> {
+    disassemble_blob_with_options(bytes, DisassembleOptions::default())
+}
 
-    let expected_status: Option<String> = None;
-    let mut input_lines = Vec::new();
-    for line in input.lines() {
-        if let Some(line) = line.strip_prefix("pre:") {
-            parse_pre_post(line, &mut pre);
-            input_lines.push(""); // Insert dummy line to not mess up the line count.
-            continue;
+/// Like [`disassemble_blob`], but with the given options; a whole blob records its own bitness, so `is_64_bit` only
+/// applies to a bare code and jump table.
+pub fn disassemble_blob_with_options(bytes: Vec<u8>, options: DisassembleOptions) -> Result<String, String> {
+    // A whole blob is also handled by `roundtrip`, which keeps its exports.
+    if options.reassemblable || !bytes.starts_with(&polkavm_common::program::BLOB_MAGIC) {
+        return disassemble_with_options(bytes, options);
+    }
+
+    let parts = ProgramParts::from_bytes(bytes.into()).map_err(to_string)?;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+    let mut disassembler =
+        polkavm_disassembler::Disassembler::new(&blob, polkavm_disassembler::DisassemblyFormat::Guest).map_err(to_string)?;
+
+    disassembler.show_raw_bytes(options.show_raw_bytes);
+    disassembler.prefer_non_abi_reg_names(options.prefer_non_abi_reg_names);
+    disassembler.prefer_unaliased(true);
+    disassembler.prefer_offset_jump_targets(options.prefer_offset_jump_targets);
+    disassembler.prefer_export_jump_targets(options.prefer_export_jump_targets);
+    disassembler.prefer_hex_offsets(options.prefer_hex_offsets);
+    disassembler.show_gas_costs(options.show_gas_costs).map_err(to_string)?;
+    disassembler.emit_header(true);
+    disassembler.emit_exports(true);
+
+    let mut disassembly = Vec::new();
+    disassembler.disassemble_into(&mut disassembly).map_err(to_string)?;
+    String::from_utf8(disassembly).map_err(to_string)
+}
+
+/// The instructions of a program's code, without the implicit trap past its end.
+fn code_instructions(blob: &ProgramBlob) -> Vec<ParsedInstruction> {
+    blob_instructions(blob)
+        .into_iter()
+        .take_while(|instruction| (instruction.offset.0 as usize) < blob.code().len())
+        .collect()
+}
+
+/// Renders a program as assembly which the assembler accepts: every basic block gets an `@N` label which the jumps
+/// refer to, and the loads of the jump table's addresses are turned back into loads of those labels.
+fn reassemblable_disassembly(blob: &ProgramBlob) -> Result<String, String> {
+    let instructions = code_instructions(blob);
+
+    // This numbers the basic blocks in the same way as the assembler does.
+    let mut basic_block_for_offset = BTreeMap::new();
+    let mut at_block_start = true;
+    for instruction in &instructions {
+        if at_block_start {
+            basic_block_for_offset.insert(instruction.offset.0, basic_block_for_offset.len());
         }
+        at_block_start = instruction.kind.starts_new_basic_block();
+    }
 
-        if let Some(line) = line.strip_prefix("post:") {
-            parse_pre_post(line, &mut post);
-            input_lines.push(""); // Insert dummy line to not mess up the line count.
+    let mut exports_for_offset: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for export in blob.exports() {
+        // Only the symbols which can be written down as a label are kept; their order doesn't matter since the
+        // assembler sorts them anyway.
+        let Ok(symbol) = core::str::from_utf8(export.symbol().as_bytes()) else {
             continue;
+        };
+        let is_label = !symbol.is_empty()
+            && !symbol.bytes().all(|byte| byte.is_ascii_digit())
+            && symbol.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+        if is_label {
+            exports_for_offset
+                .entry(export.program_counter().0)
+                .or_default()
+                .push(symbol.to_owned());
         }
-
-        input_lines.push(line);
     }
 
-    let input = input_lines.join("\n");
-    let blob = match assemble(&input) {
-        Ok(blob) => blob,
-        Err(error) => {
-            let msg = format!("Failed to assemble {internal_name}: {error}");
-            eprintln!("{}", msg);
-            return Err(msg);
+    let invalid_jump_target = core::cell::Cell::new(None);
+    let jump_target_formatter = |target: u32, fmt: &mut core::fmt::Formatter| {
+        if let Some(index) = basic_block_for_offset.get(&target) {
+            write!(fmt, "@{index}")
+        } else {
+            invalid_jump_target.set(Some(target));
+            write!(fmt, "{target}")
         }
     };
 
-    let initial_gas = pre.gas.unwrap_or(10000);
-    let initial_regs = pre.regs.map(|value| value.unwrap_or(0));
-    assert!(pre.pc.is_none(), "'pre: pc = ...' is currently unsupported");
+    let mut format = InstructionFormat::default();
+    format.prefer_non_abi_reg_names = true;
+    format.prefer_unaliased = true;
+    format.is_64_bit = blob.is_64_bit();
+    format.jump_target_formatter = Some(&jump_target_formatter);
 
-    let parts = ProgramParts::from_bytes(blob.into()).unwrap();
-    let blob = ProgramBlob::from_parts(parts.clone()).unwrap();
+    let jump_table = blob.jump_table();
+    let mut loaded_jump_table_entries = 0;
+    let mut output = String::new();
+    for instruction in &instructions {
+        if let Some(&index) = basic_block_for_offset.get(&instruction.offset.0) {
+            for symbol in exports_for_offset.get(&instruction.offset.0).into_iter().flatten() {
+                writeln!(&mut output, "pub @{symbol}:").unwrap();
+            }
+            writeln!(&mut output, "@{index}:").unwrap();
+        }
 
-    let mut module_config = ModuleConfig::default();
-    module_config.set_strict(true);
-    module_config.set_gas_metering(Some(polkavm::GasMeteringKind::Sync));
-    module_config.set_step_tracing(true);
-    module_config.set_dynamic_paging(true);
+        // The assembler appends a new entry to the jump table for every load of a label's address.
+        let next_jump_table_address = (loaded_jump_table_entries + 1) * polkavm_common::abi::VM_CODE_ADDRESS_ALIGNMENT;
+        let line = match instruction.kind {
+            Instruction::load_imm(dst, value) if value == next_jump_table_address => jump_table
+                .get_by_index(loaded_jump_table_entries)
+                .and_then(|target| basic_block_for_offset.get(&target.0))
+                .map(|index| {
+                    loaded_jump_table_entries += 1;
+                    format!("{} = @{index}", dst.get().name_non_abi())
+                }),
+            // Otherwise a small value would be assembled as a `load_imm`.
+            Instruction::load_imm64(dst, value) => Some(format!("{} = i64 0x{value:x}", dst.get().name_non_abi())),
+            _ => None,
+        };
 
-    let module = Module::from_blob(&engine, &module_config, blob.clone()).unwrap();
-    let mut instance = module.instantiate().unwrap();
+        let line = line.unwrap_or_else(|| instruction.kind.display(&format).to_string());
+        if let Some(target) = invalid_jump_target.take() {
+            return Err(format!(
+                "the target of '{line}' at {} isn't the start of a basic block: {target}",
+                instruction.offset
+            ));
+        }
 
-    let mut initial_page_map = Vec::new();
-    let mut initial_memory = Vec::new();
+        writeln!(&mut output, "    {line}").unwrap();
+    }
 
-    if module.memory_map().ro_data_size() > 0 {
-        initial_page_map.push(Page {
-            address: module.memory_map().ro_data_address(),
-            length: module.memory_map().ro_data_size(),
-            is_writable: false,
-        });
+    Ok(output)
+}
+
+/// Disassembles a program into assembly and assembles it back, checking that the result is identical.
+///
+/// The program is either a whole program blob, or a bare code and jump table of the given bitness; only the code and
+/// the jump table are compared. On success the reassemblable disassembly is returned, otherwise the first divergence.
+pub fn roundtrip(bytes: Vec<u8>, is_64_bit: bool) -> Result<String, String> {
+    let parts = if bytes.starts_with(&polkavm_common::program::BLOB_MAGIC) {
+        ProgramParts::from_bytes(bytes.into()).map_err(to_string)?
+    } else {
+        let mut parts = ProgramParts::default();
+        parts.code_and_jump_table = bytes.into();
+        parts.is_64_bit = is_64_bit;
+        parts
+    };
+    let blob = ProgramBlob::from_parts(parts.clone()).map_err(to_string)?;
+    let assembly = reassemblable_disassembly(&blob)?;
 
-        initial_memory.extend(extract_chunks(module.memory_map().ro_data_address(), blob.ro_data()));
+    let (reassembled, _) = assemble_with_labels(&assembly, blob.is_64_bit()).map_err(|error| format!("failed to reassemble: {error}"))?;
+    let reassembled_parts = ProgramParts::from_bytes(reassembled.into()).map_err(to_string)?;
+    if reassembled_parts.code_and_jump_table == parts.code_and_jump_table {
+        return Ok(assembly);
     }
 
-    if module.memory_map().rw_data_size() > 0 {
-        initial_page_map.push(Page {
-            address: module.memory_map().rw_data_address(),
-            length: module.memory_map().rw_data_size(),
-            is_writable: true,
-        });
+    let reassembled_blob = ProgramBlob::from_parts(reassembled_parts).map_err(to_string)?;
+    let mut format = InstructionFormat::default();
+    format.is_64_bit = blob.is_64_bit();
+    let display = |instruction: Option<&(ProgramCounter, Instruction)>| match instruction {
+        Some((offset, instruction)) => format!("'{}' at {offset}", instruction.display(&format)),
+        None => "nothing".to_owned(),
+    };
 
-        initial_memory.extend(extract_chunks(module.memory_map().rw_data_address(), blob.rw_data()));
+    let instructions = |blob: &ProgramBlob| -> Vec<_> {
+        code_instructions(blob)
+            .into_iter()
+            .map(|instruction| (instruction.offset, instruction.kind))
+            .collect()
+    };
+    let original = instructions(&blob);
+    let reassembled = instructions(&reassembled_blob);
+    for nth_instruction in 0..original.len().max(reassembled.len()) {
+        let (lhs, rhs) = (original.get(nth_instruction), reassembled.get(nth_instruction));
+        if lhs != rhs {
+            return Err(format!(
+                "instruction #{nth_instruction} diverges: {} was reassembled as {}",
+                display(lhs),
+                display(rhs)
+            ));
+        }
     }
 
-    if module.memory_map().stack_size() > 0 {
-        initial_page_map.push(Page {
-            address: module.memory_map().stack_address_low(),
-            length: module.memory_map().stack_size(),
-            is_writable: true,
-        });
+    let original_jump_table: Vec<_> = blob.jump_table().into_iter().collect();
+    let reassembled_jump_table: Vec<_> = reassembled_blob.jump_table().into_iter().collect();
+    if let Some(nth_entry) = (0..original_jump_table.len().max(reassembled_jump_table.len()))
+        .find(|&nth_entry| original_jump_table.get(nth_entry) != reassembled_jump_table.get(nth_entry))
+    {
+        let display = |entry: Option<&ProgramCounter>| entry.map_or_else(|| "nothing".to_owned(), |pc| pc.to_string());
+        return Err(format!(
+            "jump table entry #{nth_entry} diverges: {} was reassembled as {}",
+            display(original_jump_table.get(nth_entry)),
+            display(reassembled_jump_table.get(nth_entry))
+        ));
     }
 
-    let initial_pc = blob.exports().find(|export| export.symbol() == "main").unwrap().program_counter();
+    // Same instructions, but encoded differently.
+    let (lhs, rhs) = (blob.code(), reassembled_blob.code());
+    let nth_byte = (0..lhs.len().max(rhs.len()))
+        .find(|&nth_byte| lhs.get(nth_byte) != rhs.get(nth_byte))
+        .unwrap_or(0);
+    Err(format!("the encoding of the code diverges at byte {nth_byte}"))
+}
 
-    let expected_final_pc = if let Some(export) = blob.exports().find(|export| export.symbol() == "expected_exit") {
-        assert!(
-            post.pc.is_none(),
-            "'@expected_exit' label and 'post: pc = ...' should not be used together"
-        );
-        export.program_counter().0
-    } else if let Some(ProgramCounterRef::ByLabel { label, instruction_offset }) = post.pc {
-        let Some(export) = blob.exports().find(|export| export.symbol().as_bytes() == label.as_bytes()) else {
-            panic!("label specified in 'post: pc = ...' is missing: @{label}");
-        };
+/// An operand of a [`StructuredInstruction`], serialized as `{ "kind": ..., "value": ... }`.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "kebab-case")]
+pub enum StructuredOperand {
+    /// A register, by its name as in the textual disassembly.
+    Reg(&'static str),
+    Imm(u32),
+    /// A jump target, as a program counter.
+    Offset(u32),
+    Imm64(u64),
+}
 
-        let instructions: Vec<_> = blob.instructions(ISA64_V1).collect();
-        let index = instructions
-            .iter()
-            .position(|inst| inst.offset == export.program_counter())
-            .expect("failed to find label specified in 'post: pc = ...'");
-        let instruction = instructions
-            .get(index + instruction_offset as usize)
-            .expect("invalid 'post: pc = ...': offset goes out of bounds of the basic block");
-        instruction.offset.0
-    } else if let Some(ProgramCounterRef::Preset(pc)) = post.pc {
-        pc.0
+/// A single decoded instruction, as emitted by [`disassemble_structured`].
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize)]
+pub struct StructuredInstruction {
+    /// The program counter of the instruction.
+    pub offset: u32,
+    /// The length of the instruction in bytes, including its operands.
+    pub length: u32,
+    pub raw_bytes: Vec<u8>,
+    /// The name of the instruction's opcode, e.g. `add_64`.
+    pub mnemonic: &'static str,
+    /// The operands, in the order of their encoding; see [`Opcode::operand_kinds`].
+    pub operands: Vec<StructuredOperand>,
+    /// The index of the basic block to which the instruction belongs, as in the `@N` labels of the textual disassembly.
+    pub basic_block: u32,
+    /// The static targets of a jump or a branch; empty for any other instruction, including the indirect jumps.
+    pub targets: Vec<u32>,
+}
+
+/// Decodes every instruction of a bare code and jump table, in the same way as the textual disassembly does.
+pub fn structured_instructions(bytecode: Vec<u8>, options: DisassembleOptions) -> Result<Vec<StructuredInstruction>, String> {
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = bytecode.into();
+    parts.is_64_bit = options.is_64_bit;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+    let instructions: Vec<_> = if options.is_64_bit {
+        blob.instructions(ISA64_V1).collect()
     } else {
-        blob.code().len() as u32
+        blob.instructions(ISA32_V1).collect()
     };
 
-    instance.set_gas(initial_gas);
-    instance.set_next_program_counter(initial_pc);
+    let code = blob.code();
+    let reg_name = |reg: polkavm::program::RawReg| {
+        if options.prefer_non_abi_reg_names {
+            reg.get().name_non_abi()
+        } else {
+            reg.get().name()
+        }
+    };
 
-    for (reg, value) in Reg::ALL.into_iter().zip(initial_regs) {
-        instance.set_reg(reg, value);
-    }
+    let mut basic_block = 0;
+    let mut output = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        let offset = instruction.offset.0;
+        let length = core::cmp::min(instruction.next_offset.0, code.len() as u32) - offset;
+        let opcode = instruction.kind.opcode();
 
-    if module_config.dynamic_paging() {
-        for page in &initial_page_map {
-            instance.zero_memory(page.address, page.length).unwrap();
-            if !page.is_writable {
-                instance.protect_memory(page.address, page.length).unwrap();
-            }
-        }
+        let (regs, imms, imm64) = instruction.kind.operands();
+        let (mut regs, mut imms) = (regs.into_iter(), imms.into_iter());
+        let operands = opcode
+            .operand_kinds()
+            .iter()
+            .map(|&kind| match kind {
+                "reg" => StructuredOperand::Reg(reg_name(regs.next().unwrap())),
+                "imm" => StructuredOperand::Imm(imms.next().unwrap()),
+                "offset" => StructuredOperand::Offset(imms.next().unwrap()),
+                "imm64" => StructuredOperand::Imm64(imm64),
+                _ => unreachable!("unknown operand kind: {kind}"),
+            })
+            .collect();
 
-        for chunk in &initial_memory {
-            instance.write_memory(chunk.address, &chunk.contents).unwrap();
-        }
-    }
+        output.push(StructuredInstruction {
+            offset,
+            length,
+            raw_bytes: code[offset as usize..(offset + length) as usize].to_vec(),
+            mnemonic: opcode.name(),
+            operands,
+            basic_block,
+            targets: cfg::jump_target(instruction.kind).into_iter().collect(),
+        });
 
-    let mut final_pc = initial_pc;
-    let (final_status, page_fault_address) = if execute {
-        loop {
-            match instance.run().unwrap() {
-                InterruptKind::Finished => break ("halt", None),
-                InterruptKind::Trap => break ("panic", None),
-                InterruptKind::Ecalli(..) => todo!(),
-                InterruptKind::NotEnoughGas => break ("out-of-gas", None),
-                InterruptKind::Segfault(segfault) => break ("page-fault", Some(segfault.page_address)),
-                InterruptKind::Step => {
-                    final_pc = instance.program_counter().unwrap();
-                    continue;
-                }
-            }
+        if instruction.starts_new_basic_block() {
+            basic_block += 1;
         }
-    } else {
-        final_pc.0 = expected_final_pc;
-        (expected_status.as_deref().unwrap_or("ok"), None)
-    };
-
-    if final_status != "halt" {
-        final_pc = instance.program_counter().unwrap_or_else(|| ProgramCounter(expected_final_pc));
     }
 
-    if let Some(expected_status) = expected_status.clone() {
-        if final_status != expected_status {
-            let msg = format!("Unexpected final status for {internal_name}: expected {expected_status}, is {final_status}");
-            eprintln!("{}", msg);
-            return Err(msg);
-        }
+    Ok(output)
+}
+
+/// Disassembles a bare code and jump table into a JSON array with a [`StructuredInstruction`] for every instruction.
+pub fn disassemble_structured(bytecode: Vec<u8>) -> Result<String, String> {
+    disassemble_structured_with_options(bytecode, DisassembleOptions::default())
+}
+
+/// Like [`disassemble_structured`]; only `is_64_bit` and `prefer_non_abi_reg_names` of the options apply.
+pub fn disassemble_structured_with_options(bytecode: Vec<u8>, options: DisassembleOptions) -> Result<String, String> {
+    let instructions = structured_instructions(bytecode, options)?;
+    Ok(serde_json::to_string(&instructions).unwrap())
+}
+
+/// Describes an instruction of the ISA, e.g. for autocompletion in editors.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstructionDescription {
+    pub name: &'static str,
+    pub opcode: u8,
+    /// The instruction's assembly syntax, with its operands replaced by `<reg1>`, `<imm1>`, `<offset>` and so on.
+    pub syntax: String,
+    /// The kinds of the operands, in the order of their encoding; see [`Opcode::operand_kinds`].
+    pub operands: &'static [&'static str],
+    /// Whether the instruction ends a basic block.
+    pub is_terminator: bool,
+    pub is_32_bit: bool,
+    pub is_64_bit: bool,
+}
+
+/// Describes every instruction of either the 32-bit or the 64-bit ISA, ordered by their opcodes.
+pub fn instruction_descriptions() -> Vec<InstructionDescription> {
+    let jump_target_formatter = |_: u32, fmt: &mut core::fmt::Formatter| fmt.write_str("<offset>");
+    let mut format = polkavm_common::program::InstructionFormat::default();
+    format.prefer_non_abi_reg_names = true;
+    format.prefer_unaliased = true;
+    format.jump_target_formatter = Some(&jump_target_formatter);
+    format.is_64_bit = true;
+
+    // The operands are distinct values which are substituted with placeholders after the instruction is displayed.
+    let regs = [Reg::A0, Reg::A1, Reg::A2];
+    let imms = [0x111, 0x222];
+    let imm64 = 0x333;
+    let mut placeholders = vec![
+        (format!("{:#x}", imms[0]), "<imm1>"),
+        (format!("{:#x}", imms[1]), "<imm2>"),
+        (format!("{imm64:#x}"), "<imm64>"),
+        (imms[0].to_string(), "<imm1>"),
+        (imms[1].to_string(), "<imm2>"),
+        (imm64.to_string(), "<imm64>"),
+    ];
+    placeholders.extend(
+        regs.iter()
+            .zip(["<reg1>", "<reg2>", "<reg3>"])
+            .map(|(reg, placeholder)| (reg.name_non_abi().to_owned(), placeholder)),
+    );
+
+    (0..=255)
+        .filter_map(Opcode::from_u8_any)
+        .filter_map(|opcode| {
+            let is_32_bit = ISA32_V1.opcode_from_u8(opcode as u8).is_some();
+            let is_64_bit = ISA64_V1.opcode_from_u8(opcode as u8).is_some();
+            if !is_32_bit && !is_64_bit {
+                return None;
+            }
+
+            let instruction = Instruction::from_opcode(opcode, regs.map(Into::into), imms, imm64);
+            let mut syntax = instruction.display(&format).to_string();
+            for (value, placeholder) in &placeholders {
+                syntax = syntax.replace(value.as_str(), placeholder);
+            }
+
+            Some(InstructionDescription {
+                name: opcode.name(),
+                opcode: opcode as u8,
+                syntax,
+                operands: opcode.operand_kinds(),
+                is_terminator: opcode.starts_new_basic_block(),
+                is_32_bit,
+                is_64_bit,
+            })
+        })
+        .collect()
+}
+
+/// Returns the ABI name and the `rN` name of every register.
+pub fn register_aliases() -> Vec<(&'static str, &'static str)> {
+    Reg::ALL.iter().map(|reg| (reg.name(), reg.name_non_abi())).collect()
+}
+
+/// Creates an engine which uses the recompiler, if it's supported on this platform.
+pub fn new_compiler_engine() -> Option<Engine> {
+    if !polkavm::BackendKind::Compiler.is_supported() {
+        return None;
     }
 
-    if execute && final_pc.0 != expected_final_pc {
-        let msg = format!("Unexpected final program counter for {internal_name}: expected {expected_final_pc}, is {final_pc}");
-        eprintln!("{}", msg);
-        return Err(msg);
+    let mut config = polkavm::Config::new();
+    config.set_backend(Some(polkavm::BackendKind::Compiler));
+    config.set_allow_dynamic_paging(true);
+
+    Engine::new(&config).ok()
+}
+
+/// A single testcase defined by a source file.
+pub struct SourceCase {
+    /// The name from the case's `=== <name>` separator, or `None` if the source defines only a single testcase.
+    pub name: Option<String>,
+    /// The input of this case; the lines which belong to the other cases are blanked out to keep the line numbers intact.
+    pub input: String,
+}
+
+/// The prefixes of every directive which `prepare_input_with_options` handles itself instead of passing it to the assembler.
+const DIRECTIVES: &[&str] = &[
+    "page:",
+    "on-ecalli",
+    "pre:",
+    "post:",
+    "desc:",
+    "entry:",
+    "modes:",
+    "stack-size:",
+    "gas-limit:",
+    "gas-model:",
+    "randomize:",
+    "category:",
+    "on-fault",
+    "assert",
+    "strict-post",
+    "expect-assembly-error:",
+];
+
+/// Splits a source into its testcases along the `=== <name>` separators.
+///
+/// Everything before the first separator and the assembly below the last separator is shared by all of the cases,
+/// while the directives within each section only apply to that section's case.
+///
+/// A case with a `modes: 32,64` directive is further split into one case per mode, suffixed with `_32` and `_64`.
+pub fn split_cases(input: &str, internal_name: &str) -> Result<Vec<SourceCase>, String> {
+    let mut cases = Vec::new();
+    for case in split_sections(input, internal_name)? {
+        cases.extend(split_modes(case, internal_name)?);
     }
 
-    let mut expected_regs = Vec::new();
-    for reg in Reg::ALL {
-        let value = instance.reg(reg);
-        expected_regs.push(value);
+    Ok(cases)
+}
+
+fn split_modes(case: SourceCase, internal_name: &str) -> Result<Vec<SourceCase>, String> {
+    let lines: Vec<&str> = case.input.lines().collect();
+    let Some((modes_line, modes)) = find_modes(&lines, internal_name)? else {
+        return Ok(vec![case]);
+    };
+
+    if modes.len() == 1 {
+        return Ok(vec![case]);
     }
 
-    let mut expected_memory = Vec::new();
-    for page in &initial_page_map {
-        let memory = instance.read_memory(page.address, page.length).unwrap();
-        expected_memory.extend(extract_chunks(page.address, &memory));
+    let cases = modes
+        .into_iter()
+        .map(|mode| {
+            // Keep the directive in place so that the line numbers stay intact.
+            let directive = format!("modes: {mode}");
+            let case_lines: Vec<&str> = lines
+                .iter()
+                .enumerate()
+                .map(|(nth_line, line)| if nth_line == modes_line { directive.as_str() } else { line })
+                .collect();
+
+            SourceCase {
+                name: Some(match case.name {
+                    Some(ref name) => format!("{name}_{mode}"),
+                    None => mode.to_string(),
+                }),
+                input: case_lines.join("\n"),
+            }
+        })
+        .collect();
+
+    Ok(cases)
+}
+
+/// Finds the `category: <name>` directive of a source, which overrides the category derived from the source's directory.
+pub fn find_category(input: &str, internal_name: &str) -> Result<Option<String>, String> {
+    let mut category = None;
+    for (nth_line, line) in input.lines().enumerate() {
+        let Some(name) = line.strip_prefix("category:") else {
+            continue;
+        };
+
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!(
+                "{internal_name}:{}: invalid 'category' directive: expected a name",
+                nth_line + 1
+            ));
+        }
+
+        if category.replace(name.to_owned()).is_some() {
+            return Err(format!("{internal_name}:{}: duplicate 'category' directive", nth_line + 1));
+        }
     }
 
-    let expected_gas = instance.gas();
+    Ok(category)
+}
 
-    let mut found_post_check_errors = false;
+/// Finds the `modes:` directive of a source; returns its (0-based) line number along with the parsed modes.
+fn find_modes(lines: &[&str], internal_name: &str) -> Result<Option<(usize, Vec<u32>)>, String> {
+    let mut found = None;
+    for (nth_line, line) in lines.iter().enumerate() {
+        let Some(text) = line.strip_prefix("modes:") else { continue };
+        let at_line = |error: &str| format!("{internal_name}:{}: {error}", nth_line + 1);
+        if found.is_some() {
+            return Err(at_line("duplicate 'modes' directive"));
+        }
 
-    if execute {
-        for ((final_value, reg), required_value) in expected_regs.iter().zip(Reg::ALL).zip(post.regs.iter()) {
-            if let Some(required_value) = required_value {
-                if final_value != required_value {
-                    eprintln!("{internal_name}: unexpected {reg}: 0x{final_value:x} (expected: 0x{required_value:x})");
-                    found_post_check_errors = true;
-                }
+        let mut modes = Vec::new();
+        for mode in text.split(',') {
+            let mode = match mode.trim() {
+                "32" => 32,
+                "64" => 64,
+                mode => return Err(at_line(&format!("invalid mode: '{mode}' (expected '32' or '64')"))),
+            };
+
+            if modes.contains(&mode) {
+                return Err(at_line(&format!("duplicate mode: '{mode}'")));
             }
+            modes.push(mode);
         }
 
-        if let Some(post_gas) = post.gas {
-            if expected_gas != post_gas {
-                eprintln!("{internal_name}: unexpected gas: {expected_gas} (expected: {post_gas})");
-                found_post_check_errors = true;
-            }
+        found = Some((nth_line, modes));
+    }
+
+    Ok(found)
+}
+
+fn split_sections(input: &str, internal_name: &str) -> Result<Vec<SourceCase>, String> {
+    let lines: Vec<&str> = input.lines().collect();
+    let mut sections = Vec::new();
+    for (nth_line, line) in lines.iter().enumerate() {
+        let Some(name) = line.strip_prefix("===") else { continue };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-') {
+            return Err(format!("{internal_name}:{}: invalid case name: '{name}'", nth_line + 1));
+        }
+
+        if sections.iter().any(|&(_, other_name)| other_name == name) {
+            return Err(format!("{internal_name}:{}: duplicate case name: '{name}'", nth_line + 1));
         }
+
+        sections.push((nth_line, name));
     }
 
-    if found_post_check_errors {
-        return Err("Found post check errors.".to_string());
+    if sections.is_empty() {
+        return Ok(vec![SourceCase {
+            name: None,
+            input: input.to_owned(),
+        }]);
     }
 
-    let mut disassembler = polkavm_disassembler::Disassembler::new(&blob, polkavm_disassembler::DisassemblyFormat::Guest).unwrap();
-    disassembler.show_raw_bytes(true);
-    disassembler.prefer_non_abi_reg_names(true);
-    disassembler.prefer_unaliased(true);
-    disassembler.prefer_offset_jump_targets(true);
-    disassembler.emit_header(false);
-    disassembler.emit_exports(false);
+    // The section to which each line belongs, if any.
+    let mut section_of_line = vec![None; lines.len()];
+    for (nth_section, &(start, _)) in sections.iter().enumerate() {
+        let end = sections.get(nth_section + 1).map_or(lines.len(), |&(end, _)| end);
+        for section in &mut section_of_line[start..end] {
+            *section = Some(nth_section);
+        }
+    }
 
-    let mut disassembly = Vec::new();
-    disassembler.disassemble_into(&mut disassembly).unwrap();
-    let disassembly = String::from_utf8(disassembly).unwrap();
+    let last_section = sections.len() - 1;
+    for (nth_line, line) in lines.iter().enumerate() {
+        let is_assembly = !line.trim().is_empty() && !line.trim().starts_with("//");
+        let is_directive = DIRECTIVES.iter().any(|prefix| line.starts_with(prefix)) || line.starts_with("===");
+        let is_in_shared_section = section_of_line[nth_line].map_or(true, |section| section == last_section);
+        if is_assembly && !is_directive && !is_in_shared_section {
+            return Err(format!(
+                "{internal_name}:{}: only directives can be used before the last case's separator",
+                nth_line + 1
+            ));
+        }
+    }
 
-    Ok(Testcase {
-        disassembly,
-        json: TestcaseJson {
-            name: name.into(),
-            initial_regs,
-            initial_pc: initial_pc.0,
-            initial_page_map,
-            initial_memory,
-            initial_gas,
-            program: parts.code_and_jump_table.to_vec(),
-            expected_status: final_status.to_owned(),
-            expected_regs,
-            expected_pc: expected_final_pc,
-            expected_memory,
-            expected_gas,
-            expected_page_fault_address: page_fault_address,
-        },
+    let cases = sections
+        .iter()
+        .enumerate()
+        .map(|(nth_section, &(_, name))| {
+            let case_lines: Vec<&str> = lines
+                .iter()
+                .zip(&section_of_line)
+                .map(|(line, section)| {
+                    let is_directive = DIRECTIVES.iter().any(|prefix| line.starts_with(prefix));
+                    match *section {
+                        Some(_) if line.starts_with("===") => "",
+                        Some(section) if section != nth_section && is_directive => "",
+                        _ => line,
+                    }
+                })
+                .collect();
+
+            SourceCase {
+                name: Some(name.to_owned()),
+                input: case_lines.join("\n"),
+            }
+        })
+        .collect();
+
+    Ok(cases)
+}
+
+/// A source which is expected to fail to assemble, as declared with an `expect-assembly-error: <substring>` directive.
+pub struct NegativeTestcase {
+    pub name: String,
+    /// The substring which the assembler's error message must contain.
+    pub expected_error: String,
+    /// The error actually returned by the assembler, with its line number mapped back to the source.
+    pub error: String,
+}
+
+/// Checks a source carrying an `expect-assembly-error: <substring>` directive.
+///
+/// Returns `None` if the source has no such directive, and an error if it either assembles successfully
+/// or fails to assemble with an unexpected message.
+pub fn check_assembly_error(input: &str, name: &str, internal_name: &str) -> Option<Result<NegativeTestcase, String>> {
+    let mut expected_error = None;
+    for (nth_line, line) in input.lines().enumerate() {
+        let Some(text) = line.strip_prefix("expect-assembly-error:") else {
+            continue;
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Some(Err(format!(
+                "{internal_name}:{}: invalid 'expect-assembly-error' directive: expected a substring of the error",
+                nth_line + 1
+            )));
+        }
+
+        if expected_error.replace(text).is_some() {
+            return Some(Err(format!(
+                "{internal_name}:{}: duplicate 'expect-assembly-error' directive",
+                nth_line + 1
+            )));
+        }
+    }
+
+    let expected_error = expected_error?;
+    let input = match expand_constants(input, internal_name) {
+        Ok(input) => input,
+        Err(error) => return Some(Err(error)),
+    };
+    let is_64_bit = match find_modes(&input.lines().collect::<Vec<_>>(), internal_name) {
+        Ok(None) => true,
+        Ok(Some((_, modes))) => modes.contains(&64),
+        Err(error) => return Some(Err(error)),
+    };
+
+    // The directives are only stripped here, so that the error is the same one the assembler would report for the testcase.
+    let input_lines: Vec<(usize, &str)> = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !DIRECTIVES.iter().any(|prefix| line.starts_with(prefix)))
+        .map(|(nth_line, line)| (nth_line + 1, line))
+        .collect();
+
+    let assembler_input = input_lines.iter().map(|(_, line)| *line).collect::<Vec<_>>().join("\n");
+    let assembled = assemble_program(&assembler_input, is_64_bit);
+
+    Some(match assembled {
+        Ok(_) => Err(format!(
+            "{internal_name}: expected an assembly error containing '{expected_error}', but the program assembled successfully"
+        )),
+        Err(error) if error.contains(expected_error) => Ok(NegativeTestcase {
+            name: name.to_owned(),
+            expected_error: expected_error.to_owned(),
+            error: map_assembler_error(&error, internal_name, &input_lines),
+        }),
+        Err(error) => Err(format!(
+            "{internal_name}: expected an assembly error containing '{expected_error}', got: {}",
+            map_assembler_error(&error, internal_name, &input_lines)
+        )),
     })
 }
 
-fn to_string<E: core::fmt::Debug>(e: E) -> String {
-    format!("{:?}", e)
+/// A program assembled from its source, along with where each of its labels ended up.
+pub struct AssembledProgram {
+    /// The whole program blob, as emitted by the assembler.
+    pub blob: Vec<u8>,
+    /// The code and the jump table of the program, as stored in a testcase's `program`.
+    pub code_and_jump_table: Vec<u8>,
+    /// The program counter of every exported (`pub @...`) label.
+    pub exports: BTreeMap<String, u32>,
+    /// The program counter of every other label, derived from the index of the basic block it starts.
+    ///
+    /// A label which doesn't start any instruction (e.g. one at the very end of the source) is missing from here.
+    pub local_labels: BTreeMap<String, u32>,
+    /// The address of every label of the `%ro_data { ... }` and `%rw_data { ... }` blocks.
+    pub data_labels: BTreeMap<String, u32>,
 }
 
-fn extract_chunks(base_address: u32, slice: &[u8]) -> Vec<MemoryChunk> {
-    let mut output = Vec::new();
-    let mut position = 0;
-    while let Some(next_position) = slice[position..].iter().position(|&byte| byte != 0).map(|offset| position + offset) {
-        position = next_position;
-        let length = slice[position..].iter().take_while(|&&byte| byte != 0).count();
-        output.push(MemoryChunk {
-            address: base_address + position as u32,
-            contents: slice[position..position + length].into(),
-        });
-        position += length;
+impl AssembledProgram {
+    /// The value of a label: the program counter of a code label, or the address of a data label.
+    fn label_value(&self, label: &str) -> Option<u32> {
+        [&self.exports, &self.local_labels, &self.data_labels]
+            .into_iter()
+            .find_map(|labels| labels.get(label).copied())
     }
+}
 
-    output
+/// Assembles a source, returning the program along with its symbol map.
+///
+/// The directives of the testcase sources are ignored and their constants are expanded, so a source can be passed in
+/// as-is; one with a `modes: 32` directive is assembled as a 32-bit program. The line numbers in the errors are the ones of the source.
+pub fn assemble_with_symbols(source: &str) -> Result<AssembledProgram, String> {
+    let source = &expand_constants(source, "<input>")?;
+    let is_64_bit = match find_modes(&source.lines().collect::<Vec<_>>(), "<input>")? {
+        None => true,
+        Some((_, modes)) => modes.contains(&64),
+    };
+
+    // The directives are blanked out instead of being removed to keep the line numbers intact.
+    let input = source
+        .lines()
+        .map(|line| {
+            if DIRECTIVES.iter().any(|prefix| line.starts_with(prefix)) {
+                ""
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    assemble_program(&input, is_64_bit)
 }
 
-enum ProgramCounterRef {
-    ByLabel {
-        label: String,
-        instruction_offset: u32,
-    },
-    #[allow(dead_code)]
-    Preset(ProgramCounter),
+/// The prefixes of the lines in which the constants aren't expanded, since they're free-form text.
+const LINES_WITHOUT_CONSTANTS: &[&str] = &["desc:", "category:", "expect-assembly-error:", "==="];
+
+/// Whether a word is the name of a constant, which is always upper case (e.g. `PAGE_SIZE`).
+fn is_constant_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|ch| ch.is_ascii_uppercase()) && chars.all(|ch| ch.is_ascii_uppercase() || ch.is_ascii_digit() || ch == '_')
+}
+
+/// Replaces every constant in a single line with its value, leaving the labels, the comments and the strings as they are.
+fn substitute_constants(line: &str, constants: &BTreeMap<&str, (usize, String)>) -> Result<String, String> {
+    let mut output = String::with_capacity(line.len());
+    let mut in_string = false;
+    let mut rest = line;
+    while let Some(ch) = rest.chars().next() {
+        if !in_string && rest.starts_with("//") {
+            output.push_str(rest);
+            break;
+        }
+
+        if in_string || !(ch.is_ascii_alphanumeric() || ch == '_') {
+            let mut length = ch.len_utf8();
+            if ch == '"' {
+                in_string = !in_string;
+            } else if in_string && ch == '\\' {
+                length += rest[length..].chars().next().map_or(0, char::len_utf8);
+            }
+
+            output.push_str(&rest[..length]);
+            rest = &rest[length..];
+            continue;
+        }
+
+        let length = rest.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_').unwrap_or(rest.len());
+        let word = &rest[..length];
+        if !output.ends_with('@') && is_constant_name(word) {
+            let Some((_, value)) = constants.get(word) else {
+                return Err(format!("undefined constant '{word}'"));
+            };
+            output.push_str(value);
+        } else {
+            output.push_str(word);
+        }
+        rest = &rest[length..];
+    }
+
+    Ok(output)
+}
+
+/// Expands the `const NAME = <value>` definitions of a source, replacing every use of a constant in the code and in the
+/// directives with its value.
+///
+/// A constant can be used wherever an immediate is accepted, and in the definitions of the constants which follow it. The
+/// definitions are blanked out to keep the line numbers intact.
+fn expand_constants(input: &str, internal_name: &str) -> Result<String, String> {
+    let mut constants = BTreeMap::new();
+    let mut lines = Vec::new();
+    for (nth_line, line) in input.lines().enumerate() {
+        let at_line = |error: String| format!("{internal_name}:{}: {error}", nth_line + 1);
+        let Some(definition) = line.trim_start().strip_prefix("const ") else {
+            lines.push(Some(line));
+            continue;
+        };
+
+        let Some((name, value)) = definition.split_once('=') else {
+            return Err(at_line("invalid 'const' definition: expected 'const NAME = <value>'".to_owned()));
+        };
+
+        let name = name.trim();
+        if !is_constant_name(name) {
+            return Err(at_line(format!(
+                "invalid 'const' definition: the name '{name}' must be upper case, e.g. 'PAGE_SIZE'"
+            )));
+        }
+
+        let value = substitute_constants(value.trim(), &constants).map_err(at_line)?;
+        if polkavm_common::utils::parse_immediate(&value).is_none() {
+            return Err(at_line(format!("invalid 'const' definition: '{value}' is not a valid value")));
+        }
+
+        if let Some((first_nth_line, _)) = constants.insert(name, (nth_line + 1, value)) {
+            return Err(at_line(format!(
+                "redefinition of the constant '{name}', which is already defined on line {first_nth_line}"
+            )));
+        }
+
+        lines.push(None);
+    }
+
+    let mut output = Vec::with_capacity(lines.len());
+    for (nth_line, line) in lines.into_iter().enumerate() {
+        let line = match line {
+            None => String::new(),
+            Some(line) if LINES_WITHOUT_CONSTANTS.iter().any(|prefix| line.starts_with(prefix)) => line.to_owned(),
+            Some(line) => substitute_constants(line, &constants).map_err(|error| format!("{internal_name}:{}: {error}", nth_line + 1))?,
+        };
+        output.push(line);
+    }
+
+    Ok(output.join("\n"))
 }
 
+/// The contents of a `%ro_data { ... }` or a `%rw_data { ... }` block, along with the offsets of its labels.
 #[derive(Default)]
-struct PrePost {
-    gas: Option<i64>,
-    regs: [Option<u64>; 13],
-    pc: Option<ProgramCounterRef>,
+struct DataSection {
+    /// The (1-based) line on which the block starts, if the source has one.
+    nth_line: Option<usize>,
+    bytes: Vec<u8>,
+    labels: Vec<(String, u32)>,
+    /// The size from a `%ro_data_size = ...` or a `%rw_data_size = ...` directive, along with its line.
+    declared_size: Option<(usize, u32)>,
+    /// The line of a `%ro_data = ...` or a `%rw_data = ...` directive, which can't be combined with a block.
+    hex_line: Option<usize>,
 }
 
-fn parse_pre_post(line: &str, output: &mut PrePost) {
-    let line = line.trim();
-    let index = line.find('=').expect("invalid 'pre' / 'post' directive: no '=' found");
-    let lhs = line[..index].trim();
-    let rhs = line[index + 1..].trim();
-    if lhs == "gas" {
-        output.gas = Some(rhs.parse::<i64>().expect("invalid 'pre' / 'post' directive: failed to parse rhs"));
-    } else if lhs == "pc" {
-        let rhs = rhs
-            .strip_prefix('@')
-            .expect("invalid 'pre' / 'post' directive: failed to parse 'pc': no '@' found")
-            .trim();
-        let index = rhs
-            .find('[')
-            .expect("invalid 'pre' / 'post' directive: failed to parse 'pc': no '[' found");
-        let label = &rhs[..index];
-        let rhs = &rhs[index + 1..];
-        let index = rhs
-            .find(']')
-            .expect("invalid 'pre' / 'post' directive: failed to parse 'pc': no ']' found");
-        let offset = rhs[..index]
-            .parse::<u32>()
-            .expect("invalid 'pre' / 'post' directive: failed to parse 'pc': invalid offset");
-        if !rhs[index + 1..].trim().is_empty() {
-            panic!("invalid 'pre' / 'post' directive: failed to parse 'pc': junk after ']'");
-        }
-
-        output.pc = Some(ProgramCounterRef::ByLabel {
-            label: label.to_owned(),
-            instruction_offset: offset,
-        });
-    } else {
-        let lhs = polkavm_common::utils::parse_reg(lhs).expect("invalid 'pre' / 'post' directive: failed to parse lhs");
-        let rhs = polkavm_common::utils::parse_immediate(rhs)
-            .map(Into::into)
-            .expect("invalid 'pre' / 'post' directive: failed to parse rhs");
-        output.regs[lhs as usize] = Some(rhs);
+impl DataSection {
+    /// The size of the section, which defaults to the size of the block's contents.
+    fn size(&self, name: &str) -> Result<u32, String> {
+        let Some(nth_line) = self.nth_line else {
+            return Ok(self.declared_size.map_or(0, |(_, size)| size));
+        };
+
+        if let Some(hex_line) = self.hex_line {
+            return Err(format!(
+                "cannot parse line {hex_line}: '%{name} = ...' can't be combined with the '%{name}' block on line {nth_line}"
+            ));
+        }
+
+        match self.declared_size {
+            Some((nth_size_line, size)) if (size as usize) < self.bytes.len() => Err(format!(
+                "cannot parse line {nth_size_line}: the '%{name}' block on line {nth_line} doesn't fit into {size} bytes"
+            )),
+            Some((_, size)) => Ok(size),
+            None => u32::try_from(self.bytes.len()).map_err(|_| format!("cannot parse line {nth_line}: the data is too big")),
+        }
+    }
+}
+
+/// Parses the contents of a data block, starting right after its `{`; returns whatever follows the block's `}`, if it
+/// ends on this line.
+fn parse_data_block_line<'a>(line: &'a str, nth_line: usize, section: &mut DataSection) -> Result<Option<&'a str>, String> {
+    let error = |message: &str| format!("cannot parse line {nth_line}: {message}");
+    let mut width = None;
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if rest.starts_with("//") {
+            break;
+        }
+
+        if let Some(after) = rest.strip_prefix('}') {
+            if width.is_some() {
+                return Err(error("expected a value after the width"));
+            }
+            return Ok(Some(after));
+        }
+
+        if let Some(after) = rest.strip_prefix('"') {
+            if width.is_some() {
+                return Err(error("a string can't have a width"));
+            }
+
+            let mut chars = after.char_indices();
+            let end = loop {
+                let Some((position, ch)) = chars.next() else {
+                    return Err(error("unterminated string"));
+                };
+
+                let mut buffer = [0; 4];
+                match ch {
+                    '"' => break position + 1,
+                    '\\' => match chars.next().map(|(_, ch)| ch) {
+                        Some('n') => section.bytes.push(b'\n'),
+                        Some('t') => section.bytes.push(b'\t'),
+                        Some('0') => section.bytes.push(0),
+                        Some('\\') => section.bytes.push(b'\\'),
+                        Some('"') => section.bytes.push(b'"'),
+                        Some('x') => {
+                            let digits: String = (0..2).filter_map(|_| chars.next().map(|(_, ch)| ch)).collect();
+                            let byte = u8::from_str_radix(&digits, 16).map_err(|_| error("invalid '\\x' escape"))?;
+                            section.bytes.push(byte);
+                        }
+                        _ => return Err(error("invalid escape")),
+                    },
+                    ch => section.bytes.extend_from_slice(ch.encode_utf8(&mut buffer).as_bytes()),
+                }
+            };
+
+            rest = after[end..].trim_start();
+            continue;
+        }
+
+        let length = rest
+            .find(|ch: char| ch.is_whitespace() || ch == '}' || ch == '"')
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(length);
+        rest = after.trim_start();
+
+        if let Some(label) = word.strip_prefix('@').and_then(|label| label.strip_suffix(':')) {
+            if width.is_some() {
+                return Err(error("expected a value after the width"));
+            }
+
+            let offset = u32::try_from(section.bytes.len()).map_err(|_| error("the data is too big"))?;
+            section.labels.push((label.to_owned(), offset));
+            continue;
+        }
+
+        let new_width = match word {
+            "u8" => Some(1),
+            "u16" => Some(2),
+            "u32" => Some(4),
+            "u64" => Some(8),
+            _ => None,
+        };
+
+        if let Some(new_width) = new_width {
+            if width.replace(new_width).is_some() {
+                return Err(error("expected a value after the width"));
+            }
+            continue;
+        }
+
+        let value = if let Some(digits) = word.strip_prefix("0x") {
+            u64::from_str_radix(digits, 16).ok()
+        } else if let Some(digits) = word.strip_prefix("0b") {
+            u64::from_str_radix(digits, 2).ok()
+        } else {
+            word.parse::<u64>().ok()
+        };
+
+        let width = width.take().unwrap_or(1);
+        let Some(value) = value.filter(|value| width == 8 || value >> (width * 8) == 0) else {
+            return Err(format!("cannot parse line {nth_line}: invalid {}-bit value: \"{word}\"", width * 8));
+        };
+
+        section.bytes.extend_from_slice(&value.to_le_bytes()[..width]);
+    }
+
+    if width.is_some() {
+        return Err(error("expected a value after the width"));
+    }
+
+    Ok(None)
+}
+
+/// Expands the `%ro_data { ... }` and `%rw_data { ... }` blocks of a program which is already stripped of every directive.
+///
+/// A block contains bytes (`0x01`), wider little-endian values (`u32 0x01020304`), strings (`"abc"`) and `@label:`s,
+/// and can span several lines; wherever a label of a block is used in the code it's replaced with the address of its
+/// data. The lines of the blocks are blanked out to keep the line numbers intact, and the assembler's own `%ro_data = ...`
+/// and `%ro_data_size = ...` directives are appended at the end. Returns the expanded program along with the address of
+/// every label of the blocks.
+fn expand_data_sections(input: &str) -> Result<(String, BTreeMap<String, u32>), String> {
+    let mut ro_data = DataSection::default();
+    let mut rw_data = DataSection::default();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut open_block = None;
+    for (nth_line, line) in input.lines().enumerate() {
+        let nth_line = nth_line + 1;
+        let error = |message: &str| format!("cannot parse line {nth_line}: {message}");
+        if let Some(is_read_only) = open_block {
+            let section = if is_read_only { &mut ro_data } else { &mut rw_data };
+            lines.push("");
+            if let Some(after) = parse_data_block_line(line, nth_line, section)? {
+                if !after.trim().is_empty() && !after.trim().starts_with("//") {
+                    return Err(error("unexpected characters after the end of the data block"));
+                }
+                open_block = None;
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        let block = if let Some(rest) = trimmed.strip_prefix("%ro_data") {
+            rest.trim_start().strip_prefix('{').map(|rest| (true, rest))
+        } else if let Some(rest) = trimmed.strip_prefix("%rw_data") {
+            rest.trim_start().strip_prefix('{').map(|rest| (false, rest))
+        } else {
+            None
+        };
+
+        let Some((is_read_only, rest)) = block else {
+            for (name, section) in [("%ro_data", &mut ro_data), ("%rw_data", &mut rw_data)] {
+                let Some(rest) = trimmed.strip_prefix(name) else {
+                    continue;
+                };
+
+                // An invalid size is left for the assembler to report.
+                if let Some(size) = rest.strip_prefix("_size = ").and_then(|size| size.trim().parse::<u32>().ok()) {
+                    section.declared_size = Some((nth_line, size));
+                } else if rest.starts_with(" = ") {
+                    section.hex_line = Some(nth_line);
+                }
+            }
+            lines.push(line);
+            continue;
+        };
+
+        let section = if is_read_only { &mut ro_data } else { &mut rw_data };
+        if section.nth_line.replace(nth_line).is_some() {
+            return Err(error("duplicate data block"));
+        }
+
+        lines.push("");
+        match parse_data_block_line(rest, nth_line, section)? {
+            Some(after) if !after.trim().is_empty() && !after.trim().starts_with("//") => {
+                return Err(error("unexpected characters after the end of the data block"));
+            }
+            Some(_) => {}
+            None => open_block = Some(is_read_only),
+        }
+    }
+
+    if let Some(is_read_only) = open_block {
+        let section = if is_read_only { &ro_data } else { &rw_data };
+        return Err(format!(
+            "cannot parse line {}: unterminated data block",
+            section.nth_line.unwrap_or_default()
+        ));
+    }
+
+    if ro_data.nth_line.is_none() && rw_data.nth_line.is_none() {
+        return Ok((input.to_owned(), BTreeMap::new()));
+    }
+
+    let ro_data_size = ro_data.size("ro_data")?;
+    let rw_data_size = rw_data.size("rw_data")?;
+
+    // The page size doesn't affect where the sections start.
+    let memory_map = polkavm_common::abi::MemoryMapBuilder::new(polkavm_common::abi::VM_MIN_PAGE_SIZE)
+        .ro_data_size(ro_data_size)
+        .rw_data_size(rw_data_size)
+        .build()
+        .map_err(|error| format!("invalid data blocks: {error}"))?;
+
+    let mut addresses = BTreeMap::new();
+    for (section, base_address) in [(&ro_data, memory_map.ro_data_address()), (&rw_data, memory_map.rw_data_address())] {
+        for (label, offset) in &section.labels {
+            if addresses.insert(label.as_str(), base_address + offset).is_some() {
+                return Err(format!(
+                    "duplicate label \"{label}\" on line {}",
+                    section.nth_line.unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for (nth_line, line) in lines.into_iter().enumerate() {
+        let nth_line = nth_line + 1;
+        let definition = line.trim().strip_prefix("pub ").unwrap_or(line.trim());
+        if let Some(label) = definition.strip_prefix('@').and_then(|label| label.strip_suffix(':')) {
+            if addresses.contains_key(label) {
+                return Err(format!("duplicate label \"{label}\" on line {nth_line}"));
+            }
+        }
+
+        let mut rest = line;
+        while let Some(position) = rest.find('@') {
+            output.push_str(&rest[..position]);
+            let after = &rest[position + 1..];
+            let length = after
+                .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+                .unwrap_or(after.len());
+            match addresses.get(&after[..length]) {
+                Some(address) if !after[length..].starts_with(':') => write!(&mut output, "0x{address:x}").unwrap(),
+                _ => output.push_str(&rest[position..position + 1 + length]),
+            }
+            rest = &after[length..];
+        }
+        output.push_str(rest);
+        output.push('\n');
+    }
+
+    for (section, size, name) in [(&ro_data, ro_data_size, "ro_data"), (&rw_data, rw_data_size, "rw_data")] {
+        if section.nth_line.is_none() {
+            continue;
+        }
+
+        if !section.bytes.is_empty() {
+            let bytes: String = section.bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            writeln!(&mut output, "%{name} = {bytes}").unwrap();
+        }
+        writeln!(&mut output, "%{name}_size = {size}").unwrap();
+    }
+
+    let addresses = addresses.into_iter().map(|(label, address)| (label.to_owned(), address)).collect();
+    Ok((output, addresses))
+}
+
+/// A term of a label expression.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum ExpressionTerm {
+    Label(String),
+    Value(i64),
+}
+
+/// A sum of labels and immediates, e.g. `@end - @start` or `@table + 4`; each term is paired with whether it's subtracted.
+type LabelExpression = Vec<(bool, ExpressionTerm)>;
+
+fn is_label_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// Parses a single `@label` or non-negative immediate from the start of the text, returning it along with its length.
+fn parse_expression_term(text: &str) -> Option<(ExpressionTerm, usize)> {
+    let (is_label, body) = match text.strip_prefix('@') {
+        Some(body) => (true, body),
+        None => (false, text),
+    };
+
+    let length = body.find(|ch: char| !is_label_char(ch)).unwrap_or(body.len());
+    let token = &body[..length];
+    if token.is_empty() {
+        return None;
+    }
+
+    let term = if is_label {
+        ExpressionTerm::Label(token.to_owned())
+    } else {
+        let value = if let Some(digits) = token.strip_prefix("0x") {
+            u64::from_str_radix(digits, 16).ok()?
+        } else if let Some(digits) = token.strip_prefix("0b") {
+            u64::from_str_radix(digits, 2).ok()?
+        } else {
+            token.parse::<u64>().ok()?
+        };
+        ExpressionTerm::Value(i64::try_from(value).ok()?)
+    };
+
+    Some((term, length + usize::from(is_label)))
+}
+
+/// Parses a whole label expression, e.g. `@end - @start`.
+fn parse_label_expression(text: &str) -> Option<LabelExpression> {
+    let mut expression = Vec::new();
+    let mut is_negative = false;
+    let mut rest = text.trim();
+    loop {
+        let (term, length) = parse_expression_term(rest)?;
+        expression.push((is_negative, term));
+        rest = rest[length..].trim_start();
+        is_negative = match rest.chars().next() {
+            None => return Some(expression),
+            Some('+') => false,
+            Some('-') => true,
+            Some(_) => return None,
+        };
+        rest = rest[1..].trim_start();
+    }
+}
+
+/// Evaluates a label expression; the error is the first label which can't be resolved.
+fn evaluate_label_expression(expression: &LabelExpression, resolve: impl Fn(&str) -> Option<u32>) -> Result<i64, String> {
+    expression.iter().try_fold(0_i64, |value, (is_negative, term)| {
+        let term = match term {
+            ExpressionTerm::Label(label) => i64::from(resolve(label).ok_or_else(|| label.clone())?),
+            ExpressionTerm::Value(term) => *term,
+        };
+
+        Ok(if *is_negative {
+            value.wrapping_sub(term)
+        } else {
+            value.wrapping_add(term)
+        })
+    })
+}
+
+/// Finds the label expressions in a line of code, returning their spans.
+///
+/// Only a sequence of at least two terms is an expression, since a lone `@label` is the assembler's own syntax.
+fn find_label_expressions(line: &str) -> Vec<(usize, usize, LabelExpression)> {
+    let code = line.find("//").map_or(line, |position| &line[..position]);
+    let mut expressions = Vec::new();
+    let mut position = 0;
+    while let Some(ch) = code[position..].chars().next() {
+        let rest = &code[position..];
+        // An expression which continues an operation (e.g. the `4 + 2` in `a0 = a1 - 4 + 2`) can't be evaluated on its own.
+        let at_boundary =
+            !code[..position].ends_with(|ch: char| is_label_char(ch) || ch == '@') && !code[..position].trim_end().ends_with(['+', '-']);
+        let first_term = (at_boundary && (ch == '@' || ch.is_ascii_digit()))
+            .then(|| parse_expression_term(rest))
+            .flatten();
+
+        let Some((term, mut end)) = first_term else {
+            position += ch.len_utf8();
+            continue;
+        };
+
+        let mut expression = vec![(false, term)];
+        loop {
+            let after = rest[end..].trim_start();
+            let is_negative = match after.chars().next() {
+                Some('+') => false,
+                Some('-') => true,
+                _ => break,
+            };
+
+            let operand = after[1..].trim_start();
+            let Some((term, length)) = parse_expression_term(operand) else {
+                break;
+            };
+
+            expression.push((is_negative, term));
+            end = rest.len() - operand.len() + length;
+        }
+
+        if expression.len() > 1 {
+            expressions.push((position, position + end, expression));
+        }
+        position += end;
+    }
+
+    expressions
+}
+
+/// How many times a program with label expressions is reassembled, at most, before its labels settle.
+const MAX_LABEL_EXPRESSION_PASSES: usize = 16;
+
+/// Assembles a program which is already stripped of every directive; the error is the assembler's own.
+///
+/// The label expressions in the code (e.g. `a0 = @end - @start`) are replaced with their values. Since those change
+/// the sizes of the instructions, and with them the program counters of the labels, the program is reassembled until
+/// the values settle.
+fn assemble_program(input: &str, is_64_bit: bool) -> Result<AssembledProgram, String> {
+    let (input, data_labels) = expand_data_sections(input)?;
+    let lines: Vec<&str> = input.lines().collect();
+    let mut expressions = Vec::new();
+    for (nth_line, line) in lines.iter().enumerate() {
+        if !line.trim_start().starts_with('%') {
+            for (start, end, expression) in find_label_expressions(line) {
+                expressions.push((nth_line, start, end, expression));
+            }
+        }
+    }
+
+    let mut values = vec![0_i64; expressions.len()];
+    for _ in 0..MAX_LABEL_EXPRESSION_PASSES {
+        let mut substituted = lines.iter().map(|line| (*line).to_owned()).collect::<Vec<_>>();
+        // The expressions are replaced from the last one, so that the spans of the earlier ones on the same line stay valid.
+        for ((nth_line, start, end, _), value) in expressions.iter().zip(&values).rev() {
+            let value = if *value < 0 {
+                format!("-{}", value.unsigned_abs())
+            } else {
+                format!("0x{value:x}")
+            };
+            substituted[*nth_line].replace_range(*start..*end, &value);
+        }
+
+        let program = assemble_expanded(&substituted.join("\n"), is_64_bit, data_labels.clone())?;
+        let new_values = expressions
+            .iter()
+            .map(|(nth_line, start, end, expression)| {
+                evaluate_label_expression(expression, |label| program.label_value(label)).map_err(|label| {
+                    format!(
+                        "cannot parse line {}: undefined label \"{label}\" in '{}'",
+                        nth_line + 1,
+                        &lines[*nth_line][*start..*end]
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if new_values == values {
+            return Ok(program);
+        }
+        values = new_values;
+    }
+
+    Err(format!(
+        "the label expressions didn't settle after reassembling the program {MAX_LABEL_EXPRESSION_PASSES} times"
+    ))
+}
+
+/// Assembles a program in which every data block and label expression is already expanded.
+fn assemble_expanded(input: &str, is_64_bit: bool, data_labels: BTreeMap<String, u32>) -> Result<AssembledProgram, String> {
+    let (blob, labels) = assemble_with_labels(input, is_64_bit)?;
+    let parts = ProgramParts::from_bytes(blob.clone().into()).map_err(to_string)?;
+    let code_and_jump_table = parts.code_and_jump_table.to_vec();
+    let parsed = ProgramBlob::from_parts(parts).map_err(to_string)?;
+
+    let exports: BTreeMap<String, u32> = parsed
+        .exports()
+        .map(|export| {
+            (
+                String::from_utf8_lossy(export.symbol().as_bytes()).into_owned(),
+                export.program_counter().0,
+            )
+        })
+        .collect();
+
+    // The assembler numbers the basic blocks in order, each starting right after an instruction which ends the previous one.
+    let mut basic_block_starts = vec![0];
+    for instruction in blob_instructions(&parsed)
+        .into_iter()
+        .take_while(|instruction| (instruction.offset.0 as usize) < parsed.code().len())
+    {
+        if instruction.kind.starts_new_basic_block() {
+            basic_block_starts.push(instruction.next_offset.0);
+        }
+    }
+
+    let local_labels = labels
+        .into_iter()
+        .filter(|(label, _)| !exports.contains_key(label))
+        .filter_map(|(label, index)| {
+            let pc = *basic_block_starts.get(index as usize)?;
+            ((pc as usize) < parsed.code().len()).then_some((label, pc))
+        })
+        .collect();
+
+    Ok(AssembledProgram {
+        blob,
+        code_and_jump_table,
+        exports,
+        local_labels,
+        data_labels,
+    })
+}
+
+pub fn prepare_input(input: &str, engine: &Engine, name: &str, internal_name: &str, execute: bool) -> Result<Testcase, String> {
+    prepare_input_with_options(input, engine, name, internal_name, execute, PrepareOptions::default())
+}
+
+pub fn prepare_input_with_options(
+    input: &str,
+    engine: &Engine,
+    name: &str,
+    internal_name: &str,
+    execute: bool,
+    options: PrepareOptions,
+) -> Result<Testcase, String> {
+    let input = &expand_constants(input, internal_name)?;
+    let category = find_category(input, internal_name)?;
+    let is_64_bit = match find_modes(&input.lines().collect::<Vec<_>>(), internal_name)? {
+        None => true,
+        Some((_, modes)) if modes.len() == 1 => modes[0] == 64,
+        Some((nth_line, _)) => {
+            return Err(format!(
+                "{internal_name}:{}: a source with several modes must be split into one testcase per mode with 'split_cases' first",
+                nth_line + 1
+            ));
+        }
+    };
+
+    let mut directives = parse_directives(input, internal_name, is_64_bit, &options)?;
+    load_memory_files(&mut directives.pre, internal_name, options.source_directory)?;
+
+    let mut state = set_up_testcase(&mut directives, engine, internal_name, is_64_bit, execute, &options)?;
+    let execution = execute_testcase(&mut state, &directives, internal_name, execute, options.record_trace)?;
+
+    // Without execution the final state is whatever the 'post' directives say, with the rest left at its initial values.
+    let post = &directives.post;
+    let mut expected_regs = Vec::new();
+    for (nth, reg) in Reg::ALL.into_iter().enumerate() {
+        let value = if execute {
+            state.instance.reg(reg)
+        } else {
+            post.regs[nth].map_or(state.initial_regs[nth], |value| value & state.reg_mask)
+        };
+        expected_regs.push(value);
+    }
+
+    let expected_gas = if execute {
+        state.instance.gas()
+    } else {
+        post.gas.unwrap_or(state.initial_gas)
+    };
+
+    check_post_conditions(
+        &state,
+        &directives,
+        &execution,
+        &expected_regs,
+        expected_gas,
+        internal_name,
+        execute,
+    )?;
+
+    let mut expected_memory = extract_final_memory(
+        &state.instance,
+        &state.initial_page_map,
+        &state.initial_memory,
+        state.module.memory_map().page_size(),
+        options.max_chunk_gap,
+    );
+
+    if !post.memory.is_empty() {
+        // Record the assertions even without execution, where the final memory is just the initial one.
+        expected_memory = overlay_memory(&expected_memory, &post.memory, options.max_chunk_gap);
+    }
+
+    if let Some(cross_check_engine) = options.cross_check_engine.filter(|_| execute) {
+        cross_check_testcase(
+            cross_check_engine,
+            &state,
+            &directives,
+            &execution,
+            &expected_regs,
+            expected_gas,
+            internal_name,
+        )?;
+    }
+
+    let Execution {
+        final_status,
+        page_fault_address,
+        final_pc,
+        host_calls,
+        interrupts,
+        step_count,
+        faults_handled,
+        trace,
+    } = execution;
+
+    let page_fault_access = if page_fault_address.is_some() {
+        memory_access_kind(&state.blob, final_pc)
+    } else {
+        None
+    };
+
+    let expected_exit_code = if final_status == "halt" {
+        Some(expected_regs[Reg::A0.to_usize()])
+    } else {
+        None
+    };
+
+    let gas_model = state.gas_model;
+    Ok(Testcase {
+        disassembly: disassemble_testcase(&state.blob, options.disassembly_style, gas_model),
+        json: TestcaseJson {
+            name: name.into(),
+            description: if directives.description_lines.is_empty() {
+                None
+            } else {
+                Some(directives.description_lines.join("\n"))
+            },
+            initial_regs: state.initial_regs,
+            initial_pc: state.initial_pc.0,
+            initial_page_map: state.initial_page_map,
+            initial_memory: state.initial_memory,
+            initial_gas: state.initial_gas,
+            program: state.parts.code_and_jump_table.to_vec(),
+            program_file: None,
+            program_ref: None,
+            program_hash_algorithm: Some(PROGRAM_HASH_ALGORITHMS[0].to_owned()),
+            program_hash: program_hash(PROGRAM_HASH_ALGORITHMS[0], &state.parts.code_and_jump_table),
+            expected_status: final_status,
+            expected_regs,
+            expected_pc: state.expected_final_pc,
+            expected_memory,
+            expected_gas,
+            expected_page_fault_address: page_fault_address,
+            expected_page_fault_access: page_fault_access.map(ToOwned::to_owned),
+            expected_exit_code,
+            expected_host_calls: if host_calls.is_empty() { None } else { Some(host_calls) },
+            // Only emitted when requested, since it's redundant with the other fields for most of the testcases.
+            expected_interrupts: directives.post.interrupts.is_some().then_some(interrupts),
+            // A step is reported right before every instruction is executed, including the terminal one.
+            expected_step_count: execute.then_some(step_count),
+            faults_handled: (!faults_handled.is_empty()).then_some(faults_handled),
+            checkpoints: (!state.checkpoints.is_empty()).then_some(state.checkpoints),
+            // Omitted for the default model, so that the testcases which don't use another one stay unchanged.
+            gas_model: (gas_model != GasModel::Polkavm).then_some(gas_model),
+            format_version: Some(FORMAT_VERSION),
+            generator: Some(GENERATOR.to_owned()),
+        },
+        trace: trace.map(|trace| trace.output),
+        block_costs: gas_model.apply_to_block_costs(block_costs_of_module(&state.module, &state.blob)),
+        entry_point: directives.entry_point,
+        stack_size: directives.stack_size,
+        is_64_bit,
+        category,
+        out_of_gas_at: state.out_of_gas_at,
+    })
+}
+
+/// The directives of a testcase source, as parsed by [`parse_directives`].
+struct Directives<'a> {
+    pre: PrePost,
+    post: PrePost,
+    on_ecalli: BTreeMap<u32, PrePost>,
+    on_fault: BTreeMap<u32, &'static str>,
+    assertions: BTreeMap<String, PrePost>,
+    page_directives: Vec<PageDirective>,
+    description_lines: Vec<&'a str>,
+    entry_point: Option<String>,
+    stack_size: Option<u32>,
+    gas_limit: Option<i64>,
+    gas_model: Option<GasModel>,
+    strict_post: bool,
+    /// The lines which will be passed to the assembler, along with their original (1-based) line numbers.
+    input_lines: Vec<(usize, &'a str)>,
+}
+
+/// Parses the directives of a source, separating them from the lines which are passed to the assembler.
+fn parse_directives<'a>(input: &'a str, internal_name: &str, is_64_bit: bool, options: &PrepareOptions) -> Result<Directives<'a>, String> {
+    let mut directives = Directives {
+        pre: PrePost::default(),
+        post: PrePost::default(),
+        on_ecalli: BTreeMap::new(),
+        on_fault: BTreeMap::new(),
+        assertions: BTreeMap::new(),
+        page_directives: Vec::new(),
+        description_lines: Vec::new(),
+        entry_point: None,
+        stack_size: None,
+        gas_limit: None,
+        gas_model: None,
+        strict_post: options.strict_post,
+        input_lines: Vec::new(),
+    };
+
+    for (nth_line, line) in input.lines().enumerate() {
+        let at_line = |error: String| format!("{internal_name}:{}: {error}", nth_line + 1);
+        if let Some(line) = line.strip_prefix("page:") {
+            directives.page_directives.push(parse_page_directive(line).map_err(at_line)?);
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("on-ecalli") {
+            parse_on_ecalli(line, &mut directives.on_ecalli).map_err(at_line)?;
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("on-fault") {
+            parse_on_fault(line, &mut directives.on_fault).map_err(at_line)?;
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("assert") {
+            parse_assert(line, &mut directives.assertions).map_err(at_line)?;
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("pre:") {
+            parse_pre_post(line, &mut directives.pre).map_err(at_line)?;
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("post:") {
+            // Expectations which only hold in one of the modes are prefixed with '32:' or '64:'.
+            let (line, is_applicable) = if let Some(line) = line.trim_start().strip_prefix("32:") {
+                (line, !is_64_bit)
+            } else if let Some(line) = line.trim_start().strip_prefix("64:") {
+                (line, is_64_bit)
+            } else {
+                (line, true)
+            };
+
+            if is_applicable {
+                parse_pre_post(line, &mut directives.post).map_err(at_line)?;
+            }
+            continue;
+        }
+
+        if line.starts_with("modes:") {
+            continue;
+        }
+
+        if line.starts_with("expect-assembly-error:") {
+            return Err(at_line(
+                "a source with an 'expect-assembly-error' directive is a negative test; check it with 'check_assembly_error' instead"
+                    .to_owned(),
+            ));
+        }
+
+        if let Some(line) = line.strip_prefix("stack-size:") {
+            let size = polkavm_common::utils::parse_imm(line.trim())
+                .and_then(|size| u32::try_from(size).ok())
+                .ok_or_else(|| at_line("invalid 'stack-size' directive: failed to parse the size".to_owned()))?;
+
+            if directives.stack_size.replace(size).is_some() {
+                return Err(at_line("duplicate 'stack-size' directive".to_owned()));
+            }
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("gas-limit:") {
+            let limit = line
+                .trim()
+                .parse::<i64>()
+                .ok()
+                .filter(|limit| *limit >= 0)
+                .ok_or_else(|| at_line("invalid 'gas-limit' directive: failed to parse the limit".to_owned()))?;
+
+            if directives.gas_limit.replace(limit).is_some() {
+                return Err(at_line("duplicate 'gas-limit' directive".to_owned()));
+            }
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("gas-model:") {
+            if directives
+                .gas_model
+                .replace(parse_gas_model(line, options.gas_model).map_err(at_line)?)
+                .is_some()
+            {
+                return Err(at_line("duplicate 'gas-model' directive".to_owned()));
+            }
+            continue;
+        }
+
+        if line.starts_with("category:") || line.starts_with("randomize:") {
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("strict-post") {
+            if !line.trim().is_empty() {
+                return Err(at_line("invalid 'strict-post' directive: it doesn't take any arguments".to_owned()));
+            }
+
+            directives.strict_post = true;
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("desc:") {
+            directives.description_lines.push(line.strip_prefix(' ').unwrap_or(line));
+            continue;
+        }
+
+        if let Some(line) = line.strip_prefix("entry:") {
+            let Some(label) = line.trim().strip_prefix('@').filter(|label| !label.is_empty()) else {
+                return Err(at_line("invalid 'entry' directive: expected '@label'".to_owned()));
+            };
+
+            if directives.entry_point.replace(label.to_owned()).is_some() {
+                return Err(at_line("duplicate 'entry' directive".to_owned()));
+            }
+            continue;
+        }
+
+        directives.input_lines.push((nth_line + 1, line));
+    }
+
+    check_directives(&directives, internal_name)?;
+    Ok(directives)
+}
+
+/// Rejects the directives which were parsed fine, but can't be used where they were given.
+fn check_directives(directives: &Directives, internal_name: &str) -> Result<(), String> {
+    let Directives {
+        pre,
+        post,
+        on_ecalli,
+        assertions,
+        ..
+    } = directives;
+
+    if pre.status.is_some() {
+        return Err(format!("{internal_name}: 'status' can only be used in a 'post' directive"));
+    }
+
+    if pre.host_calls.is_some() {
+        return Err(format!("{internal_name}: 'host-calls' can only be used in a 'post' directive"));
+    }
+
+    if pre.interrupts.is_some() {
+        return Err(format!("{internal_name}: 'interrupts' can only be used in a 'post' directive"));
+    }
+
+    if pre.out_of_gas_at.is_some() {
+        return Err(format!("{internal_name}: 'out-of-gas-at' can only be used in a 'post' directive"));
+    }
+
+    if pre.waived_regs.iter().any(|is_waived| *is_waived) {
+        return Err(format!("{internal_name}: '<reg> = *' can only be used in a 'post' directive"));
+    }
+
+    if post.out_of_gas_at.is_some() {
+        if post.pc.is_some() {
+            return Err(format!(
+                "{internal_name}: 'post: out-of-gas-at = ...' and 'post: pc = ...' should not be used together"
+            ));
+        }
+
+        if let Some(status) = post.status.as_ref().filter(|status| *status != "out-of-gas") {
+            return Err(format!(
+                "{internal_name}: 'post: out-of-gas-at = ...' contradicts 'post: status = {status}'"
+            ));
+        }
+    }
+
+    for (host_call, values) in on_ecalli {
+        if values.gas.is_some()
+            || values.pc.is_some()
+            || values.out_of_gas_at.is_some()
+            || values.status.is_some()
+            || values.host_calls.is_some()
+            || values.interrupts.is_some()
+            || !values.memory.is_empty()
+            || !values.memory_files.is_empty()
+            || values.waived_regs.iter().any(|is_waived| *is_waived)
+        {
+            return Err(format!(
+                "{internal_name}: 'on-ecalli {host_call}: ...' can only be used to set registers"
+            ));
+        }
+    }
+
+    for (label, values) in assertions {
+        if values.pc.is_some()
+            || values.out_of_gas_at.is_some()
+            || values.status.is_some()
+            || values.host_calls.is_some()
+            || values.interrupts.is_some()
+            || !values.memory.is_empty()
+            || !values.memory_files.is_empty()
+            || !values.label_regs.is_empty()
+            || values.waived_regs.iter().any(|is_waived| *is_waived)
+        {
+            return Err(format!(
+                "{internal_name}: 'assert @{label}: ...' can only be used to check registers and gas"
+            ));
+        }
+    }
+
+    if !post.memory_files.is_empty() {
+        return Err(format!(
+            "{internal_name}: 'memory[...] = file ...' can only be used in a 'pre' directive"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads the files of the `pre: memory[...] = file "<path>"` directives, relative to the directory of the source.
+fn load_memory_files(pre: &mut PrePost, internal_name: &str, source_directory: Option<&Path>) -> Result<(), String> {
+    for (address, path) in core::mem::take(&mut pre.memory_files) {
+        let full_path = match source_directory {
+            Some(directory) => directory.join(&path),
+            None => Path::new(&path).to_owned(),
+        };
+
+        let contents = std::fs::read(&full_path).map_err(|error| {
+            format!(
+                "{internal_name}: 'pre: memory[0x{address:x}] = file \"{path}\"': failed to read '{}': {error}",
+                full_path.display()
+            )
+        })?;
+
+        if contents.is_empty() {
+            return Err(format!(
+                "{internal_name}: 'pre: memory[0x{address:x}] = file \"{path}\"': the file is empty"
+            ));
+        }
+
+        pre.memory.push((address, contents));
+    }
+
+    Ok(())
+}
+
+/// The assembled program of a testcase and the instance on which it's executed, as set up by [`set_up_testcase`].
+struct TestcaseState {
+    parts: ProgramParts,
+    blob: ProgramBlob,
+    module: Module,
+    instance: RawInstance,
+    gas_model: GasModel,
+    checkpoints: Vec<Checkpoint>,
+    initial_regs: [u64; 13],
+    initial_pc: ProgramCounter,
+    initial_gas: i64,
+    initial_page_map: Vec<Page>,
+    initial_memory: Vec<MemoryChunk>,
+    /// Where the initial gas came from, if it wasn't given with 'pre: gas = ...'.
+    gas_source: Option<String>,
+    /// In 32-bit mode only the lower halves of the registers are observable.
+    reg_mask: u64,
+    expected_status: Option<String>,
+    expected_final_pc: u32,
+    out_of_gas_at: Option<u32>,
+}
+
+/// Assembles the program of a testcase and sets up an instance in its initial state.
+fn set_up_testcase(
+    directives: &mut Directives,
+    engine: &Engine,
+    internal_name: &str,
+    is_64_bit: bool,
+    execute: bool,
+    options: &PrepareOptions,
+) -> Result<TestcaseState, String> {
+    let input_lines = &directives.input_lines;
+    let input = input_lines.iter().map(|(_, line)| *line).collect::<Vec<_>>().join("\n");
+    let assembled = assemble_program(&input, is_64_bit)
+        .map_err(|error| format!("Failed to assemble {}", map_assembler_error(&error, internal_name, input_lines)))?;
+
+    let mut parts = ProgramParts::from_bytes(assembled.blob.clone().into()).unwrap();
+    if let Some(stack_size) = directives.stack_size {
+        parts.stack_size = stack_size;
+    }
+    let blob = ProgramBlob::from_parts(parts.clone()).unwrap();
+
+    let resolve_labels = |values: &mut PrePost, directive: &str| {
+        resolve_label_regs(&assembled, &blob, values, directive).map_err(|error| format!("{internal_name}: {error}"))
+    };
+    resolve_labels(&mut directives.pre, "pre")?;
+    resolve_labels(&mut directives.post, "post")?;
+    for (host_call, values) in &mut directives.on_ecalli {
+        resolve_labels(values, &format!("on-ecalli {host_call}"))?;
+    }
+
+    let Directives { pre, post, .. } = &*directives;
+
+    let mut checkpoints = Vec::new();
+    for (label, values) in &directives.assertions {
+        let Some(export) = blob.exports().find(|export| export.symbol().as_bytes() == label.as_bytes()) else {
+            return Err(format!(
+                "{internal_name}: label specified in 'assert @{label}: ...' is missing: @{label} (found: {})",
+                format_exports(&blob)
+            ));
+        };
+
+        checkpoints.push(Checkpoint {
+            label: label.clone(),
+            pc: export.program_counter().0,
+            expected_regs: values
+                .regs
+                .iter()
+                .enumerate()
+                .filter_map(|(nth, value)| {
+                    Some(CheckpointReg {
+                        reg: nth as u8,
+                        value: (*value)?,
+                    })
+                })
+                .collect(),
+            expected_gas: values.gas,
+            reached_at_step: None,
+        });
+    }
+
+    let gas_model = directives.gas_model.unwrap_or(options.gas_model);
+
+    // The gas is capped by the 'gas-limit' directive, so running out of it is only fine if the testcase expects it.
+    let requested_gas = pre.gas.unwrap_or(options.default_gas.unwrap_or(DEFAULT_GAS));
+    let initial_gas = directives.gas_limit.map_or(requested_gas, |limit| requested_gas.min(limit));
+    let gas_source = if initial_gas < requested_gas {
+        Some(format!("the 'gas-limit: {initial_gas}' directive"))
+    } else if pre.gas.is_none() {
+        Some(format!("the default gas of {initial_gas}"))
+    } else {
+        None
+    };
+    let reg_mask = if is_64_bit { u64::MAX } else { u64::from(u32::MAX) };
+    let initial_regs = pre.regs.map(|value| value.unwrap_or(0) & reg_mask);
+
+    let mut module_config = ModuleConfig::default();
+    module_config.set_strict(true);
+    module_config.set_gas_metering(gas_model.metering());
+    module_config.set_step_tracing(true);
+    module_config.set_dynamic_paging(true);
+
+    let module = Module::from_blob(engine, &module_config, blob.clone())
+        .map_err(|error| format!("{internal_name}: failed to load the program: {error}"))?;
+
+    if let Some(stack_size) = directives.stack_size {
+        // The memory map silently rounds the stack size up, which would make the page map differ from what was requested.
+        if stack_size % module.memory_map().page_size() != 0 {
+            return Err(format!(
+                "{internal_name}: invalid 'stack-size: 0x{stack_size:x}': the size must be page aligned (0x{:x} bytes)",
+                module.memory_map().page_size()
+            ));
+        }
+    }
+
+    if let Some(address) = directives
+        .on_fault
+        .keys()
+        .find(|&&address| address % module.memory_map().page_size() != 0)
+    {
+        return Err(format!(
+            "{internal_name}: invalid 'on-fault 0x{address:x}: ...': the address must be page aligned"
+        ));
+    }
+
+    let mut instance = module.instantiate().unwrap();
+
+    let mut initial_page_map = Vec::new();
+    let mut initial_memory = Vec::new();
+
+    if module.memory_map().ro_data_size() > 0 {
+        initial_page_map.push(Page {
+            address: module.memory_map().ro_data_address(),
+            length: module.memory_map().ro_data_size(),
+            is_writable: false,
+        });
+
+        initial_memory.extend(extract_chunks(module.memory_map().ro_data_address(), blob.ro_data(), 0));
+    }
+
+    if module.memory_map().rw_data_size() > 0 {
+        initial_page_map.push(Page {
+            address: module.memory_map().rw_data_address(),
+            length: module.memory_map().rw_data_size(),
+            is_writable: true,
+        });
+
+        initial_memory.extend(extract_chunks(module.memory_map().rw_data_address(), blob.rw_data(), 0));
+    }
+
+    if module.memory_map().stack_size() > 0 {
+        initial_page_map.push(Page {
+            address: module.memory_map().stack_address_low(),
+            length: module.memory_map().stack_size(),
+            is_writable: true,
+        });
+    }
+
+    for directive in &directives.page_directives {
+        apply_page_directive(&mut initial_page_map, &initial_memory, directive, module.memory_map().page_size())
+            .map_err(|error| format!("{internal_name}: {error}"))?;
+    }
+
+    if pre.pc.is_some() && directives.entry_point.is_some() {
+        return Err(format!(
+            "{internal_name}: 'entry: ...' and 'pre: pc = ...' are ambiguous when used together"
+        ));
+    }
+
+    // An explicit 'pre: pc = ...' or 'entry: ...' makes the 'main' export optional.
+    let initial_pc = if let Some(ref label) = directives.entry_point {
+        let Some(export) = blob.exports().find(|export| export.symbol().as_bytes() == label.as_bytes()) else {
+            return Err(format!(
+                "{internal_name}: label specified in 'entry: ...' is missing: @{label} (found: {})",
+                format_exports(&blob)
+            ));
+        };
+        export.program_counter()
+    } else if let Some(ref pc) = pre.pc {
+        let pc = resolve_program_counter(&blob, pc, "pre: pc").map_err(|error| format!("{internal_name}: {error}"))?;
+        if !blob_instructions(&blob).iter().any(|inst| inst.offset == pc) {
+            return Err(format!(
+                "{internal_name}: invalid 'pre: pc = ...': {pc} is not at an instruction boundary"
+            ));
+        }
+        pc
+    } else if let Some(export) = blob.exports().find(|export| export.symbol() == "main") {
+        export.program_counter()
+    } else {
+        return Err(format!(
+            "{internal_name}: missing a 'pub @main' export (found: {})",
+            format_exports(&blob)
+        ));
+    };
+
+    let has_expected_exit = blob.exports().any(|export| export.symbol() == "expected_exit");
+    let out_of_gas_at = match post.out_of_gas_at {
+        Some(ref pc) => Some(
+            resolve_program_counter(&blob, pc, "post: out-of-gas-at")
+                .map_err(|error| format!("{internal_name}: {error}"))?
+                .0,
+        ),
+        None => None,
+    };
+    let has_post_expectations = has_expected_exit
+        || post.status.is_some()
+        || post.pc.is_some()
+        || post.out_of_gas_at.is_some()
+        || post.gas.is_some()
+        || post.regs.iter().any(Option::is_some);
+
+    if !execute && !has_post_expectations {
+        return Err(format!(
+            "{internal_name}: the testcase is not executed, so its expected state must be given with 'post: ...' directives"
+        ));
+    }
+
+    let expected_final_pc = if let Some(export) = blob.exports().find(|export| export.symbol() == "expected_exit") {
+        if post.pc.is_some() {
+            return Err(format!(
+                "{internal_name}: '@expected_exit' label and 'post: pc = ...' should not be used together"
+            ));
+        }
+        if post.out_of_gas_at.is_some() {
+            return Err(format!(
+                "{internal_name}: '@expected_exit' label and 'post: out-of-gas-at = ...' should not be used together"
+            ));
+        }
+        export.program_counter().0
+    } else if let Some(ref pc) = post.pc {
+        resolve_program_counter(&blob, pc, "post: pc")
+            .map_err(|error| format!("{internal_name}: {error}"))?
+            .0
+    } else if let Some(ref pc) = out_of_gas_at {
+        *pc
+    } else {
+        blob.code().len() as u32
+    };
+
+    instance.set_gas(initial_gas);
+    instance.set_next_program_counter(initial_pc);
+
+    for (reg, value) in Reg::ALL.into_iter().zip(initial_regs) {
+        instance.set_reg(reg, value);
+    }
+
+    if module_config.dynamic_paging() {
+        for page in &initial_page_map {
+            instance.zero_memory(page.address, page.length).unwrap();
+            if !page.is_writable {
+                instance.protect_memory(page.address, page.length).unwrap();
+            }
+        }
+
+        for chunk in &initial_memory {
+            instance.write_memory(chunk.address, &chunk.contents).unwrap();
+        }
+    }
+
+    for (address, contents) in &pre.memory {
+        let address_end = u64::from(*address) + contents.len() as u64;
+        let is_mapped = initial_page_map
+            .iter()
+            .any(|page| *address >= page.address && address_end <= u64::from(page.address) + u64::from(page.length));
+
+        if !is_mapped {
+            return Err(format!(
+                "{internal_name}: 'pre: memory[0x{address:x}] = ...' is outside of the initial page map"
+            ));
+        }
+
+        instance.write_memory(*address, contents).unwrap();
+    }
+
+    if !pre.memory.is_empty() || options.max_chunk_gap > 0 {
+        // Read back the whole initial memory so that the chunks from the blob and the directives are merged.
+        initial_memory.clear();
+        for page in &initial_page_map {
+            let memory = instance.read_memory(page.address, page.length).unwrap();
+            initial_memory.extend(extract_chunks(page.address, &memory, options.max_chunk_gap));
+        }
+    }
+
+    let expected_status = post
+        .status
+        .clone()
+        .or_else(|| post.out_of_gas_at.is_some().then(|| "out-of-gas".to_owned()));
+
+    Ok(TestcaseState {
+        parts,
+        blob,
+        module,
+        instance,
+        gas_model,
+        checkpoints,
+        initial_regs,
+        initial_pc,
+        initial_gas,
+        initial_page_map,
+        initial_memory,
+        gas_source,
+        reg_mask,
+        expected_status,
+        expected_final_pc,
+        out_of_gas_at,
+    })
+}
+
+/// How the execution of a testcase ended, as observed by [`execute_testcase`].
+struct Execution {
+    final_status: String,
+    page_fault_address: Option<u32>,
+    final_pc: ProgramCounter,
+    host_calls: Vec<u32>,
+    interrupts: Vec<Interrupt>,
+    step_count: u64,
+    faults_handled: Vec<HandledFault>,
+    trace: Option<Trace>,
+}
+
+/// Runs a testcase until it ends, emulating the host as the directives say.
+///
+/// Without execution the outcome is whatever the 'post' directives say it is.
+fn execute_testcase(
+    state: &mut TestcaseState,
+    directives: &Directives,
+    internal_name: &str,
+    execute: bool,
+    record_trace: bool,
+) -> Result<Execution, String> {
+    let mut final_pc = state.initial_pc;
+    let mut host_calls = Vec::new();
+    let mut interrupts = Vec::new();
+    let mut step_count = 0;
+    let mut faults_handled: Vec<HandledFault> = Vec::new();
+    let mut trace = if execute && record_trace {
+        Some(Trace::new(state.initial_regs))
+    } else {
+        None
+    };
+
+    let instance = &mut state.instance;
+    let (final_status, page_fault_address) = if execute {
+        loop {
+            let interrupt = instance
+                .run()
+                .map_err(|error| format!("{internal_name}: failed to execute the program: {error}"))?;
+            if let Some(ref mut trace) = trace {
+                trace.on_interrupt(instance);
+            }
+
+            match interrupt {
+                InterruptKind::Finished => break ("halt", None),
+                InterruptKind::Trap => break ("panic", None),
+                InterruptKind::Ecalli(host_call) => {
+                    // Emulate the host: resume right after the 'ecalli', optionally overriding some registers.
+                    host_calls.push(host_call);
+                    interrupts.push(Interrupt {
+                        host_call: Some(host_call),
+                        ..Interrupt::new("host")
+                    });
+                    if let Some(values) = directives.on_ecalli.get(&host_call) {
+                        for (reg, value) in Reg::ALL.into_iter().zip(values.regs) {
+                            if let Some(value) = value {
+                                instance.set_reg(reg, value);
+                            }
+                        }
+                    }
+
+                    if let Some(ref mut trace) = trace {
+                        trace.sync_regs(instance);
+                    }
+                    continue;
+                }
+                InterruptKind::NotEnoughGas => break ("out-of-gas", None),
+                InterruptKind::Segfault(segfault) if !directives.on_fault.is_empty() => {
+                    // Emulate the host: map the page and resume, re-executing the faulting instruction.
+                    let address = segfault.page_address;
+                    let Some(&action) = directives.on_fault.get(&address) else {
+                        return Err(format!(
+                            "{internal_name}: unexpected page fault at 0x{address:x}: there's no 'on-fault' directive for it"
+                        ));
+                    };
+
+                    if faults_handled.iter().any(|fault| fault.address == address) {
+                        return Err(format!("{internal_name}: page fault at 0x{address:x} after it was already handled"));
+                    }
+
+                    faults_handled.push(HandledFault {
+                        address,
+                        action: action.to_owned(),
+                    });
+
+                    if action == "abort" {
+                        break ("page-fault", Some(address));
+                    }
+
+                    interrupts.push(Interrupt {
+                        address: Some(address),
+                        ..Interrupt::new("page-fault")
+                    });
+                    map_faulted_page(instance, &segfault, action).map_err(|error| format!("{internal_name}: {error}"))?;
+                    continue;
+                }
+                InterruptKind::Segfault(segfault) => break ("page-fault", Some(segfault.page_address)),
+                InterruptKind::Step => {
+                    if !state.gas_model.charge_step(instance) {
+                        break ("out-of-gas", None);
+                    }
+
+                    step_count += 1;
+                    final_pc = instance.program_counter().unwrap();
+                    if let Some(ref mut trace) = trace {
+                        trace.on_step(final_pc);
+                    }
+
+                    for checkpoint in &mut state.checkpoints {
+                        if checkpoint.pc == final_pc.0 && checkpoint.reached_at_step.is_none() {
+                            checkpoint.reached_at_step = Some(step_count);
+                            check_checkpoint(instance, checkpoint).map_err(|error| {
+                                format!(
+                                    "{internal_name}: 'assert @{}: ...' failed at step {step_count}: {error}",
+                                    checkpoint.label
+                                )
+                            })?;
+                        }
+                    }
+                    continue;
+                }
+            }
+        }
+    } else {
+        final_pc.0 = state.expected_final_pc;
+        host_calls = directives.post.host_calls.clone().unwrap_or_default();
+        interrupts = directives.post.interrupts.clone().unwrap_or_default();
+        (state.expected_status.as_deref().unwrap_or("ok"), None)
+    };
+
+    if execute {
+        interrupts.push(Interrupt {
+            address: page_fault_address,
+            ..Interrupt::new(final_status)
+        });
+    }
+
+    if final_status != "halt" {
+        final_pc = instance
+            .program_counter()
+            .unwrap_or_else(|| ProgramCounter(state.expected_final_pc));
+    }
+
+    Ok(Execution {
+        final_status: final_status.to_owned(),
+        page_fault_address,
+        final_pc,
+        host_calls,
+        interrupts,
+        step_count,
+        faults_handled,
+        trace,
+    })
+}
+
+/// Checks the outcome of a testcase against its 'post' directives.
+///
+/// The mismatches in the final state are all reported at once, one per line.
+fn check_post_conditions(
+    state: &TestcaseState,
+    directives: &Directives,
+    execution: &Execution,
+    expected_regs: &[u64],
+    expected_gas: i64,
+    internal_name: &str,
+    execute: bool,
+) -> Result<(), String> {
+    let post = &directives.post;
+    let instance = &state.instance;
+    let final_status = execution.final_status.as_str();
+    let final_pc = execution.final_pc;
+
+    if let Some(ref gas_source) = state.gas_source {
+        if final_status == "out-of-gas" && state.expected_status.as_deref() != Some("out-of-gas") {
+            return Err(format!(
+                "{internal_name}: ran out of the gas given by {gas_source}; give it more with 'pre: gas = ...' or declare 'post: status = out-of-gas'"
+            ));
+        }
+    }
+
+    if let Some(out_of_gas_at) = state.out_of_gas_at.filter(|_| execute) {
+        if final_status != "out-of-gas" {
+            return Err(format!(
+                "{internal_name}: expected to run out of gas at pc {out_of_gas_at}, but the program ended with: {final_status}"
+            ));
+        } else if final_pc.0 != out_of_gas_at {
+            return Err(format!(
+                "{internal_name}: expected to run out of gas at pc {out_of_gas_at}, but it ran out at pc {final_pc}"
+            ));
+        }
+    }
+
+    if let Some(ref expected_status) = state.expected_status {
+        if final_status != expected_status.as_str() {
+            return Err(format!(
+                "Unexpected final status for {internal_name}: expected {expected_status}, is {final_status}"
+            ));
+        }
+    }
+
+    let expected_final_pc = state.expected_final_pc;
+    if execute && final_pc.0 != expected_final_pc {
+        return Err(format!(
+            "Unexpected final program counter for {internal_name}: expected {expected_final_pc}, is {final_pc}"
+        ));
+    }
+
+    if !post.memory.is_empty() {
+        let page_size = state.module.memory_map().page_size();
+        let mapped_pages = find_mapped_pages(instance, page_size);
+        for (address, contents) in &post.memory {
+            let first_page = u64::from(address - address % page_size);
+            let end = u64::from(*address) + contents.len() as u64;
+            let unmapped = (first_page..end)
+                .step_by(page_size as usize)
+                .find(|page_address| u32::try_from(*page_address).map_or(true, |page_address| !mapped_pages.contains(&page_address)));
+            if let Some(page_address) = unmapped {
+                return Err(format!(
+                    "{internal_name}: invalid 'post: memory[0x{address:x}] = ...': the page at 0x{page_address:x} is not mapped"
+                ));
+            }
+        }
+    }
+
+    if !execute {
+        return Ok(());
+    }
+
+    let mut mismatches = Vec::new();
+    for ((final_value, reg), required_value) in expected_regs.iter().zip(Reg::ALL).zip(post.regs.iter()) {
+        if let Some(required_value) = required_value {
+            if final_value != required_value {
+                mismatches.push(format!(
+                    "{internal_name}: unexpected {reg}: 0x{final_value:x} (expected: 0x{required_value:x})"
+                ));
+            }
+        }
+    }
+
+    if let Some(post_gas) = post.gas {
+        if expected_gas != post_gas {
+            mismatches.push(format!("{internal_name}: unexpected gas: {expected_gas} (expected: {post_gas})"));
+        }
+    }
+
+    for (address, required_contents) in &post.memory {
+        let final_contents = instance.read_memory(*address, required_contents.len() as u32).map_err(to_string)?;
+        for mismatch in diff_memory(*address, required_contents, &final_contents) {
+            mismatches.push(format!("{internal_name}: {mismatch}"));
+        }
+    }
+
+    if let Some(ref post_host_calls) = post.host_calls {
+        if execution.host_calls != *post_host_calls {
+            mismatches.push(format!(
+                "{internal_name}: unexpected host calls: [{}] (expected: [{}])",
+                format_host_calls(&execution.host_calls),
+                format_host_calls(post_host_calls)
+            ));
+        }
+    }
+
+    if let Some(ref post_interrupts) = post.interrupts {
+        if execution.interrupts != *post_interrupts {
+            mismatches.push(format!(
+                "{internal_name}: unexpected interrupts: [{}] (expected: [{}])",
+                format_interrupts(&execution.interrupts),
+                format_interrupts(post_interrupts)
+            ));
+        }
+    }
+
+    if !mismatches.is_empty() {
+        return Err(mismatches.join("\n"));
+    }
+
+    if directives.strict_post {
+        let initial_regs = &state.initial_regs;
+        let unasserted: Vec<_> = Reg::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(nth, _)| post.regs[*nth].is_none() && !post.waived_regs[*nth] && expected_regs[*nth] != initial_regs[*nth])
+            .map(|(nth, reg)| format!("{reg} (0x{:x} -> 0x{:x})", initial_regs[nth], expected_regs[nth]))
+            .collect();
+
+        if !unasserted.is_empty() {
+            return Err(format!(
+                "{internal_name}: 'strict-post': unasserted register changes: {}; assert them with 'post: <reg> = ...' or waive them with 'post: <reg> = *'",
+                unasserted.join(", ")
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes a testcase again with another backend, and checks that it ends in the same state.
+fn cross_check_testcase(
+    engine: &Engine,
+    state: &TestcaseState,
+    directives: &Directives,
+    execution: &Execution,
+    expected_regs: &[u64],
+    expected_gas: i64,
+    internal_name: &str,
+) -> Result<(), String> {
+    let mut final_pages = Vec::new();
+    for page in &state.initial_page_map {
+        final_pages.push((page.address, state.instance.read_memory(page.address, page.length).unwrap()));
+    }
+
+    // The flat gas model needs step tracing, so the cross-check also knows where the program halted.
+    let is_traced = state.gas_model != GasModel::Polkavm;
+    let expected = Outcome {
+        status: execution.final_status.clone(),
+        page_fault_address: execution.page_fault_address,
+        pc: if execution.final_status == "halt" && !is_traced {
+            None
+        } else {
+            Some(execution.final_pc.0)
+        },
+        regs: expected_regs.to_vec(),
+        gas: expected_gas,
+        pages: final_pages,
+        host_calls: execution.host_calls.clone(),
+        step_count: None,
+    };
+
+    let setup = Setup {
+        blob: &state.blob,
+        initial_pc: state.initial_pc,
+        initial_regs: state.initial_regs,
+        initial_gas: state.initial_gas,
+        initial_page_map: &state.initial_page_map,
+        initial_memory: &state.initial_memory,
+        on_ecalli: &directives.on_ecalli,
+        on_fault: &directives.on_fault,
+        gas_model: state.gas_model,
+    };
+
+    let actual =
+        execute_without_tracing(engine, &setup).map_err(|error| format!("{internal_name}: cross-check execution failed: {error}"))?;
+
+    let differences = diff_outcomes(&expected, &actual);
+    if !differences.is_empty() {
+        return Err(format!(
+            "{internal_name}: the compiler backend diverged from the interpreter:\n{}",
+            differences.join("\n")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks the structural invariants of a testcase without executing it.
+///
+/// Returns a description of every violation, each prefixed with the name of the offending field.
+pub fn verify_testcase(testcase: &TestcaseJson) -> Vec<String> {
+    let mut errors = Vec::new();
+    if testcase.expected_regs.len() != Reg::ALL.len() {
+        errors.push(format!(
+            "expected-regs: expected {} registers, found {}",
+            Reg::ALL.len(),
+            testcase.expected_regs.len()
+        ));
+    }
+
+    let mut pages: Vec<&Page> = testcase.initial_page_map.iter().collect();
+    pages.sort_by_key(|page| page.address);
+    for page in &pages {
+        if page.length == 0 || u64::from(page.address) + u64::from(page.length) > 0x100000000 {
+            errors.push(format!(
+                "initial-page-map: invalid region at 0x{:x} (0x{:x} bytes)",
+                page.address, page.length
+            ));
+        }
+    }
+
+    for pair in pages.windows(2) {
+        if u64::from(pair[0].address) + u64::from(pair[0].length) > u64::from(pair[1].address) {
+            errors.push(format!(
+                "initial-page-map: the region at 0x{:x} overlaps with the region at 0x{:x}",
+                pair[0].address, pair[1].address
+            ));
+        }
+    }
+
+    // The guest can map new pages at runtime, so only the initial memory is bound to the initial page map.
+    for (field, chunks, must_be_mapped) in [
+        ("initial-memory", &testcase.initial_memory, true),
+        ("expected-memory", &testcase.expected_memory, false),
+    ] {
+        let mut chunks: Vec<&MemoryChunk> = chunks.iter().collect();
+        chunks.sort_by_key(|chunk| chunk.address);
+        for chunk in &chunks {
+            let start = u64::from(chunk.address);
+            let end = start + chunk.contents.len() as u64;
+            if chunk.contents.is_empty() {
+                errors.push(format!("{field}: the chunk at 0x{:x} is empty", chunk.address));
+            } else if must_be_mapped
+                && !pages
+                    .iter()
+                    .any(|page| start >= u64::from(page.address) && end <= u64::from(page.address) + u64::from(page.length))
+            {
+                errors.push(format!(
+                    "{field}: the chunk at 0x{:x} (0x{:x} bytes) is outside of the initial page map",
+                    chunk.address,
+                    chunk.contents.len()
+                ));
+            }
+        }
+
+        for pair in chunks.windows(2) {
+            if u64::from(pair[0].address) + pair[0].contents.len() as u64 > u64::from(pair[1].address) {
+                errors.push(format!(
+                    "{field}: the chunk at 0x{:x} overlaps with the chunk at 0x{:x}",
+                    pair[0].address, pair[1].address
+                ));
+            }
+        }
+    }
+
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = testcase.program.clone().into();
+    parts.is_64_bit = true;
+    match ProgramBlob::from_parts(parts) {
+        Ok(blob) => {
+            let mut boundaries: BTreeSet<u32> = blob.instructions(ISA64_V1).map(|instruction| instruction.offset.0).collect();
+            boundaries.insert(blob.code().len() as u32);
+            for (field, pc) in [("initial-pc", testcase.initial_pc), ("expected-pc", testcase.expected_pc)] {
+                if !boundaries.contains(&pc) {
+                    errors.push(format!("{field}: {pc} is not at an instruction boundary"));
+                }
+            }
+
+            for checkpoint in testcase.checkpoints.iter().flatten() {
+                if !boundaries.contains(&checkpoint.pc) {
+                    errors.push(format!(
+                        "checkpoints: the pc of @{} ({}) is not at an instruction boundary",
+                        checkpoint.label, checkpoint.pc
+                    ));
+                }
+            }
+        }
+        Err(error) => errors.push(format!("program: failed to parse: {}", to_string(error))),
+    }
+
+    if let Err(error) = verify_program_hash(testcase) {
+        errors.push(error);
+    }
+
+    if !STATUSES.contains(&testcase.expected_status.as_str()) {
+        errors.push(format!("expected-status: unknown status '{}'", testcase.expected_status));
+    }
+
+    let is_page_fault = testcase.expected_status == "page-fault";
+    if is_page_fault != testcase.expected_page_fault_address.is_some() {
+        errors.push("expected-page-fault-address: must be present if and only if the status is 'page-fault'".to_owned());
+    }
+
+    if !is_page_fault && testcase.expected_page_fault_access.is_some() {
+        errors.push("expected-page-fault-access: must only be present if the status is 'page-fault'".to_owned());
+    }
+
+    if let Some(ref interrupts) = testcase.expected_interrupts {
+        let last_kind = interrupts.last().map(|interrupt| interrupt.kind.as_str());
+        if last_kind != Some(testcase.expected_status.as_str()) {
+            errors.push("expected-interrupts: the last interrupt doesn't match the expected status".to_owned());
+        }
+    }
+
+    for checkpoint in testcase.checkpoints.iter().flatten() {
+        if let Some(expected) = checkpoint
+            .expected_regs
+            .iter()
+            .find(|expected| usize::from(expected.reg) >= Reg::ALL.len())
+        {
+            errors.push(format!(
+                "checkpoints: @{}: invalid register index {}",
+                checkpoint.label, expected.reg
+            ));
+        }
+    }
+
+    if let Some(exit_code) = testcase.expected_exit_code {
+        if testcase.expected_status != "halt" {
+            errors.push("expected-exit-code: must only be present if the status is 'halt'".to_owned());
+        } else if testcase.expected_regs.get(Reg::A0 as usize) != Some(&exit_code) {
+            errors.push("expected-exit-code: doesn't match the final value of a0".to_owned());
+        }
+    }
+
+    errors
+}
+
+/// Reconstructs a testcase from its already generated JSON.
+pub fn testcase_from_json(json: TestcaseJson) -> Result<Testcase, String> {
+    testcase_from_json_with_style(json, DisassemblyStyle::default())
+}
+
+/// Disassembles a program (its code and jump table) in the default style, as stored next to the generated testcases.
+pub fn canonical_disassembly(program: &[u8]) -> Result<String, String> {
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = program.to_vec().into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+    Ok(disassemble_testcase(&blob, DisassemblyStyle::default(), GasModel::Polkavm))
+}
+
+/// Like [`testcase_from_json`], but renders the disassembly in the given style.
+pub fn testcase_from_json_with_style(json: TestcaseJson, disassembly_style: DisassemblyStyle) -> Result<Testcase, String> {
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = json.program.clone().into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+
+    Ok(Testcase {
+        disassembly: disassemble_testcase(&blob, disassembly_style, json.gas_model.unwrap_or_default()),
+        block_costs: json
+            .gas_model
+            .unwrap_or_default()
+            .apply_to_block_costs(basic_block_costs(&new_engine(), &blob)?),
+        json,
+        trace: None,
+        entry_point: None,
+        stack_size: None,
+        // Just as everywhere else, the JSON testcases are assumed to be 64-bit ones since they don't record their mode.
+        is_64_bit: true,
+        category: None,
+        out_of_gas_at: None,
+    })
+}
+
+/// Wraps a raw program (its code and jump table) into a testcase by executing it with the interpreter.
+///
+/// This is [`prepare_input`] without the assembly: the program starts at its first instruction without any memory,
+/// and whatever state it ends in is recorded as the expected one, even if it faults right away.
+pub fn wrap_program(engine: &Engine, name: &str, program: Vec<u8>, initial_regs: [u64; 13], initial_gas: i64) -> Result<Testcase, String> {
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = program.into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts.clone()).map_err(to_string)?;
+
+    // Step tracing is needed to count the steps and to know where the program halted.
+    let mut module_config = module_config_for(GasModel::Polkavm);
+    module_config.set_step_tracing(true);
+    let module = Module::from_blob(engine, &module_config, blob.clone()).map_err(to_string)?;
+
+    let initial_pc = ProgramCounter(0);
+    let setup = Setup {
+        blob: &blob,
+        initial_pc,
+        initial_regs,
+        initial_gas,
+        initial_page_map: &[],
+        initial_memory: &[],
+        on_ecalli: &BTreeMap::new(),
+        on_fault: &BTreeMap::new(),
+        gas_model: GasModel::Polkavm,
+    };
+
+    let outcome = execute_module(&module, &setup)?;
+    let expected_pc = outcome.pc.unwrap_or(initial_pc.0);
+    let page_fault_access = outcome
+        .page_fault_address
+        .and_then(|_| memory_access_kind(&blob, ProgramCounter(expected_pc)));
+
+    Ok(Testcase {
+        disassembly: disassemble_testcase(&blob, DisassemblyStyle::default(), GasModel::Polkavm),
+        json: TestcaseJson {
+            name: name.into(),
+            description: None,
+            initial_regs,
+            initial_pc: initial_pc.0,
+            initial_page_map: Vec::new(),
+            initial_memory: Vec::new(),
+            initial_gas,
+            program: parts.code_and_jump_table.to_vec(),
+            program_file: None,
+            program_ref: None,
+            program_hash_algorithm: Some(PROGRAM_HASH_ALGORITHMS[0].to_owned()),
+            program_hash: program_hash(PROGRAM_HASH_ALGORITHMS[0], &parts.code_and_jump_table),
+            expected_exit_code: (outcome.status == "halt").then_some(outcome.regs[Reg::A0.to_usize()]),
+            expected_status: outcome.status,
+            expected_regs: outcome.regs,
+            expected_pc,
+            expected_memory: Vec::new(),
+            expected_gas: outcome.gas,
+            expected_page_fault_address: outcome.page_fault_address,
+            expected_page_fault_access: page_fault_access.map(ToOwned::to_owned),
+            expected_host_calls: if outcome.host_calls.is_empty() {
+                None
+            } else {
+                Some(outcome.host_calls)
+            },
+            expected_interrupts: None,
+            expected_step_count: outcome.step_count,
+            faults_handled: None,
+            checkpoints: None,
+            gas_model: None,
+            format_version: Some(FORMAT_VERSION),
+            generator: Some(GENERATOR.to_owned()),
+        },
+        trace: None,
+        block_costs: block_costs_of_module(&module, &blob),
+        entry_point: None,
+        stack_size: None,
+        is_64_bit: true,
+        category: None,
+        out_of_gas_at: None,
+    })
+}
+
+/// Parses register assignments such as `a0=9` or `r7=0x10` into the initial registers; the other registers are zeroed.
+pub fn parse_reg_assignments(assignments: &[String]) -> Result<[u64; 13], String> {
+    let mut regs = [0; 13];
+    for assignment in assignments {
+        let (reg, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("invalid register assignment '{assignment}': no '=' found"))?;
+        let reg =
+            polkavm_common::utils::parse_reg(reg).ok_or_else(|| format!("invalid register assignment '{assignment}': unknown register"))?;
+        let value: u64 = polkavm_common::utils::parse_immediate(value)
+            .map(Into::into)
+            .ok_or_else(|| format!("invalid register assignment '{assignment}': failed to parse the value"))?;
+        regs[reg as usize] = value;
+    }
+
+    Ok(regs)
+}
+
+/// Overrides the initial gas and registers of a source, as if it had the corresponding `pre:` directives.
+///
+/// The registers are given by their names (e.g. `a0` or `r7`). Every `pre:` directive of the source which sets one of
+/// the overridden values is blanked out, keeping the line numbers intact, and reported in the returned warnings.
+pub fn override_pre_directives(
+    input: &str,
+    initial_gas: Option<i64>,
+    initial_regs: &BTreeMap<String, u64>,
+) -> Result<(String, Vec<String>), String> {
+    let mut regs = Vec::new();
+    for (name, value) in initial_regs {
+        let reg = polkavm_common::utils::parse_reg(name).ok_or_else(|| format!("invalid register override '{name}': unknown register"))?;
+        regs.push((reg, *value));
+    }
+
+    let mut output = String::new();
+    let mut warnings = Vec::new();
+    for (nth_line, line) in input.lines().enumerate() {
+        let lhs = line
+            .strip_prefix("pre:")
+            .and_then(|directive| directive.split_once('='))
+            .map(|(lhs, _)| lhs.trim());
+        let is_overridden = match lhs {
+            Some("gas") => initial_gas.is_some(),
+            Some(lhs) => polkavm_common::utils::parse_reg(lhs).map_or(false, |lhs| regs.iter().any(|(reg, _)| *reg == lhs)),
+            None => false,
+        };
+
+        if is_overridden {
+            warnings.push(format!("line {}: '{}' is overridden by the options", nth_line + 1, line.trim()));
+        } else {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+
+    if let Some(gas) = initial_gas {
+        writeln!(&mut output, "pre: gas = {gas}").unwrap();
+    }
+    for (reg, value) in regs {
+        writeln!(&mut output, "pre: {} = 0x{value:x}", reg.name()).unwrap();
+    }
+
+    Ok((output, warnings))
+}
+
+fn disassemble_testcase(blob: &ProgramBlob, style: DisassemblyStyle, gas_model: GasModel) -> String {
+    let mut disassembler = polkavm_disassembler::Disassembler::new(blob, polkavm_disassembler::DisassemblyFormat::Guest).unwrap();
+    disassembler.show_raw_bytes(style.show_raw_bytes);
+    disassembler.prefer_non_abi_reg_names(!style.prefer_abi_reg_names);
+    disassembler.prefer_unaliased(true);
+    disassembler.prefer_offset_jump_targets(style.prefer_offset_jump_targets);
+    disassembler.prefer_export_jump_targets(style.prefer_export_jump_targets);
+    disassembler.prefer_hex_offsets(style.prefer_hex_offsets);
+    if style.show_gas_costs {
+        match gas_model {
+            GasModel::Polkavm => disassembler.show_gas_costs(true).unwrap(),
+            GasModel::Flat { cost_per_instruction } => disassembler.show_gas_costs_with(|_| i64::from(cost_per_instruction)),
+        }
+    }
+    disassembler.emit_header(false);
+    disassembler.emit_exports(false);
+
+    let mut disassembly = Vec::new();
+    disassembler.disassemble_into(&mut disassembly).unwrap();
+    String::from_utf8(disassembly).unwrap()
+}
+
+/// Decodes the instruction at `pc` to find out whether it reads or writes memory.
+fn memory_access_kind(blob: &ProgramBlob, pc: ProgramCounter) -> Option<&'static str> {
+    let instruction = if blob.is_64_bit() {
+        blob.instructions_bounded_at(ISA64_V1, pc).next()?
+    } else {
+        blob.instructions_bounded_at(ISA32_V1, pc).next()?
+    };
+    match instruction.kind.opcode() {
+        Opcode::load_u8
+        | Opcode::load_i8
+        | Opcode::load_u16
+        | Opcode::load_i16
+        | Opcode::load_u32
+        | Opcode::load_i32
+        | Opcode::load_u64
+        | Opcode::load_indirect_u8
+        | Opcode::load_indirect_i8
+        | Opcode::load_indirect_u16
+        | Opcode::load_indirect_i16
+        | Opcode::load_indirect_u32
+        | Opcode::load_indirect_i32
+        | Opcode::load_indirect_u64 => Some("read"),
+        Opcode::store_u8
+        | Opcode::store_u16
+        | Opcode::store_u32
+        | Opcode::store_u64
+        | Opcode::store_imm_u8
+        | Opcode::store_imm_u16
+        | Opcode::store_imm_u32
+        | Opcode::store_imm_u64
+        | Opcode::store_indirect_u8
+        | Opcode::store_indirect_u16
+        | Opcode::store_indirect_u32
+        | Opcode::store_indirect_u64
+        | Opcode::store_imm_indirect_u8
+        | Opcode::store_imm_indirect_u16
+        | Opcode::store_imm_indirect_u32
+        | Opcode::store_imm_indirect_u64
+        | Opcode::memset => Some("write"),
+        _ => None,
+    }
+}
+
+/// Records one line per executed instruction: its program counter, the gas remaining
+/// after it was executed, and the registers which it modified.
+struct Trace {
+    output: String,
+    current_pc: Option<ProgramCounter>,
+    regs: [u64; 13],
+}
+
+impl Trace {
+    fn new(regs: [u64; 13]) -> Self {
+        Trace {
+            output: String::new(),
+            current_pc: None,
+            regs,
+        }
+    }
+
+    fn on_step(&mut self, pc: ProgramCounter) {
+        self.current_pc = Some(pc);
+    }
+
+    /// Finishes the line of the instruction which was executed before the VM was interrupted.
+    fn on_interrupt(&mut self, instance: &RawInstance) {
+        let Some(pc) = self.current_pc.take() else {
+            return;
+        };
+
+        write!(&mut self.output, "pc = {pc}, gas = {}", instance.gas()).unwrap();
+        for reg in Reg::ALL {
+            let value = instance.reg(reg);
+            if value != self.regs[reg as usize] {
+                write!(&mut self.output, ", {reg} = 0x{value:x}").unwrap();
+            }
+        }
+
+        self.output.push('\n');
+        self.sync_regs(instance);
+    }
+
+    /// Makes sure that registers modified by the host aren't attributed to the next instruction.
+    fn sync_regs(&mut self, instance: &RawInstance) {
+        for reg in Reg::ALL {
+            self.regs[reg as usize] = instance.reg(reg);
+        }
+    }
+}
+
+/// Everything which is needed to run a testcase from scratch.
+struct Setup<'a> {
+    blob: &'a ProgramBlob,
+    initial_pc: ProgramCounter,
+    initial_regs: [u64; 13],
+    initial_gas: i64,
+    initial_page_map: &'a [Page],
+    initial_memory: &'a [MemoryChunk],
+    on_ecalli: &'a BTreeMap<u32, PrePost>,
+    on_fault: &'a BTreeMap<u32, &'static str>,
+    /// The module must be loaded with [`module_config_for`] this model.
+    gas_model: GasModel,
+}
+
+/// Returns why a testcase can't be benchmarked with [`benchmark_testcase`], if it can't.
+pub fn benchmark_exclusion_reason(testcase: &TestcaseJson) -> Option<&'static str> {
+    if testcase.expected_host_calls.is_some() || testcase.expected_status == "host" {
+        Some("ends in a host call")
+    } else if testcase.faults_handled.is_some() || testcase.expected_status == "page-fault" {
+        Some("ends in a page fault")
+    } else if testcase.expected_step_count.is_none() {
+        Some("has no expected-step-count")
+    } else if testcase.gas_model.map_or(false, |gas_model| gas_model != GasModel::Polkavm) {
+        Some("uses a gas model which needs step tracing")
+    } else {
+        None
+    }
+}
+
+/// Executes a testcase `iterations` times without step tracing, returning the total time it took.
+///
+/// The program is only loaded once, but every iteration runs on a fresh instance.
+pub fn benchmark_testcase(engine: &Engine, testcase: &TestcaseJson, iterations: u32) -> Result<std::time::Duration, String> {
+    if let Some(reason) = benchmark_exclusion_reason(testcase) {
+        return Err(format!("the testcase can't be benchmarked: it {reason}"));
+    }
+
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = testcase.program.clone().into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+    let module = Module::from_blob(engine, &module_config_for(GasModel::Polkavm), blob.clone()).map_err(to_string)?;
+
+    let on_ecalli = BTreeMap::new();
+    let on_fault = BTreeMap::new();
+    let setup = Setup {
+        blob: &blob,
+        initial_pc: ProgramCounter(testcase.initial_pc),
+        initial_regs: testcase.initial_regs,
+        initial_gas: testcase.initial_gas,
+        initial_page_map: &testcase.initial_page_map,
+        initial_memory: &testcase.initial_memory,
+        on_ecalli: &on_ecalli,
+        on_fault: &on_fault,
+        gas_model: GasModel::Polkavm,
+    };
+
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        let outcome = execute_module(&module, &setup)?;
+        if outcome.status != testcase.expected_status {
+            return Err(format!(
+                "the execution ended with '{}' instead of '{}'",
+                outcome.status, testcase.expected_status
+            ));
+        }
+    }
+
+    Ok(start.elapsed())
+}
+
+/// A field of a testcase whose actual value differs from the expected one.
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Mismatch {
+    /// The mismatching field, e.g. `expected-gas`, `expected-regs[7]` or `expected-memory[0x20000]`.
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The result of running a testcase with [`run_testcase`].
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TestRunReport {
+    pub name: String,
+    /// Whether all of the expectations were met, in which case there are no `mismatches`.
+    pub passed: bool,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Runs a testcase and compares the results with its expectations.
+///
+/// The program must be embedded, so a `program-ref` has to be resolved with [`resolve_program_ref`] first. Host calls
+/// resume the execution without modifying any registers, since a testcase doesn't describe what the host does.
+pub fn run_testcase(engine: &Engine, testcase: &TestcaseJson) -> Result<TestRunReport, String> {
+    if testcase.program_ref.is_some() {
+        return Err("program-ref: the program must be embedded; resolve the reference first".to_owned());
+    }
+
+    verify_program_hash(testcase)?;
+
+    let mut on_fault = BTreeMap::new();
+    for fault in testcase.faults_handled.iter().flatten() {
+        let Some(action) = FAULT_ACTIONS.iter().find(|action| **action == fault.action) else {
+            return Err(format!("faults-handled: unknown action '{}'", fault.action));
+        };
+
+        on_fault.insert(fault.address, *action);
+    }
+
+    let mut parts = ProgramParts::default();
+    parts.code_and_jump_table = testcase.program.clone().into();
+    parts.is_64_bit = true;
+    let blob = ProgramBlob::from_parts(parts).map_err(to_string)?;
+
+    // Step tracing is needed to count the steps and to know where the program halted.
+    let gas_model = testcase.gas_model.unwrap_or_default();
+    let mut module_config = module_config_for(gas_model);
+    module_config.set_step_tracing(true);
+    let module = Module::from_blob(engine, &module_config, blob.clone()).map_err(to_string)?;
+
+    let on_ecalli = BTreeMap::new();
+    let setup = Setup {
+        blob: &blob,
+        initial_pc: ProgramCounter(testcase.initial_pc),
+        initial_regs: testcase.initial_regs,
+        initial_gas: testcase.initial_gas,
+        initial_page_map: &testcase.initial_page_map,
+        initial_memory: &testcase.initial_memory,
+        on_ecalli: &on_ecalli,
+        on_fault: &on_fault,
+        gas_model,
+    };
+
+    let outcome = execute_module(&module, &setup)?;
+
+    fn format_optional<T: core::fmt::Display>(value: Option<T>) -> String {
+        value.map_or_else(|| "none".to_owned(), |value| value.to_string())
+    }
+
+    fn format_bytes(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    let mut mismatches = Vec::new();
+    let mut check = |field: String, expected: String, actual: String| {
+        if expected != actual {
+            mismatches.push(Mismatch { field, expected, actual });
+        }
+    };
+
+    check(
+        "expected-status".to_owned(),
+        testcase.expected_status.clone(),
+        outcome.status.clone(),
+    );
+    check(
+        "expected-pc".to_owned(),
+        testcase.expected_pc.to_string(),
+        format_optional(outcome.pc),
+    );
+    check(
+        "expected-gas".to_owned(),
+        testcase.expected_gas.to_string(),
+        outcome.gas.to_string(),
+    );
+    for (nth, (expected, actual)) in testcase.expected_regs.iter().zip(&outcome.regs).enumerate() {
+        check(format!("expected-regs[{nth}]"), expected.to_string(), actual.to_string());
+    }
+
+    check(
+        "expected-page-fault-address".to_owned(),
+        format_optional(testcase.expected_page_fault_address),
+        format_optional(outcome.page_fault_address),
+    );
+
+    if let Some(exit_code) = testcase.expected_exit_code {
+        let actual = (outcome.status == "halt").then_some(outcome.regs[Reg::A0.to_usize()]);
+        check("expected-exit-code".to_owned(), exit_code.to_string(), format_optional(actual));
+    }
+
+    if let Some(ref host_calls) = testcase.expected_host_calls {
+        check(
+            "expected-host-calls".to_owned(),
+            format_host_calls(host_calls),
+            format_host_calls(&outcome.host_calls),
+        );
+    }
+
+    if let Some(step_count) = testcase.expected_step_count {
+        check(
+            "expected-step-count".to_owned(),
+            step_count.to_string(),
+            format_optional(outcome.step_count),
+        );
+    }
+
+    let read_memory = |address: u32, length: usize| {
+        outcome.pages.iter().find_map(|(page_address, contents)| {
+            let offset = address.checked_sub(*page_address)? as usize;
+            contents.get(offset..offset + length)
+        })
+    };
+
+    for chunk in &testcase.expected_memory {
+        check(
+            format!("expected-memory[0x{:x}]", chunk.address),
+            format_bytes(&chunk.contents),
+            read_memory(chunk.address, chunk.contents.len()).map_or_else(|| "unmapped".to_owned(), format_bytes),
+        );
+    }
+
+    // Anything which isn't covered by the expected chunks must have stayed zeroed.
+    for (page_address, contents) in &outcome.pages {
+        for chunk in extract_chunks(*page_address, contents, 0) {
+            let chunk_end = u64::from(chunk.address) + chunk.contents.len() as u64;
+            let is_expected = testcase.expected_memory.iter().any(|expected| {
+                expected.address <= chunk.address && chunk_end <= u64::from(expected.address) + expected.contents.len() as u64
+            });
+
+            if !is_expected {
+                check(
+                    format!("expected-memory[0x{:x}]", chunk.address),
+                    "00".repeat(chunk.contents.len()),
+                    format_bytes(&chunk.contents),
+                );
+            }
+        }
+    }
+
+    Ok(TestRunReport {
+        name: testcase.name.clone(),
+        passed: mismatches.is_empty(),
+        mismatches,
+    })
+}
+
+/// The observable results of running a testcase.
+#[derive(PartialEq, Eq)]
+struct Outcome {
+    status: String,
+    page_fault_address: Option<u32>,
+    /// The final program counter; not tracked when the program halts unless step tracing is enabled.
+    pc: Option<u32>,
+    regs: Vec<u64>,
+    gas: i64,
+    pages: Vec<(u32, Vec<u8>)>,
+    host_calls: Vec<u32>,
+    /// The number of executed instructions; only tracked when step tracing is enabled.
+    step_count: Option<u64>,
+}
+
+fn execute_without_tracing(engine: &Engine, setup: &Setup) -> Result<Outcome, String> {
+    let module = Module::from_blob(engine, &module_config_for(setup.gas_model), setup.blob.clone()).map_err(to_string)?;
+    execute_module(&module, setup)
+}
+
+/// The configuration of a module executed under the given gas model; without step tracing, unless the model needs it.
+fn module_config_for(gas_model: GasModel) -> ModuleConfig {
+    let mut module_config = ModuleConfig::default();
+    module_config.set_strict(true);
+    module_config.set_gas_metering(gas_model.metering());
+    module_config.set_step_tracing(gas_model != GasModel::Polkavm);
+    module_config.set_dynamic_paging(true);
+    module_config
+}
+
+/// Runs a fresh instance of an already loaded module until it finishes.
+fn execute_module(module: &Module, setup: &Setup) -> Result<Outcome, String> {
+    let mut instance = module.instantiate().map_err(to_string)?;
+
+    instance.set_gas(setup.initial_gas);
+    instance.set_next_program_counter(setup.initial_pc);
+    for (reg, value) in Reg::ALL.into_iter().zip(setup.initial_regs) {
+        instance.set_reg(reg, value);
+    }
+
+    for page in setup.initial_page_map {
+        instance.zero_memory(page.address, page.length).map_err(to_string)?;
+        if !page.is_writable {
+            instance.protect_memory(page.address, page.length).map_err(to_string)?;
+        }
+    }
+
+    for chunk in setup.initial_memory {
+        instance.write_memory(chunk.address, &chunk.contents).map_err(to_string)?;
+    }
+
+    let mut host_calls = Vec::new();
+    let mut faulted_pages = Vec::new();
+    let mut step_count = 0;
+    let mut last_step_pc = None;
+    let (status, page_fault_address) = loop {
+        match instance.run().map_err(to_string)? {
+            InterruptKind::Finished => break ("halt", None),
+            InterruptKind::Trap => break ("panic", None),
+            InterruptKind::Ecalli(host_call) => {
+                host_calls.push(host_call);
+                if let Some(values) = setup.on_ecalli.get(&host_call) {
+                    for (reg, value) in Reg::ALL.into_iter().zip(values.regs) {
+                        if let Some(value) = value {
+                            instance.set_reg(reg, value);
+                        }
+                    }
+                }
+            }
+            InterruptKind::NotEnoughGas => break ("out-of-gas", None),
+            InterruptKind::Segfault(segfault) => {
+                let action = setup.on_fault.get(&segfault.page_address).copied().unwrap_or("abort");
+                if action == "abort" || faulted_pages.contains(&segfault.page_address) {
+                    break ("page-fault", Some(segfault.page_address));
+                }
+
+                faulted_pages.push(segfault.page_address);
+                map_faulted_page(&mut instance, &segfault, action)?;
+            }
+            InterruptKind::Step => {
+                if !setup.gas_model.charge_step(&mut instance) {
+                    break ("out-of-gas", None);
+                }
+
+                step_count += 1;
+                last_step_pc = instance.program_counter();
+            }
+        }
+    };
+
+    let mut pages = Vec::new();
+    for page in setup.initial_page_map {
+        pages.push((page.address, instance.read_memory(page.address, page.length).map_err(to_string)?));
+    }
+
+    Ok(Outcome {
+        status: status.to_owned(),
+        page_fault_address,
+        pc: if status == "halt" {
+            last_step_pc.map(|pc| pc.0)
+        } else {
+            instance.program_counter().map(|pc| pc.0)
+        },
+        regs: Reg::ALL.into_iter().map(|reg| instance.reg(reg)).collect(),
+        gas: instance.gas(),
+        pages,
+        host_calls,
+        step_count: (step_count > 0).then_some(step_count),
+    })
+}
+
+/// Returns a human-readable description of every difference between the two outcomes.
+fn diff_outcomes(expected: &Outcome, actual: &Outcome) -> Vec<String> {
+    fn format_optional(value: Option<u32>) -> String {
+        value.map_or_else(|| "none".to_owned(), |value| format!("0x{value:x}"))
+    }
+
+    let mut differences = Vec::new();
+    if expected.status != actual.status {
+        differences.push(format!("  status: expected {}, is {}", expected.status, actual.status));
+    }
+
+    if expected.page_fault_address != actual.page_fault_address {
+        differences.push(format!(
+            "  page fault address: expected {}, is {}",
+            format_optional(expected.page_fault_address),
+            format_optional(actual.page_fault_address)
+        ));
+    }
+
+    if expected.pc != actual.pc {
+        differences.push(format!(
+            "  pc: expected {}, is {}",
+            format_optional(expected.pc),
+            format_optional(actual.pc)
+        ));
+    }
+
+    for ((reg, expected_value), actual_value) in Reg::ALL.into_iter().zip(&expected.regs).zip(&actual.regs) {
+        if expected_value != actual_value {
+            differences.push(format!("  {reg}: expected 0x{expected_value:x}, is 0x{actual_value:x}"));
+        }
+    }
+
+    if expected.gas != actual.gas {
+        differences.push(format!("  gas: expected {}, is {}", expected.gas, actual.gas));
+    }
+
+    for ((address, expected_memory), (_, actual_memory)) in expected.pages.iter().zip(&actual.pages) {
+        let mismatch = expected_memory
+            .iter()
+            .zip(actual_memory.iter())
+            .position(|(expected_byte, actual_byte)| expected_byte != actual_byte);
+
+        if let Some(offset) = mismatch {
+            let byte_address = *address + offset as u32;
+            differences.push(format!(
+                "  memory[0x{byte_address:x}]: expected 0x{:02x}, is 0x{:02x}",
+                expected_memory[offset], actual_memory[offset]
+            ));
+        }
+    }
+
+    if expected.host_calls != actual.host_calls {
+        differences.push(format!(
+            "  host calls: expected [{}], is [{}]",
+            format_host_calls(&expected.host_calls),
+            format_host_calls(&actual.host_calls)
+        ));
+    }
+
+    differences
+}
+
+pub fn format_host_calls(host_calls: &[u32]) -> String {
+    let host_calls: Vec<_> = host_calls.iter().map(|host_call| host_call.to_string()).collect();
+    host_calls.join(", ")
+}
+
+/// Maps the line number in an assembler error back to the original source and quotes the offending line.
+fn map_assembler_error(error: &str, internal_name: &str, input_lines: &[(usize, &str)]) -> String {
+    let location = error.find("line ").and_then(|position| {
+        let start = position + "line ".len();
+        let length = error[start..].bytes().take_while(u8::is_ascii_digit).count();
+        let nth_line: usize = error[start..start + length].parse().ok()?;
+        let (original_nth_line, line) = *input_lines.get(nth_line.checked_sub(1)?)?;
+        Some((start, length, original_nth_line, line))
+    });
+
+    let Some((start, length, original_nth_line, line)) = location else {
+        return format!("{internal_name}: {error}");
+    };
+
+    format!(
+        "{internal_name}:{original_nth_line}: {}{original_nth_line}{}\n    {}",
+        &error[..start],
+        &error[start + length..],
+        line.trim()
+    )
+}
+
+fn to_string<E: core::fmt::Debug>(e: E) -> String {
+    format!("{:?}", e)
+}
+
+/// Returns the addresses of all of the pages which are currently mapped.
+fn find_mapped_pages(instance: &RawInstance, page_size: u32) -> Vec<u32> {
+    (0x10000..0x100000000_u64)
+        .step_by(page_size as usize)
+        .map(|address| address as u32)
+        .filter(|&address| instance.is_memory_accessible(address, page_size, false))
+        .collect()
+}
+
+/// Reconstructs the contents of `length` bytes of memory at `address` from the given chunks.
+fn memory_image(chunks: &[MemoryChunk], address: u32, length: u32) -> Vec<u8> {
+    let start = u64::from(address);
+    let end = start + u64::from(length);
+    let mut image = vec![0; length as usize];
+    for chunk in chunks {
+        let chunk_start = u64::from(chunk.address);
+        let chunk_end = chunk_start + chunk.contents.len() as u64;
+        if chunk_end <= start || chunk_start >= end {
+            continue;
+        }
+
+        let copy_start = chunk_start.max(start);
+        let copy_end = chunk_end.min(end);
+        image[(copy_start - start) as usize..(copy_end - start) as usize]
+            .copy_from_slice(&chunk.contents[(copy_start - chunk_start) as usize..(copy_end - chunk_start) as usize]);
+    }
+
+    image
+}
+
+/// Extracts the non-zero memory chunks from every page which is mapped at the end of the execution.
+///
+/// This also covers pages which were mapped during the execution, but skips read-only pages
+/// whose contents are the same as they were initially.
+fn extract_final_memory(
+    instance: &RawInstance,
+    initial_page_map: &[Page],
+    initial_memory: &[MemoryChunk],
+    page_size: u32,
+    max_gap: usize,
+) -> Vec<MemoryChunk> {
+    let mut regions: Vec<(u32, Vec<u8>)> = Vec::new();
+    for address in find_mapped_pages(instance, page_size) {
+        let memory = instance.read_memory(address, page_size).unwrap();
+        let is_read_only = initial_page_map.iter().any(|page| {
+            !page.is_writable && address >= page.address && u64::from(address) < u64::from(page.address) + u64::from(page.length)
+        });
+
+        if is_read_only && memory == memory_image(initial_memory, address, page_size) {
+            continue;
+        }
+
+        match regions.last_mut() {
+            Some((region_address, contents)) if u64::from(*region_address) + contents.len() as u64 == u64::from(address) => {
+                contents.extend_from_slice(&memory);
+            }
+            _ => regions.push((address, memory)),
+        }
+    }
+
+    regions
+        .into_iter()
+        .flat_map(|(address, contents)| extract_chunks(address, &contents, max_gap))
+        .collect()
+}
+
+/// Describes every byte which differs from the required contents of memory at `address`.
+fn diff_memory(address: u32, required_contents: &[u8], final_contents: &[u8]) -> Vec<String> {
+    required_contents
+        .iter()
+        .zip(final_contents)
+        .enumerate()
+        .filter(|(_, (required_byte, final_byte))| required_byte != final_byte)
+        .map(|(offset, (required_byte, final_byte))| {
+            format!(
+                "unexpected memory[0x{:x}]: 0x{final_byte:02x} (expected: 0x{required_byte:02x})",
+                address + offset as u32
+            )
+        })
+        .collect()
+}
+
+/// Overwrites parts of the given memory chunks, splitting the result into chunks of non-zero bytes again.
+fn overlay_memory(chunks: &[MemoryChunk], overlays: &[(u32, Vec<u8>)], max_gap: usize) -> Vec<MemoryChunk> {
+    let mut bytes = BTreeMap::new();
+    let chunks = chunks.iter().map(|chunk| (chunk.address, &chunk.contents));
+    for (address, contents) in chunks.chain(overlays.iter().map(|(address, contents)| (*address, contents))) {
+        for (offset, byte) in contents.iter().enumerate() {
+            bytes.insert(address + offset as u32, *byte);
+        }
+    }
+
+    let mut regions: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (address, byte) in bytes {
+        match regions.last_mut() {
+            Some((region_address, contents)) if u64::from(*region_address) + contents.len() as u64 == u64::from(address) => {
+                contents.push(byte);
+            }
+            _ => regions.push((address, vec![byte])),
+        }
+    }
+
+    regions
+        .into_iter()
+        .flat_map(|(address, contents)| extract_chunks(address, &contents, max_gap))
+        .collect()
+}
+
+/// Splits the memory into chunks of non-zero bytes.
+///
+/// Runs of non-zero bytes separated by at most `max_gap` zero bytes are emitted as a single chunk (zeros included).
+fn extract_chunks(base_address: u32, slice: &[u8], max_gap: usize) -> Vec<MemoryChunk> {
+    let mut output = Vec::new();
+    let mut position = 0;
+    while let Some(next_position) = slice[position..].iter().position(|&byte| byte != 0).map(|offset| position + offset) {
+        position = next_position;
+        let mut end = position;
+        loop {
+            end += slice[end..].iter().take_while(|&&byte| byte != 0).count();
+            let gap = slice[end..].iter().take_while(|&&byte| byte == 0).count();
+            if gap == 0 || gap > max_gap || end + gap == slice.len() {
+                break;
+            }
+
+            end += gap;
+        }
+
+        output.push(MemoryChunk {
+            address: base_address + position as u32,
+            contents: slice[position..end].into(),
+        });
+        position = end;
+    }
+
+    output
+}
+
+enum ProgramCounterRef {
+    ByLabel { label: String, instruction_offset: u32 },
+    Preset(ProgramCounter),
+}
+
+fn resolve_program_counter(blob: &ProgramBlob, pc: &ProgramCounterRef, directive: &str) -> Result<ProgramCounter, String> {
+    match pc {
+        ProgramCounterRef::ByLabel { label, instruction_offset } => {
+            let Some(export) = blob.exports().find(|export| export.symbol().as_bytes() == label.as_bytes()) else {
+                return Err(format!(
+                    "label specified in '{directive} = ...' is missing: @{label} (found: {})",
+                    format_exports(blob)
+                ));
+            };
+
+            let instructions = blob_instructions(blob);
+            let Some(index) = instructions.iter().position(|inst| inst.offset == export.program_counter()) else {
+                return Err(format!("failed to find label specified in '{directive} = ...'"));
+            };
+
+            let Some(instruction) = instructions.get(index + *instruction_offset as usize) else {
+                return Err(format!("invalid '{directive} = ...': offset goes out of bounds of the basic block"));
+            };
+
+            Ok(instruction.offset)
+        }
+        ProgramCounterRef::Preset(pc) => Ok(*pc),
+    }
+}
+
+/// Lists the exported labels of a program, for error messages.
+fn format_exports(blob: &ProgramBlob) -> String {
+    let exports: Vec<_> = blob
+        .exports()
+        .map(|export| format!("@{}", String::from_utf8_lossy(export.symbol().as_bytes())))
+        .collect();
+
+    if exports.is_empty() {
+        "no exports".to_owned()
+    } else {
+        exports.join(", ")
+    }
+}
+
+#[derive(Default)]
+struct PrePost {
+    gas: Option<i64>,
+    regs: [Option<u64>; 13],
+    pc: Option<ProgramCounterRef>,
+    /// Where the execution must run out of gas; only valid in a 'post' directive.
+    out_of_gas_at: Option<ProgramCounterRef>,
+    status: Option<String>,
+    host_calls: Option<Vec<u32>>,
+    interrupts: Option<Vec<Interrupt>>,
+    memory: Vec<(u32, Vec<u8>)>,
+    /// The `memory[...] = file "<path>"` initializers, which are read (and applied after `memory`) once the source's location is known.
+    memory_files: Vec<(u32, String)>,
+    /// The registers set to a label expression (e.g. the address of a label plus an offset), which are resolved into `regs`
+    /// once the program is assembled.
+    label_regs: Vec<(Reg, LabelExpression)>,
+    /// The registers whose final values are explicitly left unchecked with `<reg> = *`; only valid in a 'post' directive.
+    waived_regs: [bool; 13],
+}
+
+/// Resolves the `<reg> = @label+offset` (or `<reg> = @end - @start`) assignments of a directive into plain register values.
+fn resolve_label_regs(program: &AssembledProgram, blob: &ProgramBlob, values: &mut PrePost, directive: &str) -> Result<(), String> {
+    for (reg, expression) in core::mem::take(&mut values.label_regs) {
+        let value = evaluate_label_expression(&expression, |label| program.label_value(label)).map_err(|label| {
+            format!(
+                "label specified in '{directive}: {reg} = ...' is missing: @{label} (found: {})",
+                format_exports(blob)
+            )
+        })?;
+
+        // A negative value (e.g. the distance to an earlier label) is sign extended, like an immediate.
+        let value = u32::try_from(value)
+            .map(u64::from)
+            .or_else(|_| i32::try_from(value).map(|value| i64::from(value) as u64))
+            .map_err(|_| format!("invalid '{directive}: {reg} = ...': the value goes out of bounds"))?;
+        values.regs[reg as usize] = Some(value);
+    }
+
+    Ok(())
+}
+
+const STATUSES: &[&str] = &["halt", "panic", "out-of-gas", "page-fault", "host"];
+
+/// Parses a comma separated list of interrupts, e.g. `host(1), page-fault(0x30000), halt`.
+fn parse_interrupts(text: &str) -> Result<Vec<Interrupt>, String> {
+    text.split(',')
+        .map(str::trim)
+        .map(|text| {
+            let (kind, argument) = match text.split_once('(') {
+                Some((kind, argument)) => {
+                    let argument = argument
+                        .strip_suffix(')')
+                        .ok_or_else(|| format!("invalid 'post' directive: failed to parse 'interrupts': missing ')' in '{text}'"))?;
+                    (kind.trim(), Some(argument))
+                }
+                None => (text, None),
+            };
+
+            if !STATUSES.contains(&kind) {
+                return Err(format!(
+                    "invalid 'post' directive: failed to parse 'interrupts': unknown interrupt '{kind}', expected one of: {}",
+                    STATUSES.join(", ")
+                ));
+            }
+
+            let takes_argument = kind == "host" || kind == "page-fault";
+            let argument = match argument {
+                Some(argument) if takes_argument => Some(
+                    polkavm_common::utils::parse_imm(argument)
+                        .ok_or_else(|| format!("invalid 'post' directive: failed to parse 'interrupts': invalid argument in '{text}'"))?
+                        as u32,
+                ),
+                None if !takes_argument => None,
+                _ => {
+                    return Err(format!(
+                        "invalid 'post' directive: failed to parse 'interrupts': '{kind}' {} an argument",
+                        if takes_argument { "requires" } else { "doesn't take" }
+                    ))
+                }
+            };
+
+            Ok(Interrupt {
+                host_call: argument.filter(|_| kind == "host"),
+                address: argument.filter(|_| kind == "page-fault"),
+                ..Interrupt::new(kind)
+            })
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PageAccess {
+    ReadOnly,
+    ReadWrite,
+    Unmapped,
+}
+
+struct PageDirective {
+    address: u32,
+    length: u32,
+    access: PageAccess,
+}
+
+/// Parses a 'page' directive, e.g. `page: 0x30000 len=4096 rw`.
+fn parse_page_directive(line: &str) -> Result<PageDirective, String> {
+    let mut parts = line.split_whitespace();
+    let address = parts
+        .next()
+        .and_then(polkavm_common::utils::parse_imm)
+        .ok_or("invalid 'page' directive: failed to parse the address")? as u32;
+    let length = parts
+        .next()
+        .and_then(|length| length.strip_prefix("len="))
+        .and_then(polkavm_common::utils::parse_imm)
+        .ok_or("invalid 'page' directive: failed to parse 'len=...'")? as u32;
+    let access = match parts.next() {
+        Some("ro") => PageAccess::ReadOnly,
+        Some("rw") => PageAccess::ReadWrite,
+        Some("unmapped") => PageAccess::Unmapped,
+        _ => return Err("invalid 'page' directive: expected one of 'ro', 'rw' or 'unmapped'".to_owned()),
+    };
+
+    if parts.next().is_some() {
+        return Err("invalid 'page' directive: junk after the access kind".to_owned());
+    }
+
+    Ok(PageDirective { address, length, access })
+}
+
+fn apply_page_directive(
+    page_map: &mut Vec<Page>,
+    initial_memory: &[MemoryChunk],
+    directive: &PageDirective,
+    page_size: u32,
+) -> Result<(), String> {
+    let address = directive.address;
+    let start = u64::from(directive.address);
+    let end = start + u64::from(directive.length);
+    if directive.length == 0 || address % page_size != 0 || directive.length % page_size != 0 || end > 0x100000000 {
+        return Err(format!(
+            "invalid 'page: 0x{address:x} len=0x{:x} ...': the region must be non-empty and page aligned",
+            directive.length
+        ));
+    }
+
+    let overlaps =
+        |region_address: u32, region_length: u64| u64::from(region_address) < end && start < u64::from(region_address) + region_length;
+    if directive.access == PageAccess::Unmapped {
+        if initial_memory
+            .iter()
+            .any(|chunk| overlaps(chunk.address, chunk.contents.len() as u64))
+        {
+            return Err(format!(
+                "'page: 0x{address:x} ... unmapped' conflicts with memory initialized by the program"
+            ));
+        }
+
+        // Punch a hole in every region which overlaps with the unmapped range.
+        let mut new_page_map = Vec::with_capacity(page_map.len() + 1);
+        for page in page_map.drain(..) {
+            let page_start = u64::from(page.address);
+            let page_end = page_start + u64::from(page.length);
+            if !overlaps(page.address, u64::from(page.length)) {
+                new_page_map.push(page);
+                continue;
+            }
+
+            if page_start < start {
+                new_page_map.push(Page {
+                    address: page.address,
+                    length: (start - page_start) as u32,
+                    is_writable: page.is_writable,
+                });
+            }
+
+            if end < page_end {
+                new_page_map.push(Page {
+                    address: end as u32,
+                    length: (page_end - end) as u32,
+                    is_writable: page.is_writable,
+                });
+            }
+        }
+
+        *page_map = new_page_map;
+    } else {
+        if let Some(page) = page_map.iter().find(|page| overlaps(page.address, u64::from(page.length))) {
+            return Err(format!(
+                "'page: 0x{address:x} ...' conflicts with an already mapped region: 0x{:x}-0x{:x}",
+                page.address,
+                u64::from(page.address) + u64::from(page.length)
+            ));
+        }
+
+        page_map.push(Page {
+            address,
+            length: directive.length,
+            is_writable: directive.access == PageAccess::ReadWrite,
+        });
+    }
+
+    Ok(())
+}
+
+/// Parses the contents of a 'memory[...]' directive: either a list of bytes (e.g. `0x01 0x02 0x03`),
+/// or a single little-endian value prefixed by its width (e.g. `u32 0x12345678`).
+fn parse_memory_contents(rhs: &str) -> Result<Vec<u8>, String> {
+    use polkavm_common::utils::{parse_immediate, ParsedImmediate};
+
+    let mut parts = rhs.split_whitespace();
+    let width = match parts.clone().next() {
+        Some("u8") => Some(1),
+        Some("u16") => Some(2),
+        Some("u32") => Some(4),
+        Some("u64") => Some(8),
+        _ => None,
+    };
+
+    if let Some(width) = width {
+        parts.next();
+        let value = parts
+            .next()
+            .and_then(parse_immediate)
+            .ok_or("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': invalid value")?;
+        if parts.next().is_some() {
+            return Err("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': junk after the value".to_owned());
+        }
+
+        let value = match value {
+            ParsedImmediate::U32(value) => u64::from(value),
+            ParsedImmediate::U64(value) => value,
+        };
+
+        if width < 8 && value >> (width * 8) != 0 {
+            return Err(format!(
+                "invalid 'pre' / 'post' directive: failed to parse 'memory[...]': value doesn't fit in {width} bytes"
+            ));
+        }
+
+        return Ok(value.to_le_bytes()[..width].to_vec());
+    }
+
+    let contents = parts
+        .map(|byte| {
+            polkavm_common::utils::parse_imm(byte)
+                .and_then(|byte| u8::try_from(byte).ok())
+                .ok_or("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': invalid byte")
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    if contents.is_empty() {
+        return Err("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': no contents".to_owned());
+    }
+
+    Ok(contents)
+}
+
+/// Parses the right hand side of a 'pc = ...' directive: either a number, or a label with an optional
+/// instruction offset (e.g. `@loop` or `@loop[2]`).
+fn parse_program_counter(rhs: &str) -> Result<ProgramCounterRef, String> {
+    let Some(rhs) = rhs.strip_prefix('@') else {
+        let pc = polkavm_common::utils::parse_imm(rhs).ok_or("invalid 'pre' / 'post' directive: failed to parse 'pc'")?;
+        return Ok(ProgramCounterRef::Preset(ProgramCounter(pc as u32)));
+    };
+
+    let rhs = rhs.trim();
+    let (label, offset) = match rhs.find('[') {
+        None => (rhs, 0),
+        Some(index) => {
+            let offset = rhs[index + 1..]
+                .strip_suffix(']')
+                .ok_or("invalid 'pre' / 'post' directive: failed to parse 'pc': expected the offset to end with ']'")?;
+            let offset = offset
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| "invalid 'pre' / 'post' directive: failed to parse 'pc': invalid offset")?;
+            (rhs[..index].trim_end(), offset)
+        }
+    };
+
+    if label.is_empty() || label.contains(|ch: char| ch.is_whitespace() || ch == ']') {
+        return Err(format!(
+            "invalid 'pre' / 'post' directive: failed to parse 'pc': invalid label '{label}'"
+        ));
+    }
+
+    Ok(ProgramCounterRef::ByLabel {
+        label: label.to_owned(),
+        instruction_offset: offset,
+    })
+}
+
+fn parse_on_ecalli(line: &str, on_ecalli: &mut BTreeMap<u32, PrePost>) -> Result<(), String> {
+    let index = line.find(':').ok_or("invalid 'on-ecalli' directive: no ':' found")?;
+    let host_call = line[..index]
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| "invalid 'on-ecalli' directive: failed to parse the host call index")?;
+    parse_pre_post(&line[index + 1..], on_ecalli.entry(host_call).or_default())
+}
+
+fn parse_assert(line: &str, assertions: &mut BTreeMap<String, PrePost>) -> Result<(), String> {
+    let index = line.find(':').ok_or("invalid 'assert' directive: no ':' found")?;
+    let Some(label) = line[..index].trim().strip_prefix('@').filter(|label| !label.is_empty()) else {
+        return Err("invalid 'assert' directive: expected '@label' before the ':'".to_owned());
+    };
+
+    parse_pre_post(&line[index + 1..], assertions.entry(label.to_owned()).or_default())
+}
+
+/// Checks the state of the instance against a checkpoint which was just reached.
+fn check_checkpoint(instance: &RawInstance, checkpoint: &Checkpoint) -> Result<(), String> {
+    for expected in &checkpoint.expected_regs {
+        let reg = Reg::ALL[usize::from(expected.reg)];
+        let value = instance.reg(reg);
+        if value != expected.value {
+            return Err(format!("unexpected {reg}: 0x{value:x} (expected: 0x{:x})", expected.value));
+        }
+    }
+
+    if let Some(expected_gas) = checkpoint.expected_gas {
+        let gas = instance.gas();
+        if gas != expected_gas {
+            return Err(format!("unexpected gas: {gas} (expected: {expected_gas})"));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_on_fault(line: &str, on_fault: &mut BTreeMap<u32, &'static str>) -> Result<(), String> {
+    let index = line.find(':').ok_or("invalid 'on-fault' directive: no ':' found")?;
+    let address =
+        polkavm_common::utils::parse_imm(line[..index].trim()).ok_or("invalid 'on-fault' directive: failed to parse the address")? as u32;
+    let action = match line[index + 1..].split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["map", "rw"] => "map-rw",
+        ["map", "ro"] => "map-ro",
+        ["abort"] => "abort",
+        _ => return Err("invalid 'on-fault' directive: expected one of 'map rw', 'map ro' or 'abort'".to_owned()),
+    };
+
+    if on_fault.insert(address, action).is_some() {
+        return Err(format!("duplicate 'on-fault' directive for 0x{address:x}"));
+    }
+
+    Ok(())
+}
+
+/// Maps a zeroed page in place of the one which faulted, as requested by an `on-fault` directive.
+fn map_faulted_page(instance: &mut RawInstance, segfault: &Segfault, action: &str) -> Result<(), String> {
+    instance.zero_memory(segfault.page_address, segfault.page_size).map_err(to_string)?;
+    if action == "map-ro" {
+        instance
+            .protect_memory(segfault.page_address, segfault.page_size)
+            .map_err(to_string)?;
+    }
+
+    Ok(())
+}
+
+fn parse_pre_post(line: &str, output: &mut PrePost) -> Result<(), String> {
+    let line = line.trim();
+    let index = line.find('=').ok_or("invalid 'pre' / 'post' directive: no '=' found")?;
+    let lhs = line[..index].trim();
+    let rhs = line[index + 1..].trim();
+    if lhs == "gas" {
+        output.gas = Some(
+            rhs.parse::<i64>()
+                .map_err(|_| "invalid 'pre' / 'post' directive: failed to parse rhs")?,
+        );
+    } else if lhs == "status" {
+        if !STATUSES.contains(&rhs) {
+            return Err(format!(
+                "invalid 'pre' / 'post' directive: unknown status '{rhs}', expected one of: {}",
+                STATUSES.join(", ")
+            ));
+        }
+        output.status = Some(rhs.to_owned());
+    } else if let Some(address) = lhs.strip_prefix("memory[").and_then(|lhs| lhs.strip_suffix(']')) {
+        let address = polkavm_common::utils::parse_imm(address)
+            .ok_or("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': invalid address")? as u32;
+        if let Some(path) = rhs.strip_prefix("file") {
+            let path = path
+                .trim()
+                .strip_prefix('"')
+                .and_then(|path| path.strip_suffix('"'))
+                .filter(|path| !path.is_empty())
+                .ok_or("invalid 'pre' / 'post' directive: failed to parse 'memory[...]': expected a quoted path after 'file'")?;
+            output.memory_files.push((address, path.to_owned()));
+        } else {
+            output.memory.push((address, parse_memory_contents(rhs)?));
+        }
+    } else if lhs == "host-calls" {
+        let rhs = rhs
+            .strip_prefix('[')
+            .and_then(|rhs| rhs.strip_suffix(']'))
+            .ok_or("invalid 'pre' / 'post' directive: failed to parse 'host-calls': expected a list in square brackets")?;
+        let host_calls = rhs
+            .split(',')
+            .map(str::trim)
+            .filter(|host_call| !host_call.is_empty())
+            .map(|host_call| {
+                host_call
+                    .parse::<u32>()
+                    .map_err(|_| "invalid 'pre' / 'post' directive: failed to parse 'host-calls': invalid host call index")
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        output.host_calls = Some(host_calls);
+    } else if lhs == "interrupts" {
+        output.interrupts = Some(parse_interrupts(rhs)?);
+    } else if lhs == "pc" {
+        output.pc = Some(parse_program_counter(rhs)?);
+    } else if lhs == "out-of-gas-at" {
+        output.out_of_gas_at = Some(parse_program_counter(rhs)?);
+    } else {
+        let lhs = polkavm_common::utils::parse_reg(lhs).ok_or("invalid 'pre' / 'post' directive: failed to parse lhs")?;
+        // A later assignment always replaces an earlier one, whether it's a label, a waiver or not.
+        output.label_regs.retain(|(reg, _)| *reg != lhs);
+        output.waived_regs[lhs as usize] = false;
+        if rhs == "*" {
+            output.regs[lhs as usize] = None;
+            output.waived_regs[lhs as usize] = true;
+        } else if rhs.starts_with('@') {
+            let expression = parse_label_expression(rhs)
+                .ok_or_else(|| format!("invalid 'pre' / 'post' directive: failed to parse rhs: invalid label expression '{rhs}'"))?;
+            output.label_regs.push((lhs, expression));
+        } else {
+            let rhs = polkavm_common::utils::parse_immediate(rhs)
+                .map(Into::into)
+                .ok_or("invalid 'pre' / 'post' directive: failed to parse rhs")?;
+            output.regs[lhs as usize] = Some(rhs);
+        }
+    }
+
+    Ok(())
+}
+
+/// A small deterministic pseudo-random number generator (SplitMix64).
+///
+/// Used instead of an external crate so that fuzzed programs are stable across dependency updates.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+        value ^ (value >> 31)
+    }
+
+    /// Returns a number in the `0..count` range.
+    pub fn below(&mut self, count: usize) -> usize {
+        (self.next_u64() % count as u64) as usize
+    }
+
+    /// Returns `true` with the given probability, expressed in percent.
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.next_u64() % 100 < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const RANDOM_MEMORY_ADDRESS: u32 = 0x20000;
+const RANDOM_MEMORY_SIZE: u32 = 4096;
+
+fn random_reg(rng: &mut Rng) -> &'static str {
+    rng.pick(&Reg::ALL).name()
+}
+
+/// Returns a 32-bit immediate which is likely to hit an edge case.
+fn random_imm(rng: &mut Rng) -> i32 {
+    match rng.below(5) {
+        0 => *rng.pick(&[0, 1, -1, i32::MIN, i32::MAX]),
+        1 => rng.below(64) as i32,
+        2 => -(rng.below(64) as i32),
+        3 => (RANDOM_MEMORY_ADDRESS + rng.below(RANDOM_MEMORY_SIZE as usize) as u32) as i32,
+        _ => rng.next_u64() as i32,
+    }
+}
+
+/// Returns a 64-bit register value which is likely to hit an edge case.
+fn random_reg_value(rng: &mut Rng) -> u64 {
+    match rng.below(4) {
+        // 0xffff0000 makes `ret` halt when it ends up in `ra`.
+        0 => *rng.pick(&[0, 1, u64::MAX, 0x80000000, 0xffffffff, 0xffff0000, 0x8000000000000000]),
+        1 => rng.below(64) as u64,
+        2 => u64::from(RANDOM_MEMORY_ADDRESS) + rng.below(RANDOM_MEMORY_SIZE as usize) as u64,
+        _ => rng.next_u64(),
+    }
+}
+
+fn random_memory_operand(rng: &mut Rng) -> String {
+    if rng.chance(50) {
+        format!("[0x{:x}]", RANDOM_MEMORY_ADDRESS + rng.below(RANDOM_MEMORY_SIZE as usize) as u32)
+    } else {
+        format!("[{} + {}]", random_reg(rng), rng.below(64))
+    }
+}
+
+fn random_instruction(rng: &mut Rng) -> String {
+    const OPS: &[&str] = &["+", "-", "&", "|", "^", "*", "/u", "/s", "%u", "%s", "<<", ">>", ">>a", "<u", "<s"];
+    const OPS_32: &[&str] = &["+", "-", "*", "/u", "/s", "%u", "%s", "<<", ">>", ">>a"];
+    const IMM_OPS: &[&str] = &["+", "&", "|", "^", "*", "<<", ">>", ">>a", "<u", "<s", ">u", ">s"];
+    const IMM_OPS_32: &[&str] = &["+", "*", "<<", ">>", ">>a"];
+    const LOADS: &[&str] = &["u8", "u16", "u32", "u64", "i8", "i16", "i32"];
+    const STORES: &[&str] = &["u8", "u16", "u32", "u64"];
+
+    let dst = random_reg(rng);
+    match rng.below(8) {
+        0 => {
+            let op = rng.pick(OPS);
+            format!("{dst} = {} {op} {}", random_reg(rng), random_reg(rng))
+        }
+        1 => {
+            let op = rng.pick(OPS_32);
+            format!("i32 {dst} = {} {op} {}", random_reg(rng), random_reg(rng))
+        }
+        2 => {
+            let op = rng.pick(IMM_OPS);
+            format!("{dst} = {} {op} {}", random_reg(rng), random_imm(rng))
+        }
+        3 => {
+            let op = rng.pick(IMM_OPS_32);
+            format!("i32 {dst} = {} {op} {}", random_reg(rng), random_imm(rng))
+        }
+        4 => match rng.below(3) {
+            0 => format!("{dst} = {}", random_reg(rng)),
+            1 => format!("{dst} = {}", random_imm(rng)),
+            _ => format!("{dst} = 0x{:x}", rng.next_u64()),
+        },
+        5 => {
+            let kind = rng.pick(LOADS);
+            format!("{dst} = {kind} {}", random_memory_operand(rng))
+        }
+        6 => {
+            let kind = rng.pick(STORES);
+            format!("{kind} {} = {}", random_memory_operand(rng), random_reg(rng))
+        }
+        _ => format!("{dst} = {} if {} == 0", random_reg(rng), random_reg(rng)),
+    }
+}
+
+fn random_terminator(rng: &mut Rng, block_count: usize, is_last: bool) -> String {
+    const CONDITIONS: &[&str] = &["==", "!=", "<u", "<s", "<=u", "<=s", ">u", ">s", ">=u", ">=s"];
+
+    let target = match rng.below(block_count) {
+        0 => "main".to_owned(),
+        index => format!("block{index}"),
+    };
+
+    let choice = if is_last { rng.below(3) } else { rng.below(6) };
+    match choice {
+        0 => "trap".to_owned(),
+        1 => "ret".to_owned(),
+        2 => format!("jump @{target}"),
+        3 => "fallthrough".to_owned(),
+        4 => format!("jump @{target} if {} {} {}", random_reg(rng), rng.pick(CONDITIONS), random_reg(rng)),
+        _ => format!("jump @{target} if {} {} {}", random_reg(rng), rng.pick(CONDITIONS), random_imm(rng)),
+    }
+}
+
+/// Produces `count` variants of a source with a `randomize: regs` directive, in which every initial register which isn't
+/// set by a `pre: <reg> = ...` directive is set to a pseudo-random value instead of zero.
+///
+/// The values only depend on `seed` and on the `name` of the testcase, so the same variants are produced on every run.
+/// The assignments are appended to the source to keep its line numbers intact. A source without the directive has no variants.
+pub fn randomized_reg_variants(input: &str, name: &str, internal_name: &str, count: usize, seed: u64) -> Result<Vec<String>, String> {
+    let mut is_randomized = false;
+    let mut pre = PrePost::default();
+    for (nth_line, line) in input.lines().enumerate() {
+        let at_line = |error: String| format!("{internal_name}:{}: {error}", nth_line + 1);
+        if let Some(what) = line.strip_prefix("randomize:") {
+            if what.trim() != "regs" {
+                return Err(at_line(format!(
+                    "invalid 'randomize' directive: expected 'regs', got '{}'",
+                    what.trim()
+                )));
+            }
+
+            if is_randomized {
+                return Err(at_line("duplicate 'randomize' directive".to_owned()));
+            }
+            is_randomized = true;
+        } else if let Some(line) = line.strip_prefix("pre:") {
+            parse_pre_post(line, &mut pre).map_err(at_line)?;
+        }
+    }
+
+    if !is_randomized {
+        return Ok(Vec::new());
+    }
+
+    let unconstrained: Vec<Reg> = Reg::ALL
+        .into_iter()
+        .filter(|reg| pre.regs[*reg as usize].is_none() && !pre.label_regs.iter().any(|(label_reg, _)| label_reg == reg))
+        .collect();
+
+    let name_hash = crate::sha256::sha256(name.as_bytes());
+    let mut rng = Rng::new(seed ^ u64::from_le_bytes(name_hash[..8].try_into().unwrap()));
+    let mut variants = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut variant = input.trim_end().to_owned();
+        variant.push('\n');
+        for reg in &unconstrained {
+            writeln!(&mut variant, "pre: {} = 0x{:x}", reg.name(), random_reg_value(&mut rng)).unwrap();
+        }
+
+        variants.push(variant);
+    }
+
+    Ok(variants)
+}
+
+/// Generates the source of a random but valid program, including its `pre` directives.
+///
+/// Every basic block ends with a terminator, and the last block never falls off the end of the code.
+pub fn random_program(rng: &mut Rng, max_instructions: usize) -> String {
+    let mut source = String::new();
+    if rng.chance(75) {
+        writeln!(&mut source, "%rw_data_size = {RANDOM_MEMORY_SIZE}\n").unwrap();
+    }
+
+    for reg in Reg::ALL {
+        if rng.chance(50) {
+            writeln!(&mut source, "pre: {} = 0x{:x}", reg.name(), random_reg_value(rng)).unwrap();
+        }
+    }
+
+    writeln!(&mut source, "pre: gas = 10000\n").unwrap();
+
+    let block_count = 1 + rng.below(4);
+    let max_block_length = (max_instructions / block_count).max(1);
+    for index in 0..block_count {
+        if index == 0 {
+            writeln!(&mut source, "pub @main:").unwrap();
+        } else {
+            writeln!(&mut source, "@block{index}:").unwrap();
+        }
+
+        for _ in 0..rng.below(max_block_length) {
+            writeln!(&mut source, "    {}", random_instruction(rng)).unwrap();
+        }
+
+        writeln!(&mut source, "    {}", random_terminator(rng, block_count, index + 1 == block_count)).unwrap();
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polkavm_common::assembler::assemble;
+
+    #[test]
+    fn post_status_mismatch_is_reported() {
+        let engine = new_engine();
+        let input = "
+pre: gas = 1
+
+pub @main:
+pub @expected_exit:
+    a0 = 1
+    a1 = 2
+
+post: status = halt
+";
+
+        let error = prepare_input(input, &engine, "test", "test", true).err().unwrap();
+        assert_eq!(error, "Unexpected final status for test: expected halt, is out-of-gas");
+    }
+
+    #[test]
+    fn post_status_is_emitted_without_execution() {
+        let engine = new_engine();
+        let input = "
+pub @main:
+    trap
+
+post: status = panic
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", false).unwrap();
+        assert_eq!(testcase.json.expected_status, "panic");
+    }
+
+    #[test]
+    fn post_directives_define_the_expectations_without_execution() {
+        let engine = new_engine();
+        let input = "
+pre: a0 = 5
+pre: a1 = 7
+pre: gas = 100
+
+pub @main:
+    a0 = a0 + a1
+    a1 = 0
+
+post: a0 = 12
+post: a1 = 0
+post: gas = 98
+post: status = halt
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", false).unwrap();
+        let mut expected_regs = testcase.json.initial_regs.to_vec();
+        expected_regs[Reg::A0.to_usize()] = 12;
+        expected_regs[Reg::A1.to_usize()] = 0;
+        assert_eq!(testcase.json.expected_regs, expected_regs);
+        assert_eq!(testcase.json.expected_gas, 98);
+        assert_eq!(testcase.json.expected_status, "halt");
+        assert_eq!(testcase.json.expected_exit_code, Some(12));
+
+        // The registers match the ones from actually running the program.
+        let executed = prepare_input(&input.replace("post: gas = 98\n", ""), &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_regs, executed.json.expected_regs);
+    }
+
+    #[test]
+    fn testcases_without_expectations_are_refused_without_execution() {
+        let engine = new_engine();
+        let input = "pre: a0 = 1\n\npub @main:\n    a0 = a0 + 1\n";
+        let error = prepare_input(input, &engine, "test", "test", false).unwrap_err();
+        assert!(error.contains("'post: ...' directives"), "{error}");
+
+        assert!(prepare_input(input, &engine, "test", "test", true).is_ok());
+        assert!(prepare_input(&format!("{input}post: a0 = 2\n"), &engine, "test", "test", false).is_ok());
+    }
+
+    #[test]
+    fn missing_labels_are_reported_as_errors() {
+        let engine = new_engine();
+        for (fixture, expected_error) in [
+            ("missing_main.txt", "missing a 'pub @main' export (found: @other)"),
+            ("missing_pre_pc_label.txt", "'pre: pc = ...' is missing: @start (found: @main)"),
+            ("missing_post_pc_label.txt", "'post: pc = ...' is missing: @finish (found: @main)"),
+            ("expected_exit_and_post_pc.txt", "should not be used together"),
+        ] {
+            let path = format!("{}/tests/fixtures/missing_labels/{fixture}", env!("CARGO_MANIFEST_DIR"));
+            let input = std::fs::read_to_string(&path).unwrap();
+            let error = prepare_input(&input, &engine, "test", fixture, true).unwrap_err();
+            assert!(error.starts_with(&format!("{fixture}: ")), "{error}");
+            assert!(error.contains(expected_error), "{error}");
+        }
+    }
+
+    #[test]
+    fn negative_tests_expect_an_assembly_error() {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/negative");
+        let input = std::fs::read_to_string(format!("{fixtures}/duplicate_label.txt")).unwrap();
+        let negative = check_assembly_error(&input, "duplicate_label", "duplicate_label.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(negative.expected_error, "duplicate label \"main\"");
+        assert!(negative.error.starts_with("duplicate_label.txt:6: "), "{}", negative.error);
+
+        let engine = new_engine();
+        let error = prepare_input(&input, &engine, "duplicate_label", "duplicate_label.txt", true).unwrap_err();
+        assert!(error.contains("is a negative test"), "{error}");
+
+        let input = std::fs::read_to_string(format!("{fixtures}/assembles_successfully.txt")).unwrap();
+        let error = check_assembly_error(&input, "assembles_successfully", "assembles_successfully.txt")
+            .unwrap()
+            .unwrap_err();
+        assert!(error.contains("but the program assembled successfully"), "{error}");
+
+        assert!(check_assembly_error("pub @main:\n    a0 = 1\n", "test", "test.txt").is_none());
+    }
+
+    #[test]
+    fn entry_points_select_the_initial_pc() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/entry_points.txt")).unwrap();
+        let cases = split_cases(&input, "entry_points.txt").unwrap();
+        let testcases: Vec<_> = cases
+            .iter()
+            .map(|case| prepare_input(&case.input, &engine, "test", "entry_points.txt", true).unwrap())
+            .collect();
+
+        assert_eq!(testcases[0].entry_point, None);
+        assert_eq!(testcases[0].json.initial_pc, 0);
+        assert_eq!(testcases[1].entry_point.as_deref(), Some("secondary"));
+        assert_ne!(testcases[1].json.initial_pc, 0);
+        assert_eq!(testcases[1].json.expected_regs[Reg::A1.to_usize()], 2);
+
+        let program = "pre: gas = 10000\n\npub @main:\n    a0 = 1\n";
+        let error = prepare_input(&format!("entry: @other\n{program}"), &engine, "test", "test", true).unwrap_err();
+        assert!(error.contains("is missing: @other (found: @main)"), "{error}");
+
+        let error = prepare_input(&format!("entry: @main\npre: pc = 0\n{program}"), &engine, "test", "test", true).unwrap_err();
+        assert!(error.contains("ambiguous"), "{error}");
+
+        assert!(prepare_input(&format!("entry: main\n{program}"), &engine, "test", "test", true).is_err());
+        assert!(prepare_input(&format!("entry: @main\nentry: @main\n{program}"), &engine, "test", "test", true).is_err());
+    }
+
+    #[test]
+    fn modes_produce_a_testcase_per_isa() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/inst_add_modes.txt")).unwrap();
+        let cases = split_cases(&input, "inst_add_modes.txt").unwrap();
+        let names: Vec<_> = cases.iter().map(|case| case.name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["32", "64"]);
+
+        let mut results = Vec::new();
+        for case in &cases {
+            let testcase = prepare_input(&case.input, &engine, "test", "inst_add_modes.txt", true).unwrap();
+            results.push(testcase.json.expected_regs[Reg::A2.to_usize()]);
+        }
+        assert_eq!(results, [0, 0x100000000]);
+
+        // The expectations of the other mode aren't checked.
+        assert!(prepare_input(&cases[0].input.replace("32: a2 = 0", "32: a2 = 1"), &engine, "test", "test", true).is_err());
+        assert!(prepare_input(&cases[1].input.replace("32: a2 = 0", "32: a2 = 1"), &engine, "test", "test", true).is_ok());
+
+        // A source which wasn't split can't be prepared, and single-mode sources aren't split at all.
+        assert!(prepare_input(&input, &engine, "test", "test", true).is_err());
+        let input = input.replace("modes: 32,64", "modes: 32");
+        let cases = split_cases(&input, "test").unwrap();
+        assert_eq!(cases.len(), 1);
+        assert!(cases[0].name.is_none());
+        let testcase = prepare_input(&cases[0].input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_regs[Reg::A2.to_usize()], 0);
+
+        for modes in ["modes: 16", "modes: 32,32", "modes:"] {
+            assert!(split_cases(&input.replace("modes: 32", modes), "test").is_err(), "{modes}");
+        }
+    }
+
+    #[test]
+    fn stack_size_directive_overrides_the_stack_size() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/stack_size.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "stack_size.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "halt");
+        assert_eq!(testcase.stack_size, Some(0x10000));
+        assert_eq!(
+            testcase.json.initial_page_map,
+            [Page {
+                address: 0xfffe0000 - 0x10000,
+                length: 0x10000,
+                is_writable: true,
+            }]
+        );
+
+        // Without the directive the program uses the default stack, and faults.
+        let input_without_directive = input.replace("stack-size: 0x10000", "").replace("post: a1 = 0x12345678", "");
+        let testcase = prepare_input(&input_without_directive, &engine, "test", "stack_size.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "page-fault");
+
+        for directive in ["stack-size: 0x1234", "stack-size: -4096", "stack-size: big"] {
+            let input = input.replace("stack-size: 0x10000", directive);
+            assert!(prepare_input(&input, &engine, "test", "test", true).is_err(), "{directive}");
+        }
+    }
+
+    #[test]
+    fn gas_models_charge_the_same_program_differently() {
+        let engine = new_engine();
+        let read = |name: &str| std::fs::read_to_string(format!("{}/spec/src/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let polkavm_input = read("gas_model_polkavm.txt");
+        let flat_input = read("gas_model_flat.txt");
+        assert_eq!(
+            polkavm_input.split_once("pub @main:").unwrap().1,
+            flat_input.split_once("pub @main:").unwrap().1.replace("94", "97")
+        );
+
+        let polkavm = prepare_input(&polkavm_input, &engine, "polkavm", "gas_model_polkavm.txt", true).unwrap();
+        assert_eq!(polkavm.json.gas_model, None);
+        assert_eq!(polkavm.json.expected_gas, 97);
+
+        let flat = prepare_input(&flat_input, &engine, "flat", "gas_model_flat.txt", true).unwrap();
+        assert_eq!(flat.json.gas_model, Some(GasModel::Flat { cost_per_instruction: 2 }));
+        assert_eq!(flat.json.expected_gas, 94);
+        assert_eq!(flat.json.expected_step_count, polkavm.json.expected_step_count);
+        assert_eq!(flat.block_costs[0].cost, 6);
+
+        for testcase in [&polkavm.json, &flat.json] {
+            let report = run_testcase(&engine, testcase).unwrap();
+            assert!(report.passed, "{:?}", report.mismatches);
+        }
+
+        // Under the flat model the execution runs out of gas in the middle of a basic block.
+        let input = flat_input
+            .replace("pre: gas = 100", "pre: gas = 3")
+            .replace("post: status = halt\npost: gas = 94\n", "");
+        let testcase = prepare_input(&format!("{input}post: status = out-of-gas\n"), &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_gas, 1);
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], 1);
+        assert_eq!(testcase.json.expected_regs[Reg::A1.to_usize()], 0);
+        assert_eq!(testcase.json.expected_step_count, Some(1));
+        assert!(run_testcase(&engine, &testcase.json).unwrap().passed);
+        assert_eq!(
+            benchmark_exclusion_reason(&testcase.json),
+            Some("uses a gas model which needs step tracing")
+        );
+
+        // Without a directive the default model is used, which also gives the cost of a bare 'gas-model: flat'.
+        let input = "pre: gas = 100\n\npub @main:\n    a0 = 1\n";
+        let with_default_model = |input: &str, gas_model| {
+            let options = PrepareOptions {
+                gas_model,
+                ..PrepareOptions::default()
+            };
+            prepare_input_with_options(input, &engine, "test", "test", true, options)
+                .unwrap()
+                .json
+                .gas_model
+        };
+        let flat_3 = GasModel::Flat { cost_per_instruction: 3 };
+        assert_eq!(with_default_model(input, flat_3), Some(flat_3));
+        assert_eq!(
+            with_default_model(&format!("gas-model: flat\n{input}"), GasModel::Polkavm),
+            Some(GasModel::Flat {
+                cost_per_instruction: DEFAULT_FLAT_GAS_COST
+            })
+        );
+        assert_eq!(with_default_model(&format!("gas-model: flat\n{input}"), flat_3), Some(flat_3));
+        assert_eq!(with_default_model(&format!("gas-model: polkavm\n{input}"), flat_3), None);
+
+        for directive in [
+            "gas-model: naive",
+            "gas-model: flat, cost = lots",
+            "gas-model: flat\ngas-model: flat",
+        ] {
+            let error = prepare_input(&format!("{directive}\n{input}"), &engine, "test", "test", true)
+                .err()
+                .unwrap();
+            assert!(error.contains("'gas-model' directive"), "{error}");
+        }
+    }
+
+    #[test]
+    fn gas_limit_directive_caps_the_gas() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/gas_limit.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "gas_limit.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "out-of-gas");
+        assert_eq!(testcase.json.initial_gas, 2);
+
+        let input_without_status = input.replace("post: status = out-of-gas", "");
+        assert_eq!(
+            prepare_input(&input_without_status, &engine, "test", "gas_limit.txt", true).err().unwrap(),
+            "gas_limit.txt: ran out of the gas given by the 'gas-limit: 2' directive; give it more with 'pre: gas = ...' or declare 'post: status = out-of-gas'"
+        );
+
+        // A limit which isn't reached leaves the requested gas alone.
+        let input_with_enough_gas = input_without_status.replace("gas-limit: 2", "gas-limit: 20000");
+        let testcase = prepare_input(&input_with_enough_gas, &engine, "test", "gas_limit.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "panic");
+        assert_eq!(testcase.json.initial_gas, 10000);
+
+        for directive in ["gas-limit: -1", "gas-limit: lots", "gas-limit: 2\ngas-limit: 3"] {
+            let input = input.replace("gas-limit: 2", directive);
+            assert!(prepare_input(&input, &engine, "test", "test", true).is_err(), "{directive}");
+        }
+    }
+
+    #[test]
+    fn default_gas_is_configurable() {
+        let engine = new_engine();
+        let input = "pub @main:\n    a0 = 1\n    a1 = 2\n    a2 = 3\n";
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.initial_gas, DEFAULT_GAS);
+
+        let prepare_with_default_gas = |default_gas| {
+            let options = PrepareOptions {
+                default_gas: Some(default_gas),
+                ..PrepareOptions::default()
+            };
+            prepare_input_with_options(input, &engine, "test", "test", true, options)
+        };
+        assert_eq!(prepare_with_default_gas(100).unwrap().json.initial_gas, 100);
+        assert_eq!(
+            prepare_with_default_gas(2).err().unwrap(),
+            "test: ran out of the gas given by the default gas of 2; give it more with 'pre: gas = ...' or declare 'post: status = out-of-gas'"
+        );
+
+        // An explicit 'pre: gas = ...' takes precedence, and running out of it is not an error.
+        let testcase = prepare_input(&format!("pre: gas = 2\n\n{input}"), &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "out-of-gas");
+    }
+
+    #[test]
+    fn out_of_gas_at_asserts_where_the_gas_runs_out() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/out_of_gas_at.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "out_of_gas_at.txt", true).unwrap();
+        let next = testcase.block_costs[1].start;
+        assert_eq!(testcase.json.expected_status, "out-of-gas");
+        assert_eq!(testcase.json.expected_pc, next);
+        assert_eq!(testcase.json.expected_gas, 0);
+        assert_eq!(testcase.out_of_gas_at, Some(next));
+
+        let input_with_wrong_pc = input.replace("out-of-gas-at = @next", "out-of-gas-at = @main");
+        assert_eq!(
+            prepare_input(&input_with_wrong_pc, &engine, "test", "out_of_gas_at.txt", true)
+                .err()
+                .unwrap(),
+            format!("out_of_gas_at.txt: expected to run out of gas at pc 0, but it ran out at pc {next}")
+        );
+
+        let input_with_enough_gas = input.replace("pre: gas = 4", "pre: gas = 100");
+        assert_eq!(
+            prepare_input(&input_with_enough_gas, &engine, "test", "out_of_gas_at.txt", true)
+                .err()
+                .unwrap(),
+            format!("out_of_gas_at.txt: expected to run out of gas at pc {next}, but the program ended with: panic")
+        );
+
+        for conflicting in [
+            "post: out-of-gas-at = @next\npost: status = panic",
+            "post: out-of-gas-at = @next\npost: pc = @next",
+            "pre: out-of-gas-at = @next",
+        ] {
+            let input = input.replace("post: out-of-gas-at = @next", conflicting);
+            assert!(prepare_input(&input, &engine, "test", "test", true).is_err(), "{conflicting}");
+        }
+    }
+
+    #[test]
+    fn strict_post_requires_every_register_change_to_be_asserted_or_waived() {
+        let engine = new_engine();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/strict_post");
+        let passing = std::fs::read_to_string(format!("{fixtures}/passing.txt")).unwrap();
+        let failing = std::fs::read_to_string(format!("{fixtures}/failing.txt")).unwrap();
+
+        let testcase = prepare_input(&passing, &engine, "test", "passing.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_regs[Reg::A3 as usize], 7);
+        assert_eq!(
+            prepare_input(&failing, &engine, "test", "failing.txt", true).err().unwrap(),
+            "failing.txt: 'strict-post': unasserted register changes: a3 (0x0 -> 0x7); assert them with 'post: <reg> = ...' or waive them with 'post: <reg> = *'"
+        );
+
+        // Without the directive the strict mode can be enabled through the options instead.
+        let lenient = failing.replace("strict-post\n", "");
+        assert!(prepare_input(&lenient, &engine, "test", "failing.txt", true).is_ok());
+        let options = PrepareOptions {
+            strict_post: true,
+            ..PrepareOptions::default()
+        };
+        assert!(prepare_input_with_options(&lenient, &engine, "test", "failing.txt", true, options).is_err());
+
+        // A later assignment replaces the waiver, and the other way around.
+        let asserted = passing.replace("post: a3 = *", "post: a3 = *\npost: a3 = 8");
+        assert!(prepare_input(&asserted, &engine, "test", "passing.txt", true).is_err());
+        let waived = failing.replace("post: a0 = 1", "post: a0 = 1\npost: a3 = 8\npost: a3 = *");
+        assert!(prepare_input(&waived, &engine, "test", "failing.txt", true).is_ok());
+
+        for invalid in ["pre: a3 = *", "strict-post: yes", "on-ecalli 1: a3 = *", "assert @main: a3 = *"] {
+            let input = format!("{invalid}\n{passing}");
+            assert!(prepare_input(&input, &engine, "test", "test", true).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn checkpoints_are_asserted_when_first_reached() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/checkpoints.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "checkpoints.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "halt");
+        let checkpoints = testcase.json.checkpoints.unwrap();
+        let labels: Vec<_> = checkpoints
+            .iter()
+            .map(|checkpoint| {
+                (
+                    checkpoint.label.as_str(),
+                    checkpoint.expected_regs.len(),
+                    checkpoint.reached_at_step,
+                )
+            })
+            .collect();
+        // The loop is entered after the three instructions of @main, and left after three iterations of three instructions.
+        assert_eq!(labels, [("done", 2, Some(13)), ("loop", 2, Some(4))]);
+        assert_eq!(
+            checkpoints[1].expected_regs,
+            [CheckpointReg { reg: 7, value: 0 }, CheckpointReg { reg: 8, value: 3 }]
+        );
+
+        // Only the first time the label is reached counts, so the later iterations don't fail it.
+        let input_with_wrong_value = input.replace("assert @done: a0 = 6", "assert @done: a0 = 7");
+        assert_eq!(
+            prepare_input(&input_with_wrong_value, &engine, "test", "checkpoints.txt", true)
+                .err()
+                .unwrap(),
+            "checkpoints.txt: 'assert @done: ...' failed at step 13: unexpected a0: 0x6 (expected: 0x7)"
+        );
+
+        let input_with_wrong_gas = format!("{input}\nassert @loop: gas = 1\n");
+        let error = prepare_input(&input_with_wrong_gas, &engine, "test", "checkpoints.txt", true)
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("checkpoints.txt: 'assert @loop: ...' failed at step 4: unexpected gas: "),
+            "{error}"
+        );
+
+        // A checkpoint which is never reached is only recorded as such.
+        let input_with_unreached_label =
+            input.replace("    ret\n", "    ret\n\npub @unreached:\n    trap\n") + "\nassert @unreached: a0 = 1\n";
+        let testcase = prepare_input(&input_with_unreached_label, &engine, "test", "checkpoints.txt", true).unwrap();
+        let unreached = testcase
+            .json
+            .checkpoints
+            .unwrap()
+            .into_iter()
+            .find(|checkpoint| checkpoint.label == "unreached")
+            .unwrap();
+        assert_eq!(unreached.reached_at_step, None);
+
+        for (directive, error) in [
+            ("assert @missing: a0 = 1", "label specified in 'assert @missing: ...' is missing"),
+            (
+                "assert @main: status = halt",
+                "'assert @main: ...' can only be used to check registers and gas",
+            ),
+            (
+                "assert main: a0 = 1",
+                "invalid 'assert' directive: expected '@label' before the ':'",
+            ),
+        ] {
+            let input = format!("{input}\n{directive}\n");
+            let result = prepare_input(&input, &engine, "test", "checkpoints.txt", true).err().unwrap();
+            assert!(result.contains(error), "{result}");
+        }
+    }
+
+    #[test]
+    fn page_faults_can_be_handled_and_resumed() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/page_fault_resume.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "page_fault_resume.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "halt");
+        assert_eq!(
+            testcase.json.faults_handled.unwrap(),
+            [
+                HandledFault {
+                    address: 0x30000,
+                    action: "map-rw".to_owned(),
+                },
+                HandledFault {
+                    address: 0x40000,
+                    action: "map-ro".to_owned(),
+                },
+            ]
+        );
+        assert!(testcase.json.expected_memory.iter().any(|chunk| chunk.address == 0x30000));
+
+        let error = prepare_input(
+            &input.replace("on-fault 0x40000: map ro", "on-fault 0x50000: map ro"),
+            &engine,
+            "test",
+            "test",
+            true,
+        )
+        .unwrap_err();
+        assert!(error.contains("unexpected page fault at 0x40000"), "{error}");
+
+        // Writing into a page which was mapped as read-only faults again.
+        let input_with_write = input.replace("a1 = u32 [0x40000]", "a1 = u32 [0x40000]\n    u32 [0x40000] = a0");
+        let error = prepare_input(&input_with_write, &engine, "test", "test", true).unwrap_err();
+        assert!(error.contains("after it was already handled"), "{error}");
+
+        let input_with_abort = input
+            .replace("on-fault 0x40000: map ro", "on-fault 0x40000: abort")
+            .replace(", halt", "");
+        let testcase = prepare_input(&input_with_abort, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "page-fault");
+        assert_eq!(testcase.json.expected_page_fault_address, Some(0x40000));
+        assert_eq!(testcase.json.faults_handled.unwrap().last().unwrap().action, "abort");
+
+        for directive in ["on-fault 0x30001: map rw", "on-fault 0x30000: map rx", "on-fault 0x30000 map rw"] {
+            let input = input.replace("on-fault 0x30000: map rw", directive);
+            assert!(prepare_input(&input, &engine, "test", "test", true).is_err(), "{directive}");
+        }
+    }
+
+    #[test]
+    fn host_calls_are_recorded() {
+        let engine = new_engine();
+        let input = "
+pre: gas = 10000
+on-ecalli 2: a1 = 0x20
+
+pub @main:
+    ecalli 1
+    a0 = a0 + 1
+    ecalli 2
+    a2 = a1 + a0
+
+post: host-calls = [1, 2]
+post: a2 = 0x21
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_host_calls, Some(vec![1, 2]));
+
+        let input = input.replace("post: host-calls = [1, 2]", "post: host-calls = [1]");
+        assert!(prepare_input(&input, &engine, "test", "test", true).is_err());
+    }
+
+    #[test]
+    fn pre_memory_can_be_loaded_from_a_file() {
+        let engine = new_engine();
+        let fixtures = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/memory_file"));
+        let options = PrepareOptions {
+            source_directory: Some(fixtures),
+            ..PrepareOptions::default()
+        };
+
+        let input = std::fs::read_to_string(fixtures.join("lookup_table.txt")).unwrap();
+        let testcase = prepare_input_with_options(&input, &engine, "test", "lookup_table.txt", true, options).unwrap();
+        let table = std::fs::read(fixtures.join("data/table.bin")).unwrap();
+        assert_eq!(table.len(), 2048);
+        assert_eq!(
+            testcase.json.initial_memory,
+            [MemoryChunk {
+                address: 0x20000,
+                contents: table
+            }]
+        );
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], 0x08070605);
+
+        let missing = input.replace("data/table.bin", "data/missing.bin");
+        let error = prepare_input_with_options(&missing, &engine, "test", "lookup_table.txt", true, options).unwrap_err();
+        assert!(
+            error.starts_with("lookup_table.txt: 'pre: memory[0x20000] = file \"data/missing.bin\"': failed to read"),
+            "{error}"
+        );
+
+        let unmapped = input.replace("%rw_data_size = 4096", "");
+        let error = prepare_input_with_options(&unmapped, &engine, "test", "lookup_table.txt", true, options).unwrap_err();
+        assert!(error.contains("is outside of the initial page map"), "{error}");
+    }
+
+    #[test]
+    fn data_blocks_are_addressable_by_their_labels() {
+        let engine = new_engine();
+        let input = r#"
+%ro_data {
+    @table: u32 10 u32 20 u32 30 u32 40
+    @greeting: "abc"
+}
+%rw_data { @result: u32 0 }
+
+pre: a0 = 2
+pre: gas = 10000
+
+pub @main:
+    a1 = a0 << 2
+    a2 = u32 [a1 + @table]
+    u32 [@result] = a2
+    a3 = @greeting
+
+post: a2 = 30
+post: a3 = 0x10010
+post: memory[0x30000] = 0x1e 0x00 0x00 0x00
+"#;
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(
+            chunk_layout(&testcase.json.initial_memory),
+            [(0x10000, 1), (0x10004, 1), (0x10008, 1), (0x1000c, 1), (0x10010, 3)]
+        );
+        assert_eq!(testcase.json.initial_memory[4].contents, b"abc");
+        assert_eq!(
+            testcase.json.expected_memory,
+            [MemoryChunk {
+                address: 0x30000,
+                contents: vec![30]
+            }]
+        );
+
+        // Without any read-only data the read-write data starts at a lower address.
+        let read_write_only =
+            "%rw_data { @value: u32 0x12345678 }\npre: gas = 10000\npub @main:\n    a0 = u32 [@value]\npost: a0 = 0x12345678\n";
+        let testcase = prepare_input(read_write_only, &engine, "test", "test", true).unwrap();
+        assert_eq!(
+            testcase.json.initial_memory,
+            [MemoryChunk {
+                address: 0x20000,
+                contents: vec![0x78, 0x56, 0x34, 0x12]
+            }]
+        );
+
+        let error = prepare_input(&input.replace("u32 40", "u8 0x100"), &engine, "test", "test", true).unwrap_err();
+        assert!(
+            error.starts_with("Failed to assemble test:3: cannot parse line 3: invalid 8-bit value"),
+            "{error}"
+        );
+
+        let error = prepare_input(&input.replace("@table:", "@main:"), &engine, "test", "test", true).unwrap_err();
+        assert!(error.contains("duplicate label \"main\""), "{error}");
+    }
+
+    #[test]
+    fn label_differences_are_evaluated() {
+        let engine = new_engine();
+        let input = "
+%ro_data {
+    @table_start: u32 1 u32 2 u32 3
+    @table_end:
+}
+
+pre: a2 = @table_start + 4
+pre: gas = 10000
+
+pub @main:
+    a0 = @table_end - @table_start
+    a1 = u32 [a2]
+    a3 = @end - @table_start
+    jump @end
+
+@end:
+    trap
+
+post: a0 = 12
+post: a1 = 2
+post: a3 = @end - @table_start
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test.txt", true).unwrap();
+        let end = u64::from(assemble_with_symbols(input).unwrap().local_labels["end"]);
+        assert_ne!(end, 0);
+        assert_eq!(testcase.json.initial_regs[Reg::A2.to_usize()], 0x10004);
+        // The difference is negative, so it's sign extended.
+        assert_eq!(testcase.json.expected_regs[Reg::A3.to_usize()], end.wrapping_sub(0x10000));
+
+        let error = prepare_input(
+            &input.replace("@end - @table_start\n    jump", "@missing - @table_start\n    jump"),
+            &engine,
+            "test",
+            "test.txt",
+            true,
+        )
+        .unwrap_err();
+        assert!(
+            error.starts_with("Failed to assemble test.txt:13: cannot parse line 13: undefined label \"missing\""),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn label_expressions_can_refer_to_later_labels() {
+        // The distance only fits into a wider immediate, which moves the label it refers to.
+        let padding = "    a1 = a1 + 1\n".repeat(100);
+        let input = format!("pre: gas = 10000\n\npub @main:\n    a0 = @far - @main\n{padding}\npub @far:\n    trap\n");
+
+        let far = assemble_with_symbols(&input).unwrap().exports["far"];
+        assert!(far > 300, "{far}");
+        let testcase = prepare_input(&input, &new_engine(), "test", "test.txt", true).unwrap();
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], u64::from(far));
+        assert!(
+            testcase.disassembly.contains(&format!("r7 = 0x{far:x}\n")),
+            "{}",
+            testcase.disassembly
+        );
+    }
+
+    #[test]
+    fn constants_are_shared_by_the_code_and_the_expectations() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/constants/shared_value.txt")).unwrap();
+        let prepare = |input: &str| prepare_input(input, &engine, "test", "shared_value.txt", true);
+
+        let testcase = prepare(&input).unwrap();
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], 0x34);
+        assert_eq!(chunk_layout(&testcase.json.expected_memory), [(0x20010, 1)]);
+        assert!(testcase.disassembly.contains("u8 [0x20010] = r7"), "{}", testcase.disassembly);
+        assert_eq!(
+            testcase.json.description.as_deref(),
+            Some("Stores a value which is shared by the code and the expectations.")
+        );
+
+        // The expectations still hold after the constants change, since they follow them.
+        let changed = input
+            .replace("const ADDRESS = 0x20010", "const ADDRESS = 0x20020")
+            .replace("const VALUE = 0x34", "const VALUE = 0x56");
+        let testcase = prepare(&changed).unwrap();
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], 0x56);
+        assert_eq!(testcase.json.expected_memory[0].address, 0x20020);
+        assert_eq!(testcase.json.expected_memory[0].contents, [0x56]);
+
+        let error = prepare(&format!("{input}const VALUE = 1\n")).unwrap_err();
+        assert_eq!(
+            error,
+            "shared_value.txt:15: redefinition of the constant 'VALUE', which is already defined on line 5"
+        );
+
+        let error = prepare(&input.replace("a0 = VALUE", "a0 = UNDEFINED")).unwrap_err();
+        assert_eq!(error, "shared_value.txt:10: undefined constant 'UNDEFINED'");
+
+        let program = assemble_with_symbols(&input).unwrap();
+        assert_eq!(program.code_and_jump_table, prepare(&input).unwrap().json.program);
+    }
+
+    #[test]
+    fn pre_memory_is_merged_into_initial_memory() {
+        let engine = new_engine();
+        let input = "
+%rw_data_size = 8
+%rw_data = 01 02
+
+pre: memory[0x20004] = u16 0x0403
+
+pub @main:
+    a0 = u64 [0x20000]
+
+post: a0 = 0x40300000201
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.initial_memory.len(), 2);
+        assert_eq!(testcase.json.initial_memory[0].address, 0x20000);
+        assert_eq!(testcase.json.initial_memory[0].contents, [1, 2]);
+        assert_eq!(testcase.json.initial_memory[1].address, 0x20004);
+        assert_eq!(testcase.json.initial_memory[1].contents, [3, 4]);
+
+        let input = input.replace("memory[0x20004]", "memory[0x40000]");
+        assert!(prepare_input(&input, &engine, "test", "test", true).is_err());
+    }
+
+    #[test]
+    fn page_directives_are_applied() {
+        let engine = new_engine();
+        let input = "
+%rw_data_size = 12288
+
+page: 0x21000 len=4096 unmapped
+page: 0x30000 len=0x1000 ro
+
+pub @main:
+    trap
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        let page_map: Vec<_> = testcase
+            .json
+            .initial_page_map
+            .iter()
+            .filter(|page| page.address < 0x100000)
+            .map(|page| (page.address, page.length, page.is_writable))
+            .collect();
+        assert_eq!(
+            page_map,
+            [(0x20000, 0x1000, true), (0x22000, 0x1000, true), (0x30000, 0x1000, false)]
+        );
+
+        let input = input.replace("page: 0x30000", "page: 0x20000");
+        assert!(prepare_input(&input, &engine, "test", "test", true).is_err());
+    }
+
+    #[test]
+    fn memory_contents_are_parsed() {
+        assert_eq!(parse_memory_contents("0x01 2 0xff").unwrap(), [1, 2, 0xff]);
+        assert_eq!(parse_memory_contents("u32 0x12345678").unwrap(), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(
+            parse_memory_contents("u64 0xdeadbeef").unwrap(),
+            [0xef, 0xbe, 0xad, 0xde, 0, 0, 0, 0]
+        );
+        assert!(parse_memory_contents("u8 0x100").is_err());
+    }
+
+    #[test]
+    fn post_status_unknown_is_rejected() {
+        let mut post = PrePost::default();
+        let error = parse_pre_post("status = crashed", &mut post).unwrap_err();
+        assert!(error.contains("unknown status 'crashed'"));
+    }
+
+    fn parse_label(rhs: &str) -> Result<(String, u32), String> {
+        match parse_program_counter(rhs)? {
+            ProgramCounterRef::ByLabel { label, instruction_offset } => Ok((label, instruction_offset)),
+            ProgramCounterRef::Preset(pc) => Err(format!("unexpected preset pc: {pc}")),
+        }
+    }
+
+    #[test]
+    fn program_counter_labels_are_parsed() {
+        assert_eq!(parse_label("@loop").unwrap(), ("loop".to_owned(), 0));
+        assert_eq!(parse_label("@loop[0]").unwrap(), ("loop".to_owned(), 0));
+        assert_eq!(parse_label("@loop[3]").unwrap(), ("loop".to_owned(), 3));
+        assert!(matches!(
+            parse_program_counter("0x10").unwrap(),
+            ProgramCounterRef::Preset(ProgramCounter(0x10))
+        ));
+    }
+
+    #[test]
+    fn malformed_program_counters_are_rejected() {
+        for rhs in ["@", "@loop[", "@loop[x]", "@loop[1", "@loop[1] junk", "@loop junk", "junk"] {
+            assert!(parse_program_counter(rhs).is_err(), "'{rhs}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn label_addresses_are_parsed() {
+        let label = |label: &str| ExpressionTerm::Label(label.to_owned());
+        assert_eq!(parse_label_expression("@table").unwrap(), [(false, label("table"))]);
+        assert_eq!(
+            parse_label_expression("@table+4").unwrap(),
+            [(false, label("table")), (false, ExpressionTerm::Value(4))]
+        );
+        assert_eq!(
+            parse_label_expression("@table - 0x10").unwrap(),
+            [(false, label("table")), (true, ExpressionTerm::Value(16))]
+        );
+        assert_eq!(
+            parse_label_expression("@end - @start + 1").unwrap(),
+            [(false, label("end")), (true, label("start")), (false, ExpressionTerm::Value(1))]
+        );
+        for rhs in ["@", "@+4", "@table+", "@table+x", "@table junk", "@table - - 4"] {
+            assert!(parse_label_expression(rhs).is_none(), "'{rhs}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn registers_can_be_set_to_label_addresses() {
+        const SOURCE: &str = "pre: a1 = @target
+post: a0 = @target
+post: a2 = @target+4
+
+pub @main:
+    a0 = a1
+    a2 = a1 + 4
+    jump @target
+
+pub @target:
+    a3 = 1
+";
+
+        let engine = new_engine();
+        let testcase = prepare_input(SOURCE, &engine, "test", "test", true).unwrap();
+        let target = testcase.json.initial_regs[Reg::A1.to_usize()];
+        assert_ne!(target, 0);
+        assert_eq!(testcase.json.expected_regs[Reg::A0.to_usize()], target);
+        assert_eq!(testcase.json.expected_regs[Reg::A2.to_usize()], target + 4);
+
+        // The expectations follow the label when the code before it changes.
+        let shifted_source = SOURCE.replace("    jump @target", "    a4 = 5\n    jump @target");
+        let shifted = prepare_input(&shifted_source, &engine, "test", "test", true).unwrap();
+        let shifted_target = shifted.json.initial_regs[Reg::A1.to_usize()];
+        assert!(shifted_target > target);
+        assert_eq!(shifted.json.expected_regs[Reg::A0.to_usize()], shifted_target);
+
+        let error = prepare_input(
+            &SOURCE.replace("pre: a1 = @target", "pre: a1 = @missing"),
+            &engine,
+            "test",
+            "test",
+            true,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error,
+            "test: label specified in 'pre: a1 = ...' is missing: @missing (found: @main, @target)"
+        );
+    }
+
+    #[test]
+    fn malformed_directives_are_reported_with_their_location() {
+        let engine = new_engine();
+        let input = "pre: gas = 10000
+post: pc = @main[
+
+pub @main:
+    trap
+";
+
+        let error = prepare_input(input, &engine, "test", "test.txt", true).err().unwrap();
+        assert!(
+            error.starts_with("test.txt:2: invalid 'pre' / 'post' directive: failed to parse 'pc'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn post_pc_without_an_offset_defaults_to_zero() {
+        let engine = new_engine();
+        let input = "
+pub @main:
+    a0 = 1
+pub @end:
+    trap
+
+post: pc = @end
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        let input = input.replace("@end\n", "@end[0]\n");
+        let testcase_with_offset = prepare_input(&input, &engine, "test", "test", true).unwrap();
+        assert_ne!(testcase.json.expected_pc, 0);
+        assert_eq!(testcase.json.expected_pc, testcase_with_offset.json.expected_pc);
+    }
+
+    #[test]
+    fn cross_check_agrees_with_the_interpreter() {
+        let Some(compiler_engine) = new_compiler_engine() else {
+            return;
+        };
+
+        let engine = new_engine();
+        let input = "
+%rw_data_size = 4
+
+pre: gas = 10000
+on-ecalli 7: a1 = 100
+
+pub @main:
+    a1 = 0x12345678
+    u32 [0x20000] = a1
+    ecalli 7
+    a2 = a1 + 1
+    trap
+";
+
+        let options = PrepareOptions {
+            cross_check_engine: Some(&compiler_engine),
+            ..PrepareOptions::default()
+        };
+        prepare_input_with_options(input, &engine, "test", "test", true, options).unwrap();
+    }
+
+    #[test]
+    fn outcome_differences_are_described() {
+        let expected = Outcome {
+            status: "halt".to_owned(),
+            page_fault_address: None,
+            pc: None,
+            regs: vec![0; 13],
+            gas: 10,
+            pages: vec![(0x20000, vec![0, 1, 2, 3])],
+            host_calls: vec![1],
+            step_count: None,
+        };
+
+        let mut actual = Outcome {
+            status: "panic".to_owned(),
+            page_fault_address: None,
+            pc: Some(4),
+            regs: vec![0; 13],
+            gas: 10,
+            pages: vec![(0x20000, vec![0, 1, 5, 3])],
+            host_calls: vec![1],
+            step_count: None,
+        };
+        actual.regs[Reg::A0 as usize] = 1;
+
+        assert_eq!(
+            diff_outcomes(&expected, &actual),
+            [
+                "  status: expected halt, is panic",
+                "  pc: expected none, is 0x4",
+                "  a0: expected 0x0, is 0x1",
+                "  memory[0x20002]: expected 0x02, is 0x05",
+            ]
+        );
+        assert!(diff_outcomes(&expected, &expected).is_empty());
+    }
+
+    #[test]
+    fn assembler_errors_refer_to_the_original_lines() {
+        let engine = new_engine();
+        let input = "pre: a0 = 1
+pre: gas = 10000
+page: 0x30000 len=4096 ro
+on-ecalli 1: a1 = 2
+
+pub @main:
+    a0 = a0 + 1
+    this is not an instruction
+
+post: a0 = 2
+";
+
+        let error = prepare_input(input, &engine, "test", "spec/src/test.txt", true).err().unwrap();
+        assert_eq!(
+            error,
+            "Failed to assemble spec/src/test.txt:8: cannot parse line 8: \"this is not an instruction\"\n    this is not an instruction"
+        );
+    }
+
+    #[test]
+    fn assembler_errors_without_a_line_are_prefixed_with_the_path() {
+        assert_eq!(
+            map_assembler_error("something went wrong", "test.txt", &[(3, "trap")]),
+            "test.txt: something went wrong"
+        );
+        assert_eq!(
+            map_assembler_error("cannot parse line 1", "test.txt", &[(3, "    trap  ")]),
+            "test.txt:3: cannot parse line 3\n    trap"
+        );
+    }
+
+    #[test]
+    fn trace_has_one_line_per_step() {
+        let engine = new_engine();
+        let input = "
+pre: gas = 10000
+on-ecalli 1: a2 = 3
+
+pub @main:
+    a0 = 1
+    ecalli 1
+    a1 = a0 + a2
+pub @expected_exit:
+    trap
+";
+
+        let options = PrepareOptions {
+            record_trace: true,
+            ..PrepareOptions::default()
+        };
+        let testcase = prepare_input_with_options(input, &engine, "test", "test", true, options).unwrap();
+        let trace = testcase.trace.unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+
+        // Five instructions were executed: 'a0 = 1', 'ecalli 1', 'a1 = a0 + a2', the implicit 'fallthrough' and 'trap'.
+        assert_eq!(lines.len(), 5);
+        assert_eq!(testcase.json.expected_step_count, Some(5));
+        assert!(lines[0].starts_with("pc = 0, "));
+        assert!(lines[0].ends_with(", a0 = 0x1"));
+        assert!(
+            !lines[1].contains("a2"),
+            "registers set by the host must not be attributed to 'ecalli'"
+        );
+        assert!(lines[2].ends_with(", a1 = 0x4"));
+        assert!(!lines[3].contains(" = 0x"));
+        assert!(!lines[4].contains(" = 0x"));
+
+        assert!(prepare_input(input, &engine, "test", "test", true).unwrap().trace.is_none());
+    }
+
+    fn chunk_layout(chunks: &[MemoryChunk]) -> Vec<(u32, usize)> {
+        chunks.iter().map(|chunk| (chunk.address, chunk.contents.len())).collect()
+    }
+
+    #[test]
+    fn chunks_are_coalesced_up_to_max_gap() {
+        let mut memory = vec![0; 64];
+        memory[1] = 1;
+        memory[3] = 2;
+        memory[4] = 3;
+        memory[7] = 4;
+        memory[24] = 5;
+        memory[42] = 6;
+
+        let base_address = 0x20000;
+        for (max_gap, expected_layout) in [
+            (0, vec![(0x20001, 1), (0x20003, 2), (0x20007, 1), (0x20018, 1), (0x2002a, 1)]),
+            (1, vec![(0x20001, 4), (0x20007, 1), (0x20018, 1), (0x2002a, 1)]),
+            (16, vec![(0x20001, 24), (0x2002a, 1)]),
+        ] {
+            let chunks = extract_chunks(base_address, &memory, max_gap);
+            assert_eq!(chunk_layout(&chunks), expected_layout, "max_gap = {max_gap}");
+
+            let mut image = vec![0; memory.len()];
+            for chunk in &chunks {
+                let offset = (chunk.address - base_address) as usize;
+                image[offset..offset + chunk.contents.len()].copy_from_slice(&chunk.contents);
+            }
+            assert_eq!(image, memory, "max_gap = {max_gap}");
+        }
+    }
+
+    #[test]
+    fn exit_code_is_only_recorded_on_halt() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/exit_code.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_exit_code, Some(42));
+
+        let input = "
+pub @main:
+    a0 = 42
+pub @expected_exit:
+    trap
+";
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_status, "panic");
+        assert_eq!(testcase.json.expected_exit_code, None);
+    }
+
+    #[test]
+    fn unchanged_read_only_memory_is_not_in_expected_memory() {
+        let engine = new_engine();
+        let input = "
+%ro_data = 0102030405060708
+%rw_data_size = 4
+
+pre: gas = 10000
+
+pub @main:
+    a0 = u64 [0x10000]
+    u64 [0x20000] = a0
+";
+
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(chunk_layout(&testcase.json.initial_memory), [(0x10000, 8)]);
+        assert_eq!(chunk_layout(&testcase.json.expected_memory), [(0x20000, 8)]);
+    }
+
+    #[test]
+    fn freshly_mapped_pages_are_in_expected_memory() {
+        let engine = new_engine();
+        let blob = ProgramBlob::parse(assemble("pub @main:\n    trap\n").unwrap().into()).unwrap();
+        let mut module_config = ModuleConfig::default();
+        module_config.set_dynamic_paging(true);
+        let module = Module::from_blob(&engine, &module_config, blob).unwrap();
+        let mut instance = module.instantiate().unwrap();
+        let page_size = module.memory_map().page_size();
+
+        let initial_page_map = [Page {
+            address: 0x10000,
+            length: page_size,
+            is_writable: false,
+        }];
+        let initial_memory = [MemoryChunk {
+            address: 0x10000,
+            contents: vec![1, 2, 3],
+        }];
+
+        instance.zero_memory(0x10000, page_size).unwrap();
+        instance.write_memory(0x10000, &[1, 2, 3]).unwrap();
+        instance.protect_memory(0x10000, page_size).unwrap();
+
+        instance.zero_memory(0x50000, page_size * 2).unwrap();
+        instance.write_memory(0x50000 + page_size - 1, &[4, 5]).unwrap();
+
+        let chunks = extract_final_memory(&instance, &initial_page_map, &initial_memory, page_size, 0);
+        assert_eq!(chunk_layout(&chunks), [(0x50000 + page_size - 1, 2)]);
+        assert_eq!(chunks[0].contents, [4, 5]);
+    }
+
+    #[test]
+    fn generated_testcases_pass_verification() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/page_fault_write.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "test", true).unwrap();
+        assert!(verify_testcase(&testcase.json).is_empty());
+    }
+
+    const MEMORY_ASSERTION_SOURCE: &str = "%rw_data_size = 1
+
+pre: a0 = 0x12345678
+pre: gas = 10000
+
+pub @main:
+    u32 [0x20000] = a0
+
+post: memory[0x20001] = 0x56 0x34
+";
+
+    #[test]
+    fn post_memory_assertions_are_checked_and_recorded() {
+        let engine = new_engine();
+        let testcase = prepare_input(MEMORY_ASSERTION_SOURCE, &engine, "test", "test", true).unwrap();
+        assert_eq!(
+            testcase.json.expected_memory,
+            [MemoryChunk {
+                address: 0x20000,
+                contents: vec![0x78, 0x56, 0x34, 0x12],
+            }]
+        );
+
+        // Without execution the assertions are all that's known about the final memory.
+        let testcase = prepare_input(MEMORY_ASSERTION_SOURCE, &engine, "test", "test", false).unwrap();
+        assert_eq!(
+            testcase.json.expected_memory,
+            [MemoryChunk {
+                address: 0x20001,
+                contents: vec![0x56, 0x34],
+            }]
+        );
+    }
+
+    #[test]
+    fn failing_post_memory_assertions_are_reported() {
+        let engine = new_engine();
+        let input = MEMORY_ASSERTION_SOURCE.replace("0x56 0x34", "0x56 0x35 0x13");
+        assert_eq!(
+            prepare_input(&input, &engine, "test", "test", true).err().unwrap(),
+            "test: unexpected memory[0x20002]: 0x34 (expected: 0x35)\ntest: unexpected memory[0x20003]: 0x12 (expected: 0x13)"
+        );
+
+        assert_eq!(
+            diff_memory(0x20001, &[0x56, 0x35, 0x13], &[0x56, 0x34, 0x12]),
+            [
+                "unexpected memory[0x20002]: 0x34 (expected: 0x35)",
+                "unexpected memory[0x20003]: 0x12 (expected: 0x13)",
+            ]
+        );
+
+        let input = MEMORY_ASSERTION_SOURCE.replace("memory[0x20001]", "memory[0x50000]");
+        assert_eq!(
+            prepare_input(&input, &engine, "test", "test", true).err().unwrap(),
+            "test: invalid 'post: memory[0x50000] = ...': the page at 0x50000 is not mapped"
+        );
+    }
+
+    #[test]
+    fn overlaid_memory_is_split_into_chunks_again() {
+        let chunks = [MemoryChunk {
+            address: 0x20000,
+            contents: vec![1, 2],
+        }];
+        let overlays = [(0x20001, vec![0, 0, 3]), (0x20010, vec![4])];
+        assert_eq!(
+            overlay_memory(&chunks, &overlays, 0),
+            [
+                MemoryChunk {
+                    address: 0x20000,
+                    contents: vec![1],
+                },
+                MemoryChunk {
+                    address: 0x20003,
+                    contents: vec![3],
+                },
+                MemoryChunk {
+                    address: 0x20010,
+                    contents: vec![4],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn the_disassembly_style_is_configurable() {
+        const SOURCE: &str = "pub @main:
+    a0 = 1
+    jump @end
+
+@end:
+    a1 = a0
+";
+
+        let engine = new_engine();
+        let disassemble = |disassembly_style: DisassemblyStyle| {
+            let options = PrepareOptions {
+                disassembly_style,
+                ..PrepareOptions::default()
+            };
+            prepare_input_with_options(SOURCE, &engine, "test", "test", false, options)
+                .unwrap()
+                .disassembly
+        };
+
+        let default = disassemble(DisassemblyStyle::default());
+        assert!(default.contains("r7 = 0x1"));
+        assert!(!default.contains("jump @"));
+
+        let with_abi_names = disassemble(DisassemblyStyle {
+            prefer_abi_reg_names: true,
+            ..DisassemblyStyle::default()
+        });
+        assert!(with_abi_names.contains("a0 = 0x1"));
+        assert!(!with_abi_names.contains("r7 = 0x1"));
+
+        let with_labels = disassemble(DisassemblyStyle {
+            prefer_offset_jump_targets: false,
+            ..DisassemblyStyle::default()
+        });
+        assert!(with_labels.contains("jump @1"));
+
+        let without_raw_bytes = disassemble(DisassemblyStyle {
+            show_raw_bytes: false,
+            ..DisassemblyStyle::default()
+        });
+        assert!(without_raw_bytes.len() < default.len());
+        assert!(without_raw_bytes.contains("r7 = 0x1"));
+        assert!(!default.contains("// gas"));
+
+        let with_hex_offsets = disassemble(DisassemblyStyle {
+            prefer_hex_offsets: true,
+            ..DisassemblyStyle::default()
+        });
+        assert!(with_hex_offsets.contains("0x0003: "), "{with_hex_offsets}");
+        assert!(with_hex_offsets.contains("jump 0x0005\n"), "{with_hex_offsets}");
+        assert!(!with_hex_offsets.contains("     3: "), "{with_hex_offsets}");
+        assert!(with_hex_offsets.contains("r7 = 0x1"), "{with_hex_offsets}");
+    }
+
+    #[test]
+    fn jump_targets_are_shown_by_the_name_of_their_export() {
+        let engine = new_engine();
+        let fib = std::fs::read_to_string(format!("{}/spec/src/fib.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let disassemble = |source: &str, prefer_export_jump_targets: bool| {
+            let options = PrepareOptions {
+                disassembly_style: DisassemblyStyle {
+                    prefer_offset_jump_targets: false,
+                    prefer_export_jump_targets,
+                    ..DisassemblyStyle::default()
+                },
+                ..PrepareOptions::default()
+            };
+            prepare_input_with_options(source, &engine, "fib", "fib.txt", false, options)
+                .unwrap()
+                .disassembly
+        };
+
+        // Neither `main` nor `expected_exit` are jumped to, so only the block indices are shown.
+        assert_eq!(disassemble(&fib, true), disassemble(&fib, false));
+
+        let fib = fib.replace("jump @end if a0 == 0", "jump @expected_exit if a0 == 0");
+        let with_exports = disassemble(&fib, true);
+        assert!(with_exports.contains("jump @expected_exit if r7 == 0\n"), "{with_exports}");
+        assert!(with_exports.contains("jump @2\n"), "{with_exports}");
+        assert!(!disassemble(&fib, false).contains("@expected_exit"));
+
+        // The same applies to the disassembly of a whole blob, which has its exports.
+        let options = DisassembleOptions {
+            prefer_export_jump_targets: true,
+            ..DisassembleOptions::default()
+        };
+        let blob = assemble_with_symbols(&fib).unwrap().blob;
+        let from_blob = disassemble_blob_with_options(blob, options).unwrap();
+        assert!(from_blob.contains("jump @expected_exit if r7 == 0\n"), "{from_blob}");
+
+        // With two exports at the same target the alphabetically first one is shown, regardless of their order.
+        for colliding in ["pub @done:\npub @expected_exit:", "pub @expected_exit:\npub @done:"] {
+            let with_exports = disassemble(&fib.replace("pub @expected_exit:", colliding), true);
+            assert!(with_exports.contains("jump @done if r7 == 0\n"), "{with_exports}");
+        }
+    }
+
+    #[test]
+    fn every_spec_source_roundtrips_through_the_assembler() {
+        let mut count = 0;
+        for entry in std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src")).unwrap() {
+            let path = entry.unwrap().path();
+            let input = std::fs::read_to_string(&path).unwrap();
+            for case in split_cases(&input, "test").unwrap() {
+                // Some of the sources are expected not to assemble.
+                let Ok(program) = assemble_with_symbols(&case.input) else {
+                    continue;
+                };
+
+                if let Err(error) = roundtrip(program.blob, true) {
+                    panic!("{}: {error}", path.display());
+                }
+                count += 1;
+            }
+        }
+
+        assert!(count > 200, "only {count} programs were round-tripped");
+    }
+
+    #[test]
+    fn generated_testcases_roundtrip_through_the_assembler() {
+        let engine = new_engine();
+        let fib = prepare_spec_source(&engine, "fib");
+        let assembly = roundtrip(fib.program.clone(), true).unwrap();
+        assert_eq!(assemble_with_symbols(&assembly).unwrap().code_and_jump_table, fib.program);
+
+        let options = DisassembleOptions {
+            reassemblable: true,
+            ..DisassembleOptions::default()
+        };
+        assert_eq!(disassemble_with_options(fib.program, options).unwrap(), assembly);
+
+        for seed in 0..50 {
+            let program = random_program(&mut Rng::new(seed), 32);
+            let testcase = prepare_input(&program, &engine, "fuzz", "fuzz", false).unwrap();
+            if let Err(error) = roundtrip(testcase.json.program, true) {
+                panic!("{error}\n{program}");
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_reports_the_first_divergence() {
+        use polkavm_common::program::asm;
+
+        // The assembler always lays out the jump table in the order of the code, so this one can't be reproduced.
+        let mut builder = polkavm_common::writer::ProgramBlobBuilder::new_64bit();
+        builder.set_code(
+            &[asm::load_imm(Reg::A0, 4), asm::jump_indirect(Reg::A0, 0), asm::trap(), asm::trap()],
+            &[1, 2],
+        );
+        let error = roundtrip(builder.to_vec(), true).unwrap_err();
+        assert_eq!(error, "jump table entry #0 diverges: 5 was reassembled as nothing");
+
+        let mut builder = polkavm_common::writer::ProgramBlobBuilder::new_64bit();
+        builder.set_code(
+            &[
+                asm::load_imm(Reg::A0, 2),
+                asm::load_imm(Reg::A1, 4),
+                asm::jump_indirect(Reg::A0, 0),
+                asm::trap(),
+                asm::trap(),
+            ],
+            &[1, 2],
+        );
+        assert!(roundtrip(builder.to_vec(), true).unwrap().contains("    r7 = @1\n    r8 = @2\n"));
+    }
+
+    #[test]
+    fn gas_cost_annotations_add_up_to_the_executed_gas() {
+        let engine = new_engine();
+        let options = PrepareOptions {
+            disassembly_style: DisassemblyStyle {
+                show_gas_costs: true,
+                ..DisassemblyStyle::default()
+            },
+            ..PrepareOptions::default()
+        };
+
+        for source in ["gas_model_polkavm.txt", "gas_model_flat.txt"] {
+            let input = std::fs::read_to_string(format!("{}/spec/src/{source}", env!("CARGO_MANIFEST_DIR"))).unwrap();
+            let testcase = prepare_input_with_options(&input, &engine, "test", source, true, options).unwrap();
+            let annotation = |line: &str, prefix: &str| -> Option<i64> { Some(line.split_once(prefix)?.1.trim().parse().unwrap()) };
+            let instruction_costs: Vec<i64> = testcase
+                .disassembly
+                .lines()
+                .filter_map(|line| annotation(line, "// gas: "))
+                .collect();
+            let block_costs: Vec<i64> = testcase
+                .disassembly
+                .lines()
+                .filter_map(|line| annotation(line, "// gas of @0: "))
+                .collect();
+
+            // The program is a single basic block, so all of it is executed and charged.
+            let executed_gas = testcase.json.initial_gas - testcase.json.expected_gas;
+            assert_eq!(instruction_costs.len(), 3, "{}", testcase.disassembly);
+            assert_eq!(instruction_costs.iter().sum::<i64>(), executed_gas, "{}", testcase.disassembly);
+            assert_eq!(block_costs, [executed_gas], "{}", testcase.disassembly);
+            assert_eq!(testcase.block_costs[0].cost, executed_gas);
+        }
+    }
+
+    fn prepare_spec_source(engine: &Engine, source: &str) -> TestcaseJson {
+        let input = std::fs::read_to_string(format!("{}/spec/src/{source}.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        prepare_input(&input, engine, source, source, true).unwrap().json
+    }
+
+    #[test]
+    fn generated_testcases_pass_when_run() {
+        let engine = new_engine();
+        for source in ["inst_store_u32", "exit_code", "page_fault_write", "page_fault_resume", "interrupts"] {
+            let report = run_testcase(&engine, &prepare_spec_source(&engine, source)).unwrap();
+            assert!(report.passed, "{source}: {:?}", report.mismatches);
+            assert!(report.mismatches.is_empty());
+        }
+    }
+
+    #[test]
+    fn running_a_testcase_reports_every_mismatching_field() {
+        let engine = new_engine();
+        let mismatched_fields = |testcase: &TestcaseJson| -> Vec<String> {
+            let report = run_testcase(&engine, testcase).unwrap();
+            assert!(!report.passed);
+            report.mismatches.into_iter().map(|mismatch| mismatch.field).collect()
+        };
+
+        let valid = prepare_spec_source(&engine, "inst_store_u32");
+        let mutations: [(&str, fn(&mut TestcaseJson)); 7] = [
+            ("expected-status", |testcase| testcase.expected_status = "halt".to_owned()),
+            ("expected-pc", |testcase| testcase.expected_pc += 1),
+            ("expected-gas", |testcase| testcase.expected_gas -= 1),
+            ("expected-regs[7]", |testcase| testcase.expected_regs[Reg::A0.to_usize()] ^= 1),
+            ("expected-memory[0x20000]", |testcase| testcase.expected_memory[0].contents[0] ^= 1),
+            ("expected-memory[0x20000]", |testcase| testcase.expected_memory.clear()),
+            ("expected-step-count", |testcase| {
+                *testcase.expected_step_count.as_mut().unwrap() += 1
+            }),
+        ];
+
+        for (field, mutate) in mutations {
+            let mut testcase = valid.clone();
+            mutate(&mut testcase);
+            assert_eq!(mismatched_fields(&testcase), [field]);
+        }
+
+        let mut testcase = valid.clone();
+        testcase.expected_memory[0].contents[0] ^= 1;
+        assert_eq!(
+            run_testcase(&engine, &testcase).unwrap().mismatches,
+            [Mismatch {
+                field: "expected-memory[0x20000]".to_owned(),
+                expected: "79563412".to_owned(),
+                actual: "78563412".to_owned(),
+            }]
+        );
+
+        let mut testcase = prepare_spec_source(&engine, "exit_code");
+        testcase.expected_exit_code = Some(43);
+        assert_eq!(mismatched_fields(&testcase), ["expected-exit-code"]);
+
+        let mut testcase = prepare_spec_source(&engine, "page_fault_write");
+        testcase.expected_page_fault_address = Some(0x31000);
+        assert_eq!(mismatched_fields(&testcase), ["expected-page-fault-address"]);
+
+        let mut testcase = prepare_spec_source(&engine, "interrupts");
+        testcase.expected_host_calls = Some(vec![2]);
+        assert_eq!(mismatched_fields(&testcase), ["expected-host-calls"]);
+    }
+
+    #[test]
+    fn testcases_which_cannot_be_run_are_rejected() {
+        let engine = new_engine();
+        let mut testcase = prepare_spec_source(&engine, "inst_store_u32");
+        testcase.program[0] ^= 1;
+        assert!(run_testcase(&engine, &testcase).unwrap_err().starts_with("program-hash:"));
+
+        let mut testcase = prepare_spec_source(&engine, "inst_store_u32");
+        testcase.program_ref = Some(program_ref_for(&testcase.program));
+        assert!(run_testcase(&engine, &testcase).unwrap_err().starts_with("program-ref:"));
+    }
+
+    // The same program as in the tests of the `pvm-shell`.
+    const FIB: &[u8] = &[
+        0, 0, 33, 51, 8, 1, 51, 9, 1, 40, 3, 0, 149, 119, 255, 81, 7, 12, 100, 138, 200, 152, 8, 100, 169, 40, 243, 100, 135, 51, 8, 51, 9,
+        1, 50, 0, 73, 147, 82, 213, 0,
+    ];
+
+    #[test]
+    fn structured_disassembly_agrees_with_the_textual_one() {
+        let instructions = structured_instructions(FIB.to_vec(), DisassembleOptions::default()).unwrap();
+        let offsets: Vec<u32> = instructions.iter().map(|instruction| instruction.offset).collect();
+        assert_eq!(offsets, [0, 3, 6, 8, 9, 12, 15, 17, 20, 22, 24, 26, 28, 30, 31]);
+
+        // Every line of the textual disassembly is either a basic block's label or an instruction preceded by its offset.
+        let mut expected = Vec::new();
+        let mut basic_block = None;
+        for line in disassemble(FIB.to_vec()).unwrap().lines() {
+            let (offset, rest) = line.split_once(": ").unwrap();
+            match offset.trim() {
+                "" => basic_block = Some(rest.strip_prefix('@').unwrap().parse::<u32>().unwrap()),
+                offset => expected.push((offset.parse::<u32>().unwrap(), basic_block.unwrap())),
+            }
+        }
+
+        let actual: Vec<_> = instructions
+            .iter()
+            .map(|instruction| (instruction.offset, instruction.basic_block))
+            .collect();
+        assert_eq!(actual, expected);
+        assert_eq!(instructions.iter().map(|instruction| instruction.length).sum::<u32>(), 33);
+
+        let at = |offset: u32| instructions.iter().find(|instruction| instruction.offset == offset).unwrap();
+        assert_eq!(at(0).mnemonic, "load_imm");
+        assert_eq!(at(0).raw_bytes, [51, 8, 1]);
+        assert_eq!(at(0).operands, [StructuredOperand::Reg("r8"), StructuredOperand::Imm(1)]);
+        assert_eq!(at(6).targets, [9]);
+        assert_eq!(
+            at(12).operands,
+            [
+                StructuredOperand::Reg("r7"),
+                StructuredOperand::Imm(0),
+                StructuredOperand::Offset(24)
+            ]
+        );
+        assert_eq!(at(12).targets, [24]);
+        assert_eq!(at(31).mnemonic, "jump_indirect");
+        assert!(at(31).targets.is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&disassemble_structured(FIB.to_vec()).unwrap()).unwrap();
+        assert_eq!(
+            json[0],
+            serde_json::json!({
+                "offset": 0,
+                "length": 3,
+                "raw_bytes": [51, 8, 1],
+                "mnemonic": "load_imm",
+                "operands": [{ "kind": "reg", "value": "r8" }, { "kind": "imm", "value": 1 }],
+                "basic_block": 0,
+                "targets": [],
+            })
+        );
+    }
+
+    #[test]
+    fn raw_programs_are_wrapped_into_testcases() {
+        let engine = new_engine();
+        let initial_regs = parse_reg_assignments(&["a0=9".to_owned()]).unwrap();
+        let testcase = wrap_program(&engine, "fib", FIB.to_vec(), initial_regs, DEFAULT_GAS).unwrap();
+        assert_eq!(testcase.json.initial_regs[7], 9);
+        assert_eq!(testcase.json.initial_gas, DEFAULT_GAS);
+        assert_eq!(testcase.json.expected_status, "panic");
+        assert_eq!(testcase.json.expected_pc, 31);
+        assert!(testcase.json.expected_gas < DEFAULT_GAS);
+        assert!(testcase.disassembly.contains("r7"));
+        assert!(verify_testcase(&testcase.json).is_empty());
+        assert!(run_testcase(&engine, &testcase.json).unwrap().passed);
+
+        // A program which faults right away still results in a valid testcase.
+        let program = prepare_input("pub @main:\n    a0 = u32 [0x20000]\n", &engine, "test", "test", true)
+            .unwrap()
+            .json
+            .program;
+        let testcase = wrap_program(&engine, "fault", program, [0; 13], DEFAULT_GAS).unwrap();
+        assert_eq!(testcase.json.expected_status, "page-fault");
+        assert_eq!(testcase.json.expected_page_fault_address, Some(0x20000));
+        assert_eq!(testcase.json.expected_page_fault_access.as_deref(), Some("read"));
+        assert!(verify_testcase(&testcase.json).is_empty());
+        assert!(run_testcase(&engine, &testcase.json).unwrap().passed);
+
+        assert!(wrap_program(&engine, "garbage", vec![0xff; 16], [0; 13], DEFAULT_GAS).is_err());
+    }
+
+    #[test]
+    fn register_assignments_are_parsed() {
+        let regs = parse_reg_assignments(&["a0=9".to_owned(), "r8 = 0x10".to_owned()]).unwrap();
+        assert_eq!(regs[7], 9);
+        assert_eq!(regs[8], 0x10);
+        assert_eq!(regs.iter().filter(|value| **value != 0).count(), 2);
+
+        assert_eq!(
+            parse_reg_assignments(&["a0".to_owned()]).unwrap_err(),
+            "invalid register assignment 'a0': no '=' found"
+        );
+        assert_eq!(
+            parse_reg_assignments(&["x0=1".to_owned()]).unwrap_err(),
+            "invalid register assignment 'x0=1': unknown register"
+        );
+        assert_eq!(
+            parse_reg_assignments(&["a0=one".to_owned()]).unwrap_err(),
+            "invalid register assignment 'a0=one': failed to parse the value"
+        );
+    }
+
+    #[test]
+    fn pre_directives_are_overridden() {
+        let input = "pre: a0 = 9\npre: gas = 100\npre: a1 = 2\n\npub @main:\n    trap\n";
+        let initial_regs = BTreeMap::from([("r7".to_owned(), 5)]);
+        let (output, warnings) = override_pre_directives(input, Some(777), &initial_regs).unwrap();
+        assert_eq!(output, "\n\npre: a1 = 2\n\npub @main:\n    trap\npre: gas = 777\npre: a0 = 0x5\n");
+        assert_eq!(
+            warnings,
+            [
+                "line 1: 'pre: a0 = 9' is overridden by the options",
+                "line 2: 'pre: gas = 100' is overridden by the options"
+            ]
+        );
+
+        let (output, warnings) = override_pre_directives(input, None, &BTreeMap::new()).unwrap();
+        assert_eq!(output, input);
+        assert!(warnings.is_empty());
+
+        let initial_regs = BTreeMap::from([("x0".to_owned(), 1)]);
+        assert_eq!(
+            override_pre_directives(input, None, &initial_regs).unwrap_err(),
+            "invalid register override 'x0': unknown register"
+        );
+    }
+
+    #[test]
+    fn verification_reports_every_violation() {
+        let valid: TestcaseJson = serde_json::from_str(include_str!("../tests/fixtures/verify/valid.json")).unwrap();
+        assert!(verify_testcase(&valid).is_empty());
+
+        let broken: TestcaseJson = serde_json::from_str(include_str!("../tests/fixtures/verify/broken.json")).unwrap();
+        assert_eq!(
+            verify_testcase(&broken),
+            [
+                "expected-regs: expected 13 registers, found 12",
+                "initial-page-map: the region at 0x20000 overlaps with the region at 0x21000",
+                "initial-memory: the chunk at 0x90000 (0x1 bytes) is outside of the initial page map",
+                "initial-memory: the chunk at 0x20000 overlaps with the chunk at 0x20002",
+                "expected-memory: the chunk at 0x20000 is empty",
+                "expected-pc: 5 is not at an instruction boundary",
+                "program-hash: doesn't match the program \
+                 (expected 0000000000000000000000000000000000000000000000000000000000000000, \
+                 found b45d6a66a3713588568a1a3c5197eea4caa53a8cacba375138c928725ec856ce)",
+                "expected-exit-code: must only be present if the status is 'halt'",
+            ]
+        );
+    }
+
+    #[test]
+    fn verification_flags_tampered_programs() {
+        let mut testcase: TestcaseJson = serde_json::from_str(include_str!("../tests/fixtures/verify/valid.json")).unwrap();
+        testcase.program[4] = 1;
+        let violations = verify_testcase(&testcase);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert!(violations[0].starts_with("program-hash: doesn't match the program"));
+
+        testcase.program_hash_algorithm = Some("md5".to_owned());
+        assert_eq!(verify_testcase(&testcase), ["program-hash-algorithm: unknown algorithm 'md5'"]);
+    }
+
+    #[test]
+    fn the_symbol_map_matches_the_disassembly() {
+        let source = include_str!("../spec/src/fib.txt");
+        let assembled = assemble_with_symbols(source).unwrap();
+        assert_eq!(assembled.exports.keys().collect::<Vec<_>>(), ["expected_exit", "main"]);
+        assert_eq!(assembled.local_labels.keys().collect::<Vec<_>>(), ["end", "loop"]);
+        assert_eq!(assembled.exports["main"], 0);
+
+        let testcase = prepare_input(source, &new_engine(), "fib", "fib.txt", false).unwrap();
+        assert_eq!(testcase.json.program, assembled.code_and_jump_table);
+
+        // Every label starts a basic block, which the disassembly precedes with a '@<index>' line.
+        let lines: Vec<&str> = testcase.disassembly.lines().collect();
+        let basic_block_starts: Vec<u32> = lines
+            .windows(2)
+            .filter(|pair| pair[0].trim_start().starts_with(": @"))
+            .map(|pair| pair[1].split(':').next().unwrap().trim().parse().unwrap())
+            .collect();
+
+        let pc = |label: &str| {
+            assembled
+                .exports
+                .get(label)
+                .or_else(|| assembled.local_labels.get(label))
+                .copied()
+                .unwrap()
+        };
+        let label_pcs: Vec<u32> = ["main", "loop", "end", "expected_exit"].into_iter().map(pc).collect();
+        for label_pc in &label_pcs {
+            assert!(basic_block_starts.contains(label_pc), "{label_pc} in:\n{}", testcase.disassembly);
+        }
+        assert!(label_pcs.windows(2).all(|pair| pair[0] < pair[1]), "{label_pcs:?}");
+
+        let error = assemble_with_symbols("pre: a0 = 1\n\npub @main:\n    jump @nowhere\n")
+            .err()
+            .unwrap();
+        assert!(error.contains("\"nowhere\""), "{error}");
+    }
+
+    #[test]
+    fn random_programs_are_deterministic_and_always_assemble() {
+        for seed in 0..500 {
+            let program = random_program(&mut Rng::new(seed), 32);
+            assert_eq!(program, random_program(&mut Rng::new(seed), 32));
+            if let Err(error) = assemble(&program) {
+                panic!("failed to assemble a random program: {error}\n{program}");
+            }
+        }
+    }
+
+    #[test]
+    fn cases_only_see_their_own_directives() {
+        let cases = split_cases(
+            "pre: gas = 100\n=== a\npre: a0 = 1\n=== b\npre: a0 = 2\npub @main:\n    trap",
+            "test",
+        )
+        .unwrap();
+        let cases: Vec<_> = cases.into_iter().map(|case| (case.name.unwrap(), case.input)).collect();
+        assert_eq!(
+            cases,
+            [
+                ("a".to_owned(), "pre: gas = 100\n\npre: a0 = 1\n\n\npub @main:\n    trap".to_owned()),
+                ("b".to_owned(), "pre: gas = 100\n\n\n\npre: a0 = 2\npub @main:\n    trap".to_owned()),
+            ]
+        );
+
+        let cases = split_cases("pub @main:\n    trap", "test").unwrap();
+        assert!(cases.len() == 1 && cases[0].name.is_none());
+    }
+
+    #[test]
+    fn malformed_cases_are_rejected() {
+        let error = |input: &str| split_cases(input, "test").err().unwrap();
+        assert_eq!(error("=== a\n=== a\n"), "test:2: duplicate case name: 'a'");
+        assert_eq!(error("=== \n"), "test:1: invalid case name: ''");
+        assert_eq!(
+            error("=== a\n    trap\n=== b\n"),
+            "test:2: only directives can be used before the last case's separator"
+        );
+    }
+
+    #[test]
+    fn block_costs_add_up_to_the_consumed_gas() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/fib.txt")).unwrap();
+        let options = PrepareOptions {
+            record_trace: true,
+            ..PrepareOptions::default()
+        };
+        let testcase = prepare_input_with_options(&input, &engine, "fib", "fib", true, options).unwrap();
+        assert_eq!(testcase.json.expected_status, "halt");
+        assert!(testcase.block_costs.len() > 1);
+
+        // Every time the execution enters a block it's charged the block's cost.
+        let mut consumed_gas = 0;
+        for line in testcase.trace.unwrap().lines() {
+            let pc: u32 = line.strip_prefix("pc = ").unwrap().split(',').next().unwrap().parse().unwrap();
+            if let Some(block) = testcase.block_costs.iter().find(|block| block.start == pc) {
+                consumed_gas += block.cost;
+            }
+        }
+
+        assert_eq!(consumed_gas, testcase.json.initial_gas - testcase.json.expected_gas);
+    }
+
+    #[test]
+    fn step_counts_include_the_terminal_instruction() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/fib.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "fib", "fib", true).unwrap();
+
+        // 3 instructions before the loop, 8 full iterations of 6 instructions, 2 more to leave the loop,
+        // and then 3 moves, the implicit 'fallthrough' and the final 'ret'.
+        assert_eq!(testcase.json.expected_step_count, Some(3 + 8 * 6 + 2 + 3 + 1 + 1));
+
+        let testcase = prepare_input("pub @main:\n    trap\n", &engine, "test", "test", true).unwrap();
+        assert_eq!(testcase.json.expected_step_count, Some(1));
+
+        let testcase = prepare_input("pub @main:\n    trap\n\npost: status = panic\n", &engine, "test", "test", false).unwrap();
+        assert_eq!(testcase.json.expected_step_count, None);
+    }
+
+    #[test]
+    fn descriptions_are_preserved_verbatim() {
+        let engine = new_engine();
+        let input = "desc: Checks that `a0 = a0 + 1` wraps.\ndesc:  Indented, with punctuation: (1 + 1) != 3!\npre: a0 = 1\n\npub @main:\n    a0 = a0 + 1\n";
+        let testcase = prepare_input(input, &engine, "test", "test", true).unwrap();
+        assert_eq!(
+            testcase.json.description.as_deref(),
+            Some("Checks that `a0 = a0 + 1` wraps.\n Indented, with punctuation: (1 + 1) != 3!")
+        );
+
+        let payload = serde_json::to_string(&testcase.json).unwrap();
+        assert!(payload.contains("\"description\":"));
+
+        let testcase = prepare_input("pub @main:\n    trap\n", &engine, "test", "test", true).unwrap();
+        assert!(!serde_json::to_string(&testcase.json).unwrap().contains("description"));
+    }
+
+    #[test]
+    fn interrupts_are_checked_in_order() {
+        let engine = new_engine();
+        let input = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/interrupts.txt")).unwrap();
+        let testcase = prepare_input(&input, &engine, "test", "test", true).unwrap();
+        let interrupts = testcase.json.expected_interrupts.unwrap();
+        assert_eq!(format_interrupts(&interrupts), "host(1), page-fault(0x30000)");
+
+        let input = input.replace("host(1), page-fault(0x30000)", "page-fault(0x30000), host(1)");
+        assert!(prepare_input(&input, &engine, "test", "test", true).is_err());
+
+        let testcase = prepare_input("pub @main:\n    trap\n", &engine, "test", "test", true).unwrap();
+        assert!(testcase.json.expected_interrupts.is_none());
+    }
+
+    #[test]
+    fn malformed_interrupts_are_rejected() {
+        assert_eq!(
+            parse_interrupts("host(1), page-fault(0x30000), halt").unwrap(),
+            [
+                Interrupt {
+                    host_call: Some(1),
+                    ..Interrupt::new("host")
+                },
+                Interrupt {
+                    address: Some(0x30000),
+                    ..Interrupt::new("page-fault")
+                },
+                Interrupt::new("halt"),
+            ]
+        );
+
+        assert!(parse_interrupts("host").is_err());
+        assert!(parse_interrupts("halt(1)").is_err());
+        assert!(parse_interrupts("host(1").is_err());
+        assert!(parse_interrupts("explode").is_err());
     }
 }
@@ -5,19 +5,351 @@
 
 use clap::Parser;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use polkavm::{Engine, Reg};
-use spectool::{new_engine, prepare_input, Testcase};
+use spectool::cfg::control_flow_graph;
+use spectool::{
+    benchmark_exclusion_reason, benchmark_testcase, canonical_disassembly, check_assembly_error, check_format_version,
+    disassemble_blob_with_options, find_category, format_host_calls, format_interrupts, migrate_testcase, new_compiler_engine, new_engine,
+    parse_reg_assignments, prepare_input, prepare_input_with_options, program_ref_for, random_program, randomized_reg_variants,
+    resolve_program_ref, roundtrip, run_testcase, split_cases, testcase_from_json_with_style, to_canonical_json, verify_testcase,
+    wrap_program, DisassembleOptions, DisassemblyStyle, GasModel, NegativeTestcase, PrepareOptions, Rng, SourceCase, TestRunReport,
+    Testcase, TestcaseJson, DEFAULT_FLAT_GAS_COST, DEFAULT_GAS,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
+/// The encodings in which the generated testcases are written.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, clap::ValueEnum)]
+enum OutputFormat {
+    Json,
+    Scale,
+    Both,
+}
+
+impl OutputFormat {
+    fn has_json(self) -> bool {
+        matches!(self, OutputFormat::Json | OutputFormat::Both)
+    }
+
+    fn has_scale(self) -> bool {
+        matches!(self, OutputFormat::Scale | OutputFormat::Both)
+    }
+}
+
+/// What `spectool prepare` emits.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, clap::ValueEnum)]
+enum PrepareFormat {
+    /// The JSON testcase (or an array of them, for a source with several cases).
+    Json,
+    /// The disassembly of the program.
+    Disasm,
+    /// A JSON object with the testcase as `testcase` and its disassembly as `disassembly` (or an array of them, for a source with several cases).
+    Both,
+}
+
+/// The flags which control how the disassemblies of the testcases are rendered.
+#[derive(clap::Args, Debug)]
+struct DisassemblyFlags {
+    /// Show the raw bytes of each instruction.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    disasm_raw_bytes: bool,
+
+    /// Show jump targets as offsets instead of labels.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    disasm_offset_targets: bool,
+
+    /// Name the registers after their ABI role (e.g. `a0`) instead of their index (e.g. `r7`).
+    #[clap(long, default_value_t = false, action = clap::ArgAction::Set)]
+    disasm_abi_names: bool,
+
+    /// Annotate every instruction and basic block with its gas cost under the testcase's gas model.
+    #[clap(long, default_value_t = false, action = clap::ArgAction::Set)]
+    disasm_gas_costs: bool,
+
+    /// Show the jump targets which coincide with an exported label by its name (e.g. `@expected_exit`).
+    #[clap(long, default_value_t = false, action = clap::ArgAction::Set)]
+    disasm_export_targets: bool,
+
+    /// Show the offsets of the instructions and the jump targets in hexadecimal.
+    #[clap(long, default_value_t = false, action = clap::ArgAction::Set)]
+    disasm_hex_offsets: bool,
+}
+
+impl DisassemblyFlags {
+    fn style(&self) -> DisassemblyStyle {
+        DisassemblyStyle {
+            show_raw_bytes: self.disasm_raw_bytes,
+            prefer_offset_jump_targets: self.disasm_offset_targets,
+            prefer_abi_reg_names: self.disasm_abi_names,
+            show_gas_costs: self.disasm_gas_costs,
+            prefer_export_jump_targets: self.disasm_export_targets,
+            prefer_hex_offsets: self.disasm_hex_offsets,
+        }
+    }
+}
+
+/// The gas models which can be selected on the command line; see [`GasModel`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, clap::ValueEnum)]
+enum GasModelKind {
+    /// The cost of a basic block is charged when it's entered, as by the interpreter.
+    Polkavm,
+    /// Every instruction costs `--flat-gas-cost`, charged right before it's executed.
+    Flat,
+}
+
+/// The flags which select the gas model of the testcases without a `gas-model: ...` directive.
+#[derive(clap::Args, Debug)]
+struct GasModelFlags {
+    /// The gas model of the testcases without a `gas-model: ...` directive.
+    #[clap(long, value_enum, default_value_t = GasModelKind::Polkavm)]
+    gas_model: GasModelKind,
+
+    /// The cost of every instruction under the flat gas model, unless given with `gas-model: flat, cost = ...`.
+    #[clap(long, default_value_t = DEFAULT_FLAT_GAS_COST)]
+    flat_gas_cost: u32,
+}
+
+impl GasModelFlags {
+    fn gas_model(&self) -> GasModel {
+        match self.gas_model {
+            GasModelKind::Polkavm => GasModel::Polkavm,
+            GasModelKind::Flat => GasModel::Flat {
+                cost_per_instruction: self.flat_gas_cost,
+            },
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[clap(version)]
 enum Args {
-    Generate,
+    Generate {
+        /// The number of testcases to prepare in parallel; defaults to the number of cores.
+        #[clap(long)]
+        jobs: Option<usize>,
+
+        /// Reference the raw `.bin` program from each JSON testcase through a `program-file` field.
+        #[clap(long)]
+        emit_program_file: bool,
+
+        /// Only prepare the sources whose name or path contains the given substring; can be given multiple times.
+        #[clap(long = "filter", alias = "only")]
+        filters: Vec<String>,
+
+        /// Also run every testcase with the compiler backend and fail if the results differ from the interpreter's.
+        #[clap(long)]
+        cross_check: bool,
+
+        /// Write a step-by-step trace of every testcase's execution into `traces/<name>.txt`.
+        #[clap(long)]
+        emit_traces: bool,
+
+        /// Write the control-flow graph of every testcase in Graphviz's dot format into `cfg/<name>.dot`.
+        #[clap(long)]
+        emit_cfg: bool,
+
+        /// Merge non-zero memory chunks which are separated by at most this many zero bytes.
+        #[clap(long, default_value_t = 0)]
+        max_chunk_gap: usize,
+
+        /// The encoding of the generated testcases; `scale` writes them into `programs/<name>.scale` using the JAM codec.
+        #[clap(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// Write every distinct program only once into `programs/blobs/<hash>.bin` and reference it from the JSON testcases
+        /// through a `program-ref` field, instead of embedding it; without this flag the programs are embedded as before.
+        #[clap(long, conflicts_with = "emit_program_file")]
+        dedup_programs: bool,
+
+        /// Don't write anything; instead compare every output with what's on disk and fail if any of them is stale.
+        #[clap(long, conflicts_with = "filters")]
+        check: bool,
+
+        /// Also write the printed summary of the generation into `SUMMARY.json`.
+        #[clap(long)]
+        emit_summary: bool,
+
+        /// The gas given to the testcases without a `pre: gas = ...` directive.
+        #[clap(long, default_value_t = DEFAULT_GAS)]
+        default_gas: i64,
+
+        /// Require every register changed by a testcase to be asserted or waived, as if every source had a `strict-post` directive.
+        #[clap(long)]
+        strict_post: bool,
+
+        /// Write every testcase into its own `docs/<name>.md` page, and only a table of contents linking to them into
+        /// `TESTCASES.md`; without this flag everything is written into `TESTCASES.md` as before.
+        #[clap(long)]
+        split_index: bool,
+
+        #[clap(flatten)]
+        disassembly_flags: DisassemblyFlags,
+
+        #[clap(flatten)]
+        gas_model_flags: GasModelFlags,
+
+        /// Also generate this many variants of every source with a `randomize: regs` directive, named `<name>_rand<i>`,
+        /// whose initial registers which the source doesn't set are random.
+        #[clap(long, default_value_t = 0)]
+        randomize_regs: usize,
+
+        /// The seed of the randomized initial registers; the same seed always generates the same variants.
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
     Prepare {
-        /// The input file.
+        /// The input file; use `-` to read from stdin.
+        input: PathBuf,
+
+        /// Write the output into this file instead of printing it.
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
+
+        /// Also write the disassembly of the testcase into this file.
+        #[clap(long)]
+        disassembly: Option<PathBuf>,
+
+        /// The gas given to the testcases without a `pre: gas = ...` directive.
+        #[clap(long, default_value_t = DEFAULT_GAS)]
+        default_gas: i64,
+
+        /// What to output: the JSON testcase, its disassembly, or both of them wrapped in a single JSON object.
+        #[clap(long, value_enum, default_value_t = PrepareFormat::Json)]
+        format: PrepareFormat,
+
+        #[clap(flatten)]
+        disassembly_flags: DisassemblyFlags,
+
+        #[clap(flatten)]
+        gas_model_flags: GasModelFlags,
+    },
+    /// Runs already generated JSON testcases and reports every expectation which isn't met.
+    Test {
+        /// A JSON testcase or a directory of them; defaults to `spec/output/programs`.
+        path: Option<PathBuf>,
+    },
+    /// Wraps a raw program into a testcase by running it with the interpreter.
+    Wrap {
+        /// The program to wrap: a raw code and jump table binary.
+        input: PathBuf,
+
+        /// The initial gas.
+        #[clap(long, default_value_t = DEFAULT_GAS)]
+        gas: i64,
+
+        /// Sets an initial register, e.g. `--reg a0=9`; can be given multiple times. The other registers are zeroed.
+        #[clap(long = "reg", value_name = "REG=VALUE")]
+        regs: Vec<String>,
+
+        /// Write the JSON testcase into this file instead of printing it.
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
+
+        /// Also write the disassembly of the testcase into this file.
+        #[clap(long)]
+        disassembly: Option<PathBuf>,
+    },
+    /// Watches `spec/src` and regenerates the outputs of every source as soon as it's changed.
+    Watch {
+        /// Only regenerate the sources whose name or path contains the given substring.
+        #[clap(long)]
+        filter: Option<String>,
+    },
+    Disassemble {
+        /// The program to disassemble: either a whole program blob, a raw code and jump table binary, or a JSON testcase.
+        input: PathBuf,
+
+        /// The bitness of the program, unless it's a whole blob; either 32 or 64.
+        #[clap(long, default_value_t = 64)]
+        bits: u32,
+
+        /// Show the raw bytes of each instruction.
+        #[clap(long)]
+        raw_bytes: bool,
+
+        /// Show jump targets as offsets instead of labels.
+        #[clap(long)]
+        offset_targets: bool,
+
+        /// Annotate every instruction and basic block with its gas cost.
+        #[clap(long)]
+        gas_costs: bool,
+
+        /// Show the jump targets which coincide with an export by its name; only a whole blob has exports.
+        #[clap(long)]
+        export_targets: bool,
+
+        /// Show the offsets of the instructions and the jump targets in hexadecimal.
+        #[clap(long)]
+        hex_offsets: bool,
+
+        /// Emit assembly which the assembler accepts, and fail unless it reassembles into the same program.
+        #[clap(long)]
+        reassemblable: bool,
+    },
+    /// Disassembles a program, reassembles the disassembly and reports the first place where the result diverges from the original.
+    Roundtrip {
+        /// The program to round-trip: either a whole program blob, a raw code and jump table binary, or a JSON testcase.
         input: PathBuf,
+
+        /// The bitness of the program, unless it's a whole blob; either 32 or 64.
+        #[clap(long, default_value_t = 64)]
+        bits: u32,
+    },
+    /// Generates random programs, runs them with the interpreter and emits them as JSON testcases.
+    Fuzz {
+        /// The number of testcases to generate.
+        #[clap(long, default_value_t = 100)]
+        count: usize,
+
+        /// The seed of the random number generator; the same seed always produces the same testcases.
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+
+        /// The maximum number of non-terminator instructions in each program.
+        #[clap(long, default_value_t = 32)]
+        max_instructions: usize,
+
+        /// The directory into which the testcases are written; defaults to `spec/output/fuzz`.
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Checks the structural consistency of already generated JSON testcases (and of their stored `.txt` disassemblies)
+    /// without executing them.
+    Verify {
+        /// A JSON testcase or a directory of them; defaults to `spec/output/programs`.
+        path: Option<PathBuf>,
+    },
+    /// Rewrites already generated JSON testcases in place in the current format, in the canonical style.
+    Migrate {
+        /// A JSON testcase or a directory of them; defaults to `spec/output/programs`.
+        path: Option<PathBuf>,
+    },
+    /// Measures the throughput of the interpreter by repeatedly executing already generated JSON testcases.
+    Bench {
+        /// The number of times each testcase is executed.
+        #[clap(long, default_value_t = 100)]
+        iterations: u32,
+
+        /// Only benchmark the testcases whose name contains the given substring.
+        #[clap(long)]
+        filter: Option<String>,
+
+        /// A JSON testcase or a directory of them; defaults to `spec/output/programs`.
+        path: Option<PathBuf>,
+
+        /// The file into which the results are written as JSON; defaults to `spec/output/bench.json`.
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Prints the JSON Schema of the JSON testcases.
+    Schema {
+        /// Write the schema into this file instead of printing it.
+        #[clap(short = 'o', long)]
+        output: Option<PathBuf>,
     },
-    Test,
 }
 
 fn main() {
@@ -25,219 +357,2969 @@ fn main() {
 
     let args = Args::parse();
     match args {
-        Args::Prepare { input } => main_prepare(input),
-        Args::Generate => main_generate(),
-        Args::Test => main_test(),
+        Args::Prepare {
+            input,
+            output,
+            disassembly,
+            default_gas,
+            format,
+            disassembly_flags,
+            gas_model_flags,
+        } => main_prepare(
+            input,
+            output,
+            disassembly,
+            disassembly_flags.style(),
+            default_gas,
+            gas_model_flags.gas_model(),
+            format,
+        ),
+        Args::Generate {
+            jobs,
+            emit_program_file,
+            filters,
+            cross_check,
+            emit_traces,
+            emit_cfg,
+            max_chunk_gap,
+            format,
+            dedup_programs,
+            check,
+            emit_summary,
+            default_gas,
+            strict_post,
+            split_index,
+            disassembly_flags,
+            gas_model_flags,
+            randomize_regs,
+            seed,
+        } => main_generate(GenerateOptions {
+            jobs: jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |jobs| jobs.get())),
+            emit_program_file,
+            filters,
+            cross_check,
+            emit_traces,
+            emit_cfg,
+            max_chunk_gap,
+            format,
+            dedup_programs,
+            check,
+            emit_summary,
+            disassembly_style: disassembly_flags.style(),
+            default_gas,
+            strict_post,
+            split_index,
+            gas_model: gas_model_flags.gas_model(),
+            randomize_regs,
+            randomize_seed: seed,
+        }),
+        Args::Test { path } => main_test(path),
+        Args::Wrap {
+            input,
+            gas,
+            regs,
+            output,
+            disassembly,
+        } => main_wrap(input, gas, regs, output, disassembly),
+        Args::Watch { filter } => main_watch(filter),
+        Args::Disassemble {
+            input,
+            bits,
+            raw_bytes,
+            offset_targets,
+            gas_costs,
+            export_targets,
+            hex_offsets,
+            reassemblable,
+        } => main_disassemble(
+            input,
+            bits,
+            DisassembleOptions {
+                show_raw_bytes: raw_bytes,
+                prefer_offset_jump_targets: offset_targets,
+                show_gas_costs: gas_costs,
+                prefer_export_jump_targets: export_targets,
+                prefer_hex_offsets: hex_offsets,
+                reassemblable,
+                ..DisassembleOptions::default()
+            },
+        ),
+        Args::Roundtrip { input, bits } => main_roundtrip(input, bits),
+        Args::Fuzz {
+            count,
+            seed,
+            max_instructions,
+            output,
+        } => main_fuzz(count, seed, max_instructions, output),
+        Args::Verify { path } => main_verify(path),
+        Args::Migrate { path } => main_migrate(path),
+        Args::Bench {
+            iterations,
+            filter,
+            path,
+            output,
+        } => main_bench(iterations, filter, path, output),
+        Args::Schema { output } => main_schema(output),
     }
 }
 
-fn main_generate() {
-    let mut tests = Vec::new();
+fn main_generate(options: GenerateOptions) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("spec");
+    if generate(&root.join("src"), &root.join("output"), &options) {
+        std::process::exit(1);
+    }
+}
 
-    let mut config = polkavm::Config::new();
-    config.set_backend(Some(polkavm::BackendKind::Interpreter));
-    config.set_allow_dynamic_paging(true);
+#[derive(Clone)]
+struct GenerateOptions {
+    /// The number of threads used to prepare the testcases.
+    jobs: usize,
+    /// Whether to set the `program-file` field of the generated testcases.
+    emit_program_file: bool,
+    /// If non-empty only the sources matching at least one of these substrings are prepared.
+    filters: Vec<String>,
+    /// Whether to compare the interpreter's results with the compiler backend.
+    cross_check: bool,
+    /// Whether to write out the execution traces.
+    emit_traces: bool,
+    /// Whether to write out the control-flow graphs.
+    emit_cfg: bool,
+    /// The maximum number of zero bytes within a single memory chunk.
+    max_chunk_gap: usize,
+    /// The encoding in which the testcases are written.
+    format: OutputFormat,
+    /// Whether to write every distinct program once into `programs/blobs` and reference it from the JSON testcases.
+    dedup_programs: bool,
+    /// Whether to only compare the outputs with the ones on disk instead of writing them.
+    check: bool,
+    /// Whether to write the summary of the generation into `SUMMARY.json`.
+    emit_summary: bool,
+    /// How the disassemblies in the index are rendered; applies to every testcase, including the ones which aren't prepared.
+    disassembly_style: DisassemblyStyle,
+    /// The gas given to the testcases without a `pre: gas = ...` directive.
+    default_gas: i64,
+    /// Whether every register changed by a testcase must be asserted or waived.
+    strict_post: bool,
+    /// Whether to write every testcase's section into its own `docs/<name>.md` and only a table of contents into the index.
+    split_index: bool,
+    /// The gas model of the testcases without a `gas-model: ...` directive.
+    gas_model: GasModel,
+    /// The number of variants with randomized initial registers generated for every source with a `randomize: regs` directive.
+    randomize_regs: usize,
+    /// The seed from which the randomized initial registers are derived.
+    randomize_seed: u64,
+}
 
-    let engine = new_engine();
+impl GenerateOptions {
+    fn is_selected(&self, source_root: &Path, source: &Source) -> bool {
+        if self.filters.is_empty() {
+            return true;
+        }
+
+        let path = source.path.strip_prefix(source_root).unwrap_or(&source.path).to_string_lossy();
+        self.filters
+            .iter()
+            .any(|filter| source.name.contains(filter.as_str()) || path.contains(filter.as_str()))
+    }
+}
+
+fn main_watch(filter: Option<String>) {
     let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("spec");
-    let mut found_errors = false;
+    let source_root = root.join("src").canonicalize().unwrap();
+    let output_root = root.join("output");
+    let options = GenerateOptions {
+        jobs: std::thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        emit_program_file: false,
+        filters: filter.into_iter().collect(),
+        cross_check: false,
+        emit_traces: false,
+        emit_cfg: false,
+        max_chunk_gap: 0,
+        format: OutputFormat::Json,
+        dedup_programs: false,
+        check: false,
+        emit_summary: false,
+        disassembly_style: DisassemblyStyle::default(),
+        default_gas: DEFAULT_GAS,
+        strict_post: false,
+        split_index: false,
+        gas_model: GasModel::Polkavm,
+        randomize_regs: 0,
+        randomize_seed: 0,
+    };
 
-    let mut paths: Vec<PathBuf> = std::fs::read_dir(root.join("src"))
-        .unwrap()
-        .map(|entry| entry.unwrap().path())
-        .collect();
+    // The sources are polled instead of relying on the OS's file change notifications, which keeps this free of extra dependencies.
+    let mut snapshot = snapshot_modification_times(&source_root);
+    println!("Watching {source_root:?} for changes...");
+    loop {
+        std::thread::sleep(WATCH_POLL_INTERVAL);
+        let current = snapshot_modification_times(&source_root);
+        if current == snapshot {
+            continue;
+        }
+
+        let removed = snapshot.keys().filter(|path| !current.contains_key(*path));
+        let paths: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, modified)| snapshot.get(*path) != Some(*modified))
+            .map(|(path, _)| path)
+            .chain(removed)
+            .cloned()
+            .collect();
+        snapshot = current;
+
+        match regenerate_changed(&source_root, &output_root, &options, &paths) {
+            None => continue,
+            Some(Ok(report)) => print!("{report}"),
+            Some(Err(error)) => eprintln!("{error}"),
+        }
+
+        println!("Watching {source_root:?} for changes...");
+    }
+}
 
-    paths.sort_by_key(|entry| entry.file_stem().unwrap().to_string_lossy().to_string());
+/// How often `watch` checks the sources for changes.
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
 
-    for path in paths {
-        let name = path.file_stem().unwrap().to_string_lossy();
-        let input = std::fs::read_to_string(&path).unwrap();
-        let internal_name = format!("{path:?}");
+/// Returns the modification time of every file under `root`; comparing two of these finds the files which changed.
+fn snapshot_modification_times(root: &Path) -> BTreeMap<PathBuf, std::time::SystemTime> {
+    let mut snapshot = BTreeMap::new();
+    let mut pending = vec![root.to_owned()];
+    while let Some(directory) = pending.pop() {
+        // A file can disappear while it's being looked at, in which case it's picked up by the next snapshot.
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
 
-        let test_case = prepare_input(&input, &engine, &name, &internal_name, true);
-        match test_case {
-            Ok(case) => tests.push(case),
-            Err(e) => {
-                eprintln!("{e}");
-                found_errors = true;
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else if let Ok(modified) = metadata.modified() {
+                snapshot.insert(entry.path(), modified);
             }
         }
     }
 
-    tests.sort_by_key(|test| test.json.name.clone());
+    snapshot
+}
+
+/// Regenerates the outputs of the sources at `changed_paths`, along with their sections of the index.
+///
+/// Returns the JSON and the disassembly of every regenerated testcase, or `None` if none of the paths is a (selected) source.
+fn regenerate_changed(
+    source_root: &Path,
+    output_root: &Path,
+    options: &GenerateOptions,
+    changed_paths: &[PathBuf],
+) -> Option<Result<String, String>> {
+    let changed_paths: BTreeSet<PathBuf> = changed_paths
+        .iter()
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+        .collect();
+    let changed: Vec<Source> = discover_sources(source_root)
+        .into_iter()
+        .filter(|source| changed_paths.contains(&source.path) && options.is_selected(source_root, source))
+        .collect();
+
+    if changed.is_empty() {
+        return None;
+    }
+
+    let options = GenerateOptions {
+        filters: changed
+            .iter()
+            .map(|source| {
+                source
+                    .path
+                    .strip_prefix(source_root)
+                    .unwrap_or(&source.path)
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect(),
+        ..options.clone()
+    };
 
-    let output_programs_root = root.join("output").join("programs");
-    std::fs::create_dir_all(&output_programs_root).unwrap();
+    if generate(source_root, output_root, &options) {
+        let names: Vec<_> = changed.iter().map(|source| source.name.as_str()).collect();
+        return Some(Err(format!(
+            "Failed to regenerate {}; waiting for the next change...",
+            names.join(", ")
+        )));
+    }
 
-    let mut index_md = String::new();
-    writeln!(&mut index_md, "# Testcases\n").unwrap();
-    writeln!(&mut index_md, "This file contains a human-readable index of all of the testcases,").unwrap();
-    writeln!(&mut index_md, "along with their disassemblies and other relevant information.\n\n").unwrap();
+    let programs_root = output_root.join("programs");
+    let mut report = String::new();
+    for source in &changed {
+        for (name, case) in load_cases(source, &options).unwrap_or_default() {
+            writeln!(&mut report, "=== {name} ===\n").unwrap();
+            if check_assembly_error(&case.input, &name, &case_internal_name(source, &case)).is_some() {
+                writeln!(&mut report, "Fails to assemble, as expected.\n").unwrap();
+                continue;
+            }
 
-    for test in tests {
-        let payload = serde_json::to_string_pretty(&test.json).unwrap();
-        let output_path = output_programs_root.join(format!("{}.json", test.json.name));
-        if !std::fs::read(&output_path)
-            .map(|old_payload| old_payload == payload.as_bytes())
-            .unwrap_or(false)
-        {
-            println!("Generating {output_path:?}...");
-            std::fs::write(output_path, payload).unwrap();
+            let json = std::fs::read_to_string(programs_root.join(format!("{name}.json"))).unwrap_or_default();
+            let testcase = match load_generated_testcase(&programs_root, &name, options.disassembly_style) {
+                Ok(testcase) => testcase,
+                Err(error) => return Some(Err(format!("{name}: {error}"))),
+            };
+            writeln!(&mut report, "{json}\n\n{}", testcase.disassembly).unwrap();
         }
+    }
 
-        writeln!(&mut index_md, "## {}\n", test.json.name).unwrap();
+    Some(Ok(report))
+}
 
-        if !test.json.initial_page_map.is_empty() {
-            writeln!(&mut index_md, "Initial page map:").unwrap();
-            for page in &test.json.initial_page_map {
-                let access = if page.is_writable { "RW" } else { "RO" };
+/// Writes `payload` into `path`, unless the file already has exactly the same contents.
+///
+/// Returns whether the file was written.
+fn write_if_changed(path: &Path, payload: &[u8]) -> bool {
+    if std::fs::read(path).map(|old_payload| old_payload == payload).unwrap_or(false) {
+        return false;
+    }
 
-                writeln!(
-                    &mut index_md,
-                    "   * {access}: 0x{:x}-0x{:x} (0x{:x} bytes)",
-                    page.address,
-                    page.address + page.length,
-                    page.length
-                )
-                .unwrap();
+    println!("Generating {path:?}...");
+    std::fs::write(path, payload).unwrap();
+    true
+}
+
+/// A single testcase source discovered under the source root.
+#[derive(Debug)]
+struct Source {
+    path: PathBuf,
+    /// The subdirectory (relative to the source root) in which the source lives; empty for top-level sources.
+    category: String,
+    /// The name of the testcase, prefixed with the category (if any).
+    name: String,
+}
+
+/// Recursively finds all of the sources under `root`, sorted by category and name.
+fn discover_sources(root: &Path) -> Vec<Source> {
+    fn walk(root: &Path, directory: &Path, output: &mut Vec<Source>) {
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(root, &path, output);
+                continue;
             }
 
-            writeln!(&mut index_md).unwrap();
+            let category: Vec<_> = path
+                .parent()
+                .unwrap()
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            let name = if category.is_empty() {
+                stem.into_owned()
+            } else {
+                format!("{}_{stem}", category.join("_"))
+            };
+
+            output.push(Source {
+                path,
+                category: category.join("/"),
+                name,
+            });
         }
+    }
 
-        if !test.json.initial_memory.is_empty() {
-            writeln!(&mut index_md, "Initial non-zero memory chunks:").unwrap();
-            for chunk in &test.json.initial_memory {
-                let contents: Vec<_> = chunk.contents.iter().map(|byte| format!("0x{:02x}", byte)).collect();
-                let contents = contents.join(", ");
-                writeln!(
-                    &mut index_md,
-                    "   * 0x{:x}-0x{:x} (0x{:x} bytes) = [{}]",
-                    chunk.address,
-                    chunk.address + chunk.contents.len() as u32,
-                    chunk.contents.len(),
-                    contents
-                )
-                .unwrap();
-            }
+    let mut sources = Vec::new();
+    walk(root, root, &mut sources);
+    sources.sort_by(|lhs, rhs| (&lhs.category, &lhs.name).cmp(&(&rhs.category, &rhs.name)));
+    sources
+}
 
-            writeln!(&mut index_md).unwrap();
+/// Reads a source and splits it into its cases, each paired with the name of the testcase it produces.
+///
+/// A case with a `randomize: regs` directive is followed by its `options.randomize_regs` variants, named `<name>_rand<i>`.
+fn load_cases(source: &Source, options: &GenerateOptions) -> Result<Vec<(String, SourceCase)>, String> {
+    let input = std::fs::read_to_string(&source.path).map_err(|error| format!("failed to read {:?}: {error}", source.path))?;
+    let case_name = |case: &SourceCase| match case.name {
+        Some(ref case_name) => format!("{}_{case_name}", source.name),
+        None => source.name.clone(),
+    };
+
+    let mut cases = Vec::new();
+    for case in split_cases(&input, &source.path.display().to_string())? {
+        let name = case_name(&case);
+        let variants = randomized_reg_variants(
+            &case.input,
+            &name,
+            &case_internal_name(source, &case),
+            options.randomize_regs,
+            options.randomize_seed,
+        )?;
+
+        let base_name = case.name.clone();
+        cases.push((name, case));
+        for (nth_variant, input) in variants.into_iter().enumerate() {
+            let variant = SourceCase {
+                name: Some(match base_name {
+                    Some(ref case_name) => format!("{case_name}_rand{nth_variant}"),
+                    None => format!("rand{nth_variant}"),
+                }),
+                input,
+            };
+            cases.push((case_name(&variant), variant));
+        }
+    }
+
+    Ok(cases)
+}
+
+/// Returns the name under which the errors of a case are reported.
+fn case_internal_name(source: &Source, case: &SourceCase) -> String {
+    match case.name {
+        Some(ref case_name) => format!("{}#{case_name}", source.path.display()),
+        None => source.path.display().to_string(),
+    }
+}
+
+/// The result of preparing a single case of a source.
+enum PreparedCase {
+    Testcase(Testcase),
+    /// A case which was checked to fail to assemble; it doesn't produce a testcase.
+    Negative(NegativeTestcase),
+}
+
+/// Returns the names of the testcases produced by a source.
+///
+/// Falls back to the name of the source itself if it can't be split; the error is reported when the source is prepared.
+fn testcase_names(source: &Source, options: &GenerateOptions) -> Vec<String> {
+    match load_cases(source, options) {
+        Ok(cases) => cases.into_iter().map(|(name, _)| name).collect(),
+        Err(_) => vec![source.name.clone()],
+    }
+}
+
+/// Returns an error for every testcase name which is used by more than one source or case.
+fn find_name_collisions(sources: &[Source], options: &GenerateOptions) -> Vec<String> {
+    let mut paths_by_name: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+    for source in sources {
+        for name in testcase_names(source, options) {
+            paths_by_name.entry(name).or_default().push(&source.path);
         }
+    }
+
+    paths_by_name
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(name, paths)| format!("Testcase name collision: '{name}' is used by {paths:?}"))
+        .collect()
+}
+
+/// Prepares all of the given sources, spreading the work over `options.jobs` threads.
+///
+/// The results are returned in the same order as the input sources, with one result for every case of each source.
+fn prepare_sources(sources: &[&Source], options: &GenerateOptions, cross_check: bool) -> Vec<Vec<Result<PreparedCase, String>>> {
+    let next_index = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Vec<Result<PreparedCase, String>>)> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..options.jobs.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let engine = new_engine();
+                    let compiler_engine = if cross_check { new_compiler_engine() } else { None };
+                    let prepare_options = PrepareOptions {
+                        cross_check_engine: compiler_engine.as_ref(),
+                        record_trace: options.emit_traces,
+                        max_chunk_gap: options.max_chunk_gap,
+                        source_directory: None,
+                        disassembly_style: options.disassembly_style,
+                        default_gas: Some(options.default_gas),
+                        strict_post: options.strict_post,
+                        gas_model: options.gas_model,
+                    };
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::Relaxed);
+                        let Some(source) = sources.get(index) else { break };
 
-        if test.json.initial_regs.iter().any(|value| *value != 0) {
-            writeln!(&mut index_md, "Initial non-zero registers:").unwrap();
-            for reg in Reg::ALL {
-                let value = test.json.initial_regs[reg as usize];
-                if value != 0 {
-                    writeln!(&mut index_md, "   * {} = 0x{:x}", reg.name_non_abi(), value).unwrap();
+                        let source_results = match load_cases(source, options) {
+                            Ok(cases) => cases
+                                .into_iter()
+                                .map(|(name, case)| {
+                                    let internal_name = case_internal_name(source, &case);
+                                    let prepare_options = PrepareOptions {
+                                        source_directory: source.path.parent(),
+                                        ..prepare_options
+                                    };
+                                    match check_assembly_error(&case.input, &name, &internal_name) {
+                                        Some(result) => result.map(PreparedCase::Negative),
+                                        None => {
+                                            prepare_input_with_options(&case.input, &engine, &name, &internal_name, true, prepare_options)
+                                                .map(PreparedCase::Testcase)
+                                        }
+                                    }
+                                })
+                                .collect(),
+                            Err(error) => vec![Err(error)],
+                        };
+
+                        results.push((index, source_results));
+                    }
+
+                    results
+                })
+            })
+            .collect();
+
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Generates the test vectors for every source in `source_root` into `output_root`.
+///
+/// Returns whether any errors were found.
+fn generate(source_root: &Path, output_root: &Path, options: &GenerateOptions) -> bool {
+    let Some(generated) = generate_outputs(source_root, output_root, options) else {
+        return true;
+    };
+
+    if options.check {
+        let stale_outputs = find_stale_outputs(output_root, &generated.files, options);
+        for stale_output in &stale_outputs {
+            match stale_output {
+                StaleOutput::Missing(path) => eprintln!("missing: {}", path.display()),
+                StaleOutput::Orphaned(path) => eprintln!("orphaned: {}", path.display()),
+                StaleOutput::Modified(path) => {
+                    let on_disk = std::fs::read(output_root.join(path)).unwrap_or_default();
+                    eprint!("{}", format_diff(path, &on_disk, &generated.files[path]));
                 }
             }
+        }
 
-            writeln!(&mut index_md).unwrap();
+        if !stale_outputs.is_empty() {
+            eprintln!(
+                "Found {} stale output file(s); run 'spectool generate' to update them",
+                stale_outputs.len()
+            );
+        }
+
+        return generated.found_errors || !stale_outputs.is_empty();
+    }
+
+    std::fs::create_dir_all(output_root.join("programs")).unwrap();
+    let mut changed_files = BTreeSet::new();
+    for (path, payload) in &generated.files {
+        let full_path = output_root.join(path);
+        std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        if write_if_changed(&full_path, payload) {
+            changed_files.insert(path.as_path());
+        }
+    }
+
+    let summary = Summary::new(&generated.processed, &changed_files);
+    print!("{}", summary.format());
+    if options.emit_summary {
+        write_if_changed(
+            &output_root.join("SUMMARY.json"),
+            serde_json::to_string_pretty(&summary).unwrap().as_bytes(),
+        );
+    }
+
+    generated.found_errors
+}
+
+/// Every file produced by a generation, keyed by its path relative to the output root.
+struct GeneratedOutputs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// The testcases which were prepared by this generation, i.e. not the ones skipped by the filters.
+    processed: Vec<ProcessedTestcase>,
+    found_errors: bool,
+}
+
+/// The properties of a prepared testcase which are collected into the [`Summary`].
+struct ProcessedTestcase {
+    name: String,
+    status: String,
+    gas_consumed: i64,
+    is_64_bit: bool,
+    step_count: Option<u64>,
+}
+
+/// The number of testcases listed as the slowest ones in the summary.
+const SLOWEST_TESTCASE_COUNT: usize = 5;
+
+/// Statistics about the testcases processed by a single generation.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Summary {
+    testcase_count: usize,
+    /// The number of testcases whose outputs were (re)written.
+    changed_count: usize,
+    total_gas_consumed: i64,
+    average_gas_consumed: i64,
+    status_counts: BTreeMap<String, usize>,
+    /// The number of testcases per mode, i.e. `32` or `64`.
+    mode_counts: BTreeMap<String, usize>,
+    /// The testcases which executed the most instructions, slowest first.
+    slowest: Vec<SlowTestcase>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct SlowTestcase {
+    name: String,
+    step_count: u64,
+}
+
+impl Summary {
+    fn new(processed: &[ProcessedTestcase], changed_files: &BTreeSet<&Path>) -> Self {
+        let is_changed = |name: &str| {
+            ["json", "scale", "bin", "txt"]
+                .iter()
+                .any(|extension| changed_files.contains(Path::new(&format!("programs/{name}.{extension}"))))
+        };
+
+        let total_gas_consumed: i64 = processed.iter().map(|test| test.gas_consumed).sum();
+        let mut status_counts = BTreeMap::new();
+        let mut mode_counts = BTreeMap::new();
+        for test in processed {
+            *status_counts.entry(test.status.clone()).or_default() += 1;
+            *mode_counts.entry(if test.is_64_bit { "64" } else { "32" }.to_owned()).or_default() += 1;
+        }
+
+        let mut slowest: Vec<SlowTestcase> = processed
+            .iter()
+            .filter_map(|test| {
+                Some(SlowTestcase {
+                    name: test.name.clone(),
+                    step_count: test.step_count?,
+                })
+            })
+            .collect();
+        slowest.sort_by(|lhs, rhs| rhs.step_count.cmp(&lhs.step_count).then_with(|| lhs.name.cmp(&rhs.name)));
+        slowest.truncate(SLOWEST_TESTCASE_COUNT);
+
+        Summary {
+            testcase_count: processed.len(),
+            changed_count: processed.iter().filter(|test| is_changed(&test.name)).count(),
+            total_gas_consumed,
+            average_gas_consumed: total_gas_consumed / processed.len().max(1) as i64,
+            status_counts,
+            mode_counts,
+            slowest,
         }
+    }
 
-        writeln!(&mut index_md, "```\n{}```\n", test.disassembly).unwrap();
+    fn format(&self) -> String {
+        let join = |counts: &BTreeMap<String, usize>, suffix: &str| {
+            let counts: Vec<_> = counts.iter().map(|(key, count)| format!("{key}{suffix} = {count}")).collect();
+            counts.join(", ")
+        };
 
-        if test
-            .json
-            .initial_regs
+        let slowest: Vec<_> = self
+            .slowest
             .iter()
-            .zip(test.json.expected_regs.iter())
-            .any(|(old_value, new_value)| *old_value != *new_value)
-        {
-            writeln!(&mut index_md, "Registers after execution (only changed registers):").unwrap();
-            for reg in Reg::ALL {
-                let value_before = test.json.initial_regs[reg as usize];
-                let value_after = test.json.expected_regs[reg as usize];
-                if value_before != value_after {
-                    writeln!(
-                        &mut index_md,
-                        "   * {} = 0x{:x} (initially was 0x{:x})",
-                        reg.name_non_abi(),
-                        value_after,
-                        value_before
-                    )
-                    .unwrap();
-                }
-            }
+            .map(|test| format!("{} ({} steps)", test.name, test.step_count))
+            .collect();
 
-            writeln!(&mut index_md).unwrap();
+        let mut output = String::new();
+        writeln!(&mut output, "Summary:").unwrap();
+        writeln!(
+            &mut output,
+            "  Testcases: {} ({} changed on disk)",
+            self.testcase_count, self.changed_count
+        )
+        .unwrap();
+        writeln!(
+            &mut output,
+            "  Gas consumed: {} in total, {} on average",
+            self.total_gas_consumed, self.average_gas_consumed
+        )
+        .unwrap();
+        writeln!(&mut output, "  Final statuses: {}", join(&self.status_counts, "")).unwrap();
+        writeln!(&mut output, "  Modes: {}", join(&self.mode_counts, "-bit")).unwrap();
+        writeln!(&mut output, "  Slowest testcases: {}", slowest.join(", ")).unwrap();
+        output
+    }
+}
+
+/// Generates the test vectors for every source in `source_root` in memory, without writing anything.
+///
+/// The testcases which aren't selected by the filters are loaded from `output_root` instead.
+/// Returns `None` if the sources can't be generated at all.
+fn generate_outputs(source_root: &Path, output_root: &Path, options: &GenerateOptions) -> Option<GeneratedOutputs> {
+    let mut files = BTreeMap::new();
+    let mut processed = Vec::new();
+    let mut tests = Vec::new();
+    let mut negative_tests = Vec::new();
+    let mut found_errors = false;
+
+    let sources = discover_sources(source_root);
+    let collisions = find_name_collisions(&sources, options);
+    if !collisions.is_empty() {
+        for error in collisions {
+            eprintln!("{error}");
         }
 
-        if !test.json.expected_memory.is_empty() {
-            if test.json.expected_memory == test.json.initial_memory {
-                writeln!(&mut index_md, "The memory contents after execution should be unchanged.").unwrap();
-            } else {
-                writeln!(&mut index_md, "Final non-zero memory chunks:").unwrap();
-                for chunk in &test.json.expected_memory {
-                    let contents: Vec<_> = chunk.contents.iter().map(|byte| format!("0x{:02x}", byte)).collect();
-                    let contents = contents.join(", ");
-                    writeln!(
-                        &mut index_md,
-                        "   * 0x{:x}-0x{:x} (0x{:x} bytes) = [{}]",
-                        chunk.address,
-                        chunk.address + chunk.contents.len() as u32,
-                        chunk.contents.len(),
-                        contents
-                    )
-                    .unwrap();
+        return None;
+    }
+
+    let output_programs_root = output_root.join("programs");
+    let programs_root = Path::new("programs");
+    let traces_root = Path::new("traces");
+    let cfg_root = Path::new("cfg");
+    let docs_root = Path::new("docs");
+
+    let is_selected: Vec<bool> = sources.iter().map(|source| options.is_selected(source_root, source)).collect();
+    let selected: Vec<&Source> = sources
+        .iter()
+        .zip(is_selected.iter())
+        .filter(|(_, is_selected)| **is_selected)
+        .map(|(source, _)| source)
+        .collect();
+
+    let cross_check = options.cross_check && new_compiler_engine().is_some();
+    if options.cross_check && !cross_check {
+        eprintln!("WARN: the compiler backend is not available on this platform; skipping the cross-check");
+    }
+
+    let mut results = prepare_sources(&selected, options, cross_check).into_iter();
+    let mut skipped = 0;
+    for (source, is_selected) in sources.iter().zip(is_selected) {
+        if !is_selected {
+            // Keep the index complete by using the testcase which was generated previously.
+            let cases = load_cases(source, options).unwrap_or_else(|error| {
+                eprintln!("WARN: '{}' will be missing from the index: {error}", source.name);
+                Vec::new()
+            });
+
+            for (name, case) in cases {
+                skipped += 1;
+                // Negative tests don't produce any output, but they're cheap enough to simply check again.
+                if let Some(result) = check_assembly_error(&case.input, &name, &case_internal_name(source, &case)) {
+                    match result {
+                        Ok(test) => negative_tests.push(test),
+                        Err(error) => eprintln!("WARN: '{name}' will be missing from the index: {error}"),
+                    }
+                    continue;
+                }
+
+                match load_generated_testcase(&output_programs_root, &name, options.disassembly_style) {
+                    Ok(loaded) => {
+                        let category = find_category(&case.input, &case_internal_name(source, &case)).ok().flatten();
+                        tests.push((category.unwrap_or_else(|| source.category.clone()), loaded, false));
+                    }
+                    Err(error) => eprintln!("WARN: '{name}' will be missing from the index: {error}"),
                 }
             }
 
-            writeln!(&mut index_md).unwrap();
+            continue;
         }
 
-        assert_eq!(
-            test.json.expected_status == "page-fault",
-            test.json.expected_page_fault_address.is_some()
-        );
-        write!(&mut index_md, "Program should end with: {}", test.json.expected_status).unwrap();
+        for result in results.next().unwrap() {
+            match result {
+                Ok(PreparedCase::Negative(test)) => negative_tests.push(test),
+                Ok(PreparedCase::Testcase(case)) => {
+                    let violations = verify_testcase(&case.json);
+                    for violation in &violations {
+                        eprintln!("{}: {}: {violation}", source.path.display(), case.json.name);
+                    }
 
-        if let Some(address) = test.json.expected_page_fault_address {
-            write!(&mut index_md, " (page address = 0x{:x})", address).unwrap();
+                    found_errors |= !violations.is_empty();
+                    processed.push(ProcessedTestcase {
+                        name: case.json.name.clone(),
+                        status: case.json.expected_status.clone(),
+                        gas_consumed: case.json.initial_gas - case.json.expected_gas,
+                        is_64_bit: case.is_64_bit,
+                        step_count: case.json.expected_step_count,
+                    });
+                    let category = case.category.clone().unwrap_or_else(|| source.category.clone());
+                    tests.push((category, case, true));
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    found_errors = true;
+                }
+            }
         }
+    }
+
+    if skipped > 0 {
+        println!("Skipped {skipped} testcase(s) not matching the filter");
+    }
 
-        writeln!(&mut index_md, "\n").unwrap();
-        writeln!(&mut index_md, "Final value of the program counter: {}\n", test.json.expected_pc).unwrap();
+    let mut index_md = String::new();
+    writeln!(&mut index_md, "# Testcases\n").unwrap();
+    // Sources with a `category:` directive are moved into their category's section.
+    tests.sort_by(|(lhs, _, _), (rhs, _, _)| lhs.cmp(rhs));
+    if options.split_index {
+        writeln!(&mut index_md, "This file contains a human-readable index of all of the testcases;").unwrap();
         writeln!(
             &mut index_md,
-            "Gas consumed: {} -> {}\n",
-            test.json.initial_gas, test.json.expected_gas
+            "each of them is described on its own page in the `docs` directory.\n"
         )
         .unwrap();
-        writeln!(&mut index_md).unwrap();
+        write_split_index(&mut index_md, &tests, &negative_tests);
+    } else {
+        writeln!(&mut index_md, "This file contains a human-readable index of all of the testcases,").unwrap();
+        writeln!(&mut index_md, "along with their disassemblies and other relevant information.\n\n").unwrap();
+        write_table_of_contents(&mut index_md, &tests, &negative_tests);
     }
 
-    std::fs::write(root.join("output").join("TESTCASES.md"), index_md).unwrap();
-
-    if found_errors {
-        std::process::exit(1);
+    let mut tests_per_category: BTreeMap<String, usize> = BTreeMap::new();
+    for (category, _, _) in &tests {
+        *tests_per_category.entry(category.clone()).or_default() += 1;
     }
-}
 
-fn prepare_file(engine: &Engine, path: &Path) -> Result<Testcase, String> {
-    let name = path.file_stem().unwrap().to_string_lossy();
-    let input = std::fs::read_to_string(path).unwrap();
-    let input = input.lines().collect::<Vec<_>>().join("\n");
-    let internal_name = format!("{path:?}");
-    prepare_input(&input, engine, &name, &internal_name, false)
-}
+    let mut combined = Vec::new();
+    let mut current_category = String::new();
+    for (category, mut test, is_fresh) in tests {
+        if is_fresh {
+            // The disassembly is always written in the default style, so that `verify` can compare it exactly.
+            match canonical_disassembly(&test.json.program) {
+                Ok(disassembly) => {
+                    files.insert(programs_root.join(format!("{}.txt", test.json.name)), disassembly.into_bytes());
+                }
+                Err(error) => {
+                    eprintln!("{}: failed to disassemble: {error}", test.json.name);
+                    found_errors = true;
+                }
+            }
 
-fn main_test() {
-    todo!();
-}
+            if !options.dedup_programs {
+                let program_file = format!("{}.bin", test.json.name);
+                files.insert(programs_root.join(&program_file), test.json.program.clone());
+                if options.emit_program_file {
+                    test.json.program_file = Some(program_file);
+                }
+            }
 
-fn main_prepare(input: PathBuf) {
-    let engine = new_engine();
+            if options.emit_cfg {
+                match control_flow_graph(&test.json.program) {
+                    Ok(graph) => {
+                        let dot = graph.to_dot(&test.json.name);
+                        files.insert(cfg_root.join(format!("{}.dot", test.json.name)), dot.into_bytes());
+                    }
+                    Err(error) => {
+                        eprintln!("{}: failed to build the control-flow graph: {error}", test.json.name);
+                        found_errors = true;
+                    }
+                }
+            }
 
-    let test = prepare_file(&engine, &input);
-    match test {
-        Ok(test) => {
-            let payload = serde_json::to_string_pretty(&test.json).unwrap();
-            println!("{payload}");
-        },
-        Err(e) => {
+            // The SCALE encoding has no way to reference a program, so it always embeds it.
+            if options.format.has_scale() {
+                match spectool::scale::encode(&test.json) {
+                    Ok(payload) => {
+                        files.insert(programs_root.join(format!("{}.scale", test.json.name)), payload);
+                    }
+                    Err(error) => {
+                        eprintln!("{}: failed to encode: {error}", test.json.name);
+                        found_errors = true;
+                    }
+                }
+            }
+
+            if options.format.has_json() {
+                if options.dedup_programs {
+                    let program = std::mem::take(&mut test.json.program);
+                    let program_ref = program_ref_for(&program);
+                    files.insert(programs_root.join(&program_ref.path), program);
+                    test.json.program_ref = Some(program_ref);
+                }
+
+                let payload = to_canonical_json(&test.json);
+                files.insert(programs_root.join(format!("{}.json", test.json.name)), payload.into_bytes());
+            }
+
+            if let Some(ref trace) = test.trace {
+                files.insert(traces_root.join(format!("{}.txt", test.json.name)), trace.clone().into_bytes());
+            }
+        } else if options.dedup_programs && options.format.has_json() {
+            // Match the JSON file which was written previously, so that it's aggregated as-is.
+            test.json.program_ref = Some(program_ref_for(&std::mem::take(&mut test.json.program)));
+        }
+
+        if options.split_index {
+            let mut page = String::new();
+            writeln!(&mut page, "# {}\n", test.json.name).unwrap();
+            write_testcase_section(&mut page, &test);
+            files.insert(docs_root.join(format!("{}.md", test.json.name)), page.into_bytes());
+        } else {
+            if category != current_category {
+                let count = tests_per_category[category.as_str()];
+                writeln!(&mut index_md, "## {category}\n").unwrap();
+                writeln!(&mut index_md, "This section contains {}.\n", format_testcase_count(count)).unwrap();
+                current_category = category.clone();
+            }
+
+            if category.is_empty() {
+                writeln!(&mut index_md, "## {}\n", test.json.name).unwrap();
+            } else {
+                writeln!(&mut index_md, "### {}\n", test.json.name).unwrap();
+            }
+
+            write_testcase_section(&mut index_md, &test);
+            writeln!(&mut index_md).unwrap();
+        }
+
+        combined.push(test.json);
+    }
+
+    // A single artifact with every testcase, for the consumers which would rather download one file.
+    combined.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    let combined_payload = serde_json::to_string_pretty(&combined).unwrap();
+    files.insert(
+        PathBuf::from("testcases.json.sha256"),
+        format!("{}  testcases.json\n", spectool::sha256::sha256_hex(combined_payload.as_bytes())).into_bytes(),
+    );
+    files.insert(PathBuf::from("testcases.json"), combined_payload.into_bytes());
+
+    if options.split_index {
+        for test in &negative_tests {
+            let mut page = String::new();
+            writeln!(&mut page, "# {}\n", test.name).unwrap();
+            writeln!(
+                &mut page,
+                "This source is expected to fail to assemble, so it doesn't produce any testcase.\n"
+            )
+            .unwrap();
+            write_negative_testcase_section(&mut page, test);
+            files.insert(docs_root.join(format!("{}.md", test.name)), page.into_bytes());
+        }
+    } else if !negative_tests.is_empty() {
+        writeln!(&mut index_md, "## Negative tests\n").unwrap();
+        writeln!(
+            &mut index_md,
+            "This section contains {}.\n",
+            format_testcase_count(negative_tests.len())
+        )
+        .unwrap();
+        writeln!(
+            &mut index_md,
+            "These sources are expected to fail to assemble, so they don't produce any testcases.\n"
+        )
+        .unwrap();
+        for test in &negative_tests {
+            writeln!(&mut index_md, "### {}\n", test.name).unwrap();
+            write_negative_testcase_section(&mut index_md, test);
+        }
+    }
+
+    files.insert(PathBuf::from("TESTCASES.md"), index_md.into_bytes());
+    Some(GeneratedOutputs {
+        files,
+        processed,
+        found_errors,
+    })
+}
+
+fn write_negative_testcase_section(output: &mut String, test: &NegativeTestcase) {
+    writeln!(output, "Expected error: `{}`\n", test.expected_error).unwrap();
+    writeln!(output, "```\n{}\n```\n", test.error).unwrap();
+}
+
+/// Writes the description of a single testcase, as shown below its header in the index.
+fn write_testcase_section(output: &mut String, test: &Testcase) {
+    if let Some(ref description) = test.json.description {
+        writeln!(output, "{description}\n").unwrap();
+    }
+
+    if let Some(ref entry_point) = test.entry_point {
+        writeln!(
+            output,
+            "Starts executing at `@{entry_point}` (pc = {}) instead of `@main`.\n",
+            test.json.initial_pc
+        )
+        .unwrap();
+    }
+
+    if let Some(stack_size) = test.stack_size {
+        writeln!(output, "Uses a stack of 0x{stack_size:x} bytes, as set with `stack-size:`.\n").unwrap();
+    }
+
+    if let Some(out_of_gas_at) = test.out_of_gas_at {
+        writeln!(
+            output,
+            "Runs out of gas at pc = {out_of_gas_at}, as asserted with `post: out-of-gas-at`.\n"
+        )
+        .unwrap();
+    }
+
+    if !test.json.initial_page_map.is_empty() {
+        writeln!(output, "Initial page map:").unwrap();
+        for page in &test.json.initial_page_map {
+            let access = if page.is_writable { "RW" } else { "RO" };
+
+            writeln!(
+                output,
+                "   * {access}: 0x{:x}-0x{:x} (0x{:x} bytes)",
+                page.address,
+                page.address + page.length,
+                page.length
+            )
+            .unwrap();
+        }
+
+        writeln!(output).unwrap();
+    }
+
+    if !test.json.initial_memory.is_empty() {
+        writeln!(output, "Initial non-zero memory chunks:").unwrap();
+        for chunk in &test.json.initial_memory {
+            let contents: Vec<_> = chunk.contents.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            let contents = contents.join(", ");
+            writeln!(
+                output,
+                "   * 0x{:x}-0x{:x} (0x{:x} bytes) = [{}]",
+                chunk.address,
+                chunk.address + chunk.contents.len() as u32,
+                chunk.contents.len(),
+                contents
+            )
+            .unwrap();
+        }
+
+        writeln!(output).unwrap();
+    }
+
+    if test.json.initial_regs.iter().any(|value| *value != 0) {
+        writeln!(output, "Initial non-zero registers:").unwrap();
+        for reg in Reg::ALL {
+            let value = test.json.initial_regs[reg as usize];
+            if value != 0 {
+                writeln!(output, "   * {} = 0x{:x}", reg.name_non_abi(), value).unwrap();
+            }
+        }
+
+        writeln!(output).unwrap();
+    }
+
+    writeln!(output, "```\n{}```\n", test.disassembly).unwrap();
+
+    if test
+        .json
+        .initial_regs
+        .iter()
+        .zip(test.json.expected_regs.iter())
+        .any(|(old_value, new_value)| *old_value != *new_value)
+    {
+        writeln!(output, "Registers after execution (only changed registers):").unwrap();
+        for reg in Reg::ALL {
+            let value_before = test.json.initial_regs[reg as usize];
+            let value_after = test.json.expected_regs[reg as usize];
+            if value_before != value_after {
+                writeln!(
+                    output,
+                    "   * {} = 0x{:x} (initially was 0x{:x})",
+                    reg.name_non_abi(),
+                    value_after,
+                    value_before
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(output).unwrap();
+    }
+
+    if !test.json.expected_memory.is_empty() {
+        if test.json.expected_memory == test.json.initial_memory {
+            writeln!(output, "The memory contents after execution should be unchanged.").unwrap();
+        } else {
+            writeln!(output, "Final non-zero memory chunks:").unwrap();
+            for chunk in &test.json.expected_memory {
+                let contents: Vec<_> = chunk.contents.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+                let contents = contents.join(", ");
+                writeln!(
+                    output,
+                    "   * 0x{:x}-0x{:x} (0x{:x} bytes) = [{}]",
+                    chunk.address,
+                    chunk.address + chunk.contents.len() as u32,
+                    chunk.contents.len(),
+                    contents
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(output).unwrap();
+    }
+
+    if let Some(ref host_calls) = test.json.expected_host_calls {
+        writeln!(output, "Host calls made (in order): {}\n", format_host_calls(host_calls)).unwrap();
+    }
+
+    if let Some(ref faults) = test.json.faults_handled {
+        let faults: Vec<_> = faults
+            .iter()
+            .map(|fault| format!("0x{:x} ({})", fault.address, fault.action))
+            .collect();
+        writeln!(output, "Page faults handled (in order): {}\n", faults.join(", ")).unwrap();
+    }
+
+    if let Some(ref checkpoints) = test.json.checkpoints {
+        let checkpoints: Vec<_> = checkpoints
+            .iter()
+            .map(|checkpoint| match checkpoint.reached_at_step {
+                Some(step) => format!("@{} (reached at step {step})", checkpoint.label),
+                None => format!("@{} (never reached)", checkpoint.label),
+            })
+            .collect();
+        writeln!(output, "Checkpoints asserted: {}\n", checkpoints.join(", ")).unwrap();
+    }
+
+    if let Some(ref interrupts) = test.json.expected_interrupts {
+        writeln!(output, "Interrupts (in order): {}\n", format_interrupts(interrupts)).unwrap();
+    }
+
+    assert_eq!(
+        test.json.expected_status == "page-fault",
+        test.json.expected_page_fault_address.is_some()
+    );
+    write!(output, "Program should end with: {}", test.json.expected_status).unwrap();
+
+    if let Some(exit_code) = test.json.expected_exit_code {
+        write!(output, " (exit code {exit_code})").unwrap();
+    }
+
+    if let Some(address) = test.json.expected_page_fault_address {
+        write!(output, " (page address = 0x{:x}", address).unwrap();
+        if let Some(ref access) = test.json.expected_page_fault_access {
+            write!(output, ", {access} access").unwrap();
+        }
+        write!(output, ")").unwrap();
+    }
+
+    writeln!(output, "\n").unwrap();
+    writeln!(output, "Final value of the program counter: {}\n", test.json.expected_pc).unwrap();
+    write!(output, "Gas consumed: {} -> {}", test.json.initial_gas, test.json.expected_gas).unwrap();
+    if let Some(GasModel::Flat { cost_per_instruction }) = test.json.gas_model {
+        write!(output, " (flat gas model, {cost_per_instruction} per instruction)").unwrap();
+    }
+    writeln!(output, "\n").unwrap();
+
+    if let Some(step_count) = test.json.expected_step_count {
+        writeln!(output, "Instructions executed: {step_count}\n").unwrap();
+    }
+
+    if !test.block_costs.is_empty() {
+        writeln!(output, "Gas cost of each basic block:\n").unwrap();
+        writeln!(output, "| Start | Instructions | Cost |").unwrap();
+        writeln!(output, "|------:|-------------:|-----:|").unwrap();
+        for block in &test.block_costs {
+            writeln!(output, "| {} | {} | {} |", block.start, block.instruction_count, block.cost).unwrap();
+        }
+
+        writeln!(output).unwrap();
+    }
+}
+
+/// Returns the anchor which GitHub generates for a header, so the links only depend on the names of the testcases.
+fn header_anchor(header: &str) -> String {
+    header
+        .chars()
+        .filter(|ch| ch.is_alphanumeric() || matches!(ch, ' ' | '-' | '_'))
+        .map(|ch| if ch == ' ' { '-' } else { ch.to_ascii_lowercase() })
+        .collect()
+}
+
+fn format_testcase_count(count: usize) -> String {
+    if count == 1 {
+        "1 testcase".to_owned()
+    } else {
+        format!("{count} testcases")
+    }
+}
+
+/// Writes a table of contents linking to every section and testcase of the index, in the order they're written.
+fn write_table_of_contents(index_md: &mut String, tests: &[(String, Testcase, bool)], negative_tests: &[NegativeTestcase]) {
+    let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (category, test, _) in tests {
+        match sections.last_mut() {
+            Some((last_category, names)) if *last_category == category.as_str() => names.push(&test.json.name),
+            _ => sections.push((category.as_str(), vec![&test.json.name])),
+        }
+    }
+
+    if !negative_tests.is_empty() {
+        sections.push(("Negative tests", negative_tests.iter().map(|test| test.name.as_str()).collect()));
+    }
+
+    writeln!(index_md, "## Table of contents\n").unwrap();
+    for (section, names) in sections {
+        // The testcases which aren't in any category have their own top-level headers.
+        let indentation = if section.is_empty() {
+            ""
+        } else {
+            writeln!(
+                index_md,
+                "* [{section}](#{}) ({})",
+                header_anchor(section),
+                format_testcase_count(names.len())
+            )
+            .unwrap();
+            "   "
+        };
+
+        for name in names {
+            writeln!(index_md, "{indentation}* [{name}](#{})", header_anchor(name)).unwrap();
+        }
+    }
+
+    writeln!(index_md).unwrap();
+}
+
+/// Returns the relative link to the page of a testcase written by `--split-index`.
+fn doc_link(name: &str) -> String {
+    let mut link = "docs/".to_owned();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.') {
+            link.push(char::from(byte));
+        } else {
+            write!(&mut link, "%{byte:02X}").unwrap();
+        }
+    }
+
+    link.push_str(".md");
+    link
+}
+
+/// Writes the slim index of `--split-index`: every testcase linked to its own page, along with a one-line summary.
+fn write_split_index(index_md: &mut String, tests: &[(String, Testcase, bool)], negative_tests: &[NegativeTestcase]) {
+    let mut sections: Vec<(&str, Vec<(&str, String)>)> = Vec::new();
+    for (category, test, _) in tests {
+        let mut summary = String::new();
+        if let Some(line) = test.json.description.as_deref().and_then(|description| description.lines().next()) {
+            write!(&mut summary, "{line}; ").unwrap();
+        }
+        write!(&mut summary, "ends with `{}`", test.json.expected_status).unwrap();
+
+        match sections.last_mut() {
+            Some((last_category, entries)) if *last_category == category.as_str() => entries.push((&test.json.name, summary)),
+            _ => sections.push((category.as_str(), vec![(&test.json.name, summary)])),
+        }
+    }
+
+    if !negative_tests.is_empty() {
+        sections.push((
+            "Negative tests",
+            negative_tests
+                .iter()
+                .map(|test| (test.name.as_str(), format!("fails to assemble with `{}`", test.expected_error)))
+                .collect(),
+        ));
+    }
+
+    let total: usize = sections.iter().map(|(_, entries)| entries.len()).sum();
+    writeln!(index_md, "There are {} in total.\n", format_testcase_count(total)).unwrap();
+    for (section, entries) in sections {
+        let indentation = if section.is_empty() {
+            ""
+        } else {
+            writeln!(index_md, "* {section} ({})", format_testcase_count(entries.len())).unwrap();
+            "   "
+        };
+
+        for (name, summary) in entries {
+            writeln!(index_md, "{indentation}* [{name}]({}): {summary}", doc_link(name)).unwrap();
+        }
+    }
+}
+
+/// An output file which doesn't match what the generation would produce.
+#[derive(PartialEq, Eq, Debug)]
+enum StaleOutput {
+    /// The file would be generated, but it doesn't exist.
+    Missing(PathBuf),
+    /// The file exists in one of the generated directories, but it wouldn't be generated anymore.
+    Orphaned(PathBuf),
+    /// The file exists, but its contents are different.
+    Modified(PathBuf),
+}
+
+/// Compares the generated files with the ones in `output_root`.
+fn find_stale_outputs(output_root: &Path, files: &BTreeMap<PathBuf, Vec<u8>>, options: &GenerateOptions) -> Vec<StaleOutput> {
+    fn walk(root: &Path, directory: &Path, output: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(directory) else { return };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(root, &path, output);
+            } else {
+                output.push(path.strip_prefix(root).unwrap().to_owned());
+            }
+        }
+    }
+
+    let mut stale_outputs = Vec::new();
+    for (path, payload) in files {
+        match std::fs::read(output_root.join(path)) {
+            Ok(on_disk) if on_disk == *payload => {}
+            Ok(_) => stale_outputs.push(StaleOutput::Modified(path.clone())),
+            Err(_) => stale_outputs.push(StaleOutput::Missing(path.clone())),
+        }
+    }
+
+    // Only the directories which are fully managed by the generation can contain orphans.
+    let mut existing = Vec::new();
+    walk(output_root, &output_root.join("programs"), &mut existing);
+    if options.emit_traces {
+        walk(output_root, &output_root.join("traces"), &mut existing);
+    }
+    if options.emit_cfg {
+        walk(output_root, &output_root.join("cfg"), &mut existing);
+    }
+    if options.split_index {
+        walk(output_root, &output_root.join("docs"), &mut existing);
+    }
+
+    existing.sort();
+    stale_outputs.extend(
+        existing
+            .into_iter()
+            .filter(|path| !files.contains_key(path))
+            .map(StaleOutput::Orphaned),
+    );
+
+    stale_outputs
+}
+
+/// Formats the difference between two versions of an output file as a single hunk of a unified diff.
+fn format_diff(path: &Path, old: &[u8], new: &[u8]) -> String {
+    const CONTEXT: usize = 3;
+
+    let (Ok(old), Ok(new)) = (core::str::from_utf8(old), core::str::from_utf8(new)) else {
+        return format!("Binary file {} differs\n", path.display());
+    };
+
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(old, new)| old == new).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+
+    let start = prefix.saturating_sub(CONTEXT);
+    let old_end = (old.len() - suffix + CONTEXT).min(old.len());
+    let new_end = (new.len() - suffix + CONTEXT).min(new.len());
+
+    let mut output = String::new();
+    writeln!(&mut output, "--- a/{}", path.display()).unwrap();
+    writeln!(&mut output, "+++ b/{}", path.display()).unwrap();
+    writeln!(
+        &mut output,
+        "@@ -{},{} +{},{} @@",
+        start + 1,
+        old_end - start,
+        start + 1,
+        new_end - start
+    )
+    .unwrap();
+
+    for line in &old[start..prefix] {
+        writeln!(&mut output, " {line}").unwrap();
+    }
+    for line in &old[prefix..old.len() - suffix] {
+        writeln!(&mut output, "-{line}").unwrap();
+    }
+    for line in &new[prefix..new.len() - suffix] {
+        writeln!(&mut output, "+{line}").unwrap();
+    }
+    for line in &old[old.len() - suffix..old_end] {
+        writeln!(&mut output, " {line}").unwrap();
+    }
+
+    output
+}
+
+/// Loads a testcase which was already written into `programs_root` by a previous run.
+///
+/// Falls back to the SCALE encoded testcase if the JSON one doesn't exist.
+fn load_generated_testcase(programs_root: &Path, name: &str, disassembly_style: DisassemblyStyle) -> Result<Testcase, String> {
+    let path = programs_root.join(format!("{name}.json"));
+    let scale_path = programs_root.join(format!("{name}.scale"));
+    let mut json = if !path.exists() && scale_path.exists() {
+        let payload = std::fs::read(&scale_path).map_err(|error| format!("failed to read {scale_path:?}: {error}"))?;
+        spectool::scale::decode(&payload).map_err(|error| format!("failed to decode {scale_path:?}: {error}"))?
+    } else {
+        let payload = std::fs::read(&path).map_err(|error| format!("failed to read {path:?}: {error}"))?;
+        serde_json::from_slice(&payload).map_err(|error| format!("failed to parse {path:?}: {error}"))?
+    };
+
+    resolve_program_ref(&mut json, programs_root).map_err(|error| format!("{path:?}: {error}"))?;
+    testcase_from_json_with_style(json, disassembly_style)
+}
+
+/// Prepares every case of a single source file without executing it.
+fn prepare_file(
+    engine: &Engine,
+    path: &Path,
+    disassembly_style: DisassemblyStyle,
+    default_gas: i64,
+    gas_model: GasModel,
+) -> Result<Vec<Testcase>, String> {
+    let (name, internal_name, input) = if path == Path::new("-") {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|error| format!("failed to read from stdin: {error}"))?;
+        ("stdin".into(), "<stdin>".to_owned(), input)
+    } else {
+        let input = std::fs::read_to_string(path).map_err(|error| format!("failed to read {path:?}: {error}"))?;
+        (path.file_stem().unwrap().to_string_lossy(), path.display().to_string(), input)
+    };
+
+    let input = input.lines().collect::<Vec<_>>().join("\n");
+    let options = PrepareOptions {
+        source_directory: path.parent(),
+        disassembly_style,
+        default_gas: Some(default_gas),
+        gas_model,
+        ..PrepareOptions::default()
+    };
+
+    split_cases(&input, &internal_name)?
+        .into_iter()
+        .map(|case| match case.name {
+            Some(case_name) => prepare_input_with_options(
+                &case.input,
+                engine,
+                &format!("{name}_{case_name}"),
+                &format!("{internal_name}#{case_name}"),
+                false,
+                options,
+            ),
+            None => prepare_input_with_options(&case.input, engine, &name, &internal_name, false, options),
+        })
+        .collect()
+}
+
+fn main_test(path: Option<PathBuf>) {
+    let path = path.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("output").join("programs"));
+    let paths = match find_json_files(&path) {
+        Ok(paths) => paths,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let engine = new_engine();
+    let mut failure_count = 0;
+    for path in &paths {
+        match test_file(&engine, path) {
+            Ok(report) if report.passed => {}
+            Ok(report) => {
+                for mismatch in &report.mismatches {
+                    eprintln!(
+                        "{}: {}: expected {}, is {}",
+                        path.display(),
+                        mismatch.field,
+                        mismatch.expected,
+                        mismatch.actual
+                    );
+                }
+                failure_count += 1;
+            }
+            Err(error) => {
+                eprintln!("{}: {error}", path.display());
+                failure_count += 1;
+            }
+        }
+    }
+
+    if failure_count > 0 {
+        eprintln!("{failure_count} out of {} testcase(s) failed", paths.len());
+        std::process::exit(1);
+    }
+
+    println!("Passed {} testcase(s)", paths.len());
+}
+
+fn test_file(engine: &Engine, path: &Path) -> Result<TestRunReport, String> {
+    let payload = std::fs::read(path).map_err(|error| format!("failed to read: {error}"))?;
+    let mut testcase: TestcaseJson = serde_json::from_slice(&payload).map_err(|error| format!("failed to parse: {error}"))?;
+    if let Some(warning) = check_format_version(&testcase)? {
+        eprintln!("WARN: {}: {warning}", path.display());
+    }
+
+    resolve_program_ref(&mut testcase, path.parent().unwrap_or(Path::new("")))?;
+    run_testcase(engine, &testcase)
+}
+
+/// Rewrites a JSON testcase in place in the current format; returns whether it had to be changed.
+fn migrate_file(path: &Path) -> Result<bool, String> {
+    let payload = std::fs::read(path).map_err(|error| format!("failed to read: {error}"))?;
+    let mut testcase: TestcaseJson = serde_json::from_slice(&payload).map_err(|error| format!("failed to parse: {error}"))?;
+    migrate_testcase(&mut testcase)?;
+
+    // Also canonicalize the testcases which were already up to date, in case they were edited by hand.
+    let migrated = to_canonical_json(&testcase);
+    if migrated.as_bytes() == payload {
+        return Ok(false);
+    }
+
+    std::fs::write(path, migrated).map_err(|error| format!("failed to write: {error}"))?;
+    Ok(true)
+}
+
+fn main_migrate(path: Option<PathBuf>) {
+    let path = path.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("output").join("programs"));
+    let paths = match find_json_files(&path) {
+        Ok(paths) => paths,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut migrated_count = 0;
+    let mut failure_count = 0;
+    for path in &paths {
+        match migrate_file(path) {
+            Ok(true) => migrated_count += 1,
+            Ok(false) => {}
+            Err(error) => {
+                eprintln!("{}: {error}", path.display());
+                failure_count += 1;
+            }
+        }
+    }
+
+    println!("Migrated {migrated_count} out of {} testcase(s)", paths.len());
+    if failure_count > 0 {
+        eprintln!("Failed to migrate {failure_count} testcase(s)");
+        std::process::exit(1);
+    }
+}
+
+/// The output of `spectool prepare --format both`.
+#[derive(serde::Serialize)]
+struct PreparedOutput<'a> {
+    testcase: &'a TestcaseJson,
+    disassembly: &'a str,
+}
+
+/// Joins the disassemblies of the testcases prepared from a single source, labeling each one with the name of its testcase.
+fn joined_disassembly(tests: &[Testcase]) -> String {
+    if let [test] = tests {
+        return test.disassembly.clone();
+    }
+
+    tests
+        .iter()
+        .map(|test| format!("// {}\n{}", test.json.name, test.disassembly))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_prepared(tests: &[Testcase], format: PrepareFormat) -> String {
+    // Sources with multiple cases produce an array of testcases, each with its own disassembly.
+    match (format, tests) {
+        (PrepareFormat::Json, [test]) => serde_json::to_string_pretty(&test.json).unwrap(),
+        (PrepareFormat::Json, _) => serde_json::to_string_pretty(&tests.iter().map(|test| &test.json).collect::<Vec<_>>()).unwrap(),
+        (PrepareFormat::Disasm, _) => joined_disassembly(tests),
+        (PrepareFormat::Both, _) => {
+            let outputs = tests
+                .iter()
+                .map(|test| PreparedOutput {
+                    testcase: &test.json,
+                    disassembly: &test.disassembly,
+                })
+                .collect::<Vec<_>>();
+
+            if let [output] = outputs.as_slice() {
+                serde_json::to_string_pretty(output).unwrap()
+            } else {
+                serde_json::to_string_pretty(&outputs).unwrap()
+            }
+        }
+    }
+}
+
+fn main_prepare(
+    input: PathBuf,
+    output: Option<PathBuf>,
+    disassembly: Option<PathBuf>,
+    disassembly_style: DisassemblyStyle,
+    default_gas: i64,
+    gas_model: GasModel,
+    format: PrepareFormat,
+) {
+    let engine = new_engine();
+
+    let tests = match prepare_file(&engine, &input, disassembly_style, default_gas, gas_model) {
+        Ok(tests) => tests,
+        Err(e) => {
             eprintln!("{e}");
-        },
+            std::process::exit(1);
+        }
+    };
+
+    let payload = format_prepared(&tests, format);
+
+    if let Some(output) = output {
+        if let Err(error) = std::fs::write(&output, payload) {
+            eprintln!("Failed to write {output:?}: {error}");
+            std::process::exit(1);
+        }
+    } else if format == PrepareFormat::Disasm {
+        // The disassembly already ends with a newline.
+        print!("{payload}");
+    } else {
+        println!("{payload}");
+    }
+
+    if let Some(disassembly) = disassembly {
+        if let Err(error) = std::fs::write(&disassembly, joined_disassembly(&tests)) {
+            eprintln!("Failed to write {disassembly:?}: {error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_schema(output: Option<PathBuf>) {
+    let payload = serde_json::to_string_pretty(&spectool::schema::testcase_schema()).unwrap();
+    if let Some(output) = output {
+        if let Err(error) = std::fs::write(&output, payload) {
+            eprintln!("Failed to write {output:?}: {error}");
+            std::process::exit(1);
+        }
+    } else {
+        println!("{payload}");
+    }
+}
+
+/// Loads a program from either a raw code and jump table binary or from the `program` field of a JSON testcase.
+fn load_program(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("failed to read {path:?}: {error}"))?;
+    let is_json_file = path.extension().map_or(false, |extension| extension == "json");
+    if !is_json_file {
+        let starts_like_json = bytes.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+        if !starts_like_json {
+            return Ok(bytes);
+        }
+    }
+
+    let json: serde_json::Value = match serde_json::from_slice(&bytes) {
+        Ok(json) => json,
+        Err(_) if !is_json_file => return Ok(bytes),
+        Err(error) => return Err(format!("{} is not valid JSON: {error}", path.display())),
+    };
+
+    let program = json
+        .get("program")
+        .ok_or_else(|| format!("{} is not a testcase: it has no 'program' field", path.display()))?;
+
+    serde_json::from_value(program.clone())
+        .map_err(|error| format!("the 'program' field of {} is not an array of bytes: {error}", path.display()))
+}
+
+fn main_wrap(input: PathBuf, gas: i64, regs: Vec<String>, output: Option<PathBuf>, disassembly: Option<PathBuf>) {
+    let test = std::fs::read(&input)
+        .map_err(|error| format!("failed to read {input:?}: {error}"))
+        .and_then(|program| {
+            let initial_regs = parse_reg_assignments(&regs)?;
+            let name = input.file_stem().map_or_else(|| "wrapped".into(), |stem| stem.to_string_lossy());
+            wrap_program(&new_engine(), &name, program, initial_regs, gas)
+                .map_err(|error| format!("{} is not a valid program: {error}", input.display()))
+        });
+
+    let test = match test {
+        Ok(test) => test,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let payload = to_canonical_json(&test.json);
+    if let Some(output) = output {
+        if let Err(error) = std::fs::write(&output, payload) {
+            eprintln!("Failed to write {output:?}: {error}");
+            std::process::exit(1);
+        }
+    } else {
+        print!("{payload}");
+    }
+
+    if let Some(disassembly) = disassembly {
+        if let Err(error) = std::fs::write(&disassembly, &test.disassembly) {
+            eprintln!("Failed to write {disassembly:?}: {error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_disassemble(input: PathBuf, bits: u32, options: DisassembleOptions) {
+    if bits != 32 && bits != 64 {
+        eprintln!("Invalid '--bits {bits}': expected either 32 or 64");
+        std::process::exit(1);
+    }
+
+    let program = match load_program(&input) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let options = DisassembleOptions {
+        is_64_bit: bits == 64,
+        ..options
+    };
+
+    match disassemble_blob_with_options(program, options) {
+        Ok(disassembly) => print!("{disassembly}"),
+        Err(error) => {
+            eprintln!("{} is not a valid program: {error}", input.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_roundtrip(input: PathBuf, bits: u32) {
+    if bits != 32 && bits != 64 {
+        eprintln!("Invalid '--bits {bits}': expected either 32 or 64");
+        std::process::exit(1);
+    }
+
+    let program = match load_program(&input) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    match roundtrip(program, bits == 64) {
+        Ok(_) => println!("{}: round-trips through the assembler", input.display()),
+        Err(error) => {
+            eprintln!("{}: {error}", input.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Generates `count` random testcases into `output_root`.
+///
+/// Programs which immediately trap on their very first instruction are discarded and don't count towards `count`.
+fn fuzz(output_root: &Path, count: usize, seed: u64, max_instructions: usize) -> Result<(), String> {
+    std::fs::create_dir_all(output_root).map_err(|error| format!("failed to create {output_root:?}: {error}"))?;
+
+    let engine = new_engine();
+    let mut rng = Rng::new(seed);
+    let mut generated = 0;
+    for _ in 0..count * 100 {
+        if generated == count {
+            break;
+        }
+
+        let name = format!("fuzz_{seed}_{generated}");
+        let source = random_program(&mut rng, max_instructions);
+        let test = prepare_input(&source, &engine, &name, &name, true).map_err(|error| format!("{error}\n{source}"))?;
+        if test.json.expected_pc == 0 && matches!(test.json.expected_status.as_str(), "panic" | "page-fault") {
+            continue;
+        }
+
+        let payload = to_canonical_json(&test.json);
+        write_if_changed(&output_root.join(format!("{name}.json")), payload.as_bytes());
+        // Not `<name>.txt`, since `verify` would take that for the stored disassembly of the program.
+        write_if_changed(&output_root.join(format!("{name}.source.txt")), source.as_bytes());
+        generated += 1;
+    }
+
+    if generated < count {
+        return Err(format!("only managed to generate {generated} out of {count} testcase(s)"));
+    }
+
+    Ok(())
+}
+
+fn main_fuzz(count: usize, seed: u64, max_instructions: usize, output: Option<PathBuf>) {
+    let output = output.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("output").join("fuzz"));
+    if let Err(error) = fuzz(&output, count, seed, max_instructions) {
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+
+    println!("Generated {count} testcase(s) into {}", output.display());
+}
+
+/// Loads and verifies a single JSON testcase, returning every violation that was found.
+fn verify_file(path: &Path) -> Vec<String> {
+    let payload = match std::fs::read(path) {
+        Ok(payload) => payload,
+        Err(error) => return vec![format!("failed to read: {error}")],
+    };
+
+    let mut testcase = match serde_json::from_slice::<TestcaseJson>(&payload) {
+        Ok(testcase) => testcase,
+        Err(error) => return vec![format!("failed to parse: {error}")],
+    };
+
+    match check_format_version(&testcase) {
+        Ok(None) => {}
+        Ok(Some(warning)) => eprintln!("WARN: {}: {warning}", path.display()),
+        Err(error) => return vec![error],
+    }
+
+    if let Err(error) = resolve_program_ref(&mut testcase, path.parent().unwrap_or(Path::new(""))) {
+        return vec![error];
+    }
+
+    let mut violations = verify_testcase(&testcase);
+    violations.extend(verify_stored_disassembly(path, &testcase));
+    violations
+}
+
+/// Compares the disassembly stored next to a JSON testcase (if any) with the one of its program.
+fn verify_stored_disassembly(path: &Path, testcase: &TestcaseJson) -> Option<String> {
+    let disassembly_path = path.with_extension("txt");
+    let stored = std::fs::read(&disassembly_path).ok()?;
+    let disassembly = match canonical_disassembly(&testcase.program) {
+        Ok(disassembly) => disassembly,
+        Err(error) => return Some(format!("disassembly: failed to disassemble the program: {error}")),
+    };
+
+    if stored == disassembly.as_bytes() {
+        return None;
+    }
+
+    Some(format!(
+        "disassembly: {} is out of sync with the program:\n{}",
+        disassembly_path.display(),
+        format_diff(&disassembly_path, &stored, disassembly.as_bytes())
+    ))
+}
+
+/// Returns `path` itself if it's a file, or every JSON file directly within it (sorted by name) if it's a directory.
+fn find_json_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let entries = std::fs::read_dir(path).map_err(|error| format!("Failed to read {path:?}: {error}"))?;
+    let mut paths: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |extension| extension == "json"))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn main_verify(path: Option<PathBuf>) {
+    let path = path.unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("output").join("programs"));
+    let paths = match find_json_files(&path) {
+        Ok(paths) => paths,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut violation_count = 0;
+    for path in &paths {
+        for violation in verify_file(path) {
+            eprintln!("{}: {violation}", path.display());
+            violation_count += 1;
+        }
+    }
+
+    if violation_count > 0 {
+        eprintln!("Found {violation_count} violation(s) in {} testcase(s)", paths.len());
+        std::process::exit(1);
+    }
+
+    println!("Verified {} testcase(s)", paths.len());
+}
+
+/// The results of benchmarking a set of testcases, as written by `spectool bench`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct BenchReport {
+    iterations: u32,
+    testcases: Vec<BenchResult>,
+    /// The testcases which weren't benchmarked, along with the reason why.
+    excluded: Vec<BenchExclusion>,
+    total_seconds: f64,
+    steps_per_second: f64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct BenchResult {
+    name: String,
+    /// The number of instructions executed by a single iteration.
+    step_count: u64,
+    /// The time taken by all of the iterations.
+    total_seconds: f64,
+    steps_per_second: f64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct BenchExclusion {
+    name: String,
+    reason: String,
+}
+
+/// Benchmarks every JSON testcase found at `path` whose name contains `filter` (if given).
+fn bench(path: &Path, iterations: u32, filter: Option<&str>) -> Result<BenchReport, String> {
+    let engine = new_engine();
+    let mut report = BenchReport {
+        iterations,
+        testcases: Vec::new(),
+        excluded: Vec::new(),
+        total_seconds: 0.0,
+        steps_per_second: 0.0,
+    };
+
+    let mut total_steps = 0;
+    for path in find_json_files(path)? {
+        let payload = std::fs::read(&path).map_err(|error| format!("failed to read {path:?}: {error}"))?;
+        let mut testcase: TestcaseJson = serde_json::from_slice(&payload).map_err(|error| format!("failed to parse {path:?}: {error}"))?;
+        if filter.map_or(false, |filter| !testcase.name.contains(filter)) {
+            continue;
+        }
+
+        resolve_program_ref(&mut testcase, path.parent().unwrap_or(Path::new(""))).map_err(|error| format!("{path:?}: {error}"))?;
+        if let Some(reason) = benchmark_exclusion_reason(&testcase) {
+            report.excluded.push(BenchExclusion {
+                name: testcase.name,
+                reason: reason.to_owned(),
+            });
+            continue;
+        }
+
+        let elapsed = benchmark_testcase(&engine, &testcase, iterations).map_err(|error| format!("{}: {error}", testcase.name))?;
+        let step_count = testcase.expected_step_count.unwrap_or(0);
+        let steps = step_count * u64::from(iterations);
+        let total_seconds = elapsed.as_secs_f64();
+        total_steps += steps;
+        report.total_seconds += total_seconds;
+        report.testcases.push(BenchResult {
+            name: testcase.name,
+            step_count,
+            total_seconds,
+            steps_per_second: steps_per_second(steps, total_seconds),
+        });
+    }
+
+    report.steps_per_second = steps_per_second(total_steps, report.total_seconds);
+    Ok(report)
+}
+
+fn steps_per_second(steps: u64, seconds: f64) -> f64 {
+    // A run which is too short to be measured isn't reported as infinitely fast.
+    if seconds > 0.0 {
+        steps as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+fn main_bench(iterations: u32, filter: Option<String>, path: Option<PathBuf>, output: Option<PathBuf>) {
+    let output_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("output");
+    let path = path.unwrap_or_else(|| output_root.join("programs"));
+    let output = output.unwrap_or_else(|| output_root.join("bench.json"));
+    let report = match bench(&path, iterations, filter.as_deref()) {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
+    for result in &report.testcases {
+        println!(
+            "{}: {:.0} steps/s ({:.3} ms for {iterations} iteration(s))",
+            result.name,
+            result.steps_per_second,
+            result.total_seconds * 1000.0
+        );
+    }
+
+    for exclusion in &report.excluded {
+        println!("{}: excluded, since it {}", exclusion.name, exclusion.reason);
+    }
+
+    println!(
+        "Total: {:.0} steps/s ({:.3} ms for {} testcase(s))",
+        report.steps_per_second,
+        report.total_seconds * 1000.0,
+        report.testcases.len()
+    );
+
+    let payload = serde_json::to_string_pretty(&report).unwrap();
+    if let Err(error) = std::fs::write(&output, payload) {
+        eprintln!("Failed to write {output:?}: {error}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spectool::disassemble;
+
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spectool-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn read_files(root: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut output = Vec::new();
+        let mut pending = vec![root.to_owned()];
+        while let Some(path) = pending.pop() {
+            if path.is_dir() {
+                pending.extend(std::fs::read_dir(&path).unwrap().map(|entry| entry.unwrap().path()));
+            } else {
+                output.push((path.strip_prefix(root).unwrap().to_owned(), std::fs::read(&path).unwrap()));
+            }
+        }
+
+        output.sort();
+        output
+    }
+
+    fn options(jobs: usize) -> GenerateOptions {
+        GenerateOptions {
+            jobs,
+            emit_program_file: false,
+            filters: Vec::new(),
+            cross_check: false,
+            emit_traces: false,
+            emit_cfg: false,
+            max_chunk_gap: 0,
+            format: OutputFormat::Json,
+            dedup_programs: false,
+            check: false,
+            emit_summary: false,
+            disassembly_style: DisassemblyStyle::default(),
+            default_gas: DEFAULT_GAS,
+            strict_post: false,
+            split_index: false,
+            gas_model: GasModel::Polkavm,
+            randomize_regs: 0,
+            randomize_seed: 0,
+        }
+    }
+
+    const PROGRAM: &str = "pub @main:\n    a0 = 1\n";
+
+    fn write_source(root: &Path, path: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("pre: gas = 10000\n\n{PROGRAM}")).unwrap();
+    }
+
+    #[test]
+    fn sources_are_discovered_recursively() {
+        let source_root = temp_dir("nested-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+        write_source(&source_root, "memory/load/u8.txt");
+
+        let sources = discover_sources(&source_root);
+        let names: Vec<_> = sources
+            .iter()
+            .map(|source| (source.category.as_str(), source.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("", "flat"), ("arithmetic", "arithmetic_add"), ("memory/load", "memory_load_u8")]
+        );
+        assert!(find_name_collisions(&sources, &options(1)).is_empty());
+
+        let output_root = temp_dir("nested-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        for name in ["flat", "arithmetic_add", "memory_load_u8"] {
+            assert!(output_root.join("programs").join(format!("{name}.json")).exists());
+        }
+
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert!(index_md.contains("## flat\n"));
+        assert!(index_md.contains("## arithmetic\n\nThis section contains 1 testcase.\n\n### arithmetic_add\n"));
+        assert!(index_md.contains("## memory/load\n\nThis section contains 1 testcase.\n\n### memory_load_u8\n"));
+        assert!(index_md.contains("| Start | Instructions | Cost |\n"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn watching_regenerates_only_the_changed_sources() {
+        let source_root = temp_dir("watch-sources");
+        write_source(&source_root, "first.txt");
+        write_source(&source_root, "arithmetic/second.txt");
+        let source_root = source_root.canonicalize().unwrap();
+        let output_root = temp_dir("watch-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        let second_json = std::fs::read(output_root.join("programs/arithmetic_second.json")).unwrap();
+
+        let first_path = source_root.join("first.txt");
+        std::fs::write(&first_path, "pre: gas = 10000\n\npub @main:\n    a0 = 2\n").unwrap();
+        let report = regenerate_changed(&source_root, &output_root, &options(1), &[first_path.clone()])
+            .unwrap()
+            .unwrap();
+        assert!(report.starts_with("=== first ===\n"), "{report}");
+        assert!(report.contains("\"expected-regs\""), "{report}");
+        assert!(report.contains("r7 = 0x2"), "{report}");
+
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(output_root.join("programs/first.json")).unwrap()).unwrap();
+        assert_eq!(json["expected-regs"][7], 2);
+        assert_eq!(
+            std::fs::read(output_root.join("programs/arithmetic_second.json")).unwrap(),
+            second_json
+        );
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert!(index_md.contains("## first\n"));
+        assert!(index_md.contains("r7 = 0x2"));
+        assert!(index_md.contains("### arithmetic_second\n"));
+
+        // An error is reported without giving up, and fixing it regenerates the source again.
+        std::fs::write(&first_path, "pub @main:\n    this is not an instruction\n").unwrap();
+        let error = regenerate_changed(&source_root, &output_root, &options(1), &[first_path.clone()])
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error, "Failed to regenerate first; waiting for the next change...");
+
+        write_source(&source_root, "first.txt");
+        assert!(regenerate_changed(&source_root, &output_root, &options(1), &[first_path.clone()])
+            .unwrap()
+            .is_ok());
+
+        // Paths which aren't selected sources are ignored.
+        let unrelated_path = source_root.join("notes.md.swp");
+        assert!(regenerate_changed(&source_root, &output_root, &options(1), &[unrelated_path]).is_none());
+        let filtered = GenerateOptions {
+            filters: vec!["arithmetic".to_owned()],
+            ..options(1)
+        };
+        assert!(regenerate_changed(&source_root, &output_root, &filtered, &[first_path]).is_none());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn the_index_starts_with_a_table_of_contents() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/toc");
+        let output_root = temp_dir("toc-output");
+        assert!(!generate(&fixtures.join("src"), &output_root, &options(1)));
+
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        let start = index_md.find("## Table of contents\n").unwrap();
+        let end = index_md.find("\n## flat\n").unwrap() + 1;
+        assert_eq!(&index_md[start..end], std::fs::read_to_string(fixtures.join("TOC.md")).unwrap());
+
+        let headers: Vec<_> = index_md.lines().filter(|line| line.starts_with("##")).collect();
+        assert_eq!(
+            headers,
+            [
+                "## Table of contents",
+                "## flat",
+                "## arithmetic",
+                "### sub",
+                "### arithmetic_add",
+                "## memory/load",
+                "### memory_load_u8"
+            ]
+        );
+        assert!(index_md.contains("## arithmetic\n\nThis section contains 2 testcases.\n"));
+
+        // The anchors only depend on the names, so regenerating produces the same index.
+        let other_output_root = temp_dir("toc-output-again");
+        assert!(!generate(&fixtures.join("src"), &other_output_root, &options(1)));
+        assert_eq!(index_md, std::fs::read_to_string(other_output_root.join("TESTCASES.md")).unwrap());
+
+        std::fs::remove_dir_all(output_root).unwrap();
+        std::fs::remove_dir_all(other_output_root).unwrap();
+    }
+
+    #[test]
+    fn the_split_index_links_to_a_page_per_testcase() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/toc");
+        let output_root = temp_dir("split-index-output");
+        let split_options = GenerateOptions {
+            split_index: true,
+            ..options(1)
+        };
+        assert!(!generate(&fixtures.join("src"), &output_root, &split_options));
+
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert_eq!(index_md, std::fs::read_to_string(fixtures.join("SPLIT_INDEX.md")).unwrap());
+
+        let targets: Vec<_> = index_md.split("](").skip(1).map(|rest| &rest[..rest.find(')').unwrap()]).collect();
+        assert_eq!(targets.len(), 4);
+        for target in targets {
+            let page = std::fs::read_to_string(output_root.join(target)).unwrap_or_else(|_| panic!("missing link target: {target}"));
+            assert!(page.starts_with("# "), "{target}");
+            assert!(page.contains("r7 = 0x1"), "{target}");
+        }
+
+        assert_eq!(doc_link("memory_load_u8"), "docs/memory_load_u8.md");
+        assert_eq!(doc_link("a b#c"), "docs/a%20b%23c.md");
+
+        let check_options = GenerateOptions {
+            check: true,
+            ..split_options
+        };
+        assert!(!generate(&fixtures.join("src"), &output_root, &check_options));
+
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn sources_with_missing_labels_fail_without_aborting_the_generation() {
+        let source_root = temp_dir("missing-labels-sources");
+        write_source(&source_root, "valid.txt");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/missing_labels");
+        for entry in std::fs::read_dir(fixtures).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(&path, source_root.join(path.file_name().unwrap())).unwrap();
+        }
+
+        let output_root = temp_dir("missing-labels-output");
+        assert!(generate(&source_root, &output_root, &options(1)));
+        assert!(output_root.join("programs/valid.json").exists());
+        assert!(!output_root.join("programs/missing_main.json").exists());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn negative_tests_are_listed_in_the_index_without_any_output() {
+        let source_root = temp_dir("negative-sources");
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/negative");
+        std::fs::copy(fixtures.join("duplicate_label.txt"), source_root.join("duplicate_label.txt")).unwrap();
+
+        let output_root = temp_dir("negative-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        assert!(!output_root.join("programs/duplicate_label.json").exists());
+
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert!(index_md.contains("## Negative tests\n"));
+        assert!(index_md.contains("### duplicate_label\n\nExpected error: `duplicate label \"main\"`\n"));
+
+        std::fs::copy(
+            fixtures.join("assembles_successfully.txt"),
+            source_root.join("assembles_successfully.txt"),
+        )
+        .unwrap();
+        assert!(generate(&source_root, &output_root, &options(1)));
+        assert!(!output_root.join("programs/assembles_successfully.json").exists());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn check_mode_reports_stale_outputs_without_writing_anything() {
+        let source_root = temp_dir("check-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let output_root = temp_dir("check-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+
+        let check_options = GenerateOptions { check: true, ..options(1) };
+        assert!(!generate(&source_root, &output_root, &check_options));
+
+        let path = output_root.join("programs/flat.json");
+        let mutated = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"initial-gas\": 10000", "\"initial-gas\": 9000");
+        std::fs::write(&path, &mutated).unwrap();
+
+        let generated = generate_outputs(&source_root, &output_root, &check_options).unwrap();
+        assert_eq!(
+            find_stale_outputs(&output_root, &generated.files, &check_options),
+            [StaleOutput::Modified(PathBuf::from("programs/flat.json"))]
+        );
+        assert!(generate(&source_root, &output_root, &check_options));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), mutated);
+
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        std::fs::remove_file(output_root.join("programs/arithmetic_add.bin")).unwrap();
+        std::fs::write(output_root.join("programs/removed.json"), "{}").unwrap();
+        assert_eq!(
+            find_stale_outputs(&output_root, &generated.files, &check_options),
+            [
+                StaleOutput::Missing(PathBuf::from("programs/arithmetic_add.bin")),
+                StaleOutput::Orphaned(PathBuf::from("programs/removed.json")),
+            ]
+        );
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn stale_disassemblies_are_reported() {
+        let source_root = temp_dir("disassembly-sources");
+        write_source(&source_root, "flat.txt");
+
+        let output_root = temp_dir("disassembly-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        let json_path = output_root.join("programs/flat.json");
+        let disassembly_path = output_root.join("programs/flat.txt");
+        assert!(std::fs::read_to_string(&disassembly_path).unwrap().contains("r7 = 0x1"));
+        assert_eq!(verify_file(&json_path), Vec::<String>::new());
+
+        let stale = std::fs::read_to_string(&disassembly_path).unwrap().replace("r7 = 0x1", "r7 = 0x2");
+        std::fs::write(&disassembly_path, stale).unwrap();
+        let violations = verify_file(&json_path);
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("disassembly: "), "{violations:?}");
+        assert!(
+            violations[0].contains("r7 = 0x2") && violations[0].contains("r7 = 0x1"),
+            "{violations:?}"
+        );
+
+        let check_options = GenerateOptions { check: true, ..options(1) };
+        let generated = generate_outputs(&source_root, &output_root, &check_options).unwrap();
+        assert_eq!(
+            find_stale_outputs(&output_root, &generated.files, &check_options),
+            [StaleOutput::Modified(PathBuf::from("programs/flat.txt"))]
+        );
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn old_testcases_are_migrated_to_the_current_format() {
+        let source_root = temp_dir("migrate-sources");
+        write_source(&source_root, "flat.txt");
+
+        let output_root = temp_dir("migrate-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        let json_path = output_root.join("programs/flat.json");
+        let current = std::fs::read_to_string(&json_path).unwrap();
+        assert!(current.ends_with("}\n"));
+        assert!(!migrate_file(&json_path).unwrap());
+
+        // A testcase from before the version was introduced, in a different style.
+        let mut json: serde_json::Value = serde_json::from_str(&current).unwrap();
+        json.as_object_mut().unwrap().remove("format-version");
+        json.as_object_mut().unwrap().remove("generator");
+        std::fs::write(&json_path, serde_json::to_string(&json).unwrap()).unwrap();
+        assert_eq!(verify_file(&json_path), Vec::<String>::new());
+
+        assert!(migrate_file(&json_path).unwrap());
+        assert_eq!(std::fs::read_to_string(&json_path).unwrap(), current);
+        assert!(!migrate_file(&json_path).unwrap());
+
+        json["format-version"] = serde_json::json!(spectool::FORMAT_VERSION + 1);
+        std::fs::write(&json_path, serde_json::to_string(&json).unwrap()).unwrap();
+        let violations = verify_file(&json_path);
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("format-version: "), "{violations:?}");
+        assert!(migrate_file(&json_path)
+            .unwrap_err()
+            .contains("is newer than the supported version"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn the_summary_only_covers_the_processed_testcases() {
+        let source_root = temp_dir("summary-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let output_root = temp_dir("summary-output");
+        let generate_options = GenerateOptions {
+            emit_summary: true,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        let read_summary = || -> Summary { serde_json::from_slice(&std::fs::read(output_root.join("SUMMARY.json")).unwrap()).unwrap() };
+        let summary = read_summary();
+        assert_eq!(summary.testcase_count, 2);
+        assert_eq!(summary.changed_count, 2);
+        assert_eq!(summary.status_counts.values().sum::<usize>(), 2);
+        assert_eq!(summary.mode_counts.get("64"), Some(&2));
+        assert!(summary.total_gas_consumed > 0);
+        assert_eq!(summary.average_gas_consumed, summary.total_gas_consumed / 2);
+        assert_eq!(summary.slowest.len(), 2);
+
+        let generate_options = GenerateOptions {
+            filters: vec!["flat".to_owned()],
+            ..generate_options
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        let summary = read_summary();
+        assert_eq!(summary.testcase_count, 1);
+        assert_eq!(summary.changed_count, 0);
+        assert_eq!(summary.slowest[0].name, "flat");
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn disassembly_flags_are_parsed_with_the_current_behavior_as_the_default() {
+        let Args::Generate { disassembly_flags, .. } = Args::try_parse_from(["spectool", "generate"]).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(disassembly_flags.style(), DisassemblyStyle::default());
+
+        let args = [
+            "spectool",
+            "prepare",
+            "-",
+            "--disasm-raw-bytes=false",
+            "--disasm-offset-targets=false",
+            "--disasm-abi-names=true",
+            "--disasm-gas-costs=true",
+            "--disasm-export-targets=true",
+            "--disasm-hex-offsets=true",
+        ];
+        let Args::Prepare { disassembly_flags, .. } = Args::try_parse_from(args).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(
+            disassembly_flags.style(),
+            DisassemblyStyle {
+                show_raw_bytes: false,
+                prefer_offset_jump_targets: false,
+                prefer_abi_reg_names: true,
+                show_gas_costs: true,
+                prefer_export_jump_targets: true,
+                prefer_hex_offsets: true,
+            }
+        );
+    }
+
+    #[test]
+    fn default_gas_flag_is_parsed() {
+        let Args::Generate { default_gas, .. } = Args::try_parse_from(["spectool", "generate"]).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(default_gas, DEFAULT_GAS);
+
+        let Args::Prepare { default_gas, .. } = Args::try_parse_from(["spectool", "prepare", "-", "--default-gas", "123"]).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(default_gas, 123);
+    }
+
+    #[test]
+    fn gas_model_flags_are_parsed() {
+        let Args::Generate { gas_model_flags, .. } = Args::try_parse_from(["spectool", "generate"]).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(gas_model_flags.gas_model(), GasModel::Polkavm);
+
+        let args = ["spectool", "prepare", "-", "--gas-model", "flat", "--flat-gas-cost", "3"];
+        let Args::Prepare { gas_model_flags, .. } = Args::try_parse_from(args).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(gas_model_flags.gas_model(), GasModel::Flat { cost_per_instruction: 3 });
+
+        let Args::Prepare { gas_model_flags, .. } = Args::try_parse_from(["spectool", "prepare", "-", "--gas-model", "flat"]).unwrap()
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            gas_model_flags.gas_model(),
+            GasModel::Flat {
+                cost_per_instruction: DEFAULT_FLAT_GAS_COST
+            }
+        );
+    }
+
+    #[test]
+    fn the_disassembly_style_applies_to_every_testcase() {
+        let source_root = temp_dir("disassembly-style-sources");
+        write_source(&source_root, "first.txt");
+        write_source(&source_root, "second.txt");
+
+        let output_root = temp_dir("disassembly-style-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert_eq!(index_md.matches("r7 = 0x1").count(), 2);
+
+        // Only the first testcase is prepared again, while the second one is loaded from its JSON.
+        let generate_options = GenerateOptions {
+            filters: vec!["first".to_owned()],
+            disassembly_style: DisassemblyStyle {
+                prefer_abi_reg_names: true,
+                ..DisassemblyStyle::default()
+            },
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        assert_eq!(index_md.matches("a0 = 0x1").count(), 2);
+        assert!(!index_md.contains("r7 = 0x1"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn benchmarks_report_plausible_rates() {
+        let source_root = temp_dir("bench-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+        std::fs::write(
+            source_root.join("host_call.txt"),
+            "pre: gas = 10000\n\npub @main:\n    ecalli 1\n\npost: host-calls = [1]\n",
+        )
+        .unwrap();
+
+        let output_root = temp_dir("bench-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+
+        let report = bench(&output_root.join("programs"), 3, None).unwrap();
+        let names: Vec<_> = report.testcases.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(names, ["arithmetic_add", "flat"]);
+        for result in &report.testcases {
+            assert!(result.step_count > 0);
+            assert!(result.total_seconds > 0.0);
+            assert!(result.steps_per_second > 0.0);
+        }
+        assert!(report.steps_per_second > 0.0);
+        assert_eq!(report.excluded.len(), 1);
+        assert_eq!(report.excluded[0].name, "host_call");
+
+        let report = bench(&output_root.join("programs"), 1, Some("flat")).unwrap();
+        assert_eq!(report.testcases.len(), 1);
+        assert!(report.excluded.is_empty());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn name_collisions_are_reported() {
+        let source_root = temp_dir("colliding-sources");
+        write_source(&source_root, "arithmetic_add.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let collisions = find_name_collisions(&discover_sources(&source_root), &options(1));
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].contains("'arithmetic_add'"));
+
+        let output_root = temp_dir("colliding-output");
+        assert!(generate(&source_root, &output_root, &options(1)));
+        assert!(!output_root.join("programs").exists());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn randomized_reg_variants_are_deterministic() {
+        let source_root = temp_dir("randomized-sources");
+        std::fs::write(
+            source_root.join("move.txt"),
+            "randomize: regs\npre: a1 = 2\npre: gas = 10000\n\npub @main:\n    a0 = a1\n\npost: a0 = 2\n",
+        )
+        .unwrap();
+        write_source(&source_root, "flat.txt");
+
+        let generate_with_seed = |name: &str, seed: u64| {
+            let output_root = temp_dir(name);
+            let generate_options = GenerateOptions {
+                randomize_regs: 2,
+                randomize_seed: seed,
+                ..options(1)
+            };
+            assert!(!generate(&source_root, &output_root, &generate_options));
+
+            let programs_root = output_root.join("programs");
+            assert!(!programs_root.join("flat_rand0.json").exists());
+            assert!(!programs_root.join("move_rand2.json").exists());
+            let files: Vec<_> = ["move", "move_rand0", "move_rand1"]
+                .into_iter()
+                .map(|name| {
+                    let path = programs_root.join(format!("{name}.json"));
+                    assert_eq!(verify_file(&path), Vec::<String>::new());
+                    serde_json::from_slice::<TestcaseJson>(&std::fs::read(path).unwrap()).unwrap()
+                })
+                .collect();
+
+            std::fs::remove_dir_all(output_root).unwrap();
+            files
+        };
+
+        let first = generate_with_seed("randomized-output-first", 1);
+        let second = generate_with_seed("randomized-output-second", 1);
+        let other_seed = generate_with_seed("randomized-output-other", 2);
+        assert_eq!(first, second);
+        assert_eq!(first[0], other_seed[0]);
+        assert_ne!(first[1], other_seed[1]);
+        assert_ne!(first[1].initial_regs, first[2].initial_regs);
+        for variant in &first[1..] {
+            assert_eq!(variant.initial_regs[Reg::A1 as usize], 2);
+            assert_eq!(variant.expected_regs[Reg::A0 as usize], 2);
+        }
+
+        std::fs::remove_dir_all(source_root).unwrap();
+    }
+
+    #[test]
+    fn program_binaries_are_emitted() {
+        let source_root = temp_dir("binary-sources");
+        write_source(&source_root, "flat.txt");
+
+        let output_root = temp_dir("binary-output");
+        let generate_options = GenerateOptions {
+            emit_program_file: true,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        let programs_root = output_root.join("programs");
+        let json: TestcaseJson = serde_json::from_slice(&std::fs::read(programs_root.join("flat.json")).unwrap()).unwrap();
+        assert_eq!(json.program_file.as_deref(), Some("flat.bin"));
+
+        let program = std::fs::read(programs_root.join("flat.bin")).unwrap();
+        assert_eq!(program, json.program);
+        assert_eq!(disassemble(program).unwrap(), disassemble(json.program).unwrap());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn filtered_generation_only_prepares_matching_sources() {
+        let source_root = temp_dir("filtered-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let output_root = temp_dir("filtered-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        let full_index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+
+        // Errors in sources which weren't selected must not fail the generation.
+        std::fs::write(source_root.join("broken.txt"), "this is not valid assembly").unwrap();
+
+        let programs_root = output_root.join("programs");
+        std::fs::remove_file(programs_root.join("flat.bin")).unwrap();
+        std::fs::remove_file(programs_root.join("arithmetic_add.bin")).unwrap();
+
+        let generate_options = GenerateOptions {
+            filters: vec!["arithmetic/".to_owned()],
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+        assert!(programs_root.join("arithmetic_add.bin").exists());
+        assert!(!programs_root.join("flat.bin").exists());
+        assert_eq!(std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap(), full_index_md);
+
+        let generate_options = GenerateOptions {
+            filters: vec!["broken".to_owned()],
+            ..options(1)
+        };
+        assert!(generate(&source_root, &output_root, &generate_options));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn cross_checked_generation_succeeds() {
+        // Without the compiler backend this only checks that the flag is ignored.
+        let source_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("src");
+        let output_root = temp_dir("cross-check");
+        let generate_options = GenerateOptions {
+            filters: vec!["inst_add".to_owned(), "host_calls".to_owned()],
+            cross_check: true,
+            ..options(4)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn traces_are_only_emitted_when_requested() {
+        let source_root = temp_dir("trace-sources");
+        write_source(&source_root, "flat.txt");
+
+        let output_root = temp_dir("trace-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        assert!(!output_root.join("traces").exists());
+
+        let generate_options = GenerateOptions {
+            emit_traces: true,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+        let trace = std::fs::read_to_string(output_root.join("traces").join("flat.txt")).unwrap();
+        assert!(trace.starts_with("pc = 0, "));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn control_flow_graphs_are_only_emitted_when_requested() {
+        let source_root = temp_dir("cfg-sources");
+        write_source(&source_root, "flat.txt");
+
+        let output_root = temp_dir("cfg-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+        assert!(!output_root.join("cfg").exists());
+
+        let generate_options = GenerateOptions {
+            emit_cfg: true,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+        let dot = std::fs::read_to_string(output_root.join("cfg").join("flat.dot")).unwrap();
+        assert!(dot.starts_with("digraph \"flat\" {"), "{dot}");
+        assert!(dot.contains("block_0 [label="), "{dot}");
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn generation_does_not_depend_on_the_number_of_jobs() {
+        let source_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("spec").join("src");
+        let output_1 = temp_dir("jobs-1");
+        let output_4 = temp_dir("jobs-4");
+
+        let found_errors_1 = generate(&source_root, &output_1, &options(1));
+        let found_errors_4 = generate(&source_root, &output_4, &options(4));
+        assert_eq!(found_errors_1, found_errors_4);
+
+        let files = read_files(&output_1);
+        assert!(!files.is_empty());
+        assert!(files == read_files(&output_4));
+
+        std::fs::remove_dir_all(output_1).unwrap();
+        std::fs::remove_dir_all(output_4).unwrap();
+    }
+
+    #[test]
+    fn fuzzing_is_deterministic_and_produces_valid_testcases() {
+        let first_root = temp_dir("fuzz-first");
+        let second_root = temp_dir("fuzz-second");
+        fuzz(&first_root, 20, 1234, 16).unwrap();
+        fuzz(&second_root, 20, 1234, 16).unwrap();
+
+        let files = read_files(&first_root);
+        assert_eq!(files.len(), 40);
+        assert_eq!(files, read_files(&second_root));
+
+        for (path, _) in files {
+            if path.extension().map_or(false, |extension| extension == "json") {
+                assert_eq!(verify_file(&first_root.join(&path)), Vec::<String>::new(), "{path:?}");
+            }
+        }
+
+        std::fs::remove_dir_all(first_root).unwrap();
+        std::fs::remove_dir_all(second_root).unwrap();
+    }
+
+    #[test]
+    fn every_case_of_a_source_produces_a_testcase() {
+        let source_root = temp_dir("multiple-cases-sources");
+        std::fs::write(
+            source_root.join("increment.txt"),
+            include_str!("../tests/fixtures/multiple_cases.txt"),
+        )
+        .unwrap();
+
+        let output_root = temp_dir("multiple-cases-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+
+        let programs_root = output_root.join("programs");
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        let mut initial_values = Vec::new();
+        for name in ["increment_large", "increment_small", "increment_wrapping"] {
+            let json: TestcaseJson = serde_json::from_slice(&std::fs::read(programs_root.join(format!("{name}.json"))).unwrap()).unwrap();
+            assert_eq!(json.name, name);
+            assert!(index_md.contains(&format!("## {name}\n")));
+            initial_values.push(json.initial_regs[Reg::A0 as usize]);
+        }
+
+        assert_eq!(initial_values, [1000, 1, u64::MAX]);
+        assert!(!programs_root.join("increment.json").exists());
+        assert!(!index_md.contains("Starts executing at"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn entry_points_are_noted_in_the_index() {
+        let source_root = temp_dir("entry-points-sources");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("spec/src/entry_points.txt"),
+            source_root.join("entry_points.txt"),
+        )
+        .unwrap();
+
+        let output_root = temp_dir("entry-points-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+
+        let index_md = std::fs::read_to_string(output_root.join("TESTCASES.md")).unwrap();
+        let (main_section, secondary_section) = index_md.split_once("## entry_points_secondary\n").unwrap();
+        assert!(!main_section.contains("Starts executing at"));
+        assert!(secondary_section.contains("Starts executing at `@secondary`"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn case_names_take_part_in_the_collision_check() {
+        let source_root = temp_dir("colliding-cases");
+        std::fs::write(
+            source_root.join("increment.txt"),
+            include_str!("../tests/fixtures/multiple_cases.txt"),
+        )
+        .unwrap();
+        write_source(&source_root, "increment_small.txt");
+
+        let collisions = find_name_collisions(&discover_sources(&source_root), &options(1));
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].contains("'increment_small'"));
+
+        std::fs::remove_dir_all(source_root).unwrap();
+    }
+
+    #[test]
+    fn identical_programs_are_only_written_once() {
+        let source_root = temp_dir("dedup-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let output_root = temp_dir("dedup-output");
+        let generate_options = GenerateOptions {
+            dedup_programs: true,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        let programs_root = output_root.join("programs");
+        let blobs: Vec<_> = std::fs::read_dir(programs_root.join("blobs")).unwrap().collect();
+        assert_eq!(blobs.len(), 1);
+        assert!(!programs_root.join("flat.bin").exists());
+
+        let mut program_refs = Vec::new();
+        for name in ["flat", "arithmetic_add"] {
+            let path = programs_root.join(format!("{name}.json"));
+            let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            assert!(json.get("program").is_none());
+            program_refs.push(json["program-ref"].clone());
+
+            assert!(verify_file(&path).is_empty());
+            let testcase = load_generated_testcase(&programs_root, name, DisassemblyStyle::default()).unwrap();
+            assert!(disassemble(testcase.json.program).unwrap().contains("r7 = 0x1"));
+        }
+        assert_eq!(program_refs[0], program_refs[1]);
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn every_testcase_is_aggregated_into_a_single_artifact() {
+        let source_root = temp_dir("combined-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+        write_source(&source_root, "memory/load/u8.txt");
+
+        let output_root = temp_dir("combined-output");
+        assert!(!generate(&source_root, &output_root, &options(1)));
+
+        let payload = std::fs::read(output_root.join("testcases.json")).unwrap();
+        let checksum = std::fs::read_to_string(output_root.join("testcases.json.sha256")).unwrap();
+        assert_eq!(checksum, format!("{}  testcases.json\n", spectool::sha256::sha256_hex(&payload)));
+
+        let combined: Vec<serde_json::Value> = serde_json::from_slice(&payload).unwrap();
+        let names: Vec<_> = combined.iter().map(|testcase| testcase["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["arithmetic_add", "flat", "memory_load_u8"]);
+
+        let standalone: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(output_root.join("programs/memory_load_u8.json")).unwrap()).unwrap();
+        assert_eq!(combined[2], standalone);
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+    }
+
+    #[test]
+    fn scale_testcases_decode_into_the_json_ones() {
+        let source_root = temp_dir("scale-sources");
+        write_source(&source_root, "flat.txt");
+        write_source(&source_root, "arithmetic/add.txt");
+
+        let output_root = temp_dir("scale-output");
+        let generate_options = GenerateOptions {
+            format: OutputFormat::Both,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &output_root, &generate_options));
+
+        let programs_root = output_root.join("programs");
+        for name in ["flat", "arithmetic_add"] {
+            let json: TestcaseJson = serde_json::from_slice(&std::fs::read(programs_root.join(format!("{name}.json"))).unwrap()).unwrap();
+            let decoded = spectool::scale::decode(&std::fs::read(programs_root.join(format!("{name}.scale"))).unwrap()).unwrap();
+            assert_eq!(decoded, json);
+        }
+
+        let scale_root = temp_dir("scale-only-output");
+        let generate_options = GenerateOptions {
+            format: OutputFormat::Scale,
+            ..options(1)
+        };
+        assert!(!generate(&source_root, &scale_root, &generate_options));
+        assert!(scale_root.join("programs").join("flat.scale").exists());
+        assert!(!scale_root.join("programs").join("flat.json").exists());
+
+        std::fs::remove_dir_all(source_root).unwrap();
+        std::fs::remove_dir_all(output_root).unwrap();
+        std::fs::remove_dir_all(scale_root).unwrap();
+    }
+
+    #[test]
+    fn prepare_emits_the_disassembly_of_every_case() {
+        let root = temp_dir("prepare-cases");
+        let path = root.join("cases.txt");
+        std::fs::write(&path, format!("modes: 32,64\n\n{PROGRAM}")).unwrap();
+
+        let tests = prepare_file(&new_engine(), &path, DisassemblyStyle::default(), DEFAULT_GAS, GasModel::Polkavm).unwrap();
+        assert_eq!(tests.len(), 2);
+
+        let both: serde_json::Value = serde_json::from_str(&format_prepared(&tests, PrepareFormat::Both)).unwrap();
+        let both = both.as_array().unwrap();
+        assert_eq!(both.len(), 2);
+        for (output, test) in both.iter().zip(&tests) {
+            assert_eq!(output["testcase"]["name"], test.json.name.as_str());
+            assert_eq!(output["disassembly"], test.disassembly.as_str());
+        }
+
+        let disassembly = format_prepared(&tests, PrepareFormat::Disasm);
+        assert_eq!(
+            disassembly,
+            format!("// cases_32\n{}\n// cases_64\n{}", tests[0].disassembly, tests[1].disassembly)
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
     }
 }
@@ -0,0 +1,613 @@
+//! The binary encoding of the testcases, following the JAM codec conventions.
+//!
+//! * Fixed-width integers are encoded in little-endian.
+//! * Booleans are encoded as a single `0` or `1` byte.
+//! * Byte strings and sequences are prefixed with their length, encoded as a JAM variable-length natural number.
+//! * Optional values are prefixed with a `0` byte if they're absent, or with a `1` byte if they're present.
+//!
+//! The fields of a testcase are encoded in the following order:
+//!
+//! | Field                         | Encoding                                                  |
+//! |-------------------------------|-----------------------------------------------------------|
+//! | `name`                        | bytes (UTF-8)                                             |
+//! | `description`                 | option(bytes (UTF-8))                                     |
+//! | `initial-regs`                | 13 × u64                                                  |
+//! | `initial-pc`                  | u32                                                       |
+//! | `initial-page-map`            | sequence(tuple(u32 address, u32 length, bool is-writable)) |
+//! | `initial-memory`              | sequence(tuple(u32 address, bytes contents))              |
+//! | `initial-gas`                 | i64 (two's complement)                                    |
+//! | `program`                     | bytes                                                     |
+//! | `program-file`                | option(bytes)                                             |
+//! | `program-hash-algorithm`      | option(bytes (UTF-8))                                     |
+//! | `program-hash`                | option(bytes (UTF-8, hex-encoded))                        |
+//! | `expected-status`             | u8 (0 = halt, 1 = panic, 2 = out-of-gas, 3 = page-fault, 4 = host) |
+//! | `expected-regs`               | 13 × u64                                                  |
+//! | `expected-pc`                 | u32                                                       |
+//! | `expected-memory`             | sequence(tuple(u32 address, bytes contents))              |
+//! | `expected-gas`                | i64 (two's complement)                                    |
+//! | `expected-page-fault-address` | option(u32)                                               |
+//! | `expected-page-fault-access`  | option(u8) (0 = read, 1 = write)                          |
+//! | `expected-exit-code`          | option(u64)                                               |
+//! | `expected-host-calls`         | option(sequence(u32))                                     |
+//! | `expected-interrupts`         | option(sequence(tuple(u8 kind, u32 argument)))            |
+//! | `expected-step-count`         | option(u64)                                               |
+//! | `faults-handled`              | option(sequence(tuple(u32 address, u8 action)))           |
+//! | `checkpoints`                 | option(sequence(checkpoint))                              |
+//! | `gas-model`                   | option(tuple(u8 kind, u32 cost-per-instruction))          |
+//! | `format-version`              | option(u32)                                               |
+//! | `generator`                   | option(bytes (UTF-8))                                     |
+//!
+//! The kind of an interrupt is encoded in the same way as `expected-status`, and its argument is the index of the host call
+//! for `host` interrupts, the page address for `page-fault` interrupts, and zero otherwise. The action taken for a handled
+//! fault is encoded as 0 = map-rw, 1 = map-ro, 2 = abort. The kind of a gas model is encoded as 0 = polkavm (with
+//! a zero cost per instruction), 1 = flat.
+//!
+//! A checkpoint is encoded as its label (bytes (UTF-8)), its `pc` (u32), its `expected-regs` (sequence(tuple(u8 reg,
+//! u64 value))), its `expected-gas` (option(i64)) and its `reached-at-step` (option(u64)).
+
+use crate::{
+    Checkpoint, CheckpointReg, GasModel, HandledFault, Interrupt, MemoryChunk, Page, TestcaseJson, FAULT_ACTIONS, PAGE_FAULT_ACCESSES,
+    STATUSES,
+};
+use polkavm::Reg;
+
+/// Encodes a testcase into its binary representation.
+pub fn encode(testcase: &TestcaseJson) -> Result<Vec<u8>, String> {
+    if testcase.program_ref.is_some() {
+        return Err("program-ref: the program must be embedded; resolve the reference first".to_owned());
+    }
+
+    let mut output = Vec::new();
+    write_bytes(&mut output, testcase.name.as_bytes());
+    write_option(&mut output, testcase.description.as_ref(), |output, description| {
+        write_bytes(output, description.as_bytes())
+    });
+    for value in testcase.initial_regs {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    output.extend_from_slice(&testcase.initial_pc.to_le_bytes());
+    write_length(&mut output, testcase.initial_page_map.len());
+    for page in &testcase.initial_page_map {
+        output.extend_from_slice(&page.address.to_le_bytes());
+        output.extend_from_slice(&page.length.to_le_bytes());
+        output.push(u8::from(page.is_writable));
+    }
+
+    write_chunks(&mut output, &testcase.initial_memory);
+    output.extend_from_slice(&testcase.initial_gas.to_le_bytes());
+    write_bytes(&mut output, &testcase.program);
+    write_option(&mut output, testcase.program_file.as_ref(), |output, path| {
+        write_bytes(output, path.as_bytes())
+    });
+    write_option(&mut output, testcase.program_hash_algorithm.as_ref(), |output, algorithm| {
+        write_bytes(output, algorithm.as_bytes())
+    });
+    write_option(&mut output, testcase.program_hash.as_ref(), |output, hash| {
+        write_bytes(output, hash.as_bytes())
+    });
+
+    output.push(index_of(STATUSES, &testcase.expected_status, "expected-status")?);
+    if testcase.expected_regs.len() != Reg::ALL.len() {
+        return Err(format!(
+            "expected-regs: expected {} registers, found {}",
+            Reg::ALL.len(),
+            testcase.expected_regs.len()
+        ));
+    }
+
+    for value in &testcase.expected_regs {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+
+    output.extend_from_slice(&testcase.expected_pc.to_le_bytes());
+    write_chunks(&mut output, &testcase.expected_memory);
+    output.extend_from_slice(&testcase.expected_gas.to_le_bytes());
+    write_option(&mut output, testcase.expected_page_fault_address.as_ref(), |output, address| {
+        output.extend_from_slice(&address.to_le_bytes())
+    });
+
+    let access = testcase
+        .expected_page_fault_access
+        .as_deref()
+        .map(|access| index_of(PAGE_FAULT_ACCESSES, access, "expected-page-fault-access"))
+        .transpose()?;
+    write_option(&mut output, access.as_ref(), |output, access| output.push(*access));
+    write_option(&mut output, testcase.expected_exit_code.as_ref(), |output, exit_code| {
+        output.extend_from_slice(&exit_code.to_le_bytes())
+    });
+    write_option(&mut output, testcase.expected_host_calls.as_ref(), |output, host_calls| {
+        write_length(output, host_calls.len());
+        for host_call in host_calls {
+            output.extend_from_slice(&host_call.to_le_bytes());
+        }
+    });
+
+    let interrupts = testcase
+        .expected_interrupts
+        .as_ref()
+        .map(|interrupts| {
+            interrupts
+                .iter()
+                .map(|interrupt| {
+                    let kind = index_of(STATUSES, &interrupt.kind, "expected-interrupts")?;
+                    Ok((kind, interrupt.host_call.or(interrupt.address).unwrap_or(0)))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .transpose()?;
+    write_option(&mut output, interrupts.as_ref(), |output, interrupts| {
+        write_length(output, interrupts.len());
+        for (kind, argument) in interrupts {
+            output.push(*kind);
+            output.extend_from_slice(&argument.to_le_bytes());
+        }
+    });
+    write_option(&mut output, testcase.expected_step_count.as_ref(), |output, step_count| {
+        output.extend_from_slice(&step_count.to_le_bytes())
+    });
+
+    let faults = testcase
+        .faults_handled
+        .as_ref()
+        .map(|faults| {
+            faults
+                .iter()
+                .map(|fault| Ok((fault.address, index_of(FAULT_ACTIONS, &fault.action, "faults-handled")?)))
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .transpose()?;
+    write_option(&mut output, faults.as_ref(), |output, faults| {
+        write_length(output, faults.len());
+        for (address, action) in faults {
+            output.extend_from_slice(&address.to_le_bytes());
+            output.push(*action);
+        }
+    });
+    write_option(&mut output, testcase.checkpoints.as_ref(), |output, checkpoints| {
+        write_length(output, checkpoints.len());
+        for checkpoint in checkpoints {
+            write_bytes(output, checkpoint.label.as_bytes());
+            output.extend_from_slice(&checkpoint.pc.to_le_bytes());
+            write_length(output, checkpoint.expected_regs.len());
+            for expected in &checkpoint.expected_regs {
+                output.push(expected.reg);
+                output.extend_from_slice(&expected.value.to_le_bytes());
+            }
+
+            write_option(output, checkpoint.expected_gas.as_ref(), |output, gas| {
+                output.extend_from_slice(&gas.to_le_bytes())
+            });
+            write_option(output, checkpoint.reached_at_step.as_ref(), |output, step| {
+                output.extend_from_slice(&step.to_le_bytes())
+            });
+        }
+    });
+    write_option(&mut output, testcase.gas_model.as_ref(), |output, gas_model| {
+        let (kind, cost_per_instruction) = match *gas_model {
+            GasModel::Polkavm => (0_u8, 0_u32),
+            GasModel::Flat { cost_per_instruction } => (1, cost_per_instruction),
+        };
+        output.push(kind);
+        output.extend_from_slice(&cost_per_instruction.to_le_bytes());
+    });
+    write_option(&mut output, testcase.format_version.as_ref(), |output, version| {
+        output.extend_from_slice(&version.to_le_bytes())
+    });
+    write_option(&mut output, testcase.generator.as_ref(), |output, generator| {
+        write_bytes(output, generator.as_bytes())
+    });
+
+    Ok(output)
+}
+
+/// Decodes a testcase from its binary representation.
+pub fn decode(input: &[u8]) -> Result<TestcaseJson, String> {
+    let mut reader = Reader { input, position: 0 };
+    let name = reader.read_string("name")?;
+    let description = if reader.read_bool("description")? {
+        Some(reader.read_string("description")?)
+    } else {
+        None
+    };
+    let mut initial_regs = [0; 13];
+    for value in &mut initial_regs {
+        *value = reader.read_u64("initial-regs")?;
+    }
+
+    let initial_pc = reader.read_u32("initial-pc")?;
+    let page_count = reader.read_length("initial-page-map")?;
+    let mut initial_page_map = Vec::new();
+    for _ in 0..page_count {
+        initial_page_map.push(Page {
+            address: reader.read_u32("initial-page-map")?,
+            length: reader.read_u32("initial-page-map")?,
+            is_writable: reader.read_bool("initial-page-map")?,
+        });
+    }
+
+    let initial_memory = reader.read_chunks("initial-memory")?;
+    let initial_gas = reader.read_u64("initial-gas")? as i64;
+    let program = reader.read_bytes("program")?.to_vec();
+    let program_file = if reader.read_bool("program-file")? {
+        Some(reader.read_string("program-file")?)
+    } else {
+        None
+    };
+    let program_hash_algorithm = if reader.read_bool("program-hash-algorithm")? {
+        Some(reader.read_string("program-hash-algorithm")?)
+    } else {
+        None
+    };
+    let program_hash = if reader.read_bool("program-hash")? {
+        Some(reader.read_string("program-hash")?)
+    } else {
+        None
+    };
+
+    let expected_status = reader.read_enum(STATUSES, "expected-status")?;
+    let mut expected_regs = Vec::new();
+    for _ in 0..Reg::ALL.len() {
+        expected_regs.push(reader.read_u64("expected-regs")?);
+    }
+
+    let expected_pc = reader.read_u32("expected-pc")?;
+    let expected_memory = reader.read_chunks("expected-memory")?;
+    let expected_gas = reader.read_u64("expected-gas")? as i64;
+    let expected_page_fault_address = if reader.read_bool("expected-page-fault-address")? {
+        Some(reader.read_u32("expected-page-fault-address")?)
+    } else {
+        None
+    };
+
+    let expected_page_fault_access = if reader.read_bool("expected-page-fault-access")? {
+        Some(reader.read_enum(PAGE_FAULT_ACCESSES, "expected-page-fault-access")?)
+    } else {
+        None
+    };
+
+    let expected_exit_code = if reader.read_bool("expected-exit-code")? {
+        Some(reader.read_u64("expected-exit-code")?)
+    } else {
+        None
+    };
+
+    let expected_host_calls = if reader.read_bool("expected-host-calls")? {
+        let count = reader.read_length("expected-host-calls")?;
+        let mut host_calls = Vec::new();
+        for _ in 0..count {
+            host_calls.push(reader.read_u32("expected-host-calls")?);
+        }
+
+        Some(host_calls)
+    } else {
+        None
+    };
+
+    let expected_interrupts = if reader.read_bool("expected-interrupts")? {
+        let count = reader.read_length("expected-interrupts")?;
+        let mut interrupts = Vec::new();
+        for _ in 0..count {
+            let kind = reader.read_enum(STATUSES, "expected-interrupts")?;
+            let argument = reader.read_u32("expected-interrupts")?;
+            interrupts.push(Interrupt {
+                host_call: (kind == "host").then_some(argument),
+                address: (kind == "page-fault").then_some(argument),
+                kind,
+            });
+        }
+
+        Some(interrupts)
+    } else {
+        None
+    };
+
+    let expected_step_count = if reader.read_bool("expected-step-count")? {
+        Some(reader.read_u64("expected-step-count")?)
+    } else {
+        None
+    };
+
+    let faults_handled = if reader.read_bool("faults-handled")? {
+        let count = reader.read_length("faults-handled")?;
+        let mut faults = Vec::new();
+        for _ in 0..count {
+            faults.push(HandledFault {
+                address: reader.read_u32("faults-handled")?,
+                action: reader.read_enum(FAULT_ACTIONS, "faults-handled")?,
+            });
+        }
+
+        Some(faults)
+    } else {
+        None
+    };
+
+    let checkpoints = if reader.read_bool("checkpoints")? {
+        let count = reader.read_length("checkpoints")?;
+        let mut checkpoints = Vec::new();
+        for _ in 0..count {
+            let label = reader.read_string("checkpoints")?;
+            let pc = reader.read_u32("checkpoints")?;
+            let reg_count = reader.read_length("checkpoints")?;
+            let mut expected_regs = Vec::new();
+            for _ in 0..reg_count {
+                expected_regs.push(CheckpointReg {
+                    reg: reader.read_u8("checkpoints")?,
+                    value: reader.read_u64("checkpoints")?,
+                });
+            }
+
+            let expected_gas = if reader.read_bool("checkpoints")? {
+                Some(reader.read_u64("checkpoints")? as i64)
+            } else {
+                None
+            };
+            let reached_at_step = if reader.read_bool("checkpoints")? {
+                Some(reader.read_u64("checkpoints")?)
+            } else {
+                None
+            };
+
+            checkpoints.push(Checkpoint {
+                label,
+                pc,
+                expected_regs,
+                expected_gas,
+                reached_at_step,
+            });
+        }
+
+        Some(checkpoints)
+    } else {
+        None
+    };
+
+    let gas_model = if reader.read_bool("gas-model")? {
+        let kind = reader.read_u8("gas-model")?;
+        let cost_per_instruction = reader.read_u32("gas-model")?;
+        Some(match kind {
+            0 => GasModel::Polkavm,
+            1 => GasModel::Flat { cost_per_instruction },
+            _ => return Err(format!("gas-model: unknown value: {kind}")),
+        })
+    } else {
+        None
+    };
+    let format_version = if reader.read_bool("format-version")? {
+        Some(reader.read_u32("format-version")?)
+    } else {
+        None
+    };
+    let generator = if reader.read_bool("generator")? {
+        Some(reader.read_string("generator")?)
+    } else {
+        None
+    };
+
+    if reader.position != input.len() {
+        return Err(format!("{} trailing byte(s) after the testcase", input.len() - reader.position));
+    }
+
+    Ok(TestcaseJson {
+        name,
+        description,
+        initial_regs,
+        initial_pc,
+        initial_page_map,
+        initial_memory,
+        initial_gas,
+        program,
+        program_file,
+        program_ref: None,
+        program_hash_algorithm,
+        program_hash,
+        expected_status,
+        expected_regs,
+        expected_pc,
+        expected_memory,
+        expected_gas,
+        expected_page_fault_address,
+        expected_page_fault_access,
+        expected_exit_code,
+        expected_host_calls,
+        expected_interrupts,
+        expected_step_count,
+        faults_handled,
+        checkpoints,
+        gas_model,
+        format_version,
+        generator,
+    })
+}
+
+fn index_of(values: &[&str], value: &str, field: &str) -> Result<u8, String> {
+    values
+        .iter()
+        .position(|known_value| *known_value == value)
+        .map(|index| index as u8)
+        .ok_or_else(|| format!("{field}: cannot encode '{value}'"))
+}
+
+/// Writes a natural number using the JAM variable-length encoding.
+fn write_natural(output: &mut Vec<u8>, value: u64) {
+    for length in 0..8 {
+        if value < 1 << (7 * (length + 1)) {
+            // The number of extra bytes is encoded as the number of leading one bits of the prefix.
+            let prefix = ((0xff_u64 << (8 - length)) & 0xff) | (value >> (8 * length));
+            output.push(prefix as u8);
+            output.extend_from_slice(&value.to_le_bytes()[..length]);
+            return;
+        }
+    }
+
+    output.push(0xff);
+    output.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    write_natural(output, length as u64);
+}
+
+fn write_bytes(output: &mut Vec<u8>, bytes: &[u8]) {
+    write_length(output, bytes.len());
+    output.extend_from_slice(bytes);
+}
+
+fn write_chunks(output: &mut Vec<u8>, chunks: &[MemoryChunk]) {
+    write_length(output, chunks.len());
+    for chunk in chunks {
+        output.extend_from_slice(&chunk.address.to_le_bytes());
+        write_bytes(output, &chunk.contents);
+    }
+}
+
+fn write_option<T>(output: &mut Vec<u8>, value: Option<&T>, write: impl FnOnce(&mut Vec<u8>, &T)) {
+    match value {
+        Some(value) => {
+            output.push(1);
+            write(output, value);
+        }
+        None => output.push(0),
+    }
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_slice(&mut self, length: usize, field: &str) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|&end| end <= self.input.len())
+            .ok_or_else(|| format!("{field}: unexpected end of input"))?;
+        let slice = &self.input[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self, field: &str) -> Result<u8, String> {
+        Ok(self.read_slice(1, field)?[0])
+    }
+
+    fn read_u32(&mut self, field: &str) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.read_slice(4, field)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self, field: &str) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.read_slice(8, field)?.try_into().unwrap()))
+    }
+
+    fn read_bool(&mut self, field: &str) -> Result<bool, String> {
+        match self.read_u8(field)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(format!("{field}: invalid boolean or option tag: {value}")),
+        }
+    }
+
+    fn read_natural(&mut self, field: &str) -> Result<u64, String> {
+        let prefix = self.read_u8(field)?;
+        let length = prefix.leading_ones() as usize;
+        if length == 8 {
+            return self.read_u64(field);
+        }
+
+        let mut bytes = [0; 8];
+        bytes[..length].copy_from_slice(self.read_slice(length, field)?);
+        let high = u64::from(prefix & ((1 << (7 - length)) - 1));
+        Ok(u64::from_le_bytes(bytes) | (high << (8 * length)))
+    }
+
+    fn read_length(&mut self, field: &str) -> Result<usize, String> {
+        let length = self.read_natural(field)?;
+        if length > (self.input.len() - self.position) as u64 {
+            return Err(format!("{field}: length out of bounds: {length}"));
+        }
+
+        Ok(length as usize)
+    }
+
+    fn read_bytes(&mut self, field: &str) -> Result<&'a [u8], String> {
+        let length = self.read_length(field)?;
+        self.read_slice(length, field)
+    }
+
+    fn read_string(&mut self, field: &str) -> Result<String, String> {
+        let bytes = self.read_bytes(field)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| format!("{field}: invalid UTF-8"))
+    }
+
+    fn read_enum(&mut self, values: &[&str], field: &str) -> Result<String, String> {
+        let index = self.read_u8(field)?;
+        values
+            .get(usize::from(index))
+            .map(|&value| value.to_owned())
+            .ok_or_else(|| format!("{field}: unknown value: {index}"))
+    }
+
+    fn read_chunks(&mut self, field: &str) -> Result<Vec<MemoryChunk>, String> {
+        let count = self.read_length(field)?;
+        let mut chunks = Vec::new();
+        for _ in 0..count {
+            chunks.push(MemoryChunk {
+                address: self.read_u32(field)?,
+                contents: self.read_bytes(field)?.to_vec(),
+            });
+        }
+
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_engine, prepare_input};
+
+    #[test]
+    fn naturals_round_trip() {
+        for value in [0, 1, 127, 128, 0x3fff, 0x4000, 0xffff_ffff, 1 << 56, u64::MAX] {
+            let mut output = Vec::new();
+            write_natural(&mut output, value);
+            let mut reader = Reader {
+                input: &output,
+                position: 0,
+            };
+            assert_eq!(reader.read_natural("test").unwrap(), value);
+            assert_eq!(reader.position, output.len());
+        }
+
+        let mut output = Vec::new();
+        write_natural(&mut output, 0x80);
+        assert_eq!(output, [0x80, 0x80]);
+    }
+
+    #[test]
+    fn decoded_testcases_are_identical_to_the_json_ones() {
+        let engine = new_engine();
+        for source in [
+            "fib.txt",
+            "host_calls.txt",
+            "exit_code.txt",
+            "page_fault_write.txt",
+            "inst_load_u32.txt",
+            "interrupts.txt",
+            "page_fault_resume.txt",
+            "checkpoints.txt",
+            "gas_model_flat.txt",
+        ] {
+            let path = format!("{}/spec/src/{source}", env!("CARGO_MANIFEST_DIR"));
+            let input = std::fs::read_to_string(path).unwrap();
+            let json = prepare_input(&input, &engine, source, source, true).unwrap().json;
+            let json: TestcaseJson = serde_json::from_str(&serde_json::to_string(&json).unwrap()).unwrap();
+
+            let encoded = encode(&json).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), json);
+            assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        }
+    }
+}
@@ -0,0 +1,507 @@
+//! The JSON Schema of the testcases, along with a validator for the subset of JSON Schema which it uses.
+//!
+//! The schema is built by hand and has to be kept in sync with [`TestcaseJson`](crate::TestcaseJson); the tests check
+//! it against the fixtures, the generated testcases and the checked-in `spec/schema.json`.
+
+use crate::{FAULT_ACTIONS, PAGE_FAULT_ACCESSES, PROGRAM_HASH_ALGORITHMS, STATUSES};
+use serde_json::{json, Map, Value};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+fn reference(definition: &str) -> Value {
+    json!({ "$ref": format!("#/$defs/{definition}") })
+}
+
+fn integer(minimum: Value, maximum: Value) -> Value {
+    json!({ "type": "integer", "minimum": minimum, "maximum": maximum })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn registers() -> Value {
+    json!({ "type": "array", "items": reference("u64"), "minItems": 13, "maxItems": 13 })
+}
+
+/// An object with the given properties, of which only the `required` ones must be present.
+fn object(properties: Vec<(&str, Value)>, required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties.into_iter().map(|(name, value)| (name.to_owned(), value)).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Builds the JSON Schema of a testcase, as emitted by `spectool schema`.
+pub fn testcase_schema() -> Value {
+    let page = object(
+        vec![
+            ("address", reference("u32")),
+            ("length", reference("u32")),
+            ("is-writable", json!({ "type": "boolean" })),
+        ],
+        &["address", "length", "is-writable"],
+    );
+
+    let program_ref = object(
+        vec![
+            ("hash", json!({ "type": "string", "pattern": "^[0-9a-f]{64}$" })),
+            ("path", json!({ "type": "string" })),
+        ],
+        &["hash", "path"],
+    );
+
+    let interrupt = object(
+        vec![
+            ("kind", one_of(STATUSES)),
+            ("host-call", reference("u32")),
+            ("address", reference("u32")),
+        ],
+        &["kind"],
+    );
+
+    let handled_fault = object(
+        vec![("address", reference("u32")), ("action", one_of(FAULT_ACTIONS))],
+        &["address", "action"],
+    );
+
+    let checkpoint = object(
+        vec![
+            ("label", json!({ "type": "string" })),
+            ("pc", reference("u32")),
+            (
+                "expected-regs",
+                array_of(object(
+                    vec![("reg", integer(json!(0), json!(12))), ("value", reference("u64"))],
+                    &["reg", "value"],
+                )),
+            ),
+            ("expected-gas", reference("i64")),
+            ("reached-at-step", reference("u64")),
+        ],
+        &["label", "pc", "expected-regs"],
+    );
+
+    let gas_model = object(
+        vec![("kind", one_of(&["polkavm", "flat"])), ("cost-per-instruction", reference("u32"))],
+        &["kind"],
+    );
+
+    let memory_chunk = array_of(object(
+        vec![("address", reference("u32")), ("contents", array_of(reference("u8")))],
+        &["address", "contents"],
+    ));
+
+    let mut schema = object(
+        vec![
+            ("name", json!({ "type": "string" })),
+            ("description", json!({ "type": "string" })),
+            ("initial-regs", registers()),
+            ("initial-pc", reference("u32")),
+            ("initial-page-map", array_of(page)),
+            ("initial-memory", reference("memory-chunk")),
+            ("initial-gas", reference("i64")),
+            ("program", array_of(reference("u8"))),
+            ("program-file", json!({ "type": "string" })),
+            ("program-ref", program_ref),
+            ("program-hash-algorithm", one_of(PROGRAM_HASH_ALGORITHMS)),
+            ("program-hash", json!({ "type": "string", "pattern": "^[0-9a-f]+$" })),
+            ("expected-status", one_of(STATUSES)),
+            ("expected-regs", registers()),
+            ("expected-pc", reference("u32")),
+            ("expected-memory", reference("memory-chunk")),
+            ("expected-gas", reference("i64")),
+            ("expected-page-fault-address", reference("u32")),
+            ("expected-page-fault-access", one_of(PAGE_FAULT_ACCESSES)),
+            ("expected-exit-code", reference("u64")),
+            ("expected-host-calls", array_of(reference("u32"))),
+            ("expected-interrupts", array_of(interrupt)),
+            ("expected-step-count", reference("u64")),
+            ("faults-handled", array_of(handled_fault)),
+            ("checkpoints", array_of(checkpoint)),
+            ("gas-model", gas_model),
+            ("format-version", reference("u32")),
+            ("generator", json!({ "type": "string" })),
+        ],
+        &[
+            "name",
+            "initial-regs",
+            "initial-pc",
+            "initial-page-map",
+            "initial-memory",
+            "initial-gas",
+            "expected-status",
+            "expected-regs",
+            "expected-pc",
+            "expected-memory",
+            "expected-gas",
+        ],
+    );
+
+    let schema_object = schema.as_object_mut().unwrap();
+    schema_object.insert("$schema".to_owned(), json!(DIALECT));
+    // The program is either embedded or shared through a reference, but never both.
+    schema_object.insert(
+        "oneOf".to_owned(),
+        json!([{ "required": ["program"] }, { "required": ["program-ref"] }]),
+    );
+    schema_object.insert(
+        "$defs".to_owned(),
+        json!({
+            "u8": integer(json!(u8::MIN), json!(u8::MAX)),
+            "u32": integer(json!(u32::MIN), json!(u32::MAX)),
+            "u64": integer(json!(u64::MIN), json!(u64::MAX)),
+            "i64": integer(json!(i64::MIN), json!(i64::MAX)),
+            "memory-chunk": memory_chunk,
+        }),
+    );
+
+    schema
+}
+
+/// Validates `value` against `schema`, returning every violation as `<path>: <message>`.
+///
+/// Only the keywords used by [`testcase_schema`] are supported; any other keyword is reported as a violation too,
+/// so that the validator can't silently go out of sync with the schema.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    validate_subschema(schema, schema, value, "$")
+}
+
+fn validate_subschema(root: &Value, schema: &Value, value: &Value, path: &str) -> Vec<String> {
+    let mut violations = Vec::new();
+    Validator {
+        root,
+        violations: &mut violations,
+    }
+    .validate(schema, value, path);
+    violations
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    violations: &'a mut Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn violation(&mut self, path: &str, message: impl core::fmt::Display) {
+        self.violations.push(format!("{path}: {message}"));
+    }
+
+    fn resolve(&mut self, reference: &str, path: &str) -> Option<&'a Value> {
+        let root = self.root;
+        let definition = reference
+            .strip_prefix("#/$defs/")
+            .and_then(|name| root.get("$defs").and_then(|definitions| definitions.get(name)));
+        if definition.is_none() {
+            self.violation(path, format!("unresolvable reference '{reference}'"));
+        }
+
+        definition
+    }
+
+    fn validate(&mut self, schema: &'a Value, value: &Value, path: &str) {
+        let Some(schema) = schema.as_object() else {
+            self.violation(path, "the schema is not an object");
+            return;
+        };
+
+        for (keyword, argument) in schema {
+            match keyword.as_str() {
+                "$schema" | "$defs" => {}
+                "$ref" => {
+                    if let Some(definition) = argument.as_str().and_then(|reference| self.resolve(reference, path)) {
+                        self.validate(definition, value, path);
+                    }
+                }
+                "type" => {
+                    let expected = argument.as_str().unwrap_or_default();
+                    let matches = match expected {
+                        "object" => value.is_object(),
+                        "array" => value.is_array(),
+                        "string" => value.is_string(),
+                        "boolean" => value.is_boolean(),
+                        "integer" => value.is_i64() || value.is_u64(),
+                        _ => {
+                            self.violation(path, format!("unsupported type '{expected}'"));
+                            continue;
+                        }
+                    };
+
+                    if !matches {
+                        self.violation(path, format!("expected {}, found {value}", with_article(expected)));
+                    }
+                }
+                "properties" => {
+                    let (Some(properties), Some(object)) = (argument.as_object(), value.as_object()) else {
+                        continue;
+                    };
+
+                    for (name, property) in properties {
+                        if let Some(value) = object.get(name) {
+                            self.validate(property, value, &format!("{path}.{name}"));
+                        }
+                    }
+                }
+                "additionalProperties" => {
+                    let (Some(false), Some(object)) = (argument.as_bool(), value.as_object()) else {
+                        continue;
+                    };
+
+                    let properties = schema.get("properties").and_then(Value::as_object);
+                    for name in object.keys() {
+                        if !properties.map_or(false, |properties| properties.contains_key(name)) {
+                            self.violation(path, format!("unknown property '{name}'"));
+                        }
+                    }
+                }
+                "required" => {
+                    let (Some(required), Some(object)) = (argument.as_array(), value.as_object()) else {
+                        continue;
+                    };
+
+                    for name in required.iter().filter_map(Value::as_str) {
+                        if !object.contains_key(name) {
+                            self.violation(path, format!("missing property '{name}'"));
+                        }
+                    }
+                }
+                "items" => {
+                    let Some(items) = value.as_array() else {
+                        continue;
+                    };
+
+                    for (index, item) in items.iter().enumerate() {
+                        self.validate(argument, item, &format!("{path}[{index}]"));
+                    }
+                }
+                "minItems" | "maxItems" => {
+                    let (Some(limit), Some(items)) = (argument.as_u64(), value.as_array()) else {
+                        continue;
+                    };
+
+                    let length = items.len() as u64;
+                    if keyword == "minItems" && length < limit {
+                        self.violation(path, format!("expected at least {limit} items, found {length}"));
+                    } else if keyword == "maxItems" && length > limit {
+                        self.violation(path, format!("expected at most {limit} items, found {length}"));
+                    }
+                }
+                "minimum" | "maximum" => {
+                    let (Some(limit), Some(number)) = (as_integer(argument), as_integer(value)) else {
+                        continue;
+                    };
+
+                    if keyword == "minimum" && number < limit {
+                        self.violation(path, format!("{number} is less than the minimum of {limit}"));
+                    } else if keyword == "maximum" && number > limit {
+                        self.violation(path, format!("{number} is greater than the maximum of {limit}"));
+                    }
+                }
+                "enum" => {
+                    let Some(variants) = argument.as_array() else {
+                        continue;
+                    };
+
+                    if !variants.contains(value) {
+                        self.violation(path, format!("{value} is not one of {argument}"));
+                    }
+                }
+                "pattern" => {
+                    let (Some(pattern), Some(string)) = (argument.as_str(), value.as_str()) else {
+                        continue;
+                    };
+
+                    match matches_pattern(pattern, string) {
+                        Some(true) => {}
+                        Some(false) => self.violation(path, format!("\"{string}\" doesn't match the pattern '{pattern}'")),
+                        None => self.violation(path, format!("unsupported pattern '{pattern}'")),
+                    }
+                }
+                "oneOf" => {
+                    let Some(alternatives) = argument.as_array() else {
+                        continue;
+                    };
+
+                    let matching = alternatives
+                        .iter()
+                        .filter(|alternative| validate_subschema(self.root, alternative, value, path).is_empty())
+                        .count();
+                    if matching != 1 {
+                        self.violation(path, format!("expected exactly one of {argument} to match, found {matching}"));
+                    }
+                }
+                _ => self.violation(path, format!("unsupported keyword '{keyword}'")),
+            }
+        }
+    }
+}
+
+fn with_article(kind: &str) -> String {
+    match kind {
+        "object" | "array" | "integer" => format!("an {kind}"),
+        _ => format!("a {kind}"),
+    }
+}
+
+fn as_integer(value: &Value) -> Option<i128> {
+    value.as_u64().map(i128::from).or_else(|| value.as_i64().map(i128::from))
+}
+
+/// Matches a string against a pattern of the form `^[<class>]+$` or `^[<class>]{<count>}$`, where the class only
+/// consists of `a-z`-style ranges; returns `None` for any other pattern.
+fn matches_pattern(pattern: &str, string: &str) -> Option<bool> {
+    let pattern = pattern.strip_prefix("^[")?.strip_suffix('$')?;
+    let (class, quantifier) = pattern.split_once(']')?;
+    let class: Vec<char> = class.chars().collect();
+    let ranges: Vec<(char, char)> = class
+        .chunks(3)
+        .map(|range| match *range {
+            [start, '-', end] => Some((start, end)),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    let count = string.chars().count();
+    let count_matches = match quantifier {
+        "+" => count > 0,
+        _ => quantifier.strip_prefix('{')?.strip_suffix('}')?.parse::<usize>().ok()? == count,
+    };
+
+    Some(
+        count_matches
+            && string
+                .chars()
+                .all(|ch| ranges.iter().any(|&(start, end)| (start..=end).contains(&ch))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_engine, prepare_input, split_cases};
+
+    fn load_json(path: &str) -> Value {
+        let path = format!("{}/{path}", env!("CARGO_MANIFEST_DIR"));
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_checked_in_schema_is_up_to_date() {
+        assert_eq!(load_json("spec/schema.json"), testcase_schema());
+    }
+
+    #[test]
+    fn fixture_vectors_match_the_schema() {
+        let schema = testcase_schema();
+        assert_eq!(
+            validate(&schema, &load_json("tests/fixtures/verify/valid.json")),
+            Vec::<String>::new()
+        );
+        // This one is structurally valid except for the number of its registers; the rest is caught by `verify`.
+        assert_eq!(
+            validate(&schema, &load_json("tests/fixtures/verify/broken.json")),
+            ["$.expected-regs: expected at least 13 items, found 12"]
+        );
+    }
+
+    #[test]
+    fn generated_vectors_match_the_schema() {
+        let schema = testcase_schema();
+        let engine = new_engine();
+        // Between them these cover all of the optional fields which are set by the generator.
+        for source in [
+            "exit_code",
+            "page_fault_write",
+            "page_fault_resume",
+            "host_calls",
+            "interrupts",
+            "entry_points",
+        ] {
+            let input = std::fs::read_to_string(format!("{}/spec/src/{source}.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+            for case in split_cases(&input, source).unwrap() {
+                let testcase = prepare_input(&case.input, &engine, source, source, true).unwrap();
+                let value = serde_json::to_value(&testcase.json).unwrap();
+                assert_eq!(validate(&schema, &value), Vec::<String>::new(), "{source}");
+            }
+        }
+    }
+
+    #[test]
+    fn every_field_is_described_by_the_schema() {
+        let mut testcase: crate::TestcaseJson = serde_json::from_value(load_json("tests/fixtures/verify/valid.json")).unwrap();
+        testcase.description = Some("description".to_owned());
+        testcase.program_file = Some("valid.bin".to_owned());
+        testcase.expected_status = "page-fault".to_owned();
+        testcase.expected_page_fault_address = Some(0x20000);
+        testcase.expected_page_fault_access = Some("write".to_owned());
+        testcase.expected_exit_code = Some(u64::MAX);
+        testcase.expected_host_calls = Some(vec![1]);
+        testcase.expected_interrupts = Some(vec![crate::Interrupt::new("page-fault")]);
+        testcase.expected_step_count = Some(1);
+        testcase.faults_handled = Some(vec![crate::HandledFault {
+            address: 0x20000,
+            action: "abort".to_owned(),
+        }]);
+        testcase.checkpoints = Some(vec![crate::Checkpoint {
+            label: "main".to_owned(),
+            pc: 0,
+            expected_regs: vec![crate::CheckpointReg { reg: 12, value: u64::MAX }],
+            expected_gas: Some(-1),
+            reached_at_step: Some(1),
+        }]);
+        testcase.gas_model = Some(crate::GasModel::Flat { cost_per_instruction: 2 });
+        testcase.format_version = Some(crate::FORMAT_VERSION);
+        testcase.generator = Some(crate::GENERATOR.to_owned());
+
+        let schema = testcase_schema();
+        let value = serde_json::to_value(&testcase).unwrap();
+        assert_eq!(validate(&schema, &value), Vec::<String>::new());
+
+        // The program can't be both embedded and referenced, so that's the only property which is missing.
+        let mut fields: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        fields.push("program-ref");
+        fields.sort_unstable();
+        let properties: Vec<&str> = schema["properties"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(fields, properties);
+    }
+
+    #[test]
+    fn malformed_vectors_are_rejected() {
+        let schema = testcase_schema();
+        assert_eq!(
+            validate(&schema, &load_json("tests/fixtures/schema/malformed.json")),
+            [
+                "$: unknown property 'expected_step_count'",
+                "$.expected-exit-code: expected an integer, found 1.5",
+                "$.expected-pc: expected an integer, found \"0\"",
+                "$.expected-status: \"crash\" is not one of [\"halt\",\"panic\",\"out-of-gas\",\"page-fault\",\"host\"]",
+                "$.initial-page-map[0]: unknown property 'is_writable'",
+                "$.initial-page-map[0]: missing property 'is-writable'",
+                "$.initial-regs[0]: -1 is less than the minimum of 0",
+                "$.program-hash: \"XYZ\" doesn't match the pattern '^[0-9a-f]+$'",
+                "$: missing property 'expected-gas'",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_program_must_be_either_embedded_or_referenced() {
+        let schema = testcase_schema();
+        let mut value = load_json("tests/fixtures/verify/valid.json");
+        value.as_object_mut().unwrap().insert(
+            "program-ref".to_owned(),
+            json!({ "hash": "00".repeat(32), "path": "blobs/program.bin" }),
+        );
+        assert_eq!(validate(&schema, &value).len(), 1);
+
+        value.as_object_mut().unwrap().remove("program");
+        assert_eq!(validate(&schema, &value), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const PROGRAM: &str = "pre: gas = 10000
+
+pub @main:
+    a0 = 1
+
+post: a0 = 1
+";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("spectool-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn spectool(args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_spectool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut child_stdin = child.stdin.take().unwrap();
+    if let Some(stdin) = stdin {
+        child_stdin.write_all(stdin.as_bytes()).unwrap();
+    }
+    drop(child_stdin);
+
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn prepare_writes_the_output_and_the_disassembly() {
+    let root = temp_dir("prepare-output");
+    let input = root.join("test.txt");
+    let output = root.join("test.json");
+    let disassembly = root.join("test.asm");
+    std::fs::write(&input, PROGRAM).unwrap();
+
+    let result = spectool(
+        &[
+            "prepare",
+            input.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--disassembly",
+            disassembly.to_str().unwrap(),
+        ],
+        None,
+    );
+    assert!(result.status.success());
+    assert!(result.stdout.is_empty());
+
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&output).unwrap()).unwrap();
+    assert_eq!(json["name"], "test");
+    assert!(std::fs::read_to_string(&disassembly).unwrap().contains("r7 = 0x1"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn prepare_reads_from_stdin() {
+    let result = spectool(&["prepare", "-"], Some(PROGRAM));
+    assert!(result.status.success());
+
+    let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(json["name"], "stdin");
+}
+
+#[test]
+fn prepare_fails_on_invalid_input() {
+    let result = spectool(&["prepare", "-"], Some("pub @main:\n    this is not an instruction\n"));
+    assert!(!result.status.success());
+    assert!(result.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("<stdin>:2: cannot parse line 2"));
+}
+
+#[test]
+fn prepare_pipes_the_fibonacci_program_through_every_format() {
+    let fib = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/src/fib.txt"));
+
+    let result = spectool(&["prepare", "-", "--format", "json"], Some(fib));
+    assert!(result.status.success());
+    assert!(result.stderr.is_empty());
+    let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(json["name"], "stdin");
+    assert_eq!(json["initial-regs"][7], 9);
+    // The JSON format is the default one.
+    assert_eq!(spectool(&["prepare", "-"], Some(fib)).stdout, result.stdout);
+
+    let result = spectool(&["prepare", "-", "--format", "disasm"], Some(fib));
+    assert!(result.status.success());
+    let disassembly = String::from_utf8(result.stdout).unwrap();
+    assert!(disassembly.contains("r8 = 0x1"), "{disassembly}");
+    assert!(serde_json::from_str::<serde_json::Value>(&disassembly).is_err());
+
+    let result = spectool(&["prepare", "-", "--format", "both"], Some(fib));
+    assert!(result.status.success());
+    let both: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(both["testcase"], json);
+    assert_eq!(both["disassembly"], disassembly.as_str());
+
+    let result = spectool(
+        &["prepare", "-", "--format", "both"],
+        Some("pub @main:\n    this is not an instruction\n"),
+    );
+    assert!(!result.status.success());
+    assert!(result.stdout.is_empty());
+    assert!(!result.stderr.is_empty());
+}
+
+#[test]
+fn prepare_uses_the_default_gas() {
+    let program = PROGRAM.replace("pre: gas = 10000\n", "");
+    let result = spectool(&["prepare", "-"], Some(&program));
+    assert!(result.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(json["initial-gas"], 10000);
+
+    let result = spectool(&["prepare", "-", "--default-gas", "123"], Some(&program));
+    assert!(result.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(json["initial-gas"], 123);
+}
+
+#[test]
+fn disassemble_accepts_json_testcases_and_raw_binaries() {
+    let root = temp_dir("disassemble");
+    let input = root.join("test.txt");
+    let json_path = root.join("test.json");
+    std::fs::write(&input, PROGRAM).unwrap();
+    assert!(
+        spectool(&["prepare", input.to_str().unwrap(), "-o", json_path.to_str().unwrap()], None)
+            .status
+            .success()
+    );
+
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&json_path).unwrap()).unwrap();
+    let program: Vec<u8> = serde_json::from_value(json["program"].clone()).unwrap();
+    let binary_path = root.join("test.bin");
+    std::fs::write(&binary_path, program).unwrap();
+
+    let from_json = spectool(&["disassemble", json_path.to_str().unwrap()], None);
+    assert!(from_json.status.success());
+    let from_binary = spectool(&["disassemble", binary_path.to_str().unwrap()], None);
+    assert!(from_binary.status.success());
+    assert_eq!(from_json.stdout, from_binary.stdout);
+    assert!(String::from_utf8_lossy(&from_json.stdout).contains("r7 = 0x1"));
+
+    let with_raw_bytes = spectool(&["disassemble", "--raw-bytes", binary_path.to_str().unwrap()], None);
+    assert!(with_raw_bytes.status.success());
+    assert_ne!(with_raw_bytes.stdout, from_binary.stdout);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn wrap_runs_a_raw_program() {
+    // The same program as in the tests of the `pvm-shell`.
+    const FIB: &[u8] = &[
+        0, 0, 33, 51, 8, 1, 51, 9, 1, 40, 3, 0, 149, 119, 255, 81, 7, 12, 100, 138, 200, 152, 8, 100, 169, 40, 243, 100, 135, 51, 8, 51, 9,
+        1, 50, 0, 73, 147, 82, 213, 0,
+    ];
+
+    let root = temp_dir("wrap");
+    let binary_path = root.join("fib.bin");
+    std::fs::write(&binary_path, FIB).unwrap();
+
+    let result = spectool(&["wrap", binary_path.to_str().unwrap(), "--reg", "a0=9", "--gas", "1000"], None);
+    assert!(result.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(json["name"], "fib");
+    assert_eq!(json["initial-regs"][7], 9);
+    assert_eq!(json["initial-gas"], 1000);
+    assert_eq!(json["expected-status"], "panic");
+    assert_eq!(json["program"], serde_json::json!(FIB));
+
+    let result = spectool(&["wrap", binary_path.to_str().unwrap(), "--reg", "a0"], None);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("invalid register assignment 'a0'"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn roundtrip_reports_whether_the_program_reassembles() {
+    let root = temp_dir("roundtrip");
+    let input = root.join("test.txt");
+    let json_path = root.join("test.json");
+    std::fs::write(&input, PROGRAM).unwrap();
+    assert!(
+        spectool(&["prepare", input.to_str().unwrap(), "-o", json_path.to_str().unwrap()], None)
+            .status
+            .success()
+    );
+
+    let result = spectool(&["roundtrip", json_path.to_str().unwrap()], None);
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("round-trips through the assembler"));
+
+    let result = spectool(&["disassemble", "--reassemblable", json_path.to_str().unwrap()], None);
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("    r7 = 0x1\n"));
+
+    let binary_path = root.join("broken.bin");
+    std::fs::write(&binary_path, [0xff; 16]).unwrap();
+    let result = spectool(&["roundtrip", binary_path.to_str().unwrap()], None);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("broken.bin: "));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn disassemble_distinguishes_invalid_json_from_invalid_programs() {
+    let root = temp_dir("disassemble-errors");
+    let json_path = root.join("broken.json");
+    std::fs::write(&json_path, "{ \"program\": [1, 2").unwrap();
+    let result = spectool(&["disassemble", json_path.to_str().unwrap()], None);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("is not valid JSON"));
+
+    let binary_path = root.join("broken.bin");
+    std::fs::write(&binary_path, [0xff; 16]).unwrap();
+    let result = spectool(&["disassemble", binary_path.to_str().unwrap()], None);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("is not a valid program"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn verify_reports_every_violation_with_its_file_and_field() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/verify");
+    let result = spectool(&["verify", &format!("{fixtures}/valid.json")], None);
+    assert!(result.status.success());
+
+    let result = spectool(&["verify", fixtures], None);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!stderr.contains("valid.json"));
+    for field in [
+        "expected-regs",
+        "initial-page-map",
+        "initial-memory",
+        "expected-memory",
+        "expected-pc",
+        "program-hash",
+        "expected-exit-code",
+    ] {
+        assert!(
+            stderr.contains(&format!("broken.json: {field}: ")),
+            "missing a violation of '{field}' in: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn schema_matches_the_checked_in_schema() {
+    let result = spectool(&["schema"], None);
+    assert!(result.status.success());
+
+    let schema: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    let checked_in: serde_json::Value =
+        serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/spec/schema.json"))).unwrap();
+    assert_eq!(schema, checked_in);
+    assert_eq!(schema["properties"]["expected-step-count"]["$ref"], "#/$defs/u64");
+}
+
+#[test]
+fn test_reports_mismatching_fields() {
+    let root = temp_dir("test");
+    let input = root.join("test.txt");
+    let json_path = root.join("test.json");
+    std::fs::write(&input, PROGRAM).unwrap();
+    assert!(
+        spectool(&["prepare", input.to_str().unwrap(), "-o", json_path.to_str().unwrap()], None)
+            .status
+            .success()
+    );
+
+    let result = spectool(&["test", json_path.to_str().unwrap()], None);
+    assert!(result.status.success());
+    assert!(String::from_utf8_lossy(&result.stdout).contains("Passed 1 testcase(s)"));
+
+    let mut json: serde_json::Value = serde_json::from_slice(&std::fs::read(&json_path).unwrap()).unwrap();
+    json["expected-gas"] = serde_json::json!(1);
+    std::fs::write(&json_path, serde_json::to_string(&json).unwrap()).unwrap();
+    let result = spectool(&["test", json_path.to_str().unwrap()], None);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("test.json: expected-gas: expected 1, is "), "{stderr}");
+
+    std::fs::remove_dir_all(root).unwrap();
+}